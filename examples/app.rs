@@ -4,7 +4,6 @@
 
 mod plugin_shortcode;
 
-use axum_router_plugin;
 use axum::{
     extract::{Extension, Request},
     response::Html,
@@ -36,7 +35,9 @@ async fn test(
 async fn main() {
 
     // Load plugins from the Plugins.toml file
-    let axum_plugins = axum_router_plugin::Plugins::new(Some(true));
+    let axum_plugins = axum_router_plugin::Plugins::new(Some(true))
+        // The prebuilt example plugins don't export plugin_abi_version
+        .allow_unversioned_plugins(true);
     let plugins_router = match axum_plugins.load() {
         Ok(router) => router,
         Err(err) => panic!("Error loading plugins: {}", err),
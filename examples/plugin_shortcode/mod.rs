@@ -7,7 +7,7 @@ use serde::Deserialize;
 use tera::Function;
 use once_cell::sync::Lazy;
 
-static CLIENT: Lazy<reqwest::Client> = Lazy::new(|| reqwest::Client::new());
+static CLIENT: Lazy<reqwest::Client> = Lazy::new(reqwest::Client::new);
 
 use crate::ADDRESS;
 
@@ -147,8 +147,7 @@ Invalid method {} for url {} (only GET and POST methods available)
 </script>"#,
     fetch_js);
 
-    if method.to_lowercase().as_str() == "get" && alt.is_some() {
-        let alt = alt.unwrap();
+    if let (true, Some(alt)) = (method.to_lowercase().as_str() == "get", alt) {
         js_code.to_string() + &format!(r#"<noscript><a href="{}">{}</a></noscript>"#, url, alt)
     } else {
         js_code
@@ -7,7 +7,9 @@ use axum::{
 #[tokio::main]
 async fn main() {
     // Load plugins from the Plugins.toml file
-    let axum_plugins = Plugins::new(Some(true));
+    let axum_plugins = Plugins::new(Some(true))
+        // The prebuilt example plugins don't export plugin_abi_version
+        .allow_unversioned_plugins(true);
     let plugins_router = match axum_plugins.load() {
         Ok(router) => router,
         Err(err) => panic!("Error loading plugins: {}", err),
@@ -0,0 +1,238 @@
+//! Access logging of plugin requests.
+//!
+//! One line is printed per request handled by a plugin route, in a plain or JSON format
+//! or in the Apache Common and Combined Log Formats understood by log-analysis tools.
+
+use crate::log::info;
+use axum::{
+    extract::{OriginalUri, Request},
+    response::Response,
+};
+use hyper::{body::Body, header, HeaderMap};
+use serde_json::json;
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The format of the access log lines.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AccessLogFormat {
+    /// `<remote> <method> <path> <status> <bytes> <duration>ms`.
+    Plain,
+    /// A JSON object per line.
+    Json,
+    /// The Apache Common Log Format:
+    /// `<remote> - - [<time>] "<request line>" <status> <bytes>`.
+    Common,
+    /// The Apache Combined Log Format, the Common Log Format followed by the quoted
+    /// `Referer` and `User-Agent` headers.
+    Combined,
+}
+
+/// The request fields captured before the request is handed to the plugin.
+pub(crate) struct AccessEntry {
+    remote: String,
+    time: SystemTime,
+    method: String,
+    target: String,
+    version: String,
+    referer: String,
+    user_agent: String,
+}
+
+impl AccessEntry {
+
+    /// Captures the logged fields of a request.
+    ///
+    /// # Arguments
+    /// * `request` - The incoming request.
+    /// * `client` - The client address, the peer or the one forwarded by a trusted proxy.
+    pub(crate) fn new(request: &Request, client: Option<IpAddr>) -> Self {
+        let remote = client
+            .map(|ip| ip.to_string())
+            .unwrap_or_else(|| "-".to_string());
+
+        let uri = match request.extensions().get::<OriginalUri>() {
+            Some(OriginalUri(uri)) => uri,
+            None => request.uri(),
+        };
+        let target = uri.path_and_query()
+            .map(|target| target.as_str().to_string())
+            .unwrap_or_else(|| uri.path().to_string());
+
+        AccessEntry {
+            remote,
+            time: SystemTime::now(),
+            method: request.method().to_string(),
+            target,
+            version: format!("{:?}", request.version()),
+            referer: header_value(request.headers(), header::REFERER),
+            user_agent: header_value(request.headers(), header::USER_AGENT),
+        }
+    }
+
+    /// Prints the log line of the request once its response is built.
+    pub(crate) fn log(&self, format: AccessLogFormat, response: &Response) {
+        info!("{}", self.line(format, response));
+    }
+
+    /// Formats the log line of the request.
+    fn line(&self, format: AccessLogFormat, response: &Response) -> String {
+
+        let status = response.status().as_u16();
+        let bytes = response_size(response);
+        let elapsed = self.time.elapsed().unwrap_or_default();
+
+        match format {
+            AccessLogFormat::Plain => format!(
+                "{} {} {} {} {} {:.3}ms",
+                self.remote,
+                self.method,
+                self.target,
+                status,
+                bytes.map_or("-".to_string(), |bytes| bytes.to_string()),
+                elapsed.as_secs_f64() * 1000.0,
+            ),
+            AccessLogFormat::Json => json!({
+                "remote_addr": self.remote,
+                "time": self.time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+                "method": self.method,
+                "target": self.target,
+                "version": self.version,
+                "status": status,
+                "bytes": bytes,
+                "referer": self.referer,
+                "user_agent": self.user_agent,
+                "duration_ms": elapsed.as_secs_f64() * 1000.0,
+            }).to_string(),
+            AccessLogFormat::Common | AccessLogFormat::Combined => {
+                // https://httpd.apache.org/docs/current/logs.html#common
+                let mut line = format!(
+                    "{} - - [{}] \"{} {} {}\" {} {}",
+                    self.remote,
+                    clf_time(self.time),
+                    self.method,
+                    escape(&self.target),
+                    self.version,
+                    status,
+                    bytes.map_or("-".to_string(), |bytes| bytes.to_string()),
+                );
+                if format == AccessLogFormat::Combined {
+                    line.push_str(&format!(
+                        " \"{}\" \"{}\"",
+                        escape(&self.referer),
+                        escape(&self.user_agent),
+                    ));
+                }
+                line
+            },
+        }
+    }
+}
+
+/// Returns a header value, or `-` when it's missing or not printable.
+fn header_value(headers: &HeaderMap, name: header::HeaderName) -> String {
+    headers.get(name)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("-")
+        .to_string()
+}
+
+/// Returns the size of the response body, when it's known.
+fn response_size(response: &Response) -> Option<u64> {
+    response.headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+        .or_else(|| response.body().size_hint().exact())
+}
+
+/// Escapes the quotes and backslashes of a quoted log field.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Formats a time as `10/Oct/2000:13:55:36 +0000`, in UTC.
+fn clf_time(time: SystemTime) -> String {
+
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let secs = time.duration_since(UNIX_EPOCH).unwrap_or(Duration::ZERO).as_secs();
+    let (days, secs_of_day) = ((secs / 86400) as i64, secs % 86400);
+
+    // Civil date from days since the epoch: https://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+        day,
+        MONTHS[(month - 1) as usize],
+        year,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, Logs, TempDir};
+    use axum::body::Body;
+
+    #[test]
+    fn time_is_formatted_as_in_the_common_log_format() {
+        let time = UNIX_EPOCH + Duration::from_secs(971_192_136);
+
+        assert_eq!(clf_time(time), "10/Oct/2000:15:35:36 +0000");
+    }
+
+    /// Checks a line against the Combined Log Format grammar:
+    /// `host ident authuser [date] "request" status bytes "referer" "user-agent"`.
+    fn is_combined(line: &str) -> bool {
+        let Some((host, rest)) = line.split_once(" - - [") else { return false };
+        let Some((date, rest)) = rest.split_once("] \"") else { return false };
+        let Some((request, rest)) = rest.split_once("\" ") else { return false };
+        let fields: Vec<&str> = rest.splitn(3, ' ').collect();
+        let [status, bytes, quoted] = fields[..] else { return false };
+        !host.is_empty()
+            && date.len() == "10/Oct/2000:13:55:36 +0000".len()
+            && request.split(' ').count() == 3
+            && status.len() == 3 && status.parse::<u16>().is_ok()
+            && (bytes == "-" || bytes.parse::<u64>().is_ok())
+            && quoted.starts_with('"') && quoted.ends_with('"') && quoted.contains("\" \"")
+    }
+
+    #[tokio::test]
+    async fn combined_log_line_matches_the_grammar() {
+        let dir = TempDir::new();
+        let (plugin, _) = testing::route_plugin(r#"{"path": "/", "method_router": "get"}"#, "hello");
+        let router = testing::plugins(&dir, true)
+            .access_log(AccessLogFormat::Combined)
+            .register("site", plugin)
+            .load()
+            .unwrap();
+
+        let (logs, _guard) = Logs::capture();
+        let request = Request::get("/site?page=1")
+            .header(header::REFERER, "https://example.com/")
+            .header(header::USER_AGENT, "curl/8.0 \"test\"")
+            .body(Body::empty())
+            .unwrap();
+        testing::send(&router, request).await;
+
+        let line = logs.messages().into_iter().find(|message| message.contains("GET /site")).unwrap();
+        assert!(is_combined(&line), "{}", line);
+        assert!(line.starts_with("- - - ["), "{}", line);
+        assert!(line.ends_with(r#""GET /site?page=1 HTTP/1.1" 200 5 "https://example.com/" "curl/8.0 \"test\"""#), "{}", line);
+    }
+}
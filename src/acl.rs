@@ -0,0 +1,294 @@
+//! Host-side access control for plugin routes.
+//!
+//! Rules match the request method and path and are checked before the plugin is invoked,
+//! so coarse authorization can be configured without changing the plugins. The path is
+//! matched once percent-decoded and normalized, so `/admin%2Fx`, `//admin/x` or
+//! `/public/../admin/x` can't escape a rule for `/admin/**`.
+
+use crate::csrf::constant_time_eq;
+use hyper::{HeaderMap, Method};
+
+/// What a matching rule does with the request.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Action {
+    Allow,
+    Deny,
+}
+
+/// A single access rule, matching a method and a path glob.
+///
+/// In the glob, `*` matches any characters inside a path segment and `**` matches any
+/// characters across segments, e.g. `/plugin/*/admin/**`.
+#[derive(Debug, Clone)]
+pub struct AccessRule {
+    action: Action,
+    method: String,
+    pattern: String,
+    unless_header: Option<(String, String)>,
+}
+
+impl AccessRule {
+
+    /// Creates a rule that allows the matching requests.
+    ///
+    /// # Arguments
+    /// * `method` - The request method, or `*` for any method.
+    /// * `pattern` - The glob the request path must match.
+    pub fn allow(method: impl Into<String>, pattern: impl Into<String>) -> Self {
+        Self::with_action(Action::Allow, method.into(), pattern.into())
+    }
+
+    /// Creates a rule that denies the matching requests with `403 Forbidden`.
+    ///
+    /// # Arguments
+    /// * `method` - The request method, or `*` for any method.
+    /// * `pattern` - The glob the request path must match.
+    pub fn deny(method: impl Into<String>, pattern: impl Into<String>) -> Self {
+        Self::with_action(Action::Deny, method.into(), pattern.into())
+    }
+
+    /// Makes the rule skip the requests whose header `name` has exactly `value`, e.g. to
+    /// deny `DELETE` under `/admin` unless an admin token is sent.
+    ///
+    /// Any client can send any header, so this is only a security boundary if `value`
+    /// is a secret, or if the header is set by a trusted proxy that removes the clients'
+    /// own.
+    pub fn unless_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.unless_header = Some((name.into(), value.into()));
+        self
+    }
+
+    fn with_action(action: Action, method: String, pattern: String) -> Self {
+        AccessRule {
+            action,
+            method,
+            pattern,
+            unless_header: None,
+        }
+    }
+
+    /// Checks whether the rule applies to a request.
+    fn matches(&self, method: &Method, path: &str, headers: &HeaderMap) -> bool {
+        if self.method != "*" && !self.method.eq_ignore_ascii_case(method.as_str()) {
+            return false;
+        }
+        if let Some((name, value)) = &self.unless_header {
+            let exempt = !value.is_empty() && headers.get_all(name.as_str())
+                .iter()
+                .any(|sent| constant_time_eq(sent.as_bytes(), value.as_bytes()));
+            if exempt {
+                return false;
+            }
+        }
+        glob_match(self.pattern.as_bytes(), path.as_bytes())
+    }
+}
+
+/// An ordered list of access rules. The first matching rule decides; requests that match
+/// no rule are allowed.
+#[derive(Debug, Clone, Default)]
+pub struct AccessControl {
+    rules: Vec<AccessRule>,
+}
+
+impl AccessControl {
+
+    /// Creates an access control list without rules, which allows every request.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a rule, checked after the rules added before it.
+    pub fn rule(mut self, rule: AccessRule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Checks whether a request is allowed.
+    ///
+    /// # Arguments
+    /// * `method` - The request method.
+    /// * `path` - The full request path, including the prefixes the plugins are mounted under.
+    /// * `headers` - The request headers.
+    pub(crate) fn is_allowed(&self, method: &Method, path: &str, headers: &HeaderMap) -> bool {
+        let path = normalize(path);
+        match self.rules.iter().find(|rule| rule.matches(method, &path, headers)) {
+            Some(rule) => rule.action == Action::Allow,
+            None => true,
+        }
+    }
+}
+
+/// Percent-decodes a request path and normalizes it: empty and `.` segments are removed
+/// and `..` segments remove the segment before them. A trailing slash is kept.
+fn normalize(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            },
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            },
+        }
+    }
+    let decoded = String::from_utf8_lossy(&decoded);
+
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in decoded.split('/') {
+        match segment {
+            "" | "." => {},
+            ".." => { segments.pop(); },
+            segment => segments.push(segment),
+        }
+    }
+    let mut normalized = format!("/{}", segments.join("/"));
+    if decoded.ends_with('/') && !segments.is_empty() {
+        normalized.push('/');
+    }
+    normalized
+}
+
+/// Matches a path against a glob where `*` stays inside a segment and `**` crosses them.
+///
+/// Computed over the prefixes of the path, one pattern byte at a time, in
+/// `O(pattern × path)` time whatever the number of wildcards.
+fn glob_match(pattern: &[u8], path: &[u8]) -> bool {
+    // Whether the pattern so far matches each prefix of the path
+    let mut matched = vec![false; path.len() + 1];
+    matched[0] = true;
+
+    let mut i = 0;
+    while i < pattern.len() {
+        let mut next = vec![false; path.len() + 1];
+        match pattern[i] {
+            b'*' if pattern.get(i + 1) == Some(&b'*') => {
+                for j in 0..=path.len() {
+                    next[j] = matched[j] || (j > 0 && next[j - 1]);
+                }
+                i += 2;
+            },
+            b'*' => {
+                for j in 0..=path.len() {
+                    next[j] = matched[j] || (j > 0 && next[j - 1] && path[j - 1] != b'/');
+                }
+                i += 1;
+            },
+            c => {
+                for j in 0..path.len() {
+                    next[j + 1] = matched[j] && path[j] == c;
+                }
+                i += 1;
+            },
+        }
+        matched = next;
+    }
+    matched[path.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TempDir};
+    use crate::{CannedInvoker, StaticPlugin};
+    use axum::{body::Body, extract::Request};
+    use hyper::StatusCode;
+    use std::sync::Arc;
+
+    #[test]
+    fn first_matching_rule_decides() {
+        let acl = AccessControl::new()
+            .rule(AccessRule::allow("GET", "/admin/health"))
+            .rule(AccessRule::deny("*", "/admin/**"))
+            .rule(AccessRule::deny("DELETE", "/*/items/*").unless_header("x-admin", "secret"));
+        let headers = HeaderMap::new();
+        let mut admin = HeaderMap::new();
+        admin.insert("x-admin", "secret".parse().unwrap());
+
+        assert!(acl.is_allowed(&Method::GET, "/admin/health", &headers));
+        assert!(!acl.is_allowed(&Method::GET, "/admin/users/1", &headers));
+        assert!(!acl.is_allowed(&Method::DELETE, "/shop/items/1", &headers));
+        assert!(acl.is_allowed(&Method::DELETE, "/shop/items/1", &admin));
+        assert!(acl.is_allowed(&Method::DELETE, "/shop/items/1/notes", &headers));
+        assert!(acl.is_allowed(&Method::GET, "/shop/items/1", &headers));
+    }
+
+    #[test]
+    fn exempting_header_needs_its_value() {
+        let acl = AccessControl::new()
+            .rule(AccessRule::deny("*", "/admin/**").unless_header("x-admin-token", "secret"));
+
+        for value in ["", "wrong", "secre", "secret2"] {
+            let mut headers = HeaderMap::new();
+            headers.insert("x-admin-token", value.parse().unwrap());
+            assert!(!acl.is_allowed(&Method::GET, "/admin/users", &headers), "{:?}", value);
+        }
+        let mut headers = HeaderMap::new();
+        headers.insert("x-admin-token", "secret".parse().unwrap());
+        assert!(acl.is_allowed(&Method::GET, "/admin/users", &headers));
+    }
+
+    #[test]
+    fn encoded_and_unnormalized_paths_dont_escape_the_rules() {
+        let acl = AccessControl::new().rule(AccessRule::deny("*", "/admin/**"));
+        let headers = HeaderMap::new();
+
+        for path in ["/admin/x", "/admin%2Fx", "/admin%2fx", "//admin/x", "/./admin/x", "/public/../admin/x", "/%61dmin/x", "/admin/"] {
+            assert!(!acl.is_allowed(&Method::GET, path, &headers), "{:?}", path);
+        }
+        assert!(acl.is_allowed(&Method::GET, "/administration", &headers));
+        assert!(acl.is_allowed(&Method::GET, "/public/admin/x", &headers));
+    }
+
+    #[test]
+    fn globs_match_segments_and_across_them() {
+        assert!(glob_match(b"/a/*/c", b"/a/b/c"));
+        assert!(glob_match(b"/a/*/c", b"/a//c"));
+        assert!(!glob_match(b"/a/*/c", b"/a/b/x/c"));
+        assert!(glob_match(b"/a/**/c", b"/a/b/x/c"));
+        assert!(glob_match(b"/a/**", b"/a/"));
+        assert!(!glob_match(b"/a/**", b"/a"));
+        assert!(glob_match(b"/*.json", b"/items.json"));
+        assert!(!glob_match(b"/*.json", b"/items.jsonp"));
+    }
+
+    #[test]
+    fn many_wildcards_dont_make_the_match_exponential() {
+        let pattern = "/**a**a**a**a**a**a**a**a**a**a**b".repeat(4);
+        let path = format!("/{}", "a".repeat(4096));
+
+        let started = std::time::Instant::now();
+        assert!(!glob_match(pattern.as_bytes(), path.as_bytes()));
+        assert!(started.elapsed() < std::time::Duration::from_secs(5), "{:?}", started.elapsed());
+    }
+
+    #[tokio::test]
+    async fn denied_requests_dont_reach_the_plugin() {
+        let dir = TempDir::new();
+        let invoker = Arc::new(CannedInvoker::new("item"));
+        let plugin = StaticPlugin::new(
+            r#"[{"path": "/items/:id", "function": "item", "method_router": "get,delete"}]"#,
+        )
+            .function("item", invoker.clone());
+        let router = testing::plugins(&dir, true)
+            .access_control(AccessControl::new().rule(AccessRule::deny("DELETE", "/shop/**")))
+            .register("shop", plugin)
+            .load()
+            .unwrap();
+
+        let response = testing::get(&router, "/shop/items/1").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let request = Request::delete("/shop/items/1").body(Body::empty()).unwrap();
+        let response = testing::send(&router, request).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(invoker.calls(), 1);
+    }
+}
@@ -0,0 +1,176 @@
+//! Debug logging of request bodies for selected plugin routes.
+//!
+//! Only active when debug events are enabled: by the subscriber's filter, or without a
+//! `tracing` subscriber by the `DEBUG` environment variable being `true`. Bodies are
+//! redacted and truncated before being logged, and only the targeted routes are logged.
+
+use crate::log::debug;
+use serde_json::Value;
+
+/// Placeholder written in place of redacted values.
+const REDACTED: &str = "[REDACTED]";
+
+/// Configuration of the request body debug logging.
+#[derive(Debug, Clone)]
+pub struct BodyLogging {
+    routes: Vec<(String, String)>,
+    max_size: usize,
+    redact: Vec<String>,
+}
+
+impl Default for BodyLogging {
+    fn default() -> Self {
+        BodyLogging {
+            routes: Vec::new(),
+            max_size: 1024,
+            redact: Vec::new(),
+        }
+    }
+}
+
+impl BodyLogging {
+
+    /// Creates a body logging configuration that logs no route, with a 1 KiB size cap and
+    /// no redacted fields.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a route whose request bodies are logged.
+    ///
+    /// # Arguments
+    /// * `plugin` - The plugin name.
+    /// * `path` - The route path as declared by the plugin (e.g. `/test-json`).
+    pub fn route(mut self, plugin: impl Into<String>, path: impl Into<String>) -> Self {
+        self.routes.push((plugin.into(), path.into()));
+        self
+    }
+
+    /// Sets the maximum number of bytes of the body that are logged.
+    pub fn max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Sets the names of the fields whose values are redacted, in JSON bodies (at any
+    /// depth) and in URL-encoded form bodies.
+    pub fn redact<I, S>(mut self, fields: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.redact = fields.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Checks whether the bodies of a route are logged.
+    pub(crate) fn is_logged(&self, plugin: &str, path: &str) -> bool {
+        self.routes.iter().any(|(p, r)| p == plugin && r == path)
+    }
+
+    /// Prints the redacted and truncated body of a request.
+    pub(crate) fn log(&self, plugin: &str, path: &str, body: &str) {
+        let redacted = self.redacted(body);
+        let total = redacted.len();
+        let mut end = total.min(self.max_size);
+        while !redacted.is_char_boundary(end) {
+            end -= 1;
+        }
+        if end < total {
+            debug!(
+                "Request Body [{} {}]: {}... ({} of {} bytes)",
+                plugin, path, &redacted[..end], end, total
+            );
+        } else {
+            debug!("Request Body [{} {}]: {}", plugin, path, redacted);
+        }
+    }
+
+    /// Redacts the configured fields of a JSON or URL-encoded body.
+    fn redacted(&self, body: &str) -> String {
+
+        if self.redact.is_empty() {
+            return body.to_string();
+        }
+
+        if let Ok(mut json) = serde_json::from_str::<Value>(body) {
+            self.redact_json(&mut json);
+            return json.to_string();
+        }
+
+        if body.contains('=') && !body.contains(char::is_whitespace) {
+            return body.split('&')
+                .map(|pair| match pair.split_once('=') {
+                    Some((key, _)) if self.redact.iter().any(|field| field == key) => {
+                        format!("{}={}", key, REDACTED)
+                    },
+                    _ => pair.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join("&");
+        }
+
+        body.to_string()
+    }
+
+    fn redact_json(&self, value: &mut Value) {
+        match value {
+            Value::Object(map) => {
+                for (key, value) in map.iter_mut() {
+                    if self.redact.iter().any(|field| field == key) {
+                        *value = Value::String(REDACTED.to_string());
+                    } else {
+                        self.redact_json(value);
+                    }
+                }
+            },
+            Value::Array(values) => values.iter_mut().for_each(|value| self.redact_json(value)),
+            _ => {},
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, Logs, TempDir};
+    use crate::{CannedInvoker, StaticPlugin};
+    use axum::{body::Body, extract::Request};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn body_is_logged_truncated_only_for_the_targeted_route() {
+        let dir = TempDir::new();
+        let plugin = StaticPlugin::new(
+            r#"[{"path": "/logged", "function": "ok", "method_router": "post"}, {"path": "/quiet", "function": "ok", "method_router": "post"}]"#,
+        )
+            .function("ok", Arc::new(CannedInvoker::new("ok")));
+        let router = testing::plugins(&dir, true)
+            .log_body(BodyLogging::new().route("echo", "/logged").max_size(8))
+            .register("echo", plugin)
+            .load()
+            .unwrap();
+
+        let (logs, _guard) = Logs::capture();
+        for path in ["/echo/logged", "/echo/quiet"] {
+            let request = Request::post(path).body(Body::from("0123456789abcdefghij")).unwrap();
+            testing::send(&router, request).await;
+        }
+
+        let bodies: Vec<String> = logs.messages().into_iter()
+            .filter(|message| message.starts_with("Request Body"))
+            .collect();
+        assert_eq!(bodies, ["Request Body [echo /logged]: 01234567... (8 of 20 bytes)"]);
+    }
+
+    #[test]
+    fn redacted_fields_are_hidden_in_json_and_form_bodies() {
+        let logging = BodyLogging::new().redact(["password"]);
+
+        assert_eq!(
+            logging.redacted(r#"{"user": {"name": "a", "password": "secret"}}"#),
+            r#"{"user":{"name":"a","password":"[REDACTED]"}}"#,
+        );
+        assert_eq!(logging.redacted("name=a&password=secret"), "name=a&password=[REDACTED]");
+    }
+}
@@ -0,0 +1,383 @@
+//! Pluggable storage for the cached responses of the caching features.
+//!
+//! Features such as the idempotency keys store their responses through a
+//! [`ResponseCache`], so the cache can be shared or moved to an external backend (e.g.
+//! Redis). [`MemoryCache`] is the default in-memory LRU implementation.
+
+use axum::{
+    body::{self, Body},
+    response::Response,
+};
+use hyper::{header::{HeaderName, HeaderValue}, StatusCode};
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// A response stored in a cache, in a form that's easy to serialize for external backends.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachedResponse {
+    /// The status code.
+    pub status: u16,
+    /// The header names and values.
+    pub headers: Vec<(String, String)>,
+    /// The body.
+    pub body: Vec<u8>,
+    /// A fingerprint of the request the response answered, e.g. the hash of the body
+    /// of an idempotent request, `None` if the feature doesn't check it.
+    pub fingerprint: Option<String>,
+}
+
+impl CachedResponse {
+
+    /// Buffers a response so it can be cached.
+    ///
+    /// # Returns
+    /// The cached form, and the response rebuilt from it.
+    pub(crate) async fn from_response(response: Response) -> Result<(Self, Response), axum::Error> {
+        let (parts, body) = response.into_parts();
+        let bytes = body::to_bytes(body, usize::MAX).await?;
+
+        let cached = CachedResponse {
+            status: parts.status.as_u16(),
+            headers: parts.headers.iter()
+                .filter_map(|(name, value)| {
+                    value.to_str().ok().map(|value| (name.to_string(), value.to_string()))
+                })
+                .collect(),
+            body: bytes.to_vec(),
+            fingerprint: None,
+        };
+        Ok((cached, Response::from_parts(parts, Body::from(bytes))))
+    }
+
+    /// Rebuilds the response.
+    pub(crate) fn into_response(self) -> Response {
+        let mut response = Response::new(Body::from(self.body));
+        *response.status_mut() = StatusCode::from_u16(self.status)
+            .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        for (name, value) in self.headers {
+            if let (Ok(name), Ok(value)) = (name.parse::<HeaderName>(), HeaderValue::from_str(&value)) {
+                response.headers_mut().append(name, value);
+            }
+        }
+        response
+    }
+}
+
+/// The future returned by the methods of a [`ResponseCache`].
+pub type CacheFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// A cache of responses, keyed by strings built by the caching features.
+///
+/// The methods return futures, so a backend reached over the network, e.g. Redis, waits
+/// for its replies without blocking the runtime's threads.
+pub trait ResponseCache: Send + Sync {
+
+    /// Returns the response stored under a key, unless it has expired.
+    fn get<'a>(&'a self, key: &'a str) -> CacheFuture<'a, Option<CachedResponse>>;
+
+    /// Stores a response under a key for `ttl`.
+    fn set<'a>(&'a self, key: &'a str, response: CachedResponse, ttl: Duration) -> CacheFuture<'a, ()>;
+}
+
+/// An entry of the in-memory cache.
+#[derive(Debug)]
+struct Entry {
+    expires: Instant,
+    /// When the entry was stored, unique among the entries.
+    stored: u64,
+    /// When the entry was last used, unique among the entries.
+    used: u64,
+    response: CachedResponse,
+}
+
+/// The entries of the in-memory cache, indexed by key, last use and expiry.
+#[derive(Debug, Default)]
+struct Entries {
+    clock: u64,
+    by_key: HashMap<String, Entry>,
+    /// The keys by last use, the least recently used first.
+    by_use: BTreeMap<u64, String>,
+    /// The keys by expiry, the first to expire first.
+    by_expiry: BTreeMap<(Instant, u64), String>,
+}
+
+impl Entries {
+
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    fn remove(&mut self, key: &str) -> Option<Entry> {
+        let entry = self.by_key.remove(key)?;
+        self.by_use.remove(&entry.used);
+        self.by_expiry.remove(&(entry.expires, entry.stored));
+        Some(entry)
+    }
+
+    /// Removes the entries expired at `now`.
+    fn remove_expired(&mut self, now: Instant) {
+        while let Some((&(expires, _), key)) = self.by_expiry.first_key_value() {
+            if expires > now {
+                break;
+            }
+            let key = key.clone();
+            self.remove(&key);
+        }
+    }
+
+    /// Removes the least recently used entry.
+    fn remove_least_recent(&mut self) {
+        if let Some((_, key)) = self.by_use.pop_first() {
+            if let Some(entry) = self.by_key.remove(&key) {
+                self.by_expiry.remove(&(entry.expires, entry.stored));
+            }
+        }
+    }
+}
+
+/// An in-memory cache evicting the least recently used response when it's full.
+///
+/// Reading or storing a response takes a time logarithmic in the number of responses.
+#[derive(Debug)]
+pub struct MemoryCache {
+    max_entries: usize,
+    entries: Mutex<Entries>,
+}
+
+impl MemoryCache {
+
+    /// Creates an in-memory cache holding up to `max_entries` responses.
+    pub fn new(max_entries: usize) -> Self {
+        MemoryCache {
+            max_entries,
+            entries: Mutex::default(),
+        }
+    }
+
+    /// Runs a closure on the entries.
+    fn with<T>(&self, f: impl FnOnce(&mut Entries) -> T) -> T {
+        match self.entries.lock() {
+            Ok(mut entries) => f(&mut entries),
+            Err(e) => f(&mut e.into_inner()),
+        }
+    }
+
+    fn lookup(&self, key: &str) -> Option<CachedResponse> {
+        self.with(|entries| {
+            if entries.by_key.get(key)?.expires <= Instant::now() {
+                entries.remove(key);
+                return None;
+            }
+
+            let used = entries.tick();
+            let entry = entries.by_key.get_mut(key)?;
+            let previous = std::mem::replace(&mut entry.used, used);
+            let response = entry.response.clone();
+            entries.by_use.remove(&previous);
+            entries.by_use.insert(used, key.to_string());
+            Some(response)
+        })
+    }
+
+    fn store(&self, key: &str, response: CachedResponse, ttl: Duration) {
+        if self.max_entries == 0 {
+            return;
+        }
+
+        self.with(|entries| {
+            let now = Instant::now();
+            entries.remove_expired(now);
+            entries.remove(key);
+            while entries.by_key.len() >= self.max_entries {
+                entries.remove_least_recent();
+            }
+
+            let clock = entries.tick();
+            let expires = now + ttl;
+            entries.by_use.insert(clock, key.to_string());
+            entries.by_expiry.insert((expires, clock), key.to_string());
+            entries.by_key.insert(key.to_string(), Entry {
+                expires,
+                stored: clock,
+                used: clock,
+                response,
+            });
+        });
+    }
+}
+
+impl ResponseCache for MemoryCache {
+
+    fn get<'a>(&'a self, key: &'a str) -> CacheFuture<'a, Option<CachedResponse>> {
+        Box::pin(std::future::ready(self.lookup(key)))
+    }
+
+    fn set<'a>(&'a self, key: &'a str, response: CachedResponse, ttl: Duration) -> CacheFuture<'a, ()> {
+        self.store(key, response, ttl);
+        Box::pin(std::future::ready(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TempDir};
+    use crate::{CannedInvoker, Idempotency, StaticPlugin};
+    use axum::extract::Request;
+    use std::sync::Arc;
+
+    fn response(body: &str) -> CachedResponse {
+        CachedResponse {
+            status: 200,
+            headers: vec![("content-type".to_string(), "text/plain".to_string())],
+            body: body.as_bytes().to_vec(),
+            fingerprint: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn memory_cache_returns_the_stored_response_until_it_expires() {
+        let cache = MemoryCache::new(10);
+
+        cache.set("live", response("live"), Duration::from_secs(60)).await;
+        cache.set("expired", response("expired"), Duration::ZERO).await;
+
+        assert_eq!(cache.get("live").await, Some(response("live")));
+        assert_eq!(cache.get("expired").await, None);
+        assert_eq!(cache.get("missing").await, None);
+    }
+
+    #[tokio::test]
+    async fn full_memory_cache_evicts_the_least_recently_used_response() {
+        let cache = MemoryCache::new(2);
+        let ttl = Duration::from_secs(60);
+
+        cache.set("a", response("a"), ttl).await;
+        cache.set("b", response("b"), ttl).await;
+        cache.get("a").await;
+        cache.set("c", response("c"), ttl).await;
+
+        assert!(cache.get("a").await.is_some());
+        assert!(cache.get("b").await.is_none());
+        assert!(cache.get("c").await.is_some());
+    }
+
+    #[tokio::test]
+    async fn expired_responses_are_evicted_before_the_least_recently_used() {
+        let cache = MemoryCache::new(2);
+
+        cache.set("expired", response("expired"), Duration::ZERO).await;
+        cache.set("a", response("a"), Duration::from_secs(60)).await;
+        cache.set("b", response("b"), Duration::from_secs(60)).await;
+
+        assert!(cache.get("a").await.is_some());
+        assert!(cache.get("b").await.is_some());
+        cache.with(|entries| {
+            assert_eq!(entries.by_key.len(), 2);
+            assert_eq!(entries.by_use.len(), 2);
+            assert_eq!(entries.by_expiry.len(), 2);
+        });
+    }
+
+    #[tokio::test]
+    async fn replaced_response_takes_a_single_entry() {
+        let cache = MemoryCache::new(2);
+        let ttl = Duration::from_secs(60);
+
+        cache.set("a", response("first"), ttl).await;
+        cache.set("a", response("second"), ttl).await;
+        cache.set("b", response("b"), ttl).await;
+
+        assert_eq!(cache.get("a").await, Some(response("second")));
+        assert!(cache.get("b").await.is_some());
+        cache.with(|entries| assert_eq!(entries.by_expiry.len(), 2));
+    }
+
+    /// A backend recording what it stores, standing in for an external cache. Reading
+    /// `blocked` waits until the test releases it, like a stalled network call.
+    #[derive(Clone, Default)]
+    struct MockCache {
+        stored: Arc<Mutex<HashMap<String, (CachedResponse, Duration)>>>,
+        blocked: Arc<tokio::sync::Notify>,
+    }
+
+    impl ResponseCache for MockCache {
+        fn get<'a>(&'a self, key: &'a str) -> CacheFuture<'a, Option<CachedResponse>> {
+            Box::pin(async move {
+                if key.ends_with("blocked") {
+                    self.blocked.notified().await;
+                }
+                self.stored.lock().unwrap().get(key).map(|(response, _)| response.clone())
+            })
+        }
+
+        fn set<'a>(&'a self, key: &'a str, response: CachedResponse, ttl: Duration) -> CacheFuture<'a, ()> {
+            self.stored.lock().unwrap().insert(key.to_string(), (response, ttl));
+            Box::pin(std::future::ready(()))
+        }
+    }
+
+    /// Loads a plugin serving `POST /shop/pay` as an idempotent route whose responses
+    /// are kept in `backend`, with the invoker of its function.
+    fn shop(dir: &TempDir, backend: &MockCache) -> (axum::Router, Arc<CannedInvoker>) {
+        let invoker = Arc::new(CannedInvoker::new("paid"));
+        let plugin = StaticPlugin::new(
+            r#"[{"path": "/pay", "function": "pay", "method_router": "post", "idempotent": true}]"#,
+        ).function("pay", invoker.clone());
+        let router = testing::plugins(dir, true)
+            .idempotency(Idempotency::new().ttl(Duration::from_secs(30)).cache(backend.clone()))
+            .register("shop", plugin)
+            .load()
+            .unwrap();
+        (router, invoker)
+    }
+
+    fn pay(key: &str) -> Request {
+        Request::post("/shop/pay")
+            .header("idempotency-key", key)
+            .body(Body::from("10"))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn idempotent_responses_are_stored_in_the_configured_backend() {
+        let dir = TempDir::new();
+        let backend = MockCache::default();
+        let (router, invoker) = shop(&dir, &backend);
+
+        testing::send(&router, pay("k")).await;
+        let stored: Vec<_> = backend.stored.lock().unwrap().values().cloned().collect();
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].0.body, b"paid");
+        assert_eq!(stored[0].1, Duration::from_secs(30));
+
+        let retry = testing::send(&router, pay("k")).await;
+        assert_eq!(testing::body_string(retry).await, "paid");
+        assert_eq!(invoker.calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn stalled_backend_doesnt_hold_up_requests_with_other_keys() {
+        let dir = TempDir::new();
+        let backend = MockCache::default();
+        let (router, invoker) = shop(&dir, &backend);
+
+        let stalled = tokio::spawn({
+            let router = router.clone();
+            async move { testing::send(&router, pay("blocked")).await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let response = tokio::time::timeout(Duration::from_secs(2), testing::send(&router, pay("other"))).await.unwrap();
+        assert_eq!(testing::body_string(response).await, "paid");
+        assert!(!stalled.is_finished());
+
+        backend.blocked.notify_one();
+        assert_eq!(testing::body_string(stalled.await.unwrap()).await, "paid");
+        assert_eq!(invoker.calls(), 2);
+    }
+}
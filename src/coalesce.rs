@@ -0,0 +1,174 @@
+//! Coalescing of identical concurrent requests to expensive routes.
+//!
+//! Routes declared with `"coalesce": true` answer concurrent `GET` and `HEAD` requests
+//! with the same method, path and query from a single plugin call: the first request
+//! calls the plugin, and the requests arriving while it runs wait for its response and
+//! get a copy of it. Nothing is cached, a request arriving after the response is sent
+//! calls the plugin again. The request headers are not part of the key, so the route's
+//! response must not depend on them.
+
+use crate::cache::CachedResponse;
+use crate::log::error;
+use axum::response::{IntoResponse, Response};
+use hyper::StatusCode;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+use tokio::sync::watch;
+
+/// The response of an in-flight call, `None` until it's available.
+type Pending = watch::Receiver<Option<CachedResponse>>;
+
+/// The in-flight calls of the coalesced routes, by request key.
+#[derive(Debug, Default)]
+pub(crate) struct Coalescer {
+    in_flight: Mutex<HashMap<String, Pending>>,
+}
+
+/// Removes the key of an in-flight call when the call ends or its request is dropped.
+struct InFlight<'a> {
+    coalescer: &'a Coalescer,
+    key: String,
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.coalescer.with(|in_flight| {
+            in_flight.remove(&self.key);
+        });
+    }
+}
+
+impl Coalescer {
+
+    /// Runs a closure on the in-flight calls.
+    fn with<T>(&self, f: impl FnOnce(&mut HashMap<String, Pending>) -> T) -> T {
+        match self.in_flight.lock() {
+            Ok(mut in_flight) => f(&mut in_flight),
+            Err(e) => f(&mut e.into_inner()),
+        }
+    }
+
+    /// Responds to a request, sharing the response of an identical request in flight.
+    ///
+    /// # Arguments
+    /// * `key` - The key of the request: its route, method, path and query.
+    /// * `respond` - Builds the response by calling the plugin.
+    ///
+    /// # Returns
+    /// The response of the request, or a copy of the in-flight one.
+    pub(crate) async fn run<F, Fut>(&self, key: String, respond: F) -> Response
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Response>,
+    {
+        let started = self.with(|in_flight| match in_flight.get(&key) {
+            Some(pending) => Err(pending.clone()),
+            None => {
+                let (sender, pending) = watch::channel(None);
+                in_flight.insert(key.clone(), pending);
+                Ok(sender)
+            },
+        });
+
+        let sender = match started {
+            Ok(sender) => sender,
+            Err(mut pending) => {
+                if let Ok(response) = pending.wait_for(Option::is_some).await {
+                    if let Some(response) = response.clone() {
+                        return response.into_response();
+                    }
+                }
+                // The first request was dropped before its response, e.g. the client
+                // disconnected, so this one calls the plugin itself
+                return respond().await;
+            },
+        };
+
+        let in_flight = InFlight {
+            coalescer: self,
+            key,
+        };
+        let response = respond().await;
+
+        match CachedResponse::from_response(response).await {
+            Ok((cached, response)) => {
+                // Requests arriving from now on get a new call
+                drop(in_flight);
+                sender.send_replace(Some(cached));
+                response
+            },
+            Err(e) => {
+                error!("Error reading response body for coalesced requests: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{self, TempDir};
+    use crate::{CannedInvoker, StaticPlugin};
+    use axum::Router;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    /// Loads a plugin whose slow route coalesces requests or not, with the invoker of its
+    /// function to count the calls.
+    fn router(coalesce: bool) -> (TempDir, Router, Arc<CannedInvoker>) {
+        let dir = TempDir::new();
+        let canned = Arc::new(CannedInvoker::new("report"));
+        let invoker = testing::SlowInvoker {
+            delay: Duration::from_millis(200),
+            canned: canned.clone(),
+        };
+        let plugin = StaticPlugin::new(format!(
+            r#"[{{"path": "/", "function": "report", "method_router": "get", "coalesce": {}}}]"#,
+            coalesce,
+        )).function("report", Arc::new(invoker));
+        let router = testing::plugins(&dir, true).register("report", plugin).load().unwrap();
+        (dir, router, canned)
+    }
+
+    /// Sends a `GET` request to each URI concurrently and returns the bodies.
+    async fn concurrent_gets(router: &Router, uris: &[&str]) -> Vec<String> {
+        let requests = uris.iter().map(|uri| {
+            let (router, uri) = (router.clone(), uri.to_string());
+            tokio::spawn(async move { testing::body_string(testing::get(&router, &uri).await).await })
+        }).collect::<Vec<_>>();
+        let mut bodies = Vec::new();
+        for request in requests {
+            bodies.push(request.await.unwrap());
+        }
+        bodies
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn identical_concurrent_requests_call_the_plugin_once() {
+        let (_dir, router, canned) = router(true);
+
+        let bodies = concurrent_gets(&router, &["/report"; 20]).await;
+
+        assert_eq!(bodies, vec!["report"; 20]);
+        assert_eq!(canned.calls(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn requests_with_different_queries_are_not_coalesced() {
+        let (_dir, router, canned) = router(true);
+
+        concurrent_gets(&router, &["/report?page=1", "/report?page=2", "/report?page=1"]).await;
+
+        assert_eq!(canned.calls(), 2);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn routes_not_declaring_coalesce_call_the_plugin_for_each_request() {
+        let (_dir, router, canned) = router(false);
+
+        concurrent_gets(&router, &["/report"; 5]).await;
+
+        assert_eq!(canned.calls(), 5);
+    }
+}
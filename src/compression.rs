@@ -0,0 +1,209 @@
+//! Conditional gzip compression of plugin responses.
+//!
+//! Unlike a blanket compression layer, only responses that are large enough and that have
+//! a compressible content type are compressed, so small or already-compressed (binary)
+//! bodies don't waste CPU time.
+
+use crate::log::error;
+use axum::{body::Body, response::Response};
+use flate2::{write::GzEncoder, Compression as GzLevel};
+use hyper::header::{self, HeaderMap, HeaderValue};
+use serde::Deserialize;
+use std::io::Write;
+
+/// Configuration of the conditional response compression.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Compression {
+    min_size: usize,
+    content_types: Vec<String>,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression {
+            min_size: 1024,
+            content_types: vec![
+                "text/".to_string(),
+                "application/json".to_string(),
+                "application/yaml".to_string(),
+                "application/xml".to_string(),
+                "application/javascript".to_string(),
+                "image/svg+xml".to_string(),
+            ],
+        }
+    }
+}
+
+impl Compression {
+
+    /// Creates a compression configuration with the default threshold (1 KiB) and the
+    /// default set of compressible content types (text, JSON, YAML, XML, JavaScript, SVG).
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the minimum body size, in bytes, for a response to be compressed.
+    pub fn min_size(mut self, min_size: usize) -> Self {
+        self.min_size = min_size;
+        self
+    }
+
+    /// Sets the compressible content types. An entry ending with `/` (e.g. `text/`) matches
+    /// every subtype.
+    pub fn content_types<I, S>(mut self, content_types: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.content_types = content_types.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Checks whether the client accepts gzip encoded responses.
+    ///
+    /// # Arguments
+    /// * `headers` - The request headers.
+    pub(crate) fn accepts_gzip(headers: &HeaderMap) -> bool {
+        // An explicit `gzip` entry takes precedence over `*`, e.g. in `gzip;q=0, *`
+        let mut gzip = None;
+        let mut any = None;
+        for coding in headers.get_all(header::ACCEPT_ENCODING)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(',')) {
+            let mut parts = coding.trim().split(';');
+            let name = parts.next().unwrap_or_default().trim();
+            let rejected = parts.any(|param| {
+                param.trim().strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_some_and(|q| q == 0.0)
+            });
+            if name.eq_ignore_ascii_case("gzip") {
+                gzip = Some(!rejected);
+            } else if name == "*" {
+                any = Some(!rejected);
+            }
+        }
+        gzip.or(any).unwrap_or(false)
+    }
+
+    /// Checks whether a content type is compressible under this configuration.
+    fn is_compressible(&self, content_type: &str) -> bool {
+        let mime = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+        self.content_types.iter().any(|allowed| {
+            if allowed.ends_with('/') {
+                mime.starts_with(&allowed.to_ascii_lowercase())
+            } else {
+                mime.eq_ignore_ascii_case(allowed)
+            }
+        })
+    }
+
+    /// Compresses the response body when it's eligible.
+    ///
+    /// Responses that already carry a `Content-Encoding`, have a non-compressible content
+    /// type, or are smaller than the threshold are returned untouched.
+    ///
+    /// # Arguments
+    /// * `response` - The response built from the plugin output.
+    ///
+    /// # Returns
+    /// The (possibly) compressed response.
+    pub(crate) async fn apply(&self, response: Response) -> Response {
+
+        if response.headers().contains_key(header::CONTENT_ENCODING) {
+            return response;
+        }
+
+        let compressible = response.headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|content_type| self.is_compressible(content_type));
+        if !compressible {
+            return response;
+        }
+
+        let (mut parts, body) = response.into_parts();
+        parts.headers.append(header::VARY, HeaderValue::from_static("accept-encoding"));
+
+        let bytes = match axum::body::to_bytes(body, usize::MAX).await {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                error!("Error reading response body for compression: {}", e);
+                return Response::from_parts(parts, Body::empty());
+            }
+        };
+
+        if bytes.len() < self.min_size {
+            return Response::from_parts(parts, Body::from(bytes));
+        }
+
+        let mut encoder = GzEncoder::new(Vec::new(), GzLevel::default());
+        let compressed = match encoder.write_all(&bytes).and_then(|_| encoder.finish()) {
+            Ok(compressed) => compressed,
+            Err(e) => {
+                error!("Error compressing response body: {}", e);
+                return Response::from_parts(parts, Body::from(bytes));
+            }
+        };
+
+        parts.headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+        parts.headers.remove(header::CONTENT_LENGTH);
+        Response::from_parts(parts, Body::from(compressed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns request headers with an `Accept-Encoding` header.
+    fn accept_encoding(value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT_ENCODING, HeaderValue::from_static(value));
+        headers
+    }
+
+    /// Returns a response with a body and a content type.
+    fn response(content_type: &'static str, body: Vec<u8>) -> Response {
+        let mut response = Response::new(Body::from(body));
+        response.headers_mut().insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+        response
+    }
+
+    #[test]
+    fn explicitly_rejected_gzip_is_not_accepted_through_the_wildcard() {
+        assert!(Compression::accepts_gzip(&accept_encoding("gzip, deflate")));
+        assert!(Compression::accepts_gzip(&accept_encoding("br, *")));
+        assert!(!Compression::accepts_gzip(&accept_encoding("gzip;q=0, *")));
+        assert!(!Compression::accepts_gzip(&accept_encoding("*, gzip;q=0")));
+        assert!(!Compression::accepts_gzip(&accept_encoding("*;q=0")));
+        assert!(!Compression::accepts_gzip(&HeaderMap::new()));
+    }
+
+    #[test]
+    fn content_types_are_compared_case_insensitively() {
+        let compression = Compression::new();
+        assert!(compression.is_compressible("Text/HTML; charset=utf-8"));
+        assert!(compression.is_compressible("APPLICATION/JSON"));
+        assert!(!compression.is_compressible("image/png"));
+        assert!(Compression::new().content_types(["Text/"]).is_compressible("text/plain"));
+    }
+
+    #[tokio::test]
+    async fn large_text_responses_are_compressed() {
+        let response = Compression::new().apply(response("text/plain", vec![b'a'; 2048])).await;
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+    }
+
+    #[tokio::test]
+    async fn small_and_binary_responses_are_left_uncompressed() {
+        let small = Compression::new().apply(response("text/plain", vec![b'a'; 100])).await;
+        let binary = Compression::new().apply(response("image/png", vec![0; 2048])).await;
+
+        assert!(!small.headers().contains_key(header::CONTENT_ENCODING));
+        assert!(!binary.headers().contains_key(header::CONTENT_ENCODING));
+        let body = axum::body::to_bytes(binary.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body.len(), 2048);
+    }
+}
@@ -0,0 +1,67 @@
+//! Per-request nonces for the `Content-Security-Policy` of routes with inline scripts.
+//!
+//! Routes declared with `"csp_nonce": true` get a fresh random nonce in the `x-csp-nonce`
+//! request header, to be embedded in `<script nonce="...">`, and the response carries a
+//! `Content-Security-Policy` header allowing the scripts with that nonce.
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use rand::RngCore;
+
+/// Name of the request header carrying the nonce.
+pub(crate) const NONCE_HEADER: &str = "x-csp-nonce";
+
+/// The default policy, `{nonce}` is replaced with the request's nonce.
+pub(crate) const DEFAULT_POLICY: &str = "script-src 'nonce-{nonce}'";
+
+/// Generates a new random nonce of 128 bits, base64-encoded.
+pub(crate) fn generate_nonce() -> String {
+    let mut bytes = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    STANDARD.encode(bytes)
+}
+
+/// Builds the `Content-Security-Policy` value for a nonce.
+///
+/// # Arguments
+/// * `policy` - The policy template, with a `{nonce}` placeholder.
+/// * `nonce` - The request's nonce.
+pub(crate) fn policy(policy: &str, nonce: &str) -> String {
+    policy.replace("{nonce}", nonce)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, EchoHeader, TempDir};
+    use crate::StaticPlugin;
+    use hyper::header::CONTENT_SECURITY_POLICY;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn policy_nonce_matches_the_nonce_passed_to_the_plugin() {
+        let dir = TempDir::new();
+        let plugin = StaticPlugin::new(
+            r#"[{"path": "/", "function": "page", "method_router": "get", "csp_nonce": true}, {"path": "/plain", "function": "page", "method_router": "get"}]"#,
+        ).function("page", Arc::new(EchoHeader(NONCE_HEADER)));
+        let router = testing::plugins(&dir, true)
+            .csp_policy("default-src 'self'; script-src 'nonce-{nonce}'")
+            .register("app", plugin)
+            .load()
+            .unwrap();
+
+        let mut nonces = Vec::new();
+        for _ in 0..2 {
+            let response = testing::get(&router, "/app").await;
+            let header = response.headers()[CONTENT_SECURITY_POLICY].to_str().unwrap().to_string();
+            let nonce = testing::body_string(response).await;
+            assert_eq!(STANDARD.decode(&nonce).unwrap().len(), 16);
+            assert_eq!(header, format!("default-src 'self'; script-src 'nonce-{}'", nonce));
+            nonces.push(nonce);
+        }
+        assert_ne!(nonces[0], nonces[1]);
+
+        let response = testing::get(&router, "/app/plain").await;
+        assert!(!response.headers().contains_key(CONTENT_SECURITY_POLICY));
+        assert_eq!(testing::body_string(response).await, "");
+    }
+}
@@ -0,0 +1,192 @@
+//! CSRF protection for browser-facing plugin routes, using the double-submit cookie pattern.
+//!
+//! Routes declared with `"csrf": true` get a random token in the `csrf_token` cookie, which
+//! is also forwarded to the plugin in the `x-csrf-token` request header so it can embed it
+//! in its forms. State-changing requests (`POST`, `PUT`, `PATCH`, `DELETE`) must echo the
+//! cookie value back in the `x-csrf-token` header or in a `csrf_token` form field, or they
+//! are rejected with `403 Forbidden` before reaching the plugin.
+
+use hyper::{
+    header::{self, HeaderMap, HeaderValue},
+    Method,
+};
+use rand::{distributions::Alphanumeric, Rng};
+
+/// Name of the cookie and of the form field carrying the token.
+pub(crate) const CSRF_FIELD: &str = "csrf_token";
+
+/// Name of the request header carrying the token.
+pub(crate) const CSRF_HEADER: &str = "x-csrf-token";
+
+/// Generates a new random token.
+pub(crate) fn generate_token() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect()
+}
+
+/// Checks whether a request method changes state and must carry a valid token.
+pub(crate) fn is_state_changing(method: &Method) -> bool {
+    matches!(*method, Method::POST | Method::PUT | Method::PATCH | Method::DELETE)
+}
+
+/// Returns the token stored in the request's `csrf_token` cookie, if any. An empty
+/// cookie counts as missing.
+pub(crate) fn cookie_token(headers: &HeaderMap) -> Option<String> {
+    headers.get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, _)| *name == CSRF_FIELD)
+        .map(|(_, value)| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+/// Checks that the token submitted in the `x-csrf-token` header or in the `csrf_token`
+/// field of a URL-encoded form body matches the cookie token. An empty token, submitted
+/// or expected, never matches.
+///
+/// # Arguments
+/// * `expected` - The token from the cookie.
+/// * `headers` - The request headers.
+/// * `body` - The request body.
+pub(crate) fn is_valid(expected: &str, headers: &HeaderMap, body: &[u8]) -> bool {
+
+    if expected.is_empty() {
+        return false;
+    }
+
+    if let Some(token) = headers.get(CSRF_HEADER).and_then(|value| value.to_str().ok()) {
+        return !token.is_empty() && constant_time_eq(expected.as_bytes(), token.as_bytes());
+    }
+
+    let is_form = headers.get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/x-www-form-urlencoded"));
+    if is_form {
+        return form_urlencoded::parse(body)
+            .find(|(name, _)| name == CSRF_FIELD)
+            .is_some_and(|(_, token)| constant_time_eq(expected.as_bytes(), token.as_bytes()));
+    }
+
+    false
+}
+
+/// Builds the `Set-Cookie` header value issuing a token.
+pub(crate) fn set_cookie(token: &str) -> Option<HeaderValue> {
+    HeaderValue::from_str(&format!("{}={}; Path=/; SameSite=Strict", CSRF_FIELD, token)).ok()
+}
+
+/// Compares two byte strings in constant time with respect to their content.
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TempDir};
+    use crate::{CannedInvoker, StaticPlugin};
+    use axum::{body::Body, extract::Request, Router};
+    use hyper::StatusCode;
+    use std::sync::Arc;
+
+    /// Loads a plugin serving `GET /form` and `POST /save` with CSRF protection.
+    fn router(dir: &TempDir) -> (Router, Arc<CannedInvoker>) {
+        let invoker = Arc::new(CannedInvoker::new("saved"));
+        let plugin = StaticPlugin::new(
+            r#"[{"path": "/form", "function": "save", "method_router": "get", "csrf": true}, {"path": "/save", "function": "save", "method_router": "post", "csrf": true}]"#,
+        )
+            .function("save", invoker.clone());
+        let router = testing::plugins(dir, true)
+            .register("forms", plugin)
+            .load()
+            .unwrap();
+        (router, invoker)
+    }
+
+    /// Sends `POST /forms/save` with the given headers and body.
+    async fn save(router: &Router, headers: &[(&str, &str)], body: &str) -> axum::response::Response {
+        let mut request = Request::post("/forms/save");
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        testing::send(router, request.body(Body::from(body.to_string())).unwrap()).await
+    }
+
+    /// Returns request headers with the given cookie and `x-csrf-token` values.
+    fn headers(cookie: &str, token: Option<&str>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::COOKIE, HeaderValue::from_str(cookie).unwrap());
+        if let Some(token) = token {
+            headers.insert(CSRF_HEADER, HeaderValue::from_str(token).unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn empty_cookie_is_missing() {
+        assert_eq!(cookie_token(&headers("csrf_token=", None)), None);
+        assert_eq!(cookie_token(&headers("theme=dark; csrf_token=abc", None)).as_deref(), Some("abc"));
+    }
+
+    #[test]
+    fn empty_tokens_never_match() {
+        assert!(!is_valid("", &headers("csrf_token=", Some("")), b""));
+        assert!(!is_valid("abc", &headers("csrf_token=abc", Some("")), b""));
+    }
+
+    #[test]
+    fn header_or_form_token_must_match_the_cookie() {
+        assert!(is_valid("abc", &headers("csrf_token=abc", Some("abc")), b""));
+        assert!(!is_valid("abc", &headers("csrf_token=abc", Some("abd")), b""));
+
+        let mut form = headers("csrf_token=abc", None);
+        form.insert(header::CONTENT_TYPE, HeaderValue::from_static("application/x-www-form-urlencoded"));
+        assert!(is_valid("abc", &form, b"name=x&csrf_token=abc"));
+        assert!(!is_valid("abc", &form, b"name=x&csrf_token="));
+        assert!(!is_valid("abc", &form, b"name=x"));
+    }
+
+    #[tokio::test]
+    async fn issued_token_is_accepted_in_the_header_or_the_form() {
+        let dir = TempDir::new();
+        let (router, invoker) = router(&dir);
+
+        let response = testing::get(&router, "/forms/form").await;
+        let cookie = response.headers()[header::SET_COOKIE].to_str().unwrap().to_string();
+        let token = cookie.strip_prefix("csrf_token=").unwrap().split(';').next().unwrap().to_string();
+        let cookie = format!("csrf_token={}", token);
+
+        let response = save(&router, &[("cookie", &cookie), (CSRF_HEADER, &token)], "").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = save(
+            &router,
+            &[("cookie", &cookie), ("content-type", "application/x-www-form-urlencoded")],
+            &format!("name=a&csrf_token={}", token),
+        ).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(invoker.calls(), 3);
+    }
+
+    #[tokio::test]
+    async fn missing_or_invalid_token_is_rejected() {
+        let dir = TempDir::new();
+        let (router, invoker) = router(&dir);
+
+        let response = save(&router, &[], "").await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(testing::body_string(response).await, "Missing CSRF token");
+
+        let response = save(&router, &[("cookie", "csrf_token=abc"), (CSRF_HEADER, "xyz")], "").await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(testing::body_string(response).await, "Invalid CSRF token");
+
+        let response = save(&router, &[("cookie", "csrf_token="), (CSRF_HEADER, "")], "").await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(invoker.calls(), 0);
+    }
+}
@@ -0,0 +1,101 @@
+//! Draining of the in-flight plugin requests when the server shuts down.
+//!
+//! Every request to a plugin route holds an [`InFlight`] guard while its handler runs,
+//! and the plugin calls started by the handler hold a clone of it until the plugin
+//! function returns: a streamed call or a call whose client disconnected outlives its
+//! handler, and the plugin must not be shut down under it. Once draining starts, e.g. by [`crate::Plugins::shutdown_signal`], new requests are
+//! answered with a `503` without calling the plugin, and the requests already running
+//! can be waited for before the plugins' shutdown hooks are called.
+
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::Notify;
+
+tokio::task_local! {
+    /// The guard of the plugin request handled by the current task.
+    static CURRENT: InFlight;
+}
+
+/// The plugin requests in flight, shared by every router built by a `Plugins` instance.
+#[derive(Debug, Default)]
+pub(crate) struct Drain {
+    in_flight: AtomicUsize,
+    draining: AtomicBool,
+    idle: Notify,
+}
+
+/// A plugin request in flight, counted until it and all its clones are dropped.
+#[derive(Debug)]
+pub(crate) struct InFlight {
+    drain: Arc<Drain>,
+}
+
+impl Clone for InFlight {
+    fn clone(&self) -> Self {
+        self.drain.in_flight.fetch_add(1, Ordering::SeqCst);
+        InFlight { drain: self.drain.clone() }
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        if self.drain.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.drain.idle.notify_waiters();
+        }
+    }
+}
+
+#[cfg_attr(not(feature = "signal"), allow(dead_code))]
+impl Drain {
+
+    /// Counts a new request in flight.
+    ///
+    /// # Returns
+    /// The guard to keep while the request is handled, or `None` if draining started.
+    pub(crate) fn enter(self: &Arc<Self>) -> Option<InFlight> {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        let in_flight = InFlight { drain: self.clone() };
+        // Checked after counting the request, so `wait` never misses it
+        if self.draining.load(Ordering::SeqCst) {
+            return None;
+        }
+        Some(in_flight)
+    }
+
+    /// Stops accepting new requests.
+    pub(crate) fn start(&self) {
+        self.draining.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns the number of requests in flight.
+    pub(crate) fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Waits until no request is in flight.
+    pub(crate) async fn wait(&self) {
+        loop {
+            let mut idle = std::pin::pin!(self.idle.notified());
+            // Registers the waiter before checking the count, so a notification in
+            // between isn't lost
+            idle.as_mut().enable();
+            if self.in_flight() == 0 {
+                return;
+            }
+            idle.await;
+        }
+    }
+}
+
+/// Runs a request handler with its guard, which the plugin calls it starts can clone
+/// with [`current`].
+pub(crate) async fn scope<F: Future>(in_flight: InFlight, handler: F) -> F::Output {
+    CURRENT.scope(in_flight, handler).await
+}
+
+/// Returns a clone of the guard of the request handled by the current task, to keep
+/// the request in flight as long as a plugin call it started runs.
+pub(crate) fn current() -> Option<InFlight> {
+    CURRENT.try_with(InFlight::clone).ok()
+}
@@ -0,0 +1,283 @@
+//! Response envelope returned by plugins that need more control than a bare body.
+//!
+//! A route that declares `"envelope": true` must return a JSON object instead of a bare
+//! body:
+//!
+//! ```json
+//! {
+//!   "status": 404,
+//!   "reason": "Nothing Here",
+//!   "headers": { "cache-control": "no-store" },
+//!   "body": "<h1>Not found</h1>"
+//! }
+//! ```
+//!
+//! A paginated response can also return the cursors of its adjacent pages in a
+//! `pagination` field, turned into `Link` headers as described in [`crate::pagination`].
+//!
+//! All fields are optional. The `body` is rendered according to the route's
+//! `response_type`, exactly like a bare body would be. A header that must be sent several
+//! times, like `Set-Cookie`, takes an array of values: `"set-cookie": ["a=1", "b=2"]`.
+//!
+//! Hop-by-hop headers (RFC 7230, section 6.1) only make sense for a single connection and
+//! are owned by the server, so they are never forwarded from the envelope. The only
+//! exception is `Connection: close`, which is honored as a hint to close the connection
+//! after the response.
+
+use crate::log::warn;
+use crate::pagination::Pagination;
+use axum::response::{IntoResponse, Response};
+use hyper::{
+    ext::ReasonPhrase,
+    header::{self, HeaderMap, HeaderName, HeaderValue},
+    StatusCode, Uri,
+};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Hop-by-hop headers, see RFC 7230, section 6.1.
+const HOP_BY_HOP: [&str; 9] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// The value of an envelope header, either a single value or one value per header line.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum HeaderValues {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl HeaderValues {
+
+    /// Returns the values of the header.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &str> {
+        let values: &[String] = match self {
+            HeaderValues::One(value) => std::slice::from_ref(value),
+            HeaderValues::Many(values) => values,
+        };
+        values.iter().map(String::as_str)
+    }
+}
+
+/// The JSON envelope a plugin returns for routes declared with `"envelope": true`.
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct ResponseEnvelope {
+    /// The HTTP status code, defaults to `200 OK`.
+    #[serde(default)]
+    pub(crate) status: Option<u16>,
+    /// A custom reason phrase, only honored on HTTP/1.x connections.
+    #[serde(default)]
+    pub(crate) reason: Option<String>,
+    /// Response headers to add to the response, each with one or several values.
+    #[serde(default)]
+    pub(crate) headers: HashMap<String, HeaderValues>,
+    /// The response body, either a string or (for `json` routes) any JSON value.
+    #[serde(default)]
+    pub(crate) body: Value,
+    /// The cursors of the adjacent pages, sent as `Link` headers.
+    #[serde(default)]
+    pub(crate) pagination: Option<Pagination>,
+}
+
+impl ResponseEnvelope {
+
+    /// Parses the raw plugin output as an envelope.
+    ///
+    /// # Arguments
+    /// * `output` - The raw string returned by the plugin.
+    ///
+    /// # Returns
+    /// The parsed envelope or the JSON error.
+    pub(crate) fn parse(output: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(output)
+    }
+
+    /// Returns the body as the raw string expected by `set_response`.
+    pub(crate) fn body_string(&self) -> String {
+        match &self.body {
+            Value::Null => String::new(),
+            Value::String(body) => body.clone(),
+            body => body.to_string(),
+        }
+    }
+
+    /// Applies the status code and reason phrase of the envelope to a response.
+    ///
+    /// HTTP/2 and HTTP/3 have no reason phrase, so on those connections the custom
+    /// phrase is silently dropped and clients only see the status code.
+    ///
+    /// # Arguments
+    /// * `response` - The response built from the envelope body.
+    /// * `uri` - The original request URI, the base of the pagination links.
+    ///
+    /// # Returns
+    /// The updated response, or a `500 Internal Server Error` if the status is invalid.
+    pub(crate) fn apply(&self, mut response: Response, uri: &Uri) -> Response {
+
+        if let Some(status) = self.status {
+            match StatusCode::from_u16(status) {
+                Ok(status) => *response.status_mut() = status,
+                Err(_) => return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("Invalid status code from plugin: {}", status),
+                ).into_response(),
+            }
+        }
+
+        if let Some(reason) = &self.reason {
+            // Fall back to the canonical phrase when the plugin sends an invalid one
+            if let Ok(reason) = ReasonPhrase::try_from(reason.as_bytes()) {
+                response.extensions_mut().insert(reason);
+            }
+        }
+
+        merge_headers(response.headers_mut(), &self.headers);
+
+        if let Some(pagination) = &self.pagination {
+            pagination.apply(response.headers_mut(), uri);
+        }
+
+        response
+    }
+}
+
+/// Merges plugin-provided headers into the response headers, skipping hop-by-hop headers,
+/// headers listed in the plugin's `Connection` header, and `Content-Length`.
+///
+/// A plugin header replaces a default header of the same name; several values are all
+/// sent, one header line each.
+///
+/// # Arguments
+/// * `target` - The outgoing response headers.
+/// * `headers` - The headers returned by the plugin.
+pub(crate) fn merge_headers(
+    target: &mut HeaderMap,
+    headers: &HashMap<String, HeaderValues>,
+) {
+
+    // Headers named in `Connection` are hop-by-hop too
+    let connection: Vec<String> = headers.iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("connection"))
+        .flat_map(|(_, values)| values.iter())
+        .flat_map(|value| value.split(','))
+        .map(|token| token.trim().to_ascii_lowercase())
+        .collect();
+
+    for (name, values) in headers {
+        let lowercase = name.to_ascii_lowercase();
+        if HOP_BY_HOP.contains(&lowercase.as_str())
+            || lowercase == "content-length"
+            || connection.contains(&lowercase) {
+            continue;
+        }
+
+        let header_name = match HeaderName::try_from(name.as_str()) {
+            Ok(header_name) => header_name,
+            Err(_) => {
+                warn!("Skipping invalid response header from plugin: {}", name);
+                continue;
+            },
+        };
+
+        let mut replaced = false;
+        for value in values.iter() {
+            match HeaderValue::from_str(value) {
+                Ok(value) if replaced => { target.append(&header_name, value); },
+                Ok(value) => {
+                    target.insert(&header_name, value);
+                    replaced = true;
+                },
+                Err(_) => warn!("Skipping invalid response header from plugin: {}", name),
+            }
+        }
+    }
+
+    if connection.iter().any(|token| token == "close") {
+        target.insert(header::CONNECTION, HeaderValue::from_static("close"));
+    }
+}
+
+/// Removes the hop-by-hop headers, including the headers listed in `Connection`, from
+/// headers relayed between two connections.
+///
+/// # Arguments
+/// * `headers` - The relayed headers.
+#[cfg(feature = "remote")]
+pub(crate) fn strip_hop_by_hop(headers: &mut HeaderMap) {
+
+    let connection: Vec<String> = headers.get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|token| token.trim().to_ascii_lowercase())
+        .collect();
+
+    for name in HOP_BY_HOP.iter().copied().chain(connection.iter().map(String::as_str)) {
+        headers.remove(name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{self, TempDir};
+
+    #[tokio::test]
+    async fn custom_reason_phrase_is_sent_over_http_1_1() {
+        let dir = TempDir::new();
+        let (plugin, _) = testing::route_plugin(
+            r#"{"path": "/", "method_router": "get", "envelope": true}"#,
+            r#"{"status": 404, "reason": "Nothing Here", "body": "gone"}"#,
+        );
+        let router = testing::plugins(&dir, true)
+            .register("legacy", plugin)
+            .load()
+            .unwrap();
+        let addr = testing::serve(router).await;
+
+        let response = testing::raw_request(
+            addr,
+            "GET /legacy HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        ).await;
+
+        assert!(response.starts_with("HTTP/1.1 404 Nothing Here\r\n"), "{}", response);
+    }
+
+    #[tokio::test]
+    async fn hop_by_hop_headers_of_the_plugin_are_not_sent() {
+        let dir = TempDir::new();
+        let (plugin, _) = testing::route_plugin(
+            r#"{"path": "/", "method_router": "get", "envelope": true}"#,
+            r#"{"headers": {"keep-alive": "timeout=5", "transfer-encoding": "chunked", "content-length": "1000", "connection": "close, x-private", "x-private": "1", "x-kept": "yes"}, "body": "ok"}"#,
+        );
+        let router = testing::plugins(&dir, true)
+            .register("hops", plugin)
+            .load()
+            .unwrap();
+        let addr = testing::serve(router).await;
+
+        let response = testing::raw_request(
+            addr,
+            "GET /hops HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        ).await.to_ascii_lowercase();
+
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("http/1.1 200 ok\r\n"), "{}", head);
+        assert!(head.contains("\r\nx-kept: yes"), "{}", head);
+        assert!(head.contains("\r\nconnection: close"), "{}", head);
+        assert!(head.contains("\r\ncontent-length: 2"), "{}", head);
+        for name in ["keep-alive", "transfer-encoding", "x-private"] {
+            assert!(!head.contains(&format!("\r\n{}:", name)), "{}", head);
+        }
+        assert_eq!(body, "ok");
+    }
+}
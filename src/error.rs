@@ -0,0 +1,62 @@
+//! The error type of plugin loading.
+
+use std::fmt;
+use std::path::PathBuf;
+
+/// An error that prevents the plugins from being loaded.
+///
+/// Problems with a single plugin (a library that can't be loaded, a missing symbol,
+/// invalid routes) don't fail `load()`: the plugin is skipped with a logged error and
+/// the other plugins are still mounted.
+#[derive(Debug)]
+pub enum PluginError {
+    /// The plugins directory doesn't exist or can't be read.
+    PluginsDir {
+        /// The plugins directory.
+        path: PathBuf,
+        /// The underlying I/O error, if the directory exists but can't be read.
+        source: Option<std::io::Error>,
+    },
+    /// A configuration value is invalid, e.g. the `PLUGINS_MAX` environment variable.
+    Config(String),
+    /// A shared library couldn't be loaded or lacks a required symbol.
+    Library(libloading::Error),
+    /// A plugin's `routes` function returned no routes or invalid JSON.
+    Routes(String),
+    /// The state of the `Plugins` instance was poisoned by a panic in another thread.
+    /// No longer returned, poisoned state is recovered; kept for compatibility.
+    Poisoned,
+}
+
+impl fmt::Display for PluginError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PluginError::PluginsDir { path, source: Some(e) } => {
+                write!(f, "error reading plugins directory {}: {}", path.display(), e)
+            },
+            PluginError::PluginsDir { path, source: None } => {
+                write!(f, "plugins directory does not exist: {}", path.display())
+            },
+            PluginError::Config(message) => write!(f, "invalid configuration: {}", message),
+            PluginError::Library(e) => write!(f, "error loading library: {}", e),
+            PluginError::Routes(message) => write!(f, "invalid routes: {}", message),
+            PluginError::Poisoned => write!(f, "plugins state poisoned by a panic"),
+        }
+    }
+}
+
+impl std::error::Error for PluginError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PluginError::PluginsDir { source: Some(e), .. } => Some(e),
+            PluginError::Library(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<libloading::Error> for PluginError {
+    fn from(e: libloading::Error) -> Self {
+        PluginError::Library(e)
+    }
+}
@@ -0,0 +1,227 @@
+//! Automatic strong ETags and conditional `GET` handling.
+//!
+//! The ETag is the SHA-256 of the response body, so plugins get `304 Not Modified`
+//! responses for unchanged content without implementing ETags themselves. Plugins that
+//! return a `Last-Modified` header also get `If-Modified-Since` handled for them.
+
+use crate::log::error;
+use axum::{
+    body::{self, Body},
+    response::{IntoResponse, Response},
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hyper::{
+    body::Body as _,
+    header::{self, HeaderMap, HeaderValue},
+    Method, StatusCode,
+};
+use sha2::{Digest, Sha256};
+
+/// Adds an ETag to a successful `GET` or `HEAD` response that doesn't have one, and
+/// replaces the response with `304 Not Modified` when the request's `If-None-Match`
+/// matches it. Streaming responses, whose size isn't known, are left untouched.
+///
+/// # Arguments
+/// * `method` - The request method.
+/// * `if_none_match` - The request's `If-None-Match` header values.
+/// * `response` - The plugin's response.
+///
+/// # Returns
+/// The response with its ETag, a `304 Not Modified` response, or `500 Internal Server
+/// Error` if the body can't be read.
+pub(crate) async fn apply(
+    method: &Method,
+    if_none_match: &[HeaderValue],
+    response: Response,
+) -> Response {
+
+    if !matches!(*method, Method::GET | Method::HEAD)
+        || response.status() != StatusCode::OK
+        || response.body().size_hint().exact().is_none() {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+
+    let etag = match parts.headers.get(header::ETAG) {
+        Some(etag) => etag.clone(),
+        None => {
+            let bytes = match body::to_bytes(body, usize::MAX).await {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    error!("Error reading response body for the ETag: {}", e);
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                },
+            };
+            let digest = Sha256::digest(&bytes);
+            let etag = format!("\"{}\"", URL_SAFE_NO_PAD.encode(digest));
+            let etag = match HeaderValue::from_str(&etag) {
+                Ok(etag) => etag,
+                Err(_) => return Response::from_parts(parts, Body::from(bytes)),
+            };
+            parts.headers.insert(header::ETAG, etag.clone());
+
+            if !matches(if_none_match, &etag) {
+                return Response::from_parts(parts, Body::from(bytes));
+            }
+            return not_modified(parts.headers);
+        },
+    };
+
+    if matches(if_none_match, &etag) {
+        return not_modified(parts.headers);
+    }
+    Response::from_parts(parts, body)
+}
+
+/// Replaces a successful `GET` or `HEAD` response carrying a `Last-Modified` header with
+/// `304 Not Modified` when it wasn't modified after the request's `If-Modified-Since`.
+///
+/// `If-Modified-Since` is ignored when the request has an `If-None-Match`, which takes
+/// precedence (RFC 9110, section 13.1.3), or when either date can't be parsed.
+///
+/// # Arguments
+/// * `method` - The request method.
+/// * `if_none_match` - The request's `If-None-Match` header values.
+/// * `if_modified_since` - The request's `If-Modified-Since` header, if any.
+/// * `response` - The plugin's response.
+///
+/// # Returns
+/// The response, or a `304 Not Modified` response.
+pub(crate) fn apply_last_modified(
+    method: &Method,
+    if_none_match: &[HeaderValue],
+    if_modified_since: Option<&HeaderValue>,
+    response: Response,
+) -> Response {
+
+    if !matches!(*method, Method::GET | Method::HEAD)
+        || response.status() != StatusCode::OK
+        || !if_none_match.is_empty() {
+        return response;
+    }
+
+    let since = match if_modified_since.and_then(http_date) {
+        Some(since) => since,
+        None => return response,
+    };
+    let last_modified = match response.headers().get(header::LAST_MODIFIED).and_then(http_date) {
+        Some(last_modified) => last_modified,
+        None => return response,
+    };
+
+    // HTTP dates have a one second resolution
+    if last_modified > since {
+        return response;
+    }
+    not_modified(response.into_parts().0.headers)
+}
+
+/// Parses an HTTP date header value.
+fn http_date(value: &HeaderValue) -> Option<std::time::SystemTime> {
+    httpdate::parse_http_date(value.to_str().ok()?).ok()
+}
+
+/// Checks whether the request's `If-None-Match` matches an ETag, using the weak
+/// comparison required for `If-None-Match` (RFC 9110, section 13.1.2).
+fn matches(if_none_match: &[HeaderValue], etag: &HeaderValue) -> bool {
+    let etag = match etag.to_str() {
+        Ok(etag) => etag.trim_start_matches("W/"),
+        Err(_) => return false,
+    };
+
+    if_none_match.iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+/// Builds a `304 Not Modified` response keeping the headers that describe the content.
+fn not_modified(mut headers: HeaderMap) -> Response {
+    headers.remove(header::CONTENT_LENGTH);
+    headers.remove(header::CONTENT_TYPE);
+    headers.remove(header::CONTENT_ENCODING);
+
+    let mut response = StatusCode::NOT_MODIFIED.into_response();
+    *response.headers_mut() = headers;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TempDir};
+    use axum::extract::Request;
+
+    #[tokio::test]
+    async fn body_read_failure_is_a_server_error() {
+        let response = apply(&Method::GET, &[], testing::failing_response("text/plain")).await;
+
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(response.headers().get(header::ETAG).is_none());
+    }
+
+    #[tokio::test]
+    async fn repeat_request_with_the_etag_is_not_modified() {
+        let dir = TempDir::new();
+        let (plugin, _) = testing::route_plugin(r#"{"path": "/", "method_router": "get"}"#, "content");
+        let router = testing::plugins(&dir, true)
+            .etag(true)
+            .register("page", plugin)
+            .load()
+            .unwrap();
+
+        let response = testing::get(&router, "/page").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let etag = response.headers()[header::ETAG].clone();
+        assert_eq!(testing::body_string(response).await, "content");
+
+        let request = Request::get("/page")
+            .header(header::IF_NONE_MATCH, etag.clone())
+            .body(Body::empty())
+            .unwrap();
+        let response = testing::send(&router, request).await;
+
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers()[header::ETAG], etag);
+        assert_eq!(testing::body_string(response).await, "");
+    }
+
+    #[test]
+    fn if_none_match_uses_the_weak_comparison() {
+        let etag = HeaderValue::from_static("\"abc\"");
+
+        assert!(matches(&[HeaderValue::from_static("W/\"abc\"")], &etag));
+        assert!(matches(&[HeaderValue::from_static("\"x\", \"abc\"")], &etag));
+        assert!(matches(&[HeaderValue::from_static("*")], &etag));
+        assert!(!matches(&[HeaderValue::from_static("\"abcd\"")], &etag));
+    }
+
+    #[tokio::test]
+    async fn content_unchanged_since_if_modified_since_is_not_modified() {
+        let dir = TempDir::new();
+        let (plugin, _) = testing::route_plugin(
+            r#"{"path": "/", "method_router": "get", "envelope": true}"#,
+            r#"{"headers": {"last-modified": "Wed, 21 Oct 2015 07:28:00 GMT"}, "body": "report"}"#,
+        );
+        let router = testing::plugins(&dir, true)
+            .register("report", plugin)
+            .load()
+            .unwrap();
+        let request = |since: &str| Request::get("/report")
+            .header(header::IF_MODIFIED_SINCE, since)
+            .body(Body::empty())
+            .unwrap();
+
+        for since in ["Wed, 21 Oct 2015 07:28:00 GMT", "Thu, 22 Oct 2015 00:00:00 GMT"] {
+            let response = testing::send(&router, request(since)).await;
+            assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+            assert_eq!(response.headers()[header::LAST_MODIFIED], "Wed, 21 Oct 2015 07:28:00 GMT");
+        }
+        let response = testing::send(&router, request("Tue, 20 Oct 2015 00:00:00 GMT")).await;
+        assert_eq!(testing::body_string(response).await, "report");
+        let response = testing::send(&router, request("not a date")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
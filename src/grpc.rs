@@ -0,0 +1,230 @@
+//! Minimal gRPC framing for plugins implementing unary gRPC methods.
+//!
+//! A route declared with `"response_type": "grpc"` is served as a `POST` endpoint (e.g.
+//! `/package.Service/Method`). The host strips the 5-byte length prefix of the request
+//! message and passes the message to the plugin base64-encoded, since plugin functions
+//! exchange strings. The plugin returns the base64-encoded response message, which the
+//! host frames again and sends with the `grpc-status` trailer.
+//!
+//! Only unary calls with uncompressed messages are supported. gRPC requires HTTP/2, so
+//! the server must be built with Axum's `http2` feature.
+
+use axum::{body::{Body, Bytes}, response::Response};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hyper::{
+    body::{Frame, SizeHint},
+    header::{self, HeaderMap, HeaderValue},
+};
+use std::convert::Infallible;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// gRPC status codes used by the host, see
+/// <https://grpc.github.io/grpc/core/md_doc_statuscodes.html>.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum GrpcStatus {
+    Ok = 0,
+    DeadlineExceeded = 4,
+    Internal = 13,
+    Unimplemented = 12,
+}
+
+/// Decodes a single length-prefixed gRPC message from the request body.
+///
+/// # Arguments
+/// * `body` - The raw request body.
+///
+/// # Returns
+/// The message, base64-encoded for the plugin, or the status to respond with.
+pub(crate) fn decode_request(body: &[u8]) -> Result<String, (GrpcStatus, &'static str)> {
+
+    if body.len() < 5 {
+        return Err((GrpcStatus::Internal, "Incomplete gRPC message prefix"));
+    }
+
+    if body[0] != 0 {
+        return Err((GrpcStatus::Unimplemented, "Compressed gRPC messages are not supported"));
+    }
+
+    let len = u32::from_be_bytes([body[1], body[2], body[3], body[4]]) as usize;
+    let message = &body[5..];
+    if message.len() != len {
+        return Err((GrpcStatus::Internal, "gRPC message length mismatch"));
+    }
+
+    Ok(STANDARD.encode(message))
+}
+
+/// Builds a gRPC response from the base64-encoded message returned by the plugin.
+///
+/// # Arguments
+/// * `output` - The base64-encoded response message.
+///
+/// # Returns
+/// An Axum response carrying the framed message and the `grpc-status` trailer.
+pub(crate) fn response(output: &str) -> Response {
+    match STANDARD.decode(output.trim()) {
+        Ok(message) => {
+            let mut framed = Vec::with_capacity(message.len() + 5);
+            framed.push(0);
+            framed.extend_from_slice(&(message.len() as u32).to_be_bytes());
+            framed.extend_from_slice(&message);
+            build(Some(Bytes::from(framed)), GrpcStatus::Ok, None)
+        },
+        Err(_) => error(GrpcStatus::Internal, "Invalid base64 gRPC message from plugin"),
+    }
+}
+
+/// Builds a gRPC error response with no message.
+///
+/// # Arguments
+/// * `status` - The gRPC status code.
+/// * `message` - The status message sent in the `grpc-message` trailer.
+///
+/// # Returns
+/// An Axum response.
+pub(crate) fn error(status: GrpcStatus, message: &str) -> Response {
+    build(None, status, Some(message))
+}
+
+fn build(
+    data: Option<Bytes>,
+    status: GrpcStatus,
+    message: Option<&str>,
+) -> Response {
+
+    let mut trailers = HeaderMap::new();
+    trailers.insert("grpc-status", HeaderValue::from(status as u16));
+    if let Some(message) = message.and_then(|message| HeaderValue::from_str(message).ok()) {
+        trailers.insert("grpc-message", message);
+    }
+
+    let mut response = Response::new(Body::new(GrpcBody {
+        data,
+        trailers: Some(trailers),
+    }));
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/grpc"),
+    );
+    response
+}
+
+/// A response body made of an optional message frame followed by the trailers.
+struct GrpcBody {
+    data: Option<Bytes>,
+    trailers: Option<HeaderMap>,
+}
+
+impl hyper::body::Body for GrpcBody {
+    type Data = Bytes;
+    type Error = Infallible;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        if let Some(data) = self.data.take() {
+            return Poll::Ready(Some(Ok(Frame::data(data))));
+        }
+        Poll::Ready(self.trailers.take().map(|trailers| Ok(Frame::trailers(trailers))))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.data.is_none() && self.trailers.is_none()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        SizeHint::with_exact(self.data.as_ref().map_or(0, |data| data.len() as u64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TempDir};
+    use crate::{PluginInvoker, StaticPlugin};
+    use axum::{extract::Request, Router};
+    use hyper::body::Body as _;
+    use std::sync::Arc;
+
+    /// Answers with the message it was called with.
+    struct EchoInvoker;
+
+    impl PluginInvoker for EchoInvoker {
+        fn invoke(&self, _headers: HeaderMap, body: String) -> String {
+            body
+        }
+    }
+
+    /// Loads a plugin serving the unary `greet.Greeter/SayHello` method as an echo.
+    fn router(dir: &TempDir) -> Router {
+        let plugin = StaticPlugin::new(
+            r#"[{"path": "/greet.Greeter/SayHello", "function": "echo", "method_router": "post", "response_type": "grpc"}]"#,
+        )
+            .function("echo", Arc::new(EchoInvoker));
+        testing::plugins(dir, false)
+            .register("greeter", plugin)
+            .load()
+            .unwrap()
+    }
+
+    /// Frames a message as gRPC does.
+    fn frame(compressed: bool, message: &[u8]) -> Vec<u8> {
+        let mut framed = vec![compressed as u8];
+        framed.extend_from_slice(&(message.len() as u32).to_be_bytes());
+        framed.extend_from_slice(message);
+        framed
+    }
+
+    /// Reads the data and the trailers of a response body.
+    async fn read(response: Response) -> (Vec<u8>, HeaderMap) {
+        let mut body = response.into_body();
+        let mut data = Vec::new();
+        let mut trailers = HeaderMap::new();
+        while let Some(frame) = std::future::poll_fn(|cx| Pin::new(&mut body).poll_frame(cx)).await {
+            let frame = frame.unwrap();
+            if let Some(chunk) = frame.data_ref() {
+                data.extend_from_slice(chunk);
+            } else if let Ok(frame_trailers) = frame.into_trailers() {
+                trailers = frame_trailers;
+            }
+        }
+        (data, trailers)
+    }
+
+    /// Calls the method with a request body.
+    async fn call(router: &Router, body: Vec<u8>) -> Response {
+        let request = Request::post("/greet.Greeter/SayHello")
+            .header(header::CONTENT_TYPE, "application/grpc")
+            .body(Body::from(body))
+            .unwrap();
+        testing::send(router, request).await
+    }
+
+    #[tokio::test]
+    async fn unary_call_round_trips_through_the_plugin() {
+        let dir = TempDir::new();
+        let router = router(&dir);
+
+        let response = call(&router, frame(false, b"hello")).await;
+
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/grpc");
+        let (data, trailers) = read(response).await;
+        assert_eq!(data, frame(false, b"hello"));
+        assert_eq!(trailers["grpc-status"], "0");
+    }
+
+    #[tokio::test]
+    async fn compressed_message_is_answered_with_unimplemented() {
+        let dir = TempDir::new();
+        let router = router(&dir);
+
+        let response = call(&router, frame(true, b"hello")).await;
+
+        let (data, trailers) = read(response).await;
+        assert!(data.is_empty());
+        assert_eq!(trailers["grpc-status"], "12");
+        assert_eq!(trailers["grpc-message"], "Compressed gRPC messages are not supported");
+    }
+}
@@ -0,0 +1,343 @@
+//! Replay of cached responses for requests carrying an `Idempotency-Key` header.
+//!
+//! Routes declared with `"idempotent": true` cache their response under the key for a
+//! limited time, so a client retrying a state-changing request gets the first response
+//! back instead of invoking the plugin twice. A retry arriving while the first request
+//! is still running waits for its response, and a key reused with another request body
+//! is rejected. Keys are scoped to the client sending them, identified by its
+//! credentials, so a client can't replay another one's response by guessing its key.
+
+use crate::log::error;
+use crate::cache::{CachedResponse, MemoryCache, ResponseCache};
+use axum::response::{IntoResponse, Response};
+use hyper::{HeaderMap, Method, StatusCode};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// The request header carrying the idempotency key.
+pub(crate) const IDEMPOTENCY_KEY: &str = "idempotency-key";
+
+/// The body fingerprint of a request in flight, and a channel closed when it ends.
+type Pending = (String, watch::Receiver<()>);
+
+/// Configuration of the idempotency keys and the cache holding their responses.
+#[derive(Clone)]
+pub struct Idempotency {
+    ttl: Duration,
+    client_headers: Vec<String>,
+    cache: Arc<dyn ResponseCache>,
+    in_flight: Arc<Mutex<HashMap<String, Pending>>>,
+}
+
+/// A request holding its idempotency key until its response is stored. The key is
+/// released when the request ends or is dropped, waking the retries waiting for it.
+pub(crate) struct InFlight {
+    idempotency: Idempotency,
+    key: String,
+    fingerprint: String,
+    _done: watch::Sender<()>,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.idempotency.with(|in_flight| {
+            in_flight.remove(&self.key);
+        });
+    }
+}
+
+/// Returns the fingerprint of a request body: its hex-encoded SHA-256 hash.
+pub(crate) fn fingerprint(body: &[u8]) -> String {
+    Sha256::digest(body).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+impl std::fmt::Debug for Idempotency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Idempotency")
+            .field("ttl", &self.ttl)
+            .field("client_headers", &self.client_headers)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for Idempotency {
+    fn default() -> Self {
+        Idempotency {
+            ttl: Duration::from_secs(24 * 60 * 60),
+            client_headers: vec!["authorization".to_string()],
+            cache: Arc::new(MemoryCache::new(10_000)),
+            in_flight: Arc::default(),
+        }
+    }
+}
+
+impl Idempotency {
+
+    /// Creates an idempotency configuration keeping up to 10000 responses for 24 hours
+    /// in memory, with keys scoped to the client's `Authorization` header.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets how long a response is replayed for its idempotency key.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Sets the request headers identifying the client, e.g. `["cookie"]` when it's
+    /// authenticated by a session cookie. A key is only replayed to requests with the same
+    /// values of these headers.
+    pub fn client_headers<I, S>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.client_headers = names.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Sets the maximum number of responses kept by the in-memory cache. When the cache
+    /// is full the least recently used response is evicted.
+    pub fn max_entries(mut self, max_entries: usize) -> Self {
+        self.cache = Arc::new(MemoryCache::new(max_entries));
+        self
+    }
+
+    /// Stores the responses in another cache, e.g. one shared by several servers.
+    pub fn cache(mut self, cache: impl ResponseCache + 'static) -> Self {
+        self.cache = Arc::new(cache);
+        self
+    }
+
+    /// Returns the cache key of a request: its method, route, a hash of the headers
+    /// identifying its client, and its idempotency key.
+    ///
+    /// # Arguments
+    /// * `method` - The request method.
+    /// * `route` - The plugin name and route path.
+    /// * `headers` - The request headers.
+    /// * `key` - The value of its `Idempotency-Key` header.
+    pub(crate) fn key(&self, method: &Method, route: &str, headers: &HeaderMap, key: &str) -> String {
+        let mut client = Sha256::new();
+        for name in &self.client_headers {
+            for value in headers.get_all(name.as_str()) {
+                client.update(name.as_bytes());
+                client.update(b":");
+                client.update(value.as_bytes());
+                client.update(b"\n");
+            }
+        }
+        let client: String = client.finalize().iter().map(|byte| format!("{:02x}", byte)).collect();
+        format!("{} {} {} {}", method, route, client, key)
+    }
+
+    /// Runs a closure on the keys in flight.
+    fn with<T>(&self, f: impl FnOnce(&mut HashMap<String, Pending>) -> T) -> T {
+        match self.in_flight.lock() {
+            Ok(mut in_flight) => f(&mut in_flight),
+            Err(e) => f(&mut e.into_inner()),
+        }
+    }
+
+    /// Claims a key for a request, waiting for a request in flight with the same key.
+    ///
+    /// # Arguments
+    /// * `key` - The key of the request, see [`Idempotency::key`].
+    /// * `fingerprint` - The [`fingerprint`] of its body.
+    ///
+    /// # Returns
+    /// The claimed key, to store the response with, or the response to send instead of
+    /// calling the plugin: the cached one, or `422 Unprocessable Entity` if the key was
+    /// used with another body.
+    pub(crate) async fn claim(&self, key: String, fingerprint: String) -> Result<InFlight, Response> {
+        loop {
+            let claimed = self.with(|in_flight| {
+                match in_flight.get(&key) {
+                    Some((pending, _)) if *pending != fingerprint => Err(None),
+                    Some((_, done)) => Err(Some(done.clone())),
+                    None => {
+                        let (sender, done) = watch::channel(());
+                        in_flight.insert(key.clone(), (fingerprint.clone(), done));
+                        Ok(sender)
+                    },
+                }
+            });
+
+            let sender = match claimed {
+                Err(None) => return Err(mismatch()),
+                // Fails once the first request ends, then its response is looked up
+                Err(Some(mut done)) => {
+                    let _ = done.changed().await;
+                    continue;
+                },
+                Ok(sender) => sender,
+            };
+            let claimed = InFlight {
+                idempotency: self.clone(),
+                key,
+                fingerprint,
+                _done: sender,
+            };
+
+            // Looked up without the lock, a remote cache would hold up every request;
+            // a request releases its key after storing its response, so a claimed key's
+            // response can't be missed. Returning releases the key again.
+            return match self.cache.get(&claimed.key).await {
+                Some(cached) if cached.fingerprint.as_ref().is_some_and(|cached| *cached != claimed.fingerprint) => {
+                    Err(mismatch())
+                },
+                Some(cached) => Err(cached.into_response()),
+                None => Ok(claimed),
+            };
+        }
+    }
+}
+
+/// The response to a key reused with another request body.
+fn mismatch() -> Response {
+    (StatusCode::UNPROCESSABLE_ENTITY, "Idempotency-Key reused with a different request body").into_response()
+}
+
+impl InFlight {
+
+    /// Caches the response of the request under its key and returns it. Server errors
+    /// aren't cached, so the request can be retried.
+    pub(crate) async fn store(self, response: Response) -> Response {
+
+        if response.status().is_server_error() {
+            return response;
+        }
+
+        match CachedResponse::from_response(response).await {
+            Ok((mut cached, response)) => {
+                cached.fingerprint = Some(self.fingerprint.clone());
+                let idempotency = &self.idempotency;
+                idempotency.cache.set(&self.key, cached, idempotency.ttl).await;
+                response
+            },
+            Err(e) => {
+                error!("Error reading response body for the idempotency cache: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, SlowInvoker, TempDir};
+    use crate::{CannedInvoker, PluginInvoker, StaticPlugin};
+    use axum::{body::Body, extract::Request, Router};
+
+    /// Loads a plugin serving `POST /pay` as an idempotent route through `invoker`.
+    fn router(dir: &TempDir, invoker: Arc<dyn PluginInvoker>) -> Router {
+        let plugin = StaticPlugin::new(
+            r#"[{"path": "/pay", "function": "pay", "method_router": "post", "idempotent": true}]"#,
+        )
+            .function("pay", invoker);
+        testing::plugins(dir, true)
+            .idempotency(Idempotency::new())
+            .register("shop", plugin)
+            .load()
+            .unwrap()
+    }
+
+    /// Sends `POST /shop/pay` with an idempotency key.
+    async fn pay(router: &Router, key: &str, body: &str) -> Response {
+        pay_as(router, None, key, body).await
+    }
+
+    /// Sends `POST /shop/pay` with an idempotency key and an `Authorization` header.
+    async fn pay_as(router: &Router, authorization: Option<&str>, key: &str, body: &str) -> Response {
+        let mut request = Request::post("/shop/pay").header(IDEMPOTENCY_KEY, key);
+        if let Some(authorization) = authorization {
+            request = request.header("authorization", authorization);
+        }
+        testing::send(router, request.body(Body::from(body.to_string())).unwrap()).await
+    }
+
+    #[tokio::test]
+    async fn retry_with_the_same_key_replays_the_first_response() {
+        let dir = TempDir::new();
+        let invoker = Arc::new(CannedInvoker::new("paid"));
+        let router = router(&dir, invoker.clone());
+
+        let first = pay(&router, "a", "10").await;
+        let retry = pay(&router, "a", "10").await;
+        let other = pay(&router, "b", "10").await;
+
+        assert_eq!(testing::body_string(first).await, "paid");
+        assert_eq!(retry.status(), StatusCode::OK);
+        assert_eq!(testing::body_string(retry).await, "paid");
+        assert_eq!(other.status(), StatusCode::OK);
+        assert_eq!(invoker.calls(), 2);
+    }
+
+    #[tokio::test]
+    async fn concurrent_retries_call_the_plugin_once() {
+        let dir = TempDir::new();
+        let canned = Arc::new(CannedInvoker::new("paid"));
+        let invoker = SlowInvoker { delay: Duration::from_millis(200), canned: canned.clone() };
+        let router = router(&dir, Arc::new(invoker));
+
+        let (first, retry) = tokio::join!(pay(&router, "a", "10"), pay(&router, "a", "10"));
+
+        assert_eq!(testing::body_string(first).await, "paid");
+        assert_eq!(testing::body_string(retry).await, "paid");
+        assert_eq!(canned.calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn key_reused_with_another_body_is_rejected() {
+        let dir = TempDir::new();
+        let canned = Arc::new(CannedInvoker::new("paid"));
+        let invoker = SlowInvoker { delay: Duration::from_millis(200), canned: canned.clone() };
+        let router = router(&dir, Arc::new(invoker));
+
+        // While the first request is in flight, and once its response is cached
+        let (first, in_flight) = tokio::join!(pay(&router, "a", "10"), pay(&router, "a", "20"));
+        let cached = pay(&router, "a", "20").await;
+
+        assert_eq!(first.status(), StatusCode::OK);
+        assert_eq!(in_flight.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(cached.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(canned.calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn keys_of_other_clients_arent_replayed() {
+        let dir = TempDir::new();
+        let invoker = Arc::new(CannedInvoker::new("paid"));
+        let router = router(&dir, invoker.clone());
+
+        let alice = pay_as(&router, Some("Bearer alice"), "a", "10").await;
+        let bob = pay_as(&router, Some("Bearer bob"), "a", "10").await;
+        let anonymous = pay_as(&router, None, "a", "10").await;
+        let retry = pay_as(&router, Some("Bearer alice"), "a", "10").await;
+
+        for response in [alice, bob, anonymous, retry] {
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        assert_eq!(invoker.calls(), 3);
+    }
+
+    #[test]
+    fn key_depends_on_the_configured_client_headers() {
+        let idempotency = Idempotency::new().client_headers(["x-api-key"]);
+        let mut alice = HeaderMap::new();
+        alice.insert("x-api-key", "alice".parse().unwrap());
+        let mut bob = alice.clone();
+        bob.insert("x-api-key", "bob".parse().unwrap());
+        let mut other_token = alice.clone();
+        other_token.insert("authorization", "Bearer x".parse().unwrap());
+
+        let key = |headers| idempotency.key(&Method::POST, "shop/pay", headers, "a");
+        assert_ne!(key(&alice), key(&bob));
+        assert_eq!(key(&alice), key(&other_token));
+    }
+}
@@ -0,0 +1,123 @@
+//! Image responses, for plugins generating charts, QR codes and the like.
+//!
+//! Plugin functions return C strings, which can't hold binary data, so raster images are
+//! returned base64-encoded and their content type is detected from their magic bytes.
+//! SVG images are text and are returned as is.
+
+use crate::log::error;
+use axum::response::{IntoResponse, Response};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hyper::{header::CONTENT_TYPE, StatusCode};
+
+/// Magic byte signatures of the detected image formats.
+const SIGNATURES: [(&[u8], &str); 6] = [
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"BM", "image/bmp"),
+    (b"\x00\x00\x01\x00", "image/x-icon"),
+];
+
+/// Builds an image response from the output of a plugin function.
+///
+/// # Arguments
+/// * `output` - An SVG document, or the base64-encoded bytes of a raster image.
+///
+/// # Returns
+/// An Axum response with the detected `Content-Type`.
+pub(crate) fn response(output: &str) -> Response {
+
+    // SVG is text, sniffing it as bytes would only find XML
+    if is_svg(output) {
+        return ([(CONTENT_TYPE, "image/svg+xml")], output.to_string()).into_response();
+    }
+
+    match STANDARD.decode(output.trim()) {
+        Ok(bytes) => from_bytes(bytes),
+        Err(e) => {
+            error!("Error decoding image: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Error decoding image: {}", e),
+            ).into_response()
+        },
+    }
+}
+
+/// Builds an image response from the decoded bytes of an image.
+pub(crate) fn from_bytes(bytes: Vec<u8>) -> Response {
+    ([(CONTENT_TYPE, content_type(&bytes))], bytes).into_response()
+}
+
+/// Detects the content type of the decoded bytes of an image.
+pub(crate) fn content_type(bytes: &[u8]) -> &'static str {
+    match std::str::from_utf8(bytes) {
+        Ok(text) if is_svg(text) => "image/svg+xml",
+        _ => sniff(bytes),
+    }
+}
+
+/// Checks whether a text is an SVG document.
+fn is_svg(text: &str) -> bool {
+    let text = text.trim_start();
+    text.starts_with("<svg") || (text.starts_with("<?xml") && text.contains("<svg"))
+}
+
+/// Detects the content type of an image from its first bytes.
+fn sniff(bytes: &[u8]) -> &'static str {
+    if bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        return "image/webp";
+    }
+    SIGNATURES.iter()
+        .find(|(signature, _)| bytes.starts_with(signature))
+        .map_or("application/octet-stream", |(_, content_type)| content_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{self, TempDir};
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use hyper::header::CONTENT_TYPE;
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR";
+
+    /// Loads a plugin answering `GET /` of its `image` route with `output`, and requests it.
+    async fn image(output: &str) -> axum::response::Response {
+        let dir = TempDir::new();
+        let (plugin, _) = testing::route_plugin(
+            r#"{"path": "/", "method_router": "get", "response_type": "image"}"#,
+            output,
+        );
+        let router = testing::plugins(&dir, true)
+            .register("chart", plugin)
+            .load()
+            .unwrap();
+        testing::get(&router, "/chart").await
+    }
+
+    #[tokio::test]
+    async fn base64_encoded_png_is_sniffed_and_sent_decoded() {
+        let response = image(&STANDARD.encode(PNG)).await;
+
+        assert_eq!(response.headers()[CONTENT_TYPE], "image/png");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], PNG);
+    }
+
+    #[tokio::test]
+    async fn svg_is_sent_as_is_with_its_content_type() {
+        let svg = r#"<?xml version="1.0"?><svg xmlns="http://www.w3.org/2000/svg"></svg>"#;
+
+        let response = image(svg).await;
+
+        assert_eq!(response.headers()[CONTENT_TYPE], "image/svg+xml");
+        assert_eq!(testing::body_string(response).await, svg);
+    }
+
+    #[test]
+    fn webp_is_sniffed_and_unknown_bytes_are_an_octet_stream() {
+        assert_eq!(super::content_type(b"RIFF\0\0\0\0WEBPVP8 "), "image/webp");
+        assert_eq!(super::content_type(b"not an image"), "application/octet-stream");
+    }
+}
@@ -0,0 +1,159 @@
+//! The `/_info` and `/_plugins` endpoints, reporting the state of the plugins router.
+//!
+//! Endpoints served by the host itself live under the reserved `/_` namespace at the
+//! root of the plugins router, so they don't collide with the plugins mounted under
+//! their names. With [`crate::Plugins::info_endpoint`] enabled, `GET /_info` returns the
+//! crate and ABI versions, the uptime and the plugins served by the current router. With
+//! [`crate::Plugins::plugins_endpoint`] set, e.g. to `/_plugins`, the endpoint lists
+//! every plugin found with its state, the path it was loaded from and the routes it
+//! mounted.
+
+use axum::{
+    response::{IntoResponse, Json},
+    routing::{get, MethodRouter},
+};
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// The path of the info endpoint.
+pub(crate) const PATH: &str = "/_info";
+
+/// A route mounted by a plugin.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct RouteInfo {
+    /// The comma-separated methods, `any` for fallbacks.
+    pub(crate) methods: String,
+    /// The path the route is served at, including the plugin prefix.
+    pub(crate) path: String,
+    pub(crate) function: Option<String>,
+    pub(crate) response_type: Option<String>,
+}
+
+/// A plugin found while building the router.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct PluginInfo {
+    pub(crate) name: String,
+    pub(crate) version: Option<String>,
+    /// Whether the plugin is enabled in its `plugin.json`, disabled plugins mount nothing.
+    pub(crate) enabled: bool,
+    /// The library, the remote URL, or nothing for registered plugins.
+    pub(crate) path: Option<String>,
+    pub(crate) routes: Vec<RouteInfo>,
+}
+
+/// A plugin in the body of the info endpoint's response.
+#[derive(Debug, Serialize)]
+struct Summary<'a> {
+    name: &'a str,
+    version: Option<&'a str>,
+}
+
+/// The body of the info endpoint's response.
+#[derive(Debug, Serialize)]
+struct Info<'a> {
+    crate_version: &'static str,
+    abi_version: u32,
+    plugins_loaded: usize,
+    uptime_secs: u64,
+    plugins: Vec<Summary<'a>>,
+}
+
+/// The body of the plugins endpoint's response.
+#[derive(Debug, Serialize)]
+struct Plugins<'a> {
+    plugins: &'a [PluginInfo],
+}
+
+/// Builds the info endpoint's route.
+///
+/// # Arguments
+/// * `started` - When the `Plugins` instance enabled the endpoint, the origin of the uptime.
+/// * `plugins` - The plugins found while building the router, only the served ones are listed.
+///
+/// # Returns
+/// The `GET` route answering with the JSON report.
+pub(crate) fn route(started: Instant, plugins: Arc<Vec<PluginInfo>>) -> MethodRouter {
+    get(move || {
+        let plugins = plugins.clone();
+        async move {
+            let served: Vec<Summary> = plugins.iter()
+                .filter(|plugin| plugin.enabled)
+                .map(|plugin| Summary { name: &plugin.name, version: plugin.version.as_deref() })
+                .collect();
+            Json(Info {
+                crate_version: env!("CARGO_PKG_VERSION"),
+                abi_version: crate::PLUGIN_ABI_VERSION,
+                plugins_loaded: served.len(),
+                uptime_secs: started.elapsed().as_secs(),
+                plugins: served,
+            }).into_response()
+        }
+    })
+}
+
+/// Builds the plugins endpoint's route.
+///
+/// # Arguments
+/// * `plugins` - The plugins found while building the router.
+///
+/// # Returns
+/// The `GET` route answering with the JSON list.
+pub(crate) fn plugins_route(plugins: Arc<Vec<PluginInfo>>) -> MethodRouter {
+    get(move || {
+        let plugins = plugins.clone();
+        async move {
+            Json(Plugins { plugins: &plugins }).into_response()
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{self, TempDir};
+    use crate::{CannedInvoker, StaticPlugin, PLUGIN_ABI_VERSION};
+    use hyper::StatusCode;
+    use std::sync::Arc;
+
+    fn plugin(version: &str) -> StaticPlugin {
+        StaticPlugin::new(r#"[{"path": "/", "function": "index", "method_router": "get"}]"#)
+            .function("index", Arc::new(CannedInvoker::new("index")))
+            .version(version)
+    }
+
+    #[tokio::test]
+    async fn info_reports_the_versions_and_the_served_plugins() {
+        let dir = TempDir::new();
+        let router = testing::plugins(&dir, true)
+            .info_endpoint(true)
+            .register("items", plugin("1.2.0"))
+            .register("users", plugin("0.3.1"))
+            .load()
+            .unwrap();
+
+        let response = testing::get(&router, "/_info").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let info: serde_json::Value = serde_json::from_str(&testing::body_string(response).await).unwrap();
+
+        assert_eq!(info["crate_version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(info["abi_version"], PLUGIN_ABI_VERSION);
+        assert_eq!(info["plugins_loaded"], 2);
+        assert!(info["uptime_secs"].is_u64());
+        let mut plugins = info["plugins"].as_array().unwrap().clone();
+        plugins.sort_by_key(|plugin| plugin["name"].as_str().unwrap().to_string());
+        assert_eq!(plugins, [
+            serde_json::json!({"name": "items", "version": "1.2.0"}),
+            serde_json::json!({"name": "users", "version": "0.3.1"}),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn info_is_not_served_unless_enabled() {
+        let dir = TempDir::new();
+        let router = testing::plugins(&dir, true).register("items", plugin("1.2.0")).load().unwrap();
+
+        let response = testing::get(&router, "/_info").await;
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
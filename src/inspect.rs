@@ -0,0 +1,276 @@
+//! Diagnostics for plugin authors, checking a shared library before it's deployed.
+//!
+//! [`crate::Plugins::inspect_library`] loads a library on its own, outside any plugins
+//! directory, calls its `routes` function, validates the declared routes the way `load()`
+//! does, and checks that the symbols the host needs are exported.
+
+use crate::{PluginError, PluginRoute};
+use libloading::{Library, Symbol};
+use std::ffi::{c_char, c_void, CStr};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// The methods a regular route can declare, separated by commas.
+const METHODS: [&str; 6] = ["get", "post", "put", "delete", "patch", "any"];
+
+/// The result of [`crate::Plugins::inspect_library`].
+///
+/// Its `Display` implementation prints a human-readable report.
+#[derive(Debug, Clone)]
+pub struct LibraryReport {
+    path: PathBuf,
+    symbols: Vec<(&'static str, bool)>,
+    routes: Vec<PluginRoute>,
+    functions: Vec<(String, bool)>,
+    errors: Vec<String>,
+}
+
+impl LibraryReport {
+
+    /// Returns the path of the inspected library.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the well-known symbols looked up in the library, and whether each one is
+    /// exported.
+    pub fn symbols(&self) -> &[(&'static str, bool)] {
+        &self.symbols
+    }
+
+    /// Returns the routes declared by the library's `routes` function.
+    pub fn routes(&self) -> &[PluginRoute] {
+        &self.routes
+    }
+
+    /// Returns the route functions declared by the routes, and whether each one is
+    /// exported.
+    pub fn functions(&self) -> &[(String, bool)] {
+        &self.functions
+    }
+
+    /// Returns the problems found, empty if the library can be loaded as a plugin.
+    pub fn errors(&self) -> &[String] {
+        &self.errors
+    }
+
+    /// Returns whether no problem was found.
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+impl fmt::Display for LibraryReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Library: {}", self.path.display())?;
+
+        writeln!(f, "Symbols:")?;
+        for (symbol, exported) in &self.symbols {
+            writeln!(f, "  {} {}", mark(*exported), symbol)?;
+        }
+
+        writeln!(f, "Routes: {}", self.routes.len())?;
+        for route in &self.routes {
+            let method = if route.method_router.is_empty() { "-" } else { &route.method_router };
+            writeln!(f, "  {} {} -> {} ({})", method, route.path, route.function, route.response_type)?;
+        }
+
+        writeln!(f, "Functions:")?;
+        for (function, exported) in &self.functions {
+            writeln!(f, "  {} {}", mark(*exported), function)?;
+        }
+
+        if self.errors.is_empty() {
+            return writeln!(f, "OK");
+        }
+        writeln!(f, "Errors: {}", self.errors.len())?;
+        for error in &self.errors {
+            writeln!(f, "  {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the report marker of a symbol lookup.
+fn mark(exported: bool) -> &'static str {
+    if exported { "[x]" } else { "[ ]" }
+}
+
+/// Checks whether a library exports a symbol.
+fn exports(lib: &Library, name: &str) -> bool {
+    unsafe { lib.get::<*mut c_void>(name.as_bytes()).is_ok() }
+}
+
+/// Inspects a plugin shared library.
+///
+/// # Arguments
+/// * `path` - The path of the shared library.
+///
+/// # Returns
+/// The report, or an error if the library couldn't be loaded at all.
+pub(crate) fn inspect(path: &Path) -> Result<LibraryReport, PluginError> {
+
+    let lib = unsafe { Library::new(path)? };
+
+    let mut report = LibraryReport {
+        path: path.to_path_buf(),
+        symbols: Vec::new(),
+        routes: Vec::new(),
+        functions: Vec::new(),
+        errors: Vec::new(),
+    };
+
+    for symbol in ["routes", "free", "free_buffer", "plugin_init", "plugin_shutdown", "plugin_abi_version"] {
+        report.symbols.push((symbol, exports(&lib, symbol)));
+    }
+
+    match crate::loader::abi_version(&lib) {
+        Some(crate::PLUGIN_ABI_VERSION) => {},
+        Some(version) => report.errors.push(format!(
+            "built for ABI version {}, the host expects {}", version, crate::PLUGIN_ABI_VERSION
+        )),
+        None => report.errors.push(format!(
+            "no plugin_abi_version function, the host expects ABI version {}", crate::PLUGIN_ABI_VERSION
+        )),
+    }
+
+    let routes_json = unsafe {
+        let routes_fn: Result<Symbol<extern "C" fn() -> *const c_char>, _> = lib.get(b"routes\0");
+        let free_fn: Result<Symbol<extern "C" fn(*mut c_char)>, _> = lib.get(b"free\0");
+        match (routes_fn, free_fn) {
+            (Ok(routes_fn), Ok(free_fn)) => {
+                let ptr = routes_fn();
+                if ptr.is_null() {
+                    None
+                } else {
+                    let json = CStr::from_ptr(ptr).to_string_lossy().into_owned();
+                    free_fn(ptr as *mut c_char);
+                    Some(json)
+                }
+            },
+            _ => None,
+        }
+    };
+
+    let routes_json = match routes_json {
+        Some(json) => json,
+        None => {
+            report.errors.push("the routes function is missing, returned a null pointer, or there's no free function".to_string());
+            return Ok(report);
+        },
+    };
+
+    report.routes = match serde_json::from_str::<Vec<PluginRoute>>(&routes_json) {
+        Ok(routes) => routes,
+        Err(e) => {
+            report.errors.push(format!("invalid routes JSON: {}", e));
+            return Ok(report);
+        },
+    };
+
+    if report.routes.is_empty() {
+        report.errors.push("the plugin declares no routes".to_string());
+    }
+
+    for route in &report.routes {
+        let error = |reason: String| format!("{}: {} - {}", route.path, route.function, reason);
+
+        if let Err(reason) = route.validate() {
+            report.errors.push(error(reason));
+        }
+
+        // Fallbacks take every method, gRPC routes default to POST
+        let method_optional = route.kind == crate::RouteKind::Fallback
+            || route.response_type.eq_ignore_ascii_case("grpc");
+        let method_valid = route.method_router.split(',')
+            .all(|declared| METHODS.iter().any(|method| method.eq_ignore_ascii_case(declared.trim())))
+            || (method_optional && route.method_router.is_empty());
+        if !method_valid {
+            report.errors.push(error(format!("unsupported method {:?}", route.method_router)));
+        }
+
+        if route.abi.is_some() && !exports(&lib, "free_buffer") {
+            report.errors.push(error("the buffer ABI requires a free_buffer function".to_string()));
+        }
+
+        if !report.functions.iter().any(|(function, _)| function == &route.function) {
+            let exported = exports(&lib, &route.function);
+            if !exported {
+                report.errors.push(error("the function isn't exported".to_string()));
+            }
+            report.functions.push((route.function.clone(), exported));
+        }
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TempDir};
+
+    #[test]
+    fn library_declaring_no_routes_is_reported() {
+        let dir = TempDir::new();
+        let plugin_dir = dir.plugin("empty", &testing::config("empty"), None);
+        if !testing::compile_plugin(&plugin_dir, &testing::open_events_plugin(&plugin_dir.join("events"))) {
+            return;
+        }
+
+        let report = inspect(&plugin_dir.join("lib.so")).unwrap();
+
+        assert!(report.routes.is_empty());
+        assert!(report.errors.contains(&"the plugin declares no routes".to_string()));
+    }
+
+    #[test]
+    fn good_library_is_reported_ok() {
+        let dir = TempDir::new();
+        let plugin_dir = dir.plugin("hooks", &testing::config("hooks"), None);
+        if !testing::compile_plugin(&plugin_dir, &testing::hooks_plugin("hello", &plugin_dir.join("events"))) {
+            return;
+        }
+
+        let report = inspect(&plugin_dir.join("lib.so")).unwrap();
+
+        assert!(report.is_ok(), "{}", report);
+        assert_eq!(report.functions(), [("answer".to_string(), true)]);
+        let text = report.to_string();
+        assert!(text.contains("  [x] plugin_init\n"), "{}", text);
+        assert!(text.contains("  [ ] free_buffer\n"), "{}", text);
+        assert!(text.contains("Routes: 1\n  get / -> answer (text)\n"), "{}", text);
+        assert!(text.ends_with("Functions:\n  [x] answer\nOK\n"), "{}", text);
+    }
+
+    #[test]
+    fn broken_library_errors_are_reported() {
+        let dir = TempDir::new();
+        let plugin_dir = dir.plugin("broken", &testing::config("broken"), None);
+        let source = r#"
+#include <stdint.h>
+
+uint32_t plugin_abi_version(void) { return 99; }
+const char *routes(void) {
+    return "[{\"path\": \"/a\", \"function\": \"missing\", \"method_router\": \"get\"},"
+        " {\"path\": \"/b\", \"function\": \"present\", \"method_router\": \"fetch\"}]";
+}
+const char *present(void *headers, const char *body) { (void)headers; (void)body; return ""; }
+void free(void *ptr) { (void)ptr; }
+"#;
+        if !testing::compile_plugin(&plugin_dir, source) {
+            return;
+        }
+
+        let report = inspect(&plugin_dir.join("lib.so")).unwrap();
+
+        assert!(!report.is_ok());
+        assert_eq!(report.functions(), [("missing".to_string(), false), ("present".to_string(), true)]);
+        assert_eq!(report.errors(), [
+            format!("built for ABI version 99, the host expects {}", crate::PLUGIN_ABI_VERSION),
+            "/a: missing - the function isn't exported".to_string(),
+            "/b: present - unsupported method \"fetch\"".to_string(),
+        ]);
+        assert!(report.to_string().contains("Errors: 3\n"));
+    }
+}
@@ -0,0 +1,689 @@
+//! Abstraction over the FFI boundary used to call plugin functions.
+//!
+//! Route handlers never call a plugin's `extern "C"` function directly. Instead they go
+//! through a [`PluginInvoker`], which makes it possible to replace the shared library with
+//! a deterministic test double and exercise the request/response logic in isolation.
+
+use crate::log::error;
+use axum::body::Bytes;
+use hyper::HeaderMap;
+use std::any::Any;
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::io::Read;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Signature of a plugin route function: receives the request headers and the
+/// NUL-terminated request body, and returns a NUL-terminated string.
+///
+/// The host reads the returned string up to its first NUL byte, so a buggy plugin
+/// returning an unterminated buffer makes it read out of bounds. Functions of routes
+/// declaring `"abi": "buffer"` return a [`PluginBuffer`] instead, see [`BufferFn`].
+///
+/// The headers are owned by the host and only lent for the duration of the call: the host
+/// drops them as soon as the function returns, so the plugin must not keep the pointer
+/// or take ownership of the `HeaderMap`. The same applies to every route function ABI.
+pub type PluginFn = extern "C" fn(*mut HeaderMap, *const c_char) -> *const c_char;
+
+/// Signature of the plugin function that releases memory returned by a [`PluginFn`].
+pub type FreeFn = extern "C" fn(*mut c_char);
+
+/// The version of the contract between the host and the plugin libraries: the symbols
+/// and signatures above. A library exports it through its `plugin_abi_version` function
+/// and isn't loaded if it returns another version.
+pub const PLUGIN_ABI_VERSION: u32 = 1;
+
+/// Signature of the plugin function returning the [`PLUGIN_ABI_VERSION`] the library was
+/// built for.
+pub type AbiVersionFn = extern "C" fn() -> u32;
+
+/// Signature of the optional plugin function called once when the library is loaded,
+/// before any of its route functions, with the NUL-terminated JSON of the `config` key of
+/// its `plugin.json` (`{}` if unset). The string is only lent for the duration of the call.
+pub type PluginInitFn = extern "C" fn(*const c_char);
+
+/// The output of a [`BufferFn`]: a pointer to `len` bytes owned by the plugin.
+///
+/// The bytes don't need to be NUL-terminated and may contain NUL bytes.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct PluginBuffer {
+    /// A pointer to the first byte, may be null if `len` is 0.
+    pub data: *const u8,
+    /// The number of bytes.
+    pub len: usize,
+}
+
+/// Signature of a plugin route function using the length-carrying ABI: receives the
+/// request headers and the NUL-terminated request body, and returns a [`PluginBuffer`].
+pub type BufferFn = extern "C" fn(*mut HeaderMap, *const c_char) -> PluginBuffer;
+
+/// Signature of the plugin function that releases a [`PluginBuffer`] returned by a
+/// [`BufferFn`] or a [`BytesFn`], receiving its pointer and length.
+pub type FreeBufferFn = extern "C" fn(*mut u8, usize);
+
+/// Signature of a route function declaring `"abi": "bytes"`: receives the request headers
+/// and the request body as a pointer and a length, and returns a [`PluginBuffer`].
+///
+/// The body is passed as received, it may not be valid UTF-8 and may contain NUL bytes.
+/// It's only lent for the duration of the call, like the headers.
+pub type BytesFn = extern "C" fn(*mut HeaderMap, *const u8, usize) -> PluginBuffer;
+
+/// Signature of the callback a [`StreamFn`] calls with every NUL-terminated chunk of its
+/// output, passing back the context pointer it was given. Returns `false` once the client
+/// is gone, so the plugin can stop producing output.
+pub type ChunkFn = extern "C" fn(*mut c_void, *const c_char) -> bool;
+
+/// Signature of a streaming route function: receives the request headers, the
+/// NUL-terminated request body, and a [`ChunkFn`] with its context pointer, and returns
+/// once the whole output was passed to the callback. The chunks remain owned by the
+/// plugin and are copied by the callback.
+pub type StreamFn = extern "C" fn(*mut HeaderMap, *const c_char, ChunkFn, *mut c_void);
+
+/// Signature of the callback a [`DuplexFn`] calls with every chunk of its output, given
+/// as a pointer and a length, passing back the context pointer it was given. The chunks
+/// may contain any bytes, including NULs. Returns `false` once the client is gone, so the
+/// plugin can stop producing output.
+pub type BytesChunkFn = extern "C" fn(*mut c_void, *const u8, usize) -> bool;
+
+/// Signature of the callback a [`DuplexFn`] calls to read the request body, passing back
+/// the context pointer it was given, a buffer and its capacity. Blocks until data is
+/// available and returns the number of bytes copied into the buffer, `0` once the body
+/// is complete, or `-1` if it couldn't be read (e.g. the client is gone).
+pub type ReadFn = extern "C" fn(*mut c_void, *mut u8, usize) -> isize;
+
+/// Signature of a route function streaming both its request body and its output
+/// (`"request_stream": true`): receives the request headers, a [`ReadFn`] with its
+/// context pointer to read the body, and a [`BytesChunkFn`] with its context pointer to
+/// emit the output, and returns once the whole output was passed to the callback. Both
+/// directions carry raw bytes, so binary bodies pass through unchanged.
+pub type DuplexFn = extern "C" fn(*mut HeaderMap, ReadFn, *mut c_void, BytesChunkFn, *mut c_void);
+
+/// Calls a plugin function with the request headers and body.
+///
+/// Implementations are invoked from a blocking task, so they are free to block.
+pub trait PluginInvoker: Send + Sync {
+
+    /// Invokes the plugin function and returns its raw output.
+    ///
+    /// # Arguments
+    /// * `headers` - The request headers.
+    /// * `body` - The request body as a string.
+    ///
+    /// # Returns
+    /// The raw response string produced by the plugin.
+    fn invoke(&self, headers: HeaderMap, body: String) -> String;
+
+    /// Invokes the plugin function for a streamed route, passing its output to `emit`
+    /// as it's produced.
+    ///
+    /// The default implementation emits the whole output of [`PluginInvoker::invoke`]
+    /// as a single chunk.
+    ///
+    /// # Arguments
+    /// * `headers` - The request headers.
+    /// * `body` - The request body as a string.
+    /// * `emit` - Called with every chunk, returns `false` once the client is gone.
+    fn invoke_stream(&self, headers: HeaderMap, body: String, emit: &mut dyn FnMut(String) -> bool) {
+        emit(self.invoke(headers, body));
+    }
+
+    /// Invokes the plugin function for a route streaming its request body, which the
+    /// function reads from `body` as it arrives, passing its output to `emit`.
+    ///
+    /// The default implementation reads the whole body and calls
+    /// [`PluginInvoker::invoke_stream`], or doesn't call the function at all if the body
+    /// couldn't be read.
+    ///
+    /// # Arguments
+    /// * `headers` - The request headers.
+    /// * `body` - The request body, blocking until more data is available.
+    /// * `emit` - Called with every chunk of raw bytes, returns `false` once the client
+    ///   is gone.
+    fn invoke_duplex(&self, headers: HeaderMap, body: &mut dyn Read, emit: &mut dyn FnMut(Bytes) -> bool) {
+        let mut bytes = Vec::new();
+        match body.read_to_end(&mut bytes) {
+            Ok(_) => self.invoke_stream(
+                headers,
+                String::from_utf8_lossy(&bytes).into_owned(),
+                &mut |chunk| emit(Bytes::from(chunk)),
+            ),
+            Err(e) => error!("Error reading streamed request body: {}", e),
+        }
+    }
+
+    /// Invokes the plugin function for a `bytes` route and returns its raw output, which
+    /// may not be valid UTF-8.
+    ///
+    /// The default implementation returns the bytes of [`PluginInvoker::invoke`].
+    ///
+    /// # Arguments
+    /// * `headers` - The request headers.
+    /// * `body` - The request body as a string.
+    ///
+    /// # Returns
+    /// The raw response bytes produced by the plugin.
+    fn invoke_bytes(&self, headers: HeaderMap, body: String) -> Vec<u8> {
+        self.invoke(headers, body).into_bytes()
+    }
+
+    /// Invokes the plugin function for a route declaring `"abi": "bytes"` with the request
+    /// body as received, which may not be valid UTF-8, and returns its raw output.
+    ///
+    /// The default implementation replaces the invalid UTF-8 sequences of the body with
+    /// `U+FFFD` and calls [`PluginInvoker::invoke_bytes`].
+    ///
+    /// # Arguments
+    /// * `headers` - The request headers.
+    /// * `body` - The raw request body.
+    ///
+    /// # Returns
+    /// The raw response bytes produced by the plugin.
+    fn invoke_raw(&self, headers: HeaderMap, body: Vec<u8>) -> Vec<u8> {
+        self.invoke_bytes(headers, String::from_utf8_lossy(&body).into_owned())
+    }
+}
+
+/// Converts a request body to the NUL-terminated string of the string ABIs.
+///
+/// The host rejects bodies containing NUL bytes before calling a plugin, an invoker
+/// called with one anyway passes the body up to its first NUL, as the plugin would read it.
+fn c_body(body: String) -> CString {
+    let mut body = body.into_bytes();
+    if let Some(position) = body.iter().position(|&byte| byte == 0) {
+        body.truncate(position);
+    }
+    CString::new(body).unwrap_or_default()
+}
+
+/// Copies the bytes of a [`PluginBuffer`] returned by a plugin function and releases it.
+fn take_buffer(buffer: PluginBuffer, free: FreeBufferFn) -> Vec<u8> {
+    if buffer.data.is_null() {
+        if buffer.len != 0 {
+            panic!("Received null pointer with a length of {} from function", buffer.len);
+        }
+        return Vec::new();
+    }
+    if buffer.len > isize::MAX as usize {
+        panic!("Received invalid length {} from function", buffer.len);
+    }
+
+    let data = unsafe { std::slice::from_raw_parts(buffer.data, buffer.len).to_vec() };
+    free(buffer.data as *mut u8, buffer.len);
+    data
+}
+
+/// The default invoker, backed by function pointers resolved from a loaded shared library.
+pub struct FfiInvoker {
+    function: PluginFn,
+    free: FreeFn,
+    // Keeps the library the function pointers come from loaded
+    _library: Option<Arc<dyn Any + Send + Sync>>,
+}
+
+impl FfiInvoker {
+
+    /// Creates an invoker for a plugin function and its memory-freeing function.
+    ///
+    /// The library the pointers were resolved from must stay loaded for as long as the
+    /// invoker is in use.
+    ///
+    /// # Arguments
+    /// * `function` - A pointer to the plugin's function to execute.
+    /// * `free` - A pointer to the plugin's memory-freeing function.
+    ///
+    /// # Returns
+    /// A new `FfiInvoker` instance.
+    pub fn new(function: PluginFn, free: FreeFn) -> Self {
+        FfiInvoker {
+            function,
+            free,
+            _library: None,
+        }
+    }
+
+    /// Ties the lifetime of the library the function pointers were resolved from to
+    /// the invoker, so the library can't be unloaded while the invoker is alive.
+    pub(crate) fn keep_alive(mut self, library: Arc<dyn Any + Send + Sync>) -> Self {
+        self._library = Some(library);
+        self
+    }
+}
+
+impl PluginInvoker for FfiInvoker {
+
+    fn invoke(&self, headers: HeaderMap, body: String) -> String {
+        // Box the headers and convert the body to a CString
+        let box_headers = Box::new(headers);
+        let c_body = c_body(body);
+
+        // Call the external C function with the appropriate pointers
+        let headers = Box::into_raw(box_headers);
+        let ptr = (self.function)(headers, c_body.as_ptr());
+
+        // The plugin only borrows the headers for the duration of the call
+        drop(unsafe { Box::from_raw(headers) });
+
+        if ptr.is_null() {
+            panic!("Received null pointer from function");
+        }
+
+        // clean this from memory
+        unsafe {
+            let data = CStr::from_ptr(ptr).to_string_lossy().into_owned();
+            (self.free)(ptr as *mut c_char);
+            data
+        }
+    }
+}
+
+/// An invoker for route functions returning a [`PluginBuffer`], resolved from a loaded
+/// shared library.
+///
+/// The output is read from its pointer and length rather than up to a NUL byte. It's
+/// returned as is to `bytes` routes, the other routes get invalid UTF-8 sequences
+/// replaced with `U+FFFD`.
+pub struct FfiBufferInvoker {
+    function: BufferFn,
+    free: FreeBufferFn,
+    // Keeps the library the function pointers come from loaded
+    _library: Option<Arc<dyn Any + Send + Sync>>,
+}
+
+impl FfiBufferInvoker {
+
+    /// Creates an invoker for a plugin function and its buffer-freeing function.
+    ///
+    /// The library the pointers were resolved from must stay loaded for as long as the
+    /// invoker is in use.
+    ///
+    /// # Arguments
+    /// * `function` - A pointer to the plugin's function to execute.
+    /// * `free` - A pointer to the plugin's buffer-freeing function.
+    ///
+    /// # Returns
+    /// A new `FfiBufferInvoker` instance.
+    pub fn new(function: BufferFn, free: FreeBufferFn) -> Self {
+        FfiBufferInvoker {
+            function,
+            free,
+            _library: None,
+        }
+    }
+
+    /// Ties the lifetime of the library the function pointers were resolved from to
+    /// the invoker, so the library can't be unloaded while the invoker is alive.
+    pub(crate) fn keep_alive(mut self, library: Arc<dyn Any + Send + Sync>) -> Self {
+        self._library = Some(library);
+        self
+    }
+}
+
+impl PluginInvoker for FfiBufferInvoker {
+
+    fn invoke(&self, headers: HeaderMap, body: String) -> String {
+        match String::from_utf8(self.invoke_bytes(headers, body)) {
+            Ok(data) => data,
+            Err(e) => String::from_utf8_lossy(e.as_bytes()).into_owned(),
+        }
+    }
+
+    fn invoke_bytes(&self, headers: HeaderMap, body: String) -> Vec<u8> {
+        let headers = Box::into_raw(Box::new(headers));
+        let c_body = c_body(body);
+
+        let buffer = (self.function)(headers, c_body.as_ptr());
+
+        // The plugin only borrows the headers for the duration of the call
+        drop(unsafe { Box::from_raw(headers) });
+
+        take_buffer(buffer, self.free)
+    }
+}
+
+/// An invoker for route functions declaring `"abi": "bytes"`, resolved from a loaded
+/// shared library.
+///
+/// The request body is passed with its length, so binary bodies reach the plugin
+/// unchanged, and the output is read from a [`PluginBuffer`]. The text entry points
+/// pass the bytes of the string body.
+pub struct FfiBytesInvoker {
+    function: BytesFn,
+    free: FreeBufferFn,
+    // Keeps the library the function pointers come from loaded
+    _library: Option<Arc<dyn Any + Send + Sync>>,
+}
+
+impl FfiBytesInvoker {
+
+    /// Creates an invoker for a plugin function and its buffer-freeing function.
+    ///
+    /// The library the pointers were resolved from must stay loaded for as long as the
+    /// invoker is in use.
+    ///
+    /// # Arguments
+    /// * `function` - A pointer to the plugin's function to execute.
+    /// * `free` - A pointer to the plugin's buffer-freeing function.
+    ///
+    /// # Returns
+    /// A new `FfiBytesInvoker` instance.
+    pub fn new(function: BytesFn, free: FreeBufferFn) -> Self {
+        FfiBytesInvoker {
+            function,
+            free,
+            _library: None,
+        }
+    }
+
+    /// Ties the lifetime of the library the function pointers were resolved from to
+    /// the invoker, so the library can't be unloaded while the invoker is alive.
+    pub(crate) fn keep_alive(mut self, library: Arc<dyn Any + Send + Sync>) -> Self {
+        self._library = Some(library);
+        self
+    }
+}
+
+impl PluginInvoker for FfiBytesInvoker {
+
+    fn invoke(&self, headers: HeaderMap, body: String) -> String {
+        match String::from_utf8(self.invoke_raw(headers, body.into_bytes())) {
+            Ok(data) => data,
+            Err(e) => String::from_utf8_lossy(e.as_bytes()).into_owned(),
+        }
+    }
+
+    fn invoke_bytes(&self, headers: HeaderMap, body: String) -> Vec<u8> {
+        self.invoke_raw(headers, body.into_bytes())
+    }
+
+    fn invoke_raw(&self, headers: HeaderMap, body: Vec<u8>) -> Vec<u8> {
+        let headers = Box::into_raw(Box::new(headers));
+
+        let buffer = (self.function)(headers, body.as_ptr(), body.len());
+
+        // The plugin only borrows the headers and the body for the duration of the call
+        drop(unsafe { Box::from_raw(headers) });
+
+        take_buffer(buffer, self.free)
+    }
+}
+
+/// An invoker for streaming route functions, resolved from a loaded shared library.
+pub struct FfiStreamInvoker {
+    function: StreamFn,
+    // Keeps the library the function pointer comes from loaded
+    _library: Option<Arc<dyn Any + Send + Sync>>,
+}
+
+impl FfiStreamInvoker {
+
+    /// Creates an invoker for a streaming plugin function.
+    ///
+    /// The library the pointer was resolved from must stay loaded for as long as the
+    /// invoker is in use.
+    ///
+    /// # Arguments
+    /// * `function` - A pointer to the plugin's streaming function.
+    ///
+    /// # Returns
+    /// A new `FfiStreamInvoker` instance.
+    pub fn new(function: StreamFn) -> Self {
+        FfiStreamInvoker {
+            function,
+            _library: None,
+        }
+    }
+
+    /// Ties the lifetime of the library the function pointer was resolved from to the
+    /// invoker, so the library can't be unloaded while the invoker is alive.
+    pub(crate) fn keep_alive(mut self, library: Arc<dyn Any + Send + Sync>) -> Self {
+        self._library = Some(library);
+        self
+    }
+}
+
+/// Forwards a chunk received from a [`StreamFn`] to the `emit` closure behind `context`.
+extern "C" fn emit_chunk(context: *mut c_void, chunk: *const c_char) -> bool {
+    if context.is_null() || chunk.is_null() {
+        return true;
+    }
+
+    // The context is the `&mut dyn FnMut` passed by `invoke_stream`
+    let emit = unsafe { &mut *(context as *mut &mut dyn FnMut(String) -> bool) };
+    let chunk = unsafe { CStr::from_ptr(chunk) }.to_string_lossy().into_owned();
+    emit(chunk)
+}
+
+impl PluginInvoker for FfiStreamInvoker {
+
+    fn invoke(&self, headers: HeaderMap, body: String) -> String {
+        let mut output = String::new();
+        self.invoke_stream(headers, body, &mut |chunk| {
+            output.push_str(&chunk);
+            true
+        });
+        output
+    }
+
+    fn invoke_stream(&self, headers: HeaderMap, body: String, mut emit: &mut dyn FnMut(String) -> bool) {
+        let headers = Box::into_raw(Box::new(headers));
+        let c_body = c_body(body);
+
+        let context = &mut emit as *mut &mut dyn FnMut(String) -> bool as *mut c_void;
+        (self.function)(headers, c_body.as_ptr(), emit_chunk, context);
+
+        // The plugin only borrows the headers for the duration of the call
+        drop(unsafe { Box::from_raw(headers) });
+    }
+}
+
+/// An invoker for route functions streaming their request body and their output,
+/// resolved from a loaded shared library.
+pub struct FfiDuplexInvoker {
+    function: DuplexFn,
+    // Keeps the library the function pointer comes from loaded
+    _library: Option<Arc<dyn Any + Send + Sync>>,
+}
+
+impl FfiDuplexInvoker {
+
+    /// Creates an invoker for a plugin function streaming its request body.
+    ///
+    /// The library the pointer was resolved from must stay loaded for as long as the
+    /// invoker is in use.
+    ///
+    /// # Arguments
+    /// * `function` - A pointer to the plugin's duplex streaming function.
+    ///
+    /// # Returns
+    /// A new `FfiDuplexInvoker` instance.
+    pub fn new(function: DuplexFn) -> Self {
+        FfiDuplexInvoker {
+            function,
+            _library: None,
+        }
+    }
+
+    /// Ties the lifetime of the library the function pointer was resolved from to the
+    /// invoker, so the library can't be unloaded while the invoker is alive.
+    pub(crate) fn keep_alive(mut self, library: Arc<dyn Any + Send + Sync>) -> Self {
+        self._library = Some(library);
+        self
+    }
+}
+
+/// Reads the request body behind `context` into a buffer provided by a [`DuplexFn`].
+extern "C" fn read_body(context: *mut c_void, buffer: *mut u8, len: usize) -> isize {
+    if context.is_null() || buffer.is_null() || len == 0 {
+        return 0;
+    }
+
+    // The context is the `&mut dyn Read` passed by `invoke_duplex`
+    let body = unsafe { &mut *(context as *mut &mut dyn Read) };
+    let buffer = unsafe { std::slice::from_raw_parts_mut(buffer, len.min(isize::MAX as usize)) };
+    loop {
+        match body.read(buffer) {
+            Ok(read) => return read as isize,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(_) => return -1,
+        }
+    }
+}
+
+/// Forwards a chunk received from a [`DuplexFn`] to the `emit` closure behind `context`.
+extern "C" fn emit_bytes_chunk(context: *mut c_void, chunk: *const u8, len: usize) -> bool {
+    if context.is_null() || chunk.is_null() || len == 0 {
+        return true;
+    }
+    if len > isize::MAX as usize {
+        panic!("Received invalid chunk length {} from function", len);
+    }
+
+    // The context is the `&mut dyn FnMut` passed by `invoke_duplex`
+    let emit = unsafe { &mut *(context as *mut &mut dyn FnMut(Bytes) -> bool) };
+    let chunk = Bytes::copy_from_slice(unsafe { std::slice::from_raw_parts(chunk, len) });
+    emit(chunk)
+}
+
+impl PluginInvoker for FfiDuplexInvoker {
+
+    fn invoke(&self, headers: HeaderMap, body: String) -> String {
+        String::from_utf8_lossy(&self.invoke_raw(headers, body.into_bytes())).into_owned()
+    }
+
+    fn invoke_stream(&self, headers: HeaderMap, body: String, emit: &mut dyn FnMut(String) -> bool) {
+        self.invoke_duplex(headers, &mut body.as_bytes(), &mut |chunk| {
+            emit(String::from_utf8_lossy(&chunk).into_owned())
+        });
+    }
+
+    fn invoke_raw(&self, headers: HeaderMap, body: Vec<u8>) -> Vec<u8> {
+        let mut output = Vec::new();
+        self.invoke_duplex(headers, &mut body.as_slice(), &mut |chunk| {
+            output.extend_from_slice(&chunk);
+            true
+        });
+        output
+    }
+
+    fn invoke_duplex(&self, headers: HeaderMap, mut body: &mut dyn Read, mut emit: &mut dyn FnMut(Bytes) -> bool) {
+        let headers = Box::into_raw(Box::new(headers));
+
+        let body_context = &mut body as *mut &mut dyn Read as *mut c_void;
+        let emit_context = &mut emit as *mut &mut dyn FnMut(Bytes) -> bool as *mut c_void;
+        (self.function)(headers, read_body, body_context, emit_bytes_chunk, emit_context);
+
+        // The plugin only borrows the headers for the duration of the call
+        drop(unsafe { Box::from_raw(headers) });
+    }
+}
+
+/// A test double that returns a canned response without touching any shared library.
+///
+/// It also counts how many times it was invoked, which is handy to assert whether a
+/// request actually reached the "plugin".
+pub struct CannedInvoker {
+    response: String,
+    calls: AtomicUsize,
+}
+
+impl CannedInvoker {
+
+    /// Creates an invoker that always returns `response`.
+    ///
+    /// # Arguments
+    /// * `response` - The raw output returned on every invocation.
+    ///
+    /// # Returns
+    /// A new `CannedInvoker` instance.
+    pub fn new(response: impl Into<String>) -> Self {
+        CannedInvoker {
+            response: response.into(),
+            calls: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns how many times the invoker has been called.
+    pub fn calls(&self) -> usize {
+        self.calls.load(Ordering::SeqCst)
+    }
+}
+
+impl PluginInvoker for CannedInvoker {
+
+    fn invoke(&self, _headers: HeaderMap, _body: String) -> String {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        self.response.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TempDir};
+    use crate::StaticPlugin;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const BODY: &[u8] = b"key\0value\0";
+
+    static FREED: AtomicUsize = AtomicUsize::new(0);
+
+    extern "C" fn buffer(_headers: *mut HeaderMap, _body: *const c_char) -> PluginBuffer {
+        PluginBuffer { data: BODY.as_ptr(), len: BODY.len() }
+    }
+
+    extern "C" fn free_buffer(_data: *mut u8, len: usize) {
+        FREED.fetch_add(len, Ordering::SeqCst);
+    }
+
+    #[tokio::test]
+    async fn buffer_abi_body_keeps_its_embedded_nuls() {
+        let dir = TempDir::new();
+        let plugin = StaticPlugin::new(
+            r#"[{"path": "/", "function": "buffer", "method_router": "get", "response_type": "bytes", "abi": "buffer"}]"#,
+        ).function("buffer", Arc::new(FfiBufferInvoker::new(buffer, free_buffer)));
+        let router = testing::plugins(&dir, true)
+            .register("raw", plugin)
+            .load()
+            .unwrap();
+
+        let response = testing::get(&router, "/raw").await;
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], BODY);
+        assert_eq!(FREED.load(Ordering::SeqCst), BODY.len());
+    }
+
+    #[test]
+    #[should_panic(expected = "Received null pointer with a length of 4 from function")]
+    fn null_buffer_with_a_length_is_rejected() {
+        take_buffer(PluginBuffer { data: std::ptr::null(), len: 4 }, free_buffer);
+    }
+
+    extern "C" fn answer(_headers: *mut HeaderMap, _body: *const c_char) -> *const c_char {
+        c"ok".as_ptr()
+    }
+
+    extern "C" fn free_nothing(_ptr: *mut c_char) {}
+
+    #[test]
+    fn headers_passed_to_the_plugin_are_freed_after_each_call() {
+        let invoker = FfiInvoker::new(answer, free_nothing);
+        let mut headers = HeaderMap::new();
+        for i in 0..32 {
+            headers.insert(
+                hyper::header::HeaderName::from_bytes(format!("x-header-{}", i).as_bytes()).unwrap(),
+                "a value long enough to be noticed".parse().unwrap(),
+            );
+        }
+
+        // The first call may allocate lazily initialized state
+        invoker.invoke(headers.clone(), "body".to_string());
+        let before = testing::allocated_bytes();
+        for _ in 0..1000 {
+            assert_eq!(invoker.invoke(headers.clone(), "body".to_string()), "ok");
+        }
+
+        assert_eq!(testing::allocated_bytes() - before, 0);
+    }
+}
@@ -0,0 +1,121 @@
+//! Removal of JSON fields from plugin responses, for host-enforced data minimization.
+
+use serde_json::Value;
+
+/// The JSON fields removed from the responses of selected routes.
+///
+/// Fields are given as dot-separated paths (e.g. `user.ssn`). Arrays are traversed, so
+/// `items.secret` removes the `secret` field of every element of `items`.
+#[derive(Debug, Clone, Default)]
+pub struct JsonFilter {
+    routes: Vec<(String, String, Vec<Vec<String>>)>,
+}
+
+impl JsonFilter {
+
+    /// Creates a filter that removes no field.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Removes fields from the JSON responses of a route.
+    ///
+    /// # Arguments
+    /// * `plugin` - The plugin name.
+    /// * `path` - The route path as declared by the plugin (e.g. `/test-json`).
+    /// * `fields` - The dot-separated paths of the removed fields.
+    pub fn route<I, S>(
+        mut self,
+        plugin: impl Into<String>,
+        path: impl Into<String>,
+        fields: I,
+    ) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let fields = fields.into_iter()
+            .map(|field| field.into().split('.').map(str::to_string).collect())
+            .collect();
+        self.routes.push((plugin.into(), path.into(), fields));
+        self
+    }
+
+    /// Removes the configured fields of a route from a JSON body. Bodies that aren't
+    /// valid JSON, and routes without filtered fields, are returned unchanged.
+    pub(crate) fn apply(&self, plugin: &str, path: &str, body: &str) -> Option<String> {
+
+        let fields: Vec<&Vec<String>> = self.routes.iter()
+            .filter(|(p, r, _)| p == plugin && r == path)
+            .flat_map(|(_, _, fields)| fields)
+            .collect();
+        if fields.is_empty() {
+            return None;
+        }
+
+        let mut value: Value = serde_json::from_str(body).ok()?;
+        for field in fields {
+            remove(&mut value, field);
+        }
+        Some(value.to_string())
+    }
+}
+
+/// Removes the field at `path` from a JSON value, descending into arrays.
+fn remove(value: &mut Value, path: &[String]) {
+    match value {
+        Value::Array(items) => {
+            for item in items {
+                remove(item, path);
+            }
+        },
+        Value::Object(map) => match path {
+            [] => (),
+            [field] => { map.remove(field); },
+            [field, rest @ ..] => {
+                if let Some(child) = map.get_mut(field) {
+                    remove(child, rest);
+                }
+            },
+        },
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TempDir};
+
+    #[tokio::test]
+    async fn configured_fields_are_removed_and_others_kept() {
+        let dir = TempDir::new();
+        let (plugin, _) = testing::route_plugin(
+            r#"{"path": "/users", "method_router": "get", "response_type": "json"}"#,
+            r#"{"users": [{"name": "Ann", "ssn": "1", "address": {"city": "Porto", "geo": [41, -8]}}], "internal_id": 7, "total": 1}"#,
+        );
+        let router = testing::plugins(&dir, true)
+            .filter_json(JsonFilter::new()
+                .route("people", "/users", ["internal_id", "users.ssn", "users.address.geo"]))
+            .register("people", plugin)
+            .load()
+            .unwrap();
+
+        let response = testing::get(&router, "/people/users").await;
+
+        let body: Value = serde_json::from_str(&testing::body_string(response).await).unwrap();
+        assert_eq!(body, serde_json::json!({
+            "users": [{"name": "Ann", "address": {"city": "Porto"}}],
+            "total": 1,
+        }));
+    }
+
+    #[test]
+    fn other_routes_and_invalid_json_are_left_unchanged() {
+        let filter = JsonFilter::new().route("people", "/users", ["ssn"]);
+
+        assert_eq!(filter.apply("people", "/admins", r#"{"ssn": "1"}"#), None);
+        assert_eq!(filter.apply("other", "/users", r#"{"ssn": "1"}"#), None);
+        assert_eq!(filter.apply("people", "/users", "{not json"), None);
+    }
+}
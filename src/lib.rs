@@ -72,381 +72,4225 @@
 //! ```
 //!
 //! This example demonstrates how to load plugins dynamically at runtime, configure routes, and nest plugin routes under a specified path.
+mod access_log;
+mod acl;
+mod body_log;
+mod cache;
+mod coalesce;
+mod compression;
+mod csp;
+mod csrf;
+mod drain;
+mod envelope;
+mod error;
+mod etag;
+mod grpc;
+mod idempotency;
+mod image;
+mod info;
+mod inspect;
+mod invoker;
+mod json_filter;
+mod loader;
+mod log;
+mod native;
+mod negotiation;
+#[cfg(feature = "opentelemetry")]
+mod otel;
+mod pagination;
+mod panic;
+mod path;
+mod placeholders;
+mod proxy;
+mod query;
+#[cfg(feature = "remote")]
+mod remote;
+mod resolver;
+mod rewrite;
+mod routes_cache;
+mod shared;
+#[cfg(feature = "metrics")]
+mod stats;
+mod store;
+mod stream;
+mod validation;
+#[cfg(feature = "tera")]
+mod template;
+#[cfg(test)]
+mod testing;
+#[cfg(feature = "watch")]
+mod watch;
+
+pub use access_log::AccessLogFormat;
+use access_log::AccessEntry;
+pub use acl::{AccessControl, AccessRule};
+pub use body_log::BodyLogging;
+pub use cache::{CacheFuture, CachedResponse, MemoryCache, ResponseCache};
+pub use compression::Compression;
+pub use error::PluginError;
+pub use idempotency::Idempotency;
+pub use inspect::LibraryReport;
+use envelope::ResponseEnvelope;
+use loader::{LibraryResolver, LoadedPlugin};
+pub use resolver::{StaticPlugin, SymbolResolver};
+pub use rewrite::UrlRewrite;
+pub use shared::SharedRouter;
+#[cfg(feature = "metrics")]
+pub use stats::{Histogram, RouteStats, Stats};
+pub use store::{HostStore, KeyValueStore, MemoryStore, PluginStoreFn};
+pub use validation::{ValidationError, ValidationErrors};
+pub use json_filter::JsonFilter;
+pub use query::{QueryParam, QueryParamType};
+pub use native::{AxumPlugin, RequestCtx, RouteDef};
+#[doc(hidden)]
+pub use native::support as __native;
+pub use panic::PanicPolicy;
+pub use invoker::{
+    AbiVersionFn, BufferFn, BytesChunkFn, BytesFn, CannedInvoker, ChunkFn, DuplexFn, FfiBufferInvoker,
+    FfiBytesInvoker, FfiDuplexInvoker, FfiInvoker, FfiStreamInvoker, FreeBufferFn, FreeFn,
+    PluginBuffer, PluginFn, PluginInitFn, PluginInvoker, ReadFn, StreamFn, PLUGIN_ABI_VERSION,
+};
+
+use log::{debug, error, warn};
+use tracing::Instrument;
+use base64::{engine::general_purpose::STANDARD, Engine};
 use serde::Deserialize;
 use serde_json::Value;
-use std::collections::HashMap;
 use axum::{
-    extract::RawQuery,
-    response::{Html, Json, IntoResponse},
-    routing::{get, post},
+    body::Bytes,
+    extract::{
+        connect_info::IntoMakeServiceWithConnectInfo, FromRequest, FromRequestParts, OriginalUri,
+        Path, Request,
+    },
+    response::{Html, Json, IntoResponse, Response},
+    routing::{any, get, on, MethodFilter, MethodRouter, Route},
     Router,
 };
-use hyper::{HeaderMap, header::HeaderValue};
-use libloading::{Library, Symbol};
-use std::ffi::{c_char, CStr, CString};
-use once_cell::sync::Lazy;
-use std::sync::Mutex;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use tower::{util::BoxCloneService, Layer, Service};
+use tower_http::timeout::TimeoutLayer;
+use hyper::{HeaderMap, StatusCode, header::{HeaderName, HeaderValue}};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
 /// Describes a plugin route configuration, which includes:
-/// - `path`: The URL path to handle.
+/// - `path`: The URL path to handle, whose `:param` and `*wildcard` segments are
+///   forwarded as JSON in the `x-path-params` header.
 /// - `function`: The name of the function in the plugin.
-/// - `method_router`: The HTTP method (GET, POST) for this route.
-/// - `response_type`: Specifies the response format (e.g., `text`, `html`, `json`).
-#[derive(Debug, Deserialize)]
-struct PluginRoute {
+/// - `method_router`: The HTTP method (GET, POST, PUT, DELETE, PATCH) for this route, a
+///   comma-separated list of methods (e.g. `get,post`), or `any` for all methods.
+/// - `response_type`: Specifies the response format (e.g., `text`, `html`, `json`, `grpc`,
+///   `template`, `image`, `bytes`, `sse`), `text` if absent or empty, or `auto` to pick
+///   one of the `formats` from the request's `Accept` header.
+/// - `envelope`: Whether the function returns a JSON envelope with the status code,
+///   reason phrase and body instead of a bare body.
+/// - `kind`: Either a regular `route` (default) or a `fallback` that handles every
+///   unmatched path under the plugin's mount.
+/// - `query_json`: Whether to forward the parsed query string as JSON in the
+///   `x-query-json` header.
+/// - `csrf`: Whether state-changing requests must carry a valid CSRF token.
+/// - `idempotent`: Whether responses are replayed for retried `Idempotency-Key` requests.
+/// - `csp_nonce`: Whether a per-request nonce is forwarded and set in the CSP header.
+/// - `body_encoding`: `base64` if the function returns its body base64-encoded, so binary
+///   content can cross the string ABI.
+/// - `stream`: Whether the function uses the streaming ABI, its output being sent to the
+///   client chunk by chunk as it's produced.
+/// - `request_stream`: Whether a streamed function reads its request body as it arrives.
+/// - `deprecated`: Whether responses carry the `Deprecation: true` header.
+/// - `sunset`: The HTTP date after which the route may stop responding, sent in the
+///   `Sunset` header (e.g. `Sat, 31 Dec 2026 23:59:59 GMT`).
+/// - `abi`: `buffer` if the function returns a [`PluginBuffer`] (pointer and length)
+///   instead of a NUL-terminated string, `bytes` if it also receives the request body
+///   with its length, unchanged even if it isn't UTF-8 or contains NUL bytes.
+/// - `placeholders`: Whether `{{request_id}}`, `{{path}}` and `{{method}}` in the body are
+///   replaced with the values of the request.
+/// - `timeout_ms`: The maximum duration of a call to the function in milliseconds, after
+///   which the request is answered with `504 Gateway Timeout`, overriding the plugin's
+///   `timeout_ms`.
+/// - `coalesce`: Whether identical concurrent `GET` and `HEAD` requests share the response
+///   of a single call to the function.
+/// - `query_params`: The query parameters the route expects, with their `type` (`string`,
+///   `integer`, `number` or `boolean`) and whether they're `required`. Requests not
+///   matching them are answered with `400 Bad Request` without calling the function.
+/// - `formats`: The formats of an `auto` route (`text`, `html` or `json`) in preference
+///   order, `json`, `html` and `text` if absent.
+/// - `aliases`: Former paths of the route, whose `GET` requests are answered with a
+///   `301 Moved Permanently` redirect to `path`.
+///
+/// The routes declared by a loaded plugin are available through [`Plugins::plugin_routes`].
+#[derive(Debug, Clone, Deserialize)]
+pub struct PluginRoute {
+    #[serde(default)]
     path: String,
     function: String,
+    #[serde(default)]
     method_router: String,
+    #[serde(default = "default_response_type", deserialize_with = "deserialize_response_type")]
     response_type: String,
+    #[serde(default)]
+    envelope: bool,
+    #[serde(default)]
+    kind: RouteKind,
+    #[serde(default)]
+    query_json: bool,
+    #[serde(default)]
+    csrf: bool,
+    #[serde(default)]
+    idempotent: bool,
+    #[serde(default)]
+    csp_nonce: bool,
+    #[serde(default)]
+    body_encoding: Option<String>,
+    #[serde(default)]
+    stream: bool,
+    #[serde(default)]
+    request_stream: bool,
+    #[serde(default)]
+    deprecated: bool,
+    #[serde(default)]
+    sunset: Option<String>,
+    #[serde(default)]
+    abi: Option<String>,
+    #[serde(default)]
+    placeholders: bool,
+    #[serde(default)]
+    timeout_ms: Option<u64>,
+    #[serde(default)]
+    coalesce: bool,
+    #[serde(default)]
+    query_params: Vec<QueryParam>,
+    #[serde(default)]
+    formats: Vec<String>,
+    #[serde(default)]
+    aliases: Vec<String>,
 }
 
-/// Defines a plugin, with metadata such as:
-/// - `name`: The plugin name.
-/// - `version`: The plugin version.
-/// - `path`: The file system path to the shared library.
-/// - `enabled`: Indicates whether the plugin is enabled.
-#[derive(Debug, Clone, Deserialize)]
-struct Plugin {
-    name: String,
-    // description: Option<String>,
-    version: String,
-    // license: Option<String>,
-    lib_path: String,
-    enabled: bool,
-}
+impl PluginRoute {
 
-/// Struct for managing plugin loading, routing, and naming behavior.
-#[derive(Deserialize, Debug)]
-pub struct Plugins {
-    name_to_route: bool,
-}
+    /// Returns the declared URL path.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
 
-/// A global flag to enable or disable debug output, based on the `DEBUG` environment variable.
-static DEBUG: Lazy<bool> = Lazy::new(|| {
-    std::env::var("DEBUG")
-        .map(|val| val == "true")
-        .unwrap_or(false)
-});
+    /// Returns the name of the plugin function handling the route.
+    pub fn function(&self) -> &str {
+        &self.function
+    }
 
-/// A global map that stores loaded plugin libraries, with the library protected by a `Mutex` to
-/// allow safe concurrent access.
-static LIBRARIES: Lazy<HashMap<String, Mutex<Library>>> = Lazy::new(|| {
+    /// Returns the declared HTTP method, empty if unset.
+    pub fn method_router(&self) -> &str {
+        &self.method_router
+    }
 
-    let plugins_dir = std::env::var("PLUGINS_DIR")
-        .map(|val| val.is_empty()
-            .then_some("plugins".to_string()
-        )
-        .or(Some(val)).unwrap())
-        .unwrap_or("plugins".to_string());
+    /// Returns the declared response format.
+    pub fn response_type(&self) -> &str {
+        &self.response_type
+    }
 
-    let plugins_path = std::path::Path::new(&plugins_dir);
-    if !plugins_path.is_dir() {
-        eprintln!("Error: PLUGINS_DIR does not exist: {}", plugins_dir);
-        std::process::exit(1);
+    /// Returns whether the function returns a response envelope.
+    pub fn envelope(&self) -> bool {
+        self.envelope
     }
 
-    println!("Load plugins from: {}", plugins_dir);
+    /// Returns whether the route is a regular route or a fallback.
+    pub fn kind(&self) -> &RouteKind {
+        &self.kind
+    }
 
-    let mut libraries = HashMap::new();
+    /// Returns whether the parsed query is forwarded as JSON.
+    pub fn query_json(&self) -> bool {
+        self.query_json
+    }
 
-    for entry in std::fs::read_dir(plugins_path).unwrap() {
-        let entry = match entry {
-            Ok(entry) => entry,
-            Err(e) => {
-                eprintln!("Error reading plugin directory entry: {}", e);
-                continue;
-            }
-        };
+    /// Returns whether state-changing requests require a CSRF token.
+    pub fn csrf(&self) -> bool {
+        self.csrf
+    }
 
-        let path_dir = entry.path();
-        if path_dir.is_dir() {
-            println!("DIR: {}", path_dir.display());
-            let plugin_conf_path = path_dir.join("plugin.json");
-            if!plugin_conf_path.is_file() {
-                eprintln!("Error: Missing plugin.json in: {}", path_dir.display());
-                continue;
-            }
+    /// Returns whether responses are replayed for retried `Idempotency-Key` requests.
+    pub fn idempotent(&self) -> bool {
+        self.idempotent
+    }
 
-            let file = std::fs::File::open(plugin_conf_path).unwrap();
-            let reader = std::io::BufReader::new(file);
-        
-            // Deserialize the JSON data into the struct
-            let plugin_conf: Plugin = match serde_json::from_reader(reader) {
-                Ok(config) => config,
-                Err(e) => {
-                    eprintln!("Error parsing plugin.json: {}", e);
-                    continue;
-                }
-            };
+    /// Returns whether a per-request CSP nonce is generated.
+    pub fn csp_nonce(&self) -> bool {
+        self.csp_nonce
+    }
 
-            // Skip disabled plugins
-            if !plugin_conf.enabled {
-                eprintln!(
-                    "Skipping plugin: {}: {} - disabled", 
-                    plugin_conf.name, path_dir.display()
-                );
-                continue;
-            }
+    /// Returns the encoding of the returned body, if any (`base64`).
+    pub fn body_encoding(&self) -> Option<&str> {
+        self.body_encoding.as_deref()
+    }
 
-            if plugin_conf.lib_path.is_empty() {
-                eprintln!(
-                    "Skipping plugin: {}: {} - no shared library path specified", 
-                    plugin_conf.name, path_dir.display()
-                );
-                continue;
-            }
+    /// Returns whether the output is streamed with chunked transfer encoding, as declared
+    /// with `"stream": true` or implied by the `sse` response type.
+    pub fn stream(&self) -> bool {
+        self.stream || self.response_type.eq_ignore_ascii_case("sse")
+    }
 
-            let lib_path = plugin_conf.lib_path.starts_with('/')
-                .then_some(std::path::PathBuf::new().join(&plugin_conf.lib_path))
-                .or(Some(path_dir.join(&plugin_conf.lib_path))).unwrap();
+    /// Returns whether the request body is streamed to the function as it arrives.
+    pub fn request_stream(&self) -> bool {
+        self.request_stream
+    }
 
-            if !lib_path.is_file() {
-                eprintln!(
-                    "Skipping plugin: {}: {} - shared library not found", 
-                    plugin_conf.name, path_dir.display()
-                );
-                continue;
-            }
+    /// Returns whether the route is deprecated.
+    pub fn deprecated(&self) -> bool {
+        self.deprecated
+    }
 
-            let lib = unsafe {
-                match Library::new(&lib_path) {
-                    Ok(lib) => lib,
-                    Err(e) => panic!("Error loading library {}: {}", lib_path.display(), e),
-                }
-            };
-    
-            println!("Plugin loaded: {} Version: {}", plugin_conf.name, plugin_conf.version);
-    
-            libraries.insert(plugin_conf.name, Mutex::new(lib));
-        }
+    /// Returns the date after which the route may stop responding, if any.
+    pub fn sunset(&self) -> Option<&str> {
+        self.sunset.as_deref()
     }
 
-    libraries
-});
+    /// Returns the ABI of the route function, if not the default NUL-terminated one
+    /// (`buffer` or `bytes`).
+    pub fn abi(&self) -> Option<&str> {
+        self.abi.as_deref()
+    }
 
-impl Plugins {
+    /// Returns whether the function receives the request body with its length, as
+    /// received.
+    fn raw_body(&self) -> bool {
+        self.abi.as_deref().is_some_and(|abi| abi.eq_ignore_ascii_case("bytes"))
+    }
 
-    /// Creates a new instance of the `Plugins` struct.
-    ///
-    /// # Arguments
-    /// * `name_to_route` - An optional boolean indicating whether to prepend the plugin name to each route.
-    ///
-    /// # Returns
-    /// A new `Plugins` instance.
-    pub fn new(
-        name_to_route: Option<bool>,
-    ) -> Self {
+    /// Returns whether request values are substituted in the body.
+    pub fn placeholders(&self) -> bool {
+        self.placeholders
+    }
 
-        Plugins {
-            name_to_route: match name_to_route {
-                Some(true) => true,
-                Some(false) => false,
-                None => false,
-            },
-        }
+    /// Returns the maximum duration of a call to the function, if declared.
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout_ms.map(Duration::from_millis)
     }
 
-    /// Handles the execution of a plugin's function, passing headers and body as arguments.
-    /// The function is executed in a blocking task, and memory is managed for the returned C string.
-    ///
-    /// # Arguments
-    /// * `headers` - The request headers.
-    /// * `body` - The request body as a string.
-    /// * `function` - A pointer to the plugin's function to execute.
-    /// * `free` - A pointer to the plugin's memory-freeing function.
+    /// Returns whether identical concurrent requests share a single call to the function.
+    pub fn coalesce(&self) -> bool {
+        self.coalesce
+    }
+
+    /// Returns the query parameters the route expects.
+    pub fn query_params(&self) -> &[QueryParam] {
+        &self.query_params
+    }
+
+    /// Returns the formats declared for an `auto` route, in preference order.
+    pub fn formats(&self) -> &[String] {
+        &self.formats
+    }
+
+    /// Returns the paths redirected to the route.
+    pub fn aliases(&self) -> &[String] {
+        &self.aliases
+    }
+
+    /// Checks the options of the route that don't depend on the host's settings.
     ///
     /// # Returns
-    /// The response as a string.
-    async fn handle_route(
-        headers: HeaderMap,
-        body: String,
-        function: extern "C" fn(*mut HeaderMap, *const c_char) -> *const c_char,
-        free: extern "C" fn(*mut c_char),
-    ) -> String {
+    /// `Ok(())`, or the reason the route can't be mounted.
+    pub(crate) fn validate(&self) -> Result<(), String> {
+
+        if let Some(encoding) = self.body_encoding.as_deref() {
+            if !encoding.eq_ignore_ascii_case("base64") {
+                return Err(format!("unsupported body encoding {:?}", encoding));
+            }
+        }
+
+        if let Some(abi) = self.abi.as_deref() {
+            if !abi.eq_ignore_ascii_case("buffer") && !abi.eq_ignore_ascii_case("bytes") {
+                return Err(format!("unsupported ABI {:?}", abi));
+            }
+        }
+
+        if !RESPONSE_TYPES.iter().any(|response_type| response_type.eq_ignore_ascii_case(&self.response_type)) {
+            return Err(format!("unsupported response type {:?}", self.response_type));
+        }
+
+        // A C string can't carry binary data, it stops at the first NUL byte
+        if self.response_type.eq_ignore_ascii_case("bytes")
+            && self.abi.is_none()
+            && self.body_encoding.is_none() {
+            return Err("bytes responses require the buffer ABI or the base64 body encoding".to_string());
+        }
+
+        // gRPC messages are decoded by the host and base64-encoded for the plugin
+        if self.raw_body() && self.response_type.eq_ignore_ascii_case("grpc") {
+            return Err("gRPC routes can't use the bytes ABI".to_string());
+        }
+
+        if self.kind != RouteKind::Fallback {
+            path::validate_path(&self.path).map_err(|reason| format!("invalid path {:?}: {}", self.path, reason))?;
+        } else if !self.aliases.is_empty() {
+            return Err("fallback routes can't have aliases".to_string());
+        }
+
+        // The redirect fills the route's parameters with the ones captured by the alias
+        for alias in &self.aliases {
+            path::validate_path(alias).map_err(|reason| format!("invalid alias {:?}: {}", alias, reason))?;
+            if path::normalize_path(alias) == path::normalize_path(&self.path) {
+                return Err(format!("alias {:?} is the route path", alias));
+            }
+            let alias_params = path::params(alias);
+            if let Some(param) = path::params(&self.path).into_iter().find(|param| !alias_params.contains(param)) {
+                return Err(format!("alias {:?} doesn't capture the parameter {:?}", alias, param));
+            }
+        }
+
+        if self.request_stream && !self.stream() {
+            return Err("streamed request bodies require a streamed response".to_string());
+        }
+
+        let grpc = self.response_type.eq_ignore_ascii_case("grpc");
+        if self.stream() && (self.envelope || self.body_encoding.is_some() || self.abi.is_some() || grpc) {
+            return Err("streamed routes can't use an envelope, a body encoding, the buffer ABI or gRPC".to_string());
+        }
+
+        let auto = self.response_type.eq_ignore_ascii_case("auto");
+        if !self.formats.is_empty() && !auto {
+            return Err("formats require the auto response type".to_string());
+        }
+        if let Some(format) = self.formats.iter().find(|format| {
+            !negotiation::FORMATS.iter().any(|known| known.eq_ignore_ascii_case(format))
+        }) {
+            return Err(format!("unsupported format {:?}", format));
+        }
+        // The format of a shared response would be the one of the first request
+        if auto && (self.stream() || self.coalesce) {
+            return Err("auto routes can't be streamed or coalesced".to_string());
+        }
+
+        // Template responses have their own variables, binary bodies have no text to fill
+        let text = ["text", "html", "json", "auto"].iter().any(|text| text.eq_ignore_ascii_case(&self.response_type));
+        if self.placeholders && (!text || self.stream() || self.body_encoding.is_some()) {
+            return Err("placeholders require a text, html or json response that isn't streamed or encoded".to_string());
+        }
+
+        if self.timeout_ms == Some(0) {
+            return Err("timeout_ms must be greater than 0".to_string());
+        }
+        if self.timeout_ms.is_some() && self.stream() {
+            return Err("streamed routes can't declare a timeout".to_string());
+        }
 
-        if *DEBUG { println!("Handle Route Header Map: {:?}", headers); }
+        // The shared response can't carry values generated for a single request
+        if self.coalesce && (self.stream() || self.csrf || self.csp_nonce || self.placeholders) {
+            return Err("coalesced routes can't be streamed or use CSRF tokens, CSP nonces or placeholders".to_string());
+        }
 
-        tokio::task::spawn_blocking(move || -> String {
-            // Box the headers and convert the body to a CString
-            let box_headers = Box::new(headers);
-            let c_body = CString::new(body).unwrap();
-    
-            // Call the external C function with the appropriate pointers
-            let ptr = function(Box::into_raw(box_headers), c_body.as_ptr());
-            if ptr.is_null() {
-                panic!("Received null pointer from function");
+        for (i, param) in self.query_params.iter().enumerate() {
+            if param.name().is_empty() {
+                return Err("query parameters must have a name".to_string());
+            }
+            if self.query_params[..i].iter().any(|other| other.name() == param.name()) {
+                return Err(format!("query parameter {:?} declared twice", param.name()));
             }
+        }
 
-            // clean this from memory
-            unsafe {
-                let data = CStr::from_ptr(ptr).to_string_lossy().into_owned();
-                free(ptr as *mut c_char);
-                data
+        if let Some(sunset) = self.sunset.as_deref() {
+            if HeaderValue::from_str(sunset).is_err() {
+                return Err(format!("invalid sunset date {:?}", sunset));
             }
-        }).await.unwrap()
+        }
+
+        Ok(())
+    }
+}
+
+/// The response types a route can declare.
+const RESPONSE_TYPES: [&str; 9] = ["text", "html", "json", "grpc", "template", "image", "bytes", "sse", "auto"];
+
+/// The response type of routes that don't declare one.
+fn default_response_type() -> String {
+    "text".to_string()
+}
+
+/// Deserializes a response type, replacing an empty one with the default.
+fn deserialize_response_type<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let response_type = String::deserialize(deserializer)?;
+    if response_type.trim().is_empty() {
+        return Ok(default_response_type());
+    }
+    Ok(response_type)
+}
+
+/// The kind of a plugin route.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RouteKind {
+    /// A route registered at its declared path and method.
+    #[default]
+    Route,
+    /// A catch-all handler for unmatched paths, regardless of method.
+    Fallback,
+}
+
+/// Struct for managing plugin loading, routing, and naming behavior.
+///
+/// The `Plugins` instance owns the plugins it loaded: when it's dropped (or
+/// [`Plugins::unload_all`] is called) their shutdown hooks run, so keep it alive for as
+/// long as the routers it built are being served.
+#[derive(Deserialize)]
+pub struct Plugins {
+    name_to_route: bool,
+    #[serde(skip)]
+    settings: Settings,
+    #[serde(skip)]
+    loaded: Mutex<Vec<Arc<LoadedPlugin>>>,
+    #[serde(skip)]
+    memory: AtomicUsize,
+    #[serde(skip)]
+    registered: Vec<(String, Arc<dyn SymbolResolver>)>,
+    #[serde(skip)]
+    routes: Mutex<Vec<(String, Vec<PluginRoute>)>>,
+    #[serde(skip)]
+    layers: HashMap<String, LayerFn>,
+    #[serde(skip)]
+    serving: SharedRouter,
+    #[cfg(feature = "watch")]
+    #[serde(skip)]
+    watcher: Mutex<Option<notify::RecommendedWatcher>>,
+}
+
+/// Applies a host-registered layer to a plugin router.
+type LayerFn = Arc<dyn Fn(Router) -> Router + Send + Sync>;
+
+impl std::fmt::Debug for Plugins {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Plugins")
+            .field("name_to_route", &self.name_to_route)
+            .field("settings", &self.settings)
+            .field("loaded", &self.loaded)
+            .field("memory", &self.memory)
+            .field("registered", &self.registered.iter()
+                .map(|(name, _)| name)
+                .collect::<Vec<_>>())
+            .field("routes", &self.routes)
+            .field("layers", &self.layers.keys().collect::<Vec<_>>())
+            .finish()
     }
+}
+
+/// Host-side options set through the `Plugins` builder methods and shared by all the
+/// route handlers.
+#[derive(Debug, Clone, Default)]
+struct Settings {
+    server_timing: bool,
+    compression: Option<Compression>,
+    body_logging: Option<BodyLogging>,
+    handled_by: bool,
+    query_brackets: bool,
+    strict_config: bool,
+    allow_unversioned: bool,
+    unique_names: bool,
+    store: store::SharedStore,
+    #[cfg(feature = "metrics")]
+    metrics: stats::Metrics,
+    url_rewrites: HashMap<String, UrlRewrite>,
+    version_reqs: HashMap<String, String>,
+    access_control: Option<AccessControl>,
+    access_log: Option<AccessLogFormat>,
+    memory_budget: Option<usize>,
+    idempotency: Option<Idempotency>,
+    coalescer: Arc<coalesce::Coalescer>,
+    drain: Arc<drain::Drain>,
+    json_filter: Option<JsonFilter>,
+    response_types: Option<Vec<String>>,
+    csp_policy: Option<String>,
+    etag: bool,
+    pretty_json: bool,
+    parallel_loads: usize,
+    timeout: Option<Duration>,
+    plugins_dir: Option<PathBuf>,
+    plugin_version: bool,
+    trusted_proxies: Option<Vec<proxy::Cidr>>,
+    read_only: bool,
+    routes_cache: Option<PathBuf>,
+    favicon: Option<Bytes>,
+    info_started: Option<Instant>,
+    plugins_endpoint: Option<String>,
+    robots_txt: Option<String>,
+    #[cfg(feature = "tera")]
+    templates: Option<Arc<tera::Tera>>,
+    #[cfg(feature = "remote")]
+    remote_timeouts: remote::Timeouts,
+}
+
+/// The request headers the host sets for the plugin. The client's own are removed, so
+/// the plugin can trust their values.
+const HOST_HEADERS: [&str; 7] = [
+    "x-http-method",
+    "x-path-params",
+    "x-query-json",
+    "x-raw-query",
+    negotiation::RESPONSE_TYPE_HEADER,
+    csrf::CSRF_HEADER,
+    csp::NONCE_HEADER,
+];
+
+/// Per-route state shared by the request handlers generated in `load()`.
+struct RouteHandler {
+    plugin_name: String,
+    route_path: String,
+    response_type: String,
+    envelope: bool,
+    query_json: bool,
+    csrf: bool,
+    idempotent: bool,
+    csp_nonce: bool,
+    body_base64: bool,
+    raw_body: bool,
+    stream: bool,
+    request_stream: bool,
+    placeholders: bool,
+    coalesce: bool,
+    query_params: Vec<QueryParam>,
+    /// The lowercase formats of an `auto` route, empty for the other routes.
+    formats: Vec<String>,
+    timeout: Option<Duration>,
+    panic_policy: PanicPolicy,
+    lifecycle_headers: HeaderMap,
+    default_headers: Arc<HeaderMap>,
+    host: Option<String>,
+    invoker: Arc<dyn PluginInvoker>,
+    settings: Arc<Settings>,
+}
+
+impl RouteHandler {
 
-    /// Sets the appropriate response type (text, HTML, JSON) based on the `response_type` argument.
+    /// Handles a single request through the host-side checks shared by all plugin routes.
     ///
     /// # Arguments
-    /// * `response` - The raw response string.
-    /// * `response_type` - The expected format of the response.
+    /// * `request` - The incoming request.
     ///
     /// # Returns
     /// An Axum response.
-    fn set_response(
-        response: &str,
-        response_type: &str,
-    ) -> axum::response::Response {
+    async fn call(&self, request: Request) -> Response {
+        serve(
+            &self.settings,
+            &self.plugin_name,
+            &self.route_path,
+            self.host.as_deref(),
+            &self.default_headers,
+            request,
+            |request| self.respond_coalesced(request),
+        ).await
+    }
 
-        match response_type.to_lowercase().as_str() {
-            "text" => response.to_string()
-                .into_response(),
-            "html" => Html(response.to_string())
-                .into_response(),
-            "json" => {
-                // println!("Json String Response : {}", response.to_string());
-                let v: Value = match serde_json::from_str(response) {
-                    Ok(json_value) => json_value,
-                    Err(e) => {
-                        eprintln!("Error parsing JSON: {}", e);
-                        serde_json::Value::String(format!("Error parsing JSON: {}", e))
-                    },
-                };
-                Json(v).into_response()
-            },
-            _ => panic!("Unsupported response format"),
+    /// Runs `respond`, sharing the response of an identical request in flight if the
+    /// route coalesces requests.
+    async fn respond_coalesced(&self, request: Request) -> Response {
+
+        if !self.coalesce || !matches!(*request.method(), hyper::Method::GET | hyper::Method::HEAD) {
+            return self.respond(request).await;
         }
+
+        let uri = match request.extensions().get::<OriginalUri>() {
+            Some(OriginalUri(uri)) => uri,
+            None => request.uri(),
+        };
+        let key = format!(
+            "{} {}{} {}",
+            request.method(),
+            self.plugin_name,
+            self.route_path,
+            uri.path_and_query().map(|path| path.as_str()).unwrap_or("/"),
+        );
+        self.settings.coalescer.run(key, || self.respond(request)).await
     }
 
-    /// Loads and merges routes from all enabled plugins into an Axum `Router`.
-    ///
-    /// # Returns
-    /// A result containing the constructed router or an error if a plugin fails to load.
-    pub fn load(&self) -> Result<Router, libloading::Error> {
+    /// Runs the plugin function for a single request and builds the Axum response.
+    async fn respond(&self, request: Request) -> Response {
 
-        let message = || -> String {
-            let count = LIBRARIES.len();
-            format!("Loaded plugins: {}", count)
-        }();
+        // Nested routers strip their prefix from the URI, the original one still has it
+        let mount_prefix = mount_prefix(&request);
 
-        let mut router: Router = Router::new()
-            .route("/", get(|| async {
-                message
-            })
-        );
-        
-        if LIBRARIES.is_empty() {
-            return Ok(router);
+        let (mut parts, body) = request.into_parts();
+        let method = parts.method.clone();
+        let original_uri = match parts.extensions.get::<OriginalUri>() {
+            Some(OriginalUri(uri)) => uri.clone(),
+            None => parts.uri.clone(),
+        };
+        let query = parts.uri.query().map(str::to_string);
+        let mut headers = parts.headers.clone();
+
+        // The values captured by the `:param` and `*wildcard` segments, if any
+        let path_params = match Path::<HashMap<String, String>>::from_request_parts(&mut parts, &()).await {
+            Ok(Path(params)) if !params.is_empty() => Some(params),
+            _ => None,
+        };
+
+        // Malformed queries are rejected before the body is read
+        if !self.query_params.is_empty() {
+            let errors = query::validate_query(&self.query_params, query.as_deref(), self.settings.query_brackets);
+            if !errors.is_empty() {
+                return errors.into_response();
+            }
         }
 
-        for (name, lib) in LIBRARIES.iter() {
+        // A streamed request body is read by the plugin as it arrives
+        let (body, request_body) = if self.request_stream {
+            (Bytes::new(), Some(body))
+        } else {
+            match Bytes::from_request(Request::from_parts(parts, body), &()).await {
+                Ok(body) => (body, None),
+                Err(rejection) => return rejection.into_response(),
+            }
+        };
 
-            let lib = match lib.lock() {
-                Ok(lib) => lib,
-                Err(e) => panic!("Error locking library: {}", e),
+        // Validate the CSRF token before the request reaches the plugin
+        let mut issued_csrf_token = None;
+        let csrf_token = if self.csrf {
+            let token = match csrf::cookie_token(&headers) {
+                Some(token) => {
+                    if csrf::is_state_changing(&method) && !csrf::is_valid(&token, &headers, &body) {
+                        return (StatusCode::FORBIDDEN, "Invalid CSRF token").into_response();
+                    }
+                    token
+                },
+                None if csrf::is_state_changing(&method) => {
+                    return (StatusCode::FORBIDDEN, "Missing CSRF token").into_response();
+                },
+                None => {
+                    let token = csrf::generate_token();
+                    issued_csrf_token = Some(token.clone());
+                    token
+                },
             };
+            Some(token)
+        } else {
+            None
+        };
 
-            let routes_fn: Symbol<extern "C" fn() -> *const c_char> = unsafe {
-                match lib.get(b"routes\0") {
-                    Ok(symbol) => symbol,
-                    Err(e) =>  panic!("Error getting routes: {}", e),
-                }
-            };
+        // Only the host sets them, a client could otherwise forge e.g. a validated query
+        for name in HOST_HEADERS {
+            headers.remove(name);
+        }
 
-            let route_list_ptr = routes_fn();
+        if let Some(token) = csrf_token {
+            if let Ok(value) = HeaderValue::from_str(&token) {
+                headers.insert(csrf::CSRF_HEADER, value);
+            }
+        }
 
-            if route_list_ptr.is_null() {
-                panic!("Received null pointer from routes function");
+        // The plugin embeds the nonce in its inline scripts
+        let csp_nonce = self.csp_nonce.then(csp::generate_nonce);
+        if let Some(nonce) = &csp_nonce {
+            if let Ok(value) = HeaderValue::from_str(nonce) {
+                headers.insert(csp::NONCE_HEADER, value);
             }
+        }
 
-            // clean this from memory
-            let json_data = unsafe {
-                CStr::from_ptr(route_list_ptr).to_string_lossy().into_owned()
-            };
+        // Retried requests get the response of the first one, or wait for it while it
+        // runs; streams can't be replayed
+        let idempotency = match (&self.settings.idempotency, self.idempotent && !self.stream) {
+            (Some(idempotency), true) => headers.get(idempotency::IDEMPOTENCY_KEY)
+                .and_then(|key| key.to_str().ok())
+                .map(|key| {
+                    let route = format!("{}{}", self.plugin_name, self.route_path);
+                    (idempotency, idempotency.key(&method, &route, &headers, key))
+                }),
+            _ => None,
+        };
+        let idempotency = match idempotency {
+            Some((idempotency, key)) => match idempotency.claim(key, idempotency::fingerprint(&body)).await {
+                Ok(in_flight) => Some(in_flight),
+                Err(response) => return response,
+            },
+            None => None,
+        };
+
+        // The plugin gets the request id substituted in its body, to log it
+        let placeholders = self.placeholders.then(|| placeholders::Context {
+            request_id: placeholders::request_id(&headers),
+            path: original_uri.path().to_string(),
+            method: method.to_string(),
+        });
+        if let Some(context) = &placeholders {
+            if let Ok(value) = HeaderValue::from_str(&context.request_id) {
+                headers.insert(placeholders::REQUEST_ID_HEADER, value);
+            }
+        }
+
+        // Lets routes accepting several methods (`any`, fallbacks) tell them apart
+        if let Ok(value) = HeaderValue::from_str(method.as_str()) {
+            headers.insert("x-http-method", value);
+        }
+
+        // `auto` routes render the format the client prefers, which the plugin is told
+        let response_type = if self.formats.is_empty() {
+            self.response_type.as_str()
+        } else {
+            let format = negotiation::negotiate(&self.formats, headers.get(hyper::header::ACCEPT));
+            headers.insert(negotiation::RESPONSE_TYPE_HEADER, HeaderValue::from_static(match format {
+                "html" => "html",
+                "json" => "json",
+                _ => "text",
+            }));
+            format
+        };
+
+        if let Some(params) = path_params {
+            let params = params.into_iter()
+                .map(|(name, value)| (name, Value::String(value)))
+                .collect();
+            if let Ok(value) = HeaderValue::from_str(&query::to_ascii_json(&Value::Object(params))) {
+                headers.insert("x-path-params", value);
+            }
+        }
 
-            // Clean up memory allocated by plugin if necessary
-            let free_fn: Symbol<extern "C" fn(*mut c_char)> = unsafe {
-                match lib.get(b"free\0") {
-                    Ok(symbol) => symbol,
-                    Err(e) => panic!("Error getting free function: {}", e),
+        if let Some(query) = query {
+            if self.query_json {
+                let parsed = query::parse_query(&query, self.settings.query_brackets);
+                let json = query::to_ascii_json(&Value::Object(parsed));
+                if let Ok(value) = HeaderValue::from_str(&json) {
+                    headers.insert("x-query-json", value);
                 }
-            };
-        
-            // Free the memory
-            free_fn(route_list_ptr as *mut c_char);
+            }
+            // The structured query is still available in `x-query-json`
+            match HeaderValue::from_str(&query) {
+                Ok(value) => { headers.insert("x-raw-query", value); },
+                Err(_) => warn!("Skipping x-raw-query header: {}: {} - invalid header value", self.plugin_name, self.route_path),
+            }
+        }
+
+        let grpc = self.response_type.eq_ignore_ascii_case("grpc");
 
-            if *DEBUG { println!("Routes Json: {}", json_data); }
+        // The bytes ABI gets the body as received, the others a NUL-terminated string
+        let (body, raw_body) = if grpc {
+            match grpc::decode_request(&body) {
+                Ok(message) => (message, None),
+                Err((status, message)) => return grpc::error(status, message),
+            }
+        } else if self.raw_body {
+            (String::from_utf8_lossy(&body).into_owned(), Some(body.to_vec()))
+        } else {
+            match String::from_utf8(body.to_vec()) {
+                Ok(body) if body.contains('\0') => return ValidationErrors::new()
+                    .field("body", "Request body contained a NUL byte, which requires the bytes ABI")
+                    .into_response(),
+                Ok(body) => (body, None),
+                Err(e) => return ValidationErrors::new()
+                    .field("body", format!("Request body didn't contain valid UTF-8: {}", e))
+                    .into_response(),
+            }
+        };
 
-            let route_list: Vec<PluginRoute> = serde_json::from_str(&json_data).unwrap();
+        let accepts_gzip = Compression::accepts_gzip(&headers);
+        let if_none_match: Vec<HeaderValue> = headers.get_all(hyper::header::IF_NONE_MATCH)
+            .iter()
+            .cloned()
+            .collect();
+        let if_modified_since = headers.get(hyper::header::IF_MODIFIED_SINCE).cloned();
 
-            for route in route_list {
-                // Load the plugin_route_function
+        if log::debug_enabled() && request_body.is_none() {
+            if let Some(body_logging) = &self.settings.body_logging {
+                if body_logging.is_logged(&self.plugin_name, &self.route_path) {
+                    body_logging.log(&self.plugin_name, &self.route_path, &body);
+                }
+            }
+        }
 
-                let function: Symbol<extern "C" fn(*mut HeaderMap, *const c_char) -> *const c_char> = unsafe {
-                    match lib.get(route.function.as_bytes()) {
-                        Ok(symbol) => symbol,
-                        Err(e) => panic!("Error getting plugin_route_function: {}", e),
-                    }
+        let started = Instant::now();
+        let span = tracing::info_span!("handle_route", plugin = %self.plugin_name, path = %self.route_path);
+        let (mut response, plugin_elapsed) = if self.stream {
+            // The chunks are sent as they come, so the steps needing the whole body are skipped
+            let content_type = content_type(&self.response_type);
+            let events = self.response_type.eq_ignore_ascii_case("sse");
+            let response = match request_body {
+                Some(request_body) => stream::duplex_response(self.invoker.clone(), headers, request_body, content_type, events, self.panic_policy),
+                None => stream::response(self.invoker.clone(), headers, body, content_type, events, self.panic_policy),
+            };
+            (response, None)
+        } else {
+            let (mut response, plugin_elapsed) = if self.raw_bytes() {
+                // Binary output is never converted to a string
+                let output = match raw_body {
+                    Some(body) => Plugins::handle_raw_route(
+                        headers,
+                        body,
+                        self.invoker.clone(),
+                        self.timeout,
+                        self.panic_policy,
+                    ).instrument(span).await,
+                    None => Plugins::handle_bytes_route(
+                        headers,
+                        body,
+                        self.invoker.clone(),
+                        self.timeout,
+                        self.panic_policy,
+                    ).instrument(span).await,
                 };
+                self.record_call(started.elapsed(), output.is_err());
+                let response = match output {
+                    Ok(output) => self.bytes_response(output),
+                    Err(e) => self.call_error_response(e, grpc),
+                };
+                (response, started.elapsed())
+            } else {
+                let output = match raw_body {
+                    Some(body) => Plugins::handle_raw_route(
+                        headers,
+                        body,
+                        self.invoker.clone(),
+                        self.timeout,
+                        self.panic_policy,
+                    ).instrument(span).await.map(|output| String::from_utf8_lossy(&output).into_owned()),
+                    None => Plugins::handle_route(
+                        headers,
+                        body,
+                        self.invoker.clone(),
+                        self.timeout,
+                        self.panic_policy,
+                    ).instrument(span).await,
+                };
+                let plugin_elapsed = started.elapsed();
+                self.record_call(plugin_elapsed, output.is_err());
 
-                // Move the loaded function into the closure to avoid borrowing `lib`
-                let cloned_fn = *function;
-                let cloned_free_fn = *free_fn;
+                let fill = |body: String| match &placeholders {
+                    Some(context) => placeholders::substitute(&body, context, response_type),
+                    None => body,
+                };
 
-                // check if route.path start with "/"
-                let route_path = if self.name_to_route {
-                    format!("/{}/{}", &name, if route.path.starts_with("/") {
-                        &route.path[1..]
-                    } else {
-                        &route.path
-                    })
-                } else {
-                    route.path
+                let response = match output {
+                    Err(e) => self.call_error_response(e, grpc),
+                    Ok(output) if grpc => grpc::response(&output),
+                    Ok(output) if self.envelope => match ResponseEnvelope::parse(&output) {
+                        Ok(envelope) => envelope.apply(self.body_response(&fill(envelope.body_string()), response_type), &original_uri),
+                        Err(e) => {
+                            error!("Error parsing response envelope: {}", e);
+                            (
+                                StatusCode::INTERNAL_SERVER_ERROR,
+                                format!("Error parsing response envelope: {}", e),
+                            ).into_response()
+                        },
+                    },
+                    Ok(output) => self.body_response(&fill(output), response_type),
                 };
+                (response, plugin_elapsed)
+            };
 
-                // https://docs.rs/axum/latest/axum/extract/index.html
-                let r = Router::new()
-                    .route(&route_path, match route.method_router.to_lowercase().as_str() {
-                        "get" => get(move |
-                            RawQuery(query): RawQuery,
-                            mut headers: HeaderMap,
-                            body: String,
-                        | async move {
-                            if let Some(query) = query {
-                                headers.insert("x-raw-query", HeaderValue::from_str(&query).unwrap());
-                            }
-                            let response = Self::handle_route(
-                                headers,
-                                body, 
-                                cloned_fn, 
-                                cloned_free_fn,
-                            ).await;
-                            Self::set_response(&response, &route.response_type)
-                        }),
-                        "post" => post(move |
-                            RawQuery(query): RawQuery,
-                            mut headers: HeaderMap,
-                            body: String,
-                        | async move {
-                            if let Some(query) = query {
-                                headers.insert("x-raw-query", HeaderValue::from_str(&query).unwrap());
-                            }
-                            let response = Self::handle_route(
-                                headers,
-                                body, 
-                                cloned_fn, 
-                                cloned_free_fn,
-                            ).await;
-                            Self::set_response(&response, &route.response_type)
-                        }),
-                        _ => panic!("Unsupported method: {:?}", route.method_router),
-                    }
-                );
-                router = router.merge(r);
+            if let Some(rewrite) = self.settings.url_rewrites.get(&self.plugin_name) {
+                response = rewrite.apply(response, &mount_prefix).await;
+            }
+
+            if let (Some(compression), true) = (&self.settings.compression, accepts_gzip) {
+                response = compression.apply(response).await;
+            }
+
+            if self.settings.etag {
+                response = etag::apply(&method, &if_none_match, response).await;
+            }
+
+            response = etag::apply_last_modified(&method, &if_none_match, if_modified_since.as_ref(), response);
+
+            set_content_length(&mut response);
+
+            (response, Some(plugin_elapsed))
+        };
+
+        if let Some(nonce) = &csp_nonce {
+            let policy = self.settings.csp_policy.as_deref().unwrap_or(csp::DEFAULT_POLICY);
+            if let Ok(value) = HeaderValue::from_str(&csp::policy(policy, nonce)) {
+                response.headers_mut().insert(hyper::header::CONTENT_SECURITY_POLICY, value);
             }
         }
 
-        Ok(router)
-    }
-}
\ No newline at end of file
+        if let Some(cookie) = issued_csrf_token.as_deref().and_then(csrf::set_cookie) {
+            response.headers_mut().append(hyper::header::SET_COOKIE, cookie);
+        }
+
+        if let Some(context) = &placeholders {
+            if let Ok(value) = HeaderValue::from_str(&context.request_id) {
+                response.headers_mut().entry(placeholders::REQUEST_ID_HEADER).or_insert(value);
+            }
+        }
+
+        for (header_name, value) in self.lifecycle_headers.iter() {
+            response.headers_mut().insert(header_name, value.clone());
+        }
+
+        if !self.formats.is_empty() {
+            response.headers_mut().append(hyper::header::VARY, HeaderValue::from_static("accept"));
+        }
+
+        if self.settings.handled_by {
+            let value = format!("{}{}", self.plugin_name, self.route_path);
+            if let Ok(value) = HeaderValue::from_str(&value) {
+                response.headers_mut().insert("x-handled-by", value);
+            }
+        }
+
+        if let (true, Some(plugin_elapsed)) = (self.settings.server_timing, plugin_elapsed) {
+            // https://www.w3.org/TR/server-timing/
+            let value = format!(
+                "plugin;desc=\"{}\";dur={:.3}, total;dur={:.3}",
+                self.plugin_name,
+                plugin_elapsed.as_secs_f64() * 1000.0,
+                started.elapsed().as_secs_f64() * 1000.0,
+            );
+            if let Ok(value) = HeaderValue::from_str(&value) {
+                response.headers_mut().insert("server-timing", value);
+            }
+        }
+
+        match idempotency {
+            Some(in_flight) => in_flight.store(response).await,
+            None => response,
+        }
+    }
+
+    /// Records a call to the route function in the request metrics, when enabled.
+    fn record_call(&self, latency: Duration, failed: bool) {
+        #[cfg(feature = "metrics")]
+        self.settings.metrics.record(&self.plugin_name, &self.route_path, latency, failed);
+        #[cfg(not(feature = "metrics"))]
+        let _ = (latency, failed);
+    }
+
+    /// Checks whether the route returns raw bytes: a `bytes` route that has neither an
+    /// envelope nor a base64-encoded body.
+    fn raw_bytes(&self) -> bool {
+        self.response_type.eq_ignore_ascii_case("bytes") && !self.envelope && !self.body_base64
+    }
+
+    /// Builds the response for the raw output of a `bytes` route, sent with the
+    /// `content-type` default header of the plugin or as `application/octet-stream`.
+    fn bytes_response(&self, body: Vec<u8>) -> Response {
+        let content_type = self.default_headers.get(hyper::header::CONTENT_TYPE)
+            .cloned()
+            .unwrap_or(HeaderValue::from_static("application/octet-stream"));
+        ([(hyper::header::CONTENT_TYPE, content_type)], body).into_response()
+    }
+
+    /// Builds the response sent when the plugin call failed, logging the failure: `500`
+    /// if it panicked, `504` if it timed out.
+    fn call_error_response(&self, error: CallError, grpc: bool) -> Response {
+        let (status, grpc_status, message) = match error {
+            CallError::Panicked(message) => {
+                error!("{}: {}{}: {}", panic::MESSAGE, self.plugin_name, self.route_path, message);
+                (StatusCode::INTERNAL_SERVER_ERROR, grpc::GrpcStatus::Internal, panic::MESSAGE)
+            },
+            CallError::TimedOut(timeout) => {
+                error!("Plugin function timed out: {}{}: after {:?}", self.plugin_name, self.route_path, timeout);
+                (StatusCode::GATEWAY_TIMEOUT, grpc::GrpcStatus::DeadlineExceeded, "Plugin function timed out")
+            },
+        };
+        if grpc {
+            return grpc::error(grpc_status, message);
+        }
+        (status, message).into_response()
+    }
+
+    /// Builds the response for a plugin body according to the response type, the route's
+    /// or the one negotiated for an `auto` route.
+    fn body_response(&self, body: &str, response_type: &str) -> Response {
+
+        if !self.body_base64 {
+            return self.render_body(body, response_type);
+        }
+
+        let bytes = match STANDARD.decode(body.trim()) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                error!("Error decoding base64 body: {}", e);
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("Error decoding base64 body: {}", e),
+                ).into_response();
+            },
+        };
+
+        // Decoded text goes through the regular rendering, binary content is sent as is
+        let text = !response_type.eq_ignore_ascii_case("image")
+            && !response_type.eq_ignore_ascii_case("bytes");
+        match String::from_utf8(bytes) {
+            Ok(decoded) if text => self.render_body(&decoded, response_type),
+            Ok(decoded) => Plugins::set_binary_response(decoded.into_bytes(), response_type),
+            Err(e) => Plugins::set_binary_response(e.into_bytes(), response_type),
+        }
+    }
+
+    /// Renders a text body according to the response type.
+    fn render_body(&self, body: &str, response_type: &str) -> Response {
+
+        if response_type.eq_ignore_ascii_case("template") {
+            #[cfg(feature = "tera")]
+            return match &self.settings.templates {
+                Some(tera) => template::render(tera, body),
+                None => (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "No templates registered for template responses",
+                ).into_response(),
+            };
+
+            #[cfg(not(feature = "tera"))]
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Template responses require the tera feature",
+            ).into_response();
+        }
+
+        if response_type.eq_ignore_ascii_case("json") {
+            // A body that isn't JSON is rendered in the first format of an `auto` route
+            if let Some(first) = self.formats.first().filter(|first| *first != "json") {
+                if serde_json::from_str::<serde::de::IgnoredAny>(body).is_err() {
+                    return self.render_body(body, first);
+                }
+            }
+
+            let filtered = self.settings.json_filter.as_ref()
+                .and_then(|filter| filter.apply(&self.plugin_name, &self.route_path, body));
+            if let Some(filtered) = filtered {
+                return Plugins::set_response(&filtered, response_type, self.settings.pretty_json);
+            }
+        }
+
+        Plugins::set_response(body, response_type, self.settings.pretty_json)
+    }
+}
+
+/// Runs a plugin request: checks the plugin's host and the access rules, calls `respond`,
+/// adds the plugin's default headers and writes the access log line, when enabled.
+///
+/// # Arguments
+/// * `settings` - The host-side options.
+/// * `plugin` - The plugin name.
+/// * `route` - The route path as declared by the plugin.
+/// * `host` - The host the plugin is bound to, if any.
+/// * `default_headers` - The plugin's default response headers.
+/// * `request` - The incoming request.
+/// * `respond` - Builds the plugin's response for the request.
+///
+/// # Returns
+/// An Axum response.
+async fn serve<F, Fut>(
+    settings: &Settings,
+    plugin: &str,
+    route: &str,
+    host: Option<&str>,
+    default_headers: &HeaderMap,
+    mut request: Request,
+    respond: F,
+) -> Response
+where
+    F: FnOnce(Request) -> Fut,
+    Fut: std::future::Future<Output = Response>,
+{
+    let client = proxy::client(settings.trusted_proxies.as_deref(), &mut request);
+
+    let entry = settings.access_log.map(|format| (format, AccessEntry::new(&request, client.ip)));
+
+    #[cfg(feature = "opentelemetry")]
+    let span = otel::request_span(plugin, route, &request);
+    #[cfg(not(feature = "opentelemetry"))]
+    let _ = (plugin, route);
+
+    let in_flight = settings.drain.enter();
+    let draining = in_flight.is_none();
+
+    let handle = async {
+        if host.is_some_and(|host| request_host(&request, &client).as_deref() != Some(host)) {
+            // Plugins bound to a host don't exist for the other hosts
+            StatusCode::NOT_FOUND.into_response()
+        } else if draining {
+            // The server is shutting down
+            (StatusCode::SERVICE_UNAVAILABLE, "Service Unavailable").into_response()
+        } else if !is_allowed(settings, &request) {
+            (StatusCode::FORBIDDEN, "Forbidden").into_response()
+        } else if settings.read_only && !request.method().is_safe() {
+            // Fallbacks and remote plugins take every method
+            StatusCode::METHOD_NOT_ALLOWED.into_response()
+        } else {
+            respond(request).await
+        }
+    };
+    // The plugin calls keep the request in flight until they return
+    let handle = async {
+        match in_flight {
+            Some(in_flight) => drain::scope(in_flight, handle).await,
+            None => handle.await,
+        }
+    };
+
+    #[cfg(feature = "opentelemetry")]
+    let mut response = {
+        let response = handle.instrument(span.clone()).await;
+        otel::record_status(&span, response.status());
+        response
+    };
+    #[cfg(not(feature = "opentelemetry"))]
+    let mut response = handle.await;
+
+    // The response's own headers take precedence over the plugin defaults
+    for header_name in default_headers.keys() {
+        if !response.headers().contains_key(header_name) {
+            for value in default_headers.get_all(header_name) {
+                response.headers_mut().append(header_name.clone(), value.clone());
+            }
+        }
+    }
+
+    if let Some((format, entry)) = entry {
+        entry.log(format, &response);
+    }
+
+    response
+}
+
+/// Sets the `Content-Length` of a response whose body is fully materialized, so it's
+/// never sent chunked and the access log can count its bytes.
+fn set_content_length(response: &mut Response) {
+    use hyper::body::Body as _;
+
+    // These statuses have no body and must not describe one (RFC 9110, section 8.6)
+    let status = response.status();
+    if status.is_informational() || status == StatusCode::NO_CONTENT || status == StatusCode::NOT_MODIFIED {
+        return;
+    }
+
+    if response.headers().contains_key(hyper::header::CONTENT_LENGTH) {
+        return;
+    }
+
+    if let Some(length) = response.body().size_hint().exact() {
+        response.headers_mut().insert(hyper::header::CONTENT_LENGTH, HeaderValue::from(length));
+    }
+}
+
+/// Builds the method router calling a route handler for the declared methods.
+///
+/// # Arguments
+/// * `methods` - The lowercase methods, separated by commas: `get`, `post`, `put`,
+///   `delete`, `patch` or `any`.
+/// * `handler` - The route handler.
+///
+/// # Returns
+/// The method router, or `None` if a method isn't supported.
+fn method_router_for(methods: &str, handler: Arc<RouteHandler>) -> Option<MethodRouter> {
+    let call = move |request: Request| async move {
+        handler.call(request).await
+    };
+
+    let mut filter: Option<MethodFilter> = None;
+    for method in methods.split(',').map(str::trim) {
+        let method = match method {
+            "get" => MethodFilter::GET,
+            "post" => MethodFilter::POST,
+            "put" => MethodFilter::PUT,
+            "delete" => MethodFilter::DELETE,
+            "patch" => MethodFilter::PATCH,
+            "any" => return Some(any(call)),
+            _ => return None,
+        };
+        filter = Some(filter.map_or(method, |filter| filter.or(method)));
+    }
+    filter.map(|filter| on(filter, call))
+}
+
+/// Returns a method shared by two comma-separated lists of methods, `any` sharing every
+/// method.
+fn overlapping_method(methods: &str, other: &str) -> Option<String> {
+    let methods: Vec<&str> = methods.split(',').map(str::trim).collect();
+    let other: Vec<&str> = other.split(',').map(str::trim).collect();
+    if other.contains(&"any") {
+        return methods.first().map(|method| method.to_string());
+    }
+    other.iter()
+        .find(|method| methods.contains(method) || methods.contains(&"any"))
+        .map(|method| method.to_string())
+}
+
+/// Why a call to a plugin function produced no output.
+#[derive(Debug)]
+enum CallError {
+    /// The call panicked, with the panic message.
+    Panicked(String),
+    /// The call didn't complete within the timeout.
+    TimedOut(Duration),
+}
+
+/// Marks the call of a request that was dropped before the call returned: the client
+/// disconnected, making hyper drop the handler, or the call timed out.
+struct Abandoned {
+    abandoned: Arc<AtomicBool>,
+    armed: bool,
+}
+
+impl Drop for Abandoned {
+    fn drop(&mut self) {
+        if self.armed {
+            self.abandoned.store(true, Ordering::SeqCst);
+            debug!("Plugin call abandoned by its request, its output will be discarded");
+        }
+    }
+}
+
+/// Runs a plugin call in a blocking task, catching a panic (unless the panic policy
+/// aborts) and giving up waiting after the timeout, if any.
+///
+/// A running call can't be cancelled: when the request is dropped, e.g. because the
+/// client disconnected, or the call timed out, the call keeps its blocking thread until
+/// the plugin function returns, and its output is discarded. A call still waiting for a
+/// blocking thread at that point isn't made at all.
+async fn call_blocking<T: Send + 'static>(
+    timeout: Option<Duration>,
+    panic_policy: PanicPolicy,
+    call: impl FnOnce() -> T + Send + 'static,
+) -> Result<T, CallError> {
+
+    // The events of the call belong to the span of the request
+    let span = tracing::Span::current();
+    let mut abandoned = Abandoned {
+        abandoned: Arc::new(AtomicBool::new(false)),
+        armed: true,
+    };
+    let skipped = abandoned.abandoned.clone();
+    let in_flight = drain::current();
+    let task = tokio::task::spawn_blocking(move || {
+        let _in_flight = in_flight;
+        let _entered = span.enter();
+        if skipped.load(Ordering::SeqCst) {
+            debug!("Skipping plugin call: its request was dropped while it waited for a thread");
+            return None;
+        }
+        Some(panic::catch(panic_policy, call))
+    });
+    let joined = match timeout {
+        Some(timeout) => match tokio::time::timeout(timeout, task).await {
+            Ok(joined) => joined,
+            Err(_) => return Err(CallError::TimedOut(timeout)),
+        },
+        None => task.await,
+    };
+    abandoned.armed = false;
+
+    match joined {
+        Ok(Some(result)) => result.map_err(CallError::Panicked),
+        // Only skipped once abandoned, when nothing waits for the result anymore
+        Ok(None) => Err(CallError::Panicked("call skipped".to_string())),
+        Err(e) => Err(CallError::Panicked(e.to_string())),
+    }
+}
+
+/// Removes the state-changing methods from a comma-separated list of methods, for the
+/// read-only mode. `any` is narrowed to `get`, which also handles `HEAD` requests.
+///
+/// # Returns
+/// The remaining methods, or `None` if there are none left.
+fn read_only_methods(methods: &str) -> Option<String> {
+    let safe: Vec<&str> = methods.split(',')
+        .map(str::trim)
+        .filter_map(|method| match method {
+            "get" | "any" => Some("get"),
+            "post" | "put" | "delete" | "patch" => None,
+            // Left for `method_router_for` to reject
+            method => Some(method),
+        })
+        .collect();
+
+    if safe.is_empty() {
+        None
+    } else {
+        Some(safe.join(","))
+    }
+}
+
+/// Checks a request against the host's access rules, if any.
+fn is_allowed(settings: &Settings, request: &Request) -> bool {
+    let access_control = match &settings.access_control {
+        Some(access_control) => access_control,
+        None => return true,
+    };
+
+    let path = match request.extensions().get::<OriginalUri>() {
+        Some(OriginalUri(uri)) => uri.path(),
+        None => request.uri().path(),
+    };
+    access_control.is_allowed(request.method(), path, request.headers())
+}
+
+/// Parses the default headers declared by a plugin, skipping the invalid ones.
+fn default_headers(plugin: &str, headers: Vec<(String, String)>) -> HeaderMap {
+    let mut map = HeaderMap::new();
+    for (name, value) in headers {
+        match (name.parse::<HeaderName>(), HeaderValue::from_str(&value)) {
+            (Ok(header_name), Ok(value)) => { map.append(header_name, value); },
+            _ => warn!("Skipping default header: {}: {} - invalid header", plugin, name),
+        }
+    }
+    map
+}
+
+/// Returns the `Content-Type` of a body whose content can't be inspected, based on the
+/// route's response type.
+fn content_type(response_type: &str) -> &'static str {
+    match response_type.to_lowercase().as_str() {
+        "text" => "text/plain; charset=utf-8",
+        "html" => "text/html; charset=utf-8",
+        "json" => "application/json",
+        "sse" => "text/event-stream",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Returns the lowercase host name a request is for, without the port, from the
+/// forwarded host set by a trusted proxy, the `Host` header or the URI authority (HTTP/2).
+fn request_host(request: &Request, client: &proxy::Client) -> Option<String> {
+    let host = client.host.as_deref()
+        .or_else(|| request.headers()
+            .get(hyper::header::HOST)
+            .and_then(|value| value.to_str().ok()))
+        .or_else(|| request.uri().host())?;
+
+    // Keep the brackets of IPv6 literals, e.g. `[::1]:3000`
+    let host = match host.rsplit_once(':') {
+        Some((name, port)) if !name.is_empty() && !port.contains(']') => name,
+        _ => host,
+    };
+    Some(host.to_ascii_lowercase())
+}
+
+/// Answers a request to a route alias with a `301 Moved Permanently` to the route's path,
+/// filled with the parameters captured by the alias and keeping the query.
+///
+/// # Arguments
+/// * `request` - The request to the alias.
+/// * `canonical` - The normalized path of the route.
+///
+/// # Returns
+/// The redirect response.
+async fn redirect(request: Request, canonical: &str) -> Response {
+
+    let prefix = mount_prefix(&request);
+    let (mut parts, _) = request.into_parts();
+    let params = match Path::<HashMap<String, String>>::from_request_parts(&mut parts, &()).await {
+        Ok(Path(params)) => params,
+        Err(_) => HashMap::new(),
+    };
+
+    // The root route of a nested plugin is served at the prefix itself
+    let mut location = match path::fill_params(canonical, &params) {
+        path if path == "/" && !prefix.is_empty() => prefix,
+        path => format!("{}{}", prefix, path),
+    };
+    if let Some(query) = parts.uri.query() {
+        location.push('?');
+        location.push_str(query);
+    }
+
+    match HeaderValue::from_str(&location) {
+        Ok(location) => (StatusCode::MOVED_PERMANENTLY, [(hyper::header::LOCATION, location)]).into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+/// Returns the path prefix under which the request's route is mounted, e.g. `/plugin/name`
+/// for `/plugin/name/page` served by the route `/page` of a nested plugin router.
+fn mount_prefix(request: &Request) -> String {
+    let path = request.uri().path();
+    let original = match request.extensions().get::<OriginalUri>() {
+        Some(OriginalUri(uri)) => uri.path(),
+        None => return String::new(),
+    };
+
+    if path == "/" {
+        return original.trim_end_matches('/').to_string();
+    }
+    original.strip_suffix(path).unwrap_or_default().to_string()
+}
+
+impl Plugins {
+
+    /// Creates a new instance of the `Plugins` struct.
+    ///
+    /// # Arguments
+    /// * `name_to_route` - An optional boolean indicating whether to prepend the plugin name to each route.
+    ///
+    /// # Returns
+    /// A new `Plugins` instance.
+    pub fn new(
+        name_to_route: Option<bool>,
+    ) -> Self {
+
+        Plugins {
+            name_to_route: match name_to_route {
+                Some(true) => true,
+                Some(false) => false,
+                None => false,
+            },
+            settings: Settings {
+                query_brackets: true,
+                pretty_json: *log::DEBUG,
+                ..Settings::default()
+            },
+            loaded: Mutex::new(Vec::new()),
+            memory: AtomicUsize::new(0),
+            registered: Vec::new(),
+            routes: Mutex::new(Vec::new()),
+            layers: HashMap::new(),
+            serving: SharedRouter::default(),
+            #[cfg(feature = "watch")]
+            watcher: Mutex::new(None),
+        }
+    }
+
+    /// Enables or disables the `Server-Timing` response header reporting the plugin call
+    /// duration, so browser dev tools can show plugin latency. Disabled by default.
+    ///
+    /// # Arguments
+    /// * `enabled` - Whether to add the `Server-Timing` header to plugin responses.
+    ///
+    /// # Returns
+    /// The updated `Plugins` instance.
+    pub fn server_timing(mut self, enabled: bool) -> Self {
+        self.settings.server_timing = enabled;
+        self
+    }
+
+    /// Enables or disables the `x-handled-by: <plugin_name>/<route>` response header, which
+    /// shows the plugin and route that produced a response. Disabled by default to avoid
+    /// leaking internals in production.
+    ///
+    /// # Arguments
+    /// * `enabled` - Whether to add the `x-handled-by` header to plugin responses.
+    ///
+    /// # Returns
+    /// The updated `Plugins` instance.
+    pub fn handled_by(mut self, enabled: bool) -> Self {
+        self.settings.handled_by = enabled;
+        self
+    }
+
+    /// Enables or disables the `key[]` array convention when parsing the query string
+    /// forwarded to routes declared with `query_json`. Repeated keys always produce arrays;
+    /// with brackets enabled (the default) `?ids[]=1` also produces `{"ids": ["1"]}`.
+    ///
+    /// # Arguments
+    /// * `enabled` - Whether to recognize the bracket convention.
+    ///
+    /// # Returns
+    /// The updated `Plugins` instance.
+    pub fn query_brackets(mut self, enabled: bool) -> Self {
+        self.settings.query_brackets = enabled;
+        self
+    }
+
+    /// Enables gzip compression of plugin responses that are above the configured size
+    /// threshold and have a compressible content type. Disabled by default.
+    ///
+    /// # Arguments
+    /// * `compression` - The compression thresholds, see [`Compression`].
+    ///
+    /// # Returns
+    /// The updated `Plugins` instance.
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.settings.compression = Some(compression);
+        self
+    }
+
+    /// Allows loading libraries that don't export `plugin_abi_version`, built before the
+    /// ABI was versioned. By default they're skipped with an error, like libraries built
+    /// for another [`PLUGIN_ABI_VERSION`], since calling a function with the wrong
+    /// signature crashes the process.
+    ///
+    /// # Arguments
+    /// * `allowed` - Whether to load libraries without an ABI version.
+    ///
+    /// # Returns
+    /// The updated `Plugins` instance.
+    pub fn allow_unversioned_plugins(mut self, allowed: bool) -> Self {
+        self.settings.allow_unversioned = allowed;
+        self
+    }
+
+    /// Opens the plugin libraries on up to `max` threads while loading, instead of one
+    /// after the other, to speed up the startup of large plugin sets. The plugins are
+    /// still registered in directory order, so the result is the same as a sequential
+    /// load. `0` or `1`, the default, loads them sequentially.
+    ///
+    /// # Arguments
+    /// * `max` - The maximum number of libraries opened at the same time.
+    ///
+    /// # Returns
+    /// The updated `Plugins` instance.
+    pub fn parallel_loads(mut self, max: usize) -> Self {
+        self.settings.parallel_loads = max;
+        self
+    }
+
+    /// Enables or disables the strict configuration mode, in which plugins whose
+    /// `plugin.json` contains unknown keys are skipped with an error, to catch typos.
+    /// By default unknown keys are accepted and kept for forward compatibility.
+    ///
+    /// # Arguments
+    /// * `enabled` - Whether to reject unknown keys.
+    ///
+    /// # Returns
+    /// The updated `Plugins` instance.
+    pub fn strict_config(mut self, enabled: bool) -> Self {
+        self.settings.strict_config = enabled;
+        self
+    }
+
+    /// Makes `load()` fail when two plugins share a name, instead of mounting only the
+    /// last one. This covers the `plugin.json` files of the plugins directory, remote
+    /// plugins and the plugins added with [`Plugins::register`]. Disabled by default.
+    ///
+    /// # Arguments
+    /// * `enabled` - Whether duplicate plugin names are an error.
+    ///
+    /// # Returns
+    /// The updated `Plugins` instance.
+    pub fn unique_names(mut self, enabled: bool) -> Self {
+        self.settings.unique_names = enabled;
+        self
+    }
+
+    /// Registers the templates used to render routes declared with
+    /// `"response_type": "template"`, whose functions return the template name and the
+    /// data to render it with.
+    ///
+    /// # Arguments
+    /// * `tera` - The Tera instance holding the templates.
+    ///
+    /// # Returns
+    /// The updated `Plugins` instance.
+    #[cfg(feature = "tera")]
+    pub fn templates(mut self, tera: tera::Tera) -> Self {
+        self.settings.templates = Some(Arc::new(tera));
+        self
+    }
+
+    /// Sets how long the host waits for the services of the remote plugins: to connect,
+    /// and between two reads of a response. A service exceeding them is answered with a
+    /// `504 Gateway Timeout`. 5 and 30 seconds by default.
+    ///
+    /// # Arguments
+    /// * `connect` - The connect timeout.
+    /// * `read` - The read timeout.
+    ///
+    /// # Returns
+    /// The updated `Plugins` instance.
+    #[cfg(feature = "remote")]
+    pub fn remote_timeouts(mut self, connect: Duration, read: Duration) -> Self {
+        self.settings.remote_timeouts = remote::Timeouts { connect, read };
+        self
+    }
+
+    /// Registers an in-process plugin, resolved through `resolver` instead of a shared
+    /// library. Registered plugins are mounted by `load()` after the plugins loaded from
+    /// the plugins directory, which makes it possible to test route registration with a
+    /// fake [`StaticPlugin`].
+    ///
+    /// # Arguments
+    /// * `name` - The plugin name, used as route prefix when `name_to_route` is set.
+    /// * `resolver` - The resolver for the plugin's routes and functions.
+    ///
+    /// # Returns
+    /// The updated `Plugins` instance.
+    pub fn register(
+        mut self,
+        name: impl Into<String>,
+        resolver: impl SymbolResolver + 'static,
+    ) -> Self {
+        self.registered.push((name.into(), Arc::new(resolver)));
+        self
+    }
+
+    /// Logs the request bodies of selected routes as debug events, redacted and truncated
+    /// as configured. Disabled by default.
+    ///
+    /// # Arguments
+    /// * `body_logging` - The routes to log and the size cap and redaction, see [`BodyLogging`].
+    ///
+    /// # Returns
+    /// The updated `Plugins` instance.
+    pub fn log_body(mut self, body_logging: BodyLogging) -> Self {
+        self.settings.body_logging = Some(body_logging);
+        self
+    }
+
+    /// Requires the version of a plugin, as declared in its `plugin.json` (or by
+    /// [`StaticPlugin::version`]), to satisfy a semver requirement, e.g. `>=1.2, <2.0`,
+    /// to catch stale libraries. A plugin whose version doesn't match or isn't valid
+    /// semver is skipped with an error; an invalid requirement makes `load()` fail.
+    ///
+    /// # Arguments
+    /// * `plugin` - The plugin name.
+    /// * `req` - The version requirement, in Cargo's syntax.
+    ///
+    /// # Returns
+    /// The updated `Plugins` instance.
+    pub fn version_req(mut self, plugin: impl Into<String>, req: impl Into<String>) -> Self {
+        self.settings.version_reqs.insert(plugin.into(), req.into());
+        self
+    }
+
+    /// Rewrites the root-relative URLs in the HTML and JSON responses of a plugin to include
+    /// its mount prefix, so `/static/x.css` becomes `/plugin/name/static/x.css` when the
+    /// plugin is served under `/plugin/name`. Disabled by default.
+    ///
+    /// # Arguments
+    /// * `plugin` - The plugin name.
+    /// * `rewrite` - The attributes and fields to rewrite, see [`UrlRewrite`].
+    ///
+    /// # Returns
+    /// The updated `Plugins` instance.
+    pub fn rewrite_urls(mut self, plugin: impl Into<String>, rewrite: UrlRewrite) -> Self {
+        self.settings.url_rewrites.insert(plugin.into(), rewrite);
+        self
+    }
+
+    /// Sets the host-side access rules checked before a request reaches a plugin. Denied
+    /// requests are answered with `403 Forbidden` without invoking the plugin.
+    ///
+    /// # Arguments
+    /// * `access_control` - The ordered rules, see [`AccessControl`].
+    ///
+    /// # Returns
+    /// The updated `Plugins` instance.
+    pub fn access_control(mut self, access_control: AccessControl) -> Self {
+        self.settings.access_control = Some(access_control);
+        self
+    }
+
+    /// Prints an access log line for every request handled by a plugin route, in the
+    /// given format. Disabled by default.
+    ///
+    /// # Arguments
+    /// * `format` - The log line format, see [`AccessLogFormat`].
+    ///
+    /// # Returns
+    /// The updated `Plugins` instance.
+    pub fn access_log(mut self, format: AccessLogFormat) -> Self {
+        self.settings.access_log = Some(format);
+        self
+    }
+
+    /// Sets the approximate memory, in bytes, that the loaded plugins may use. Plugins
+    /// that would exceed it are skipped. Unlimited by default.
+    ///
+    /// Native library memory can't be measured exactly, so the accounting uses the file
+    /// size of the libraries and their configuration plus the size of the route tables,
+    /// see [`Plugins::memory_usage`].
+    ///
+    /// # Arguments
+    /// * `bytes` - The memory budget.
+    ///
+    /// # Returns
+    /// The updated `Plugins` instance.
+    pub fn memory_budget(mut self, bytes: usize) -> Self {
+        self.settings.memory_budget = Some(bytes);
+        self
+    }
+
+    /// Returns the approximate memory, in bytes, attributed to the plugins mounted by the
+    /// last `load()` or `reload()`, as accounted for the memory budget.
+    pub fn memory_usage(&self) -> usize {
+        self.memory.load(Ordering::SeqCst)
+    }
+
+    /// Enables the replay of responses for routes declared with `"idempotent": true`:
+    /// the response to a request carrying an `Idempotency-Key` header is cached, and a
+    /// retry with the same key gets it back without invoking the plugin again.
+    ///
+    /// # Arguments
+    /// * `idempotency` - The cache TTL and size, see [`Idempotency`].
+    ///
+    /// # Returns
+    /// The updated `Plugins` instance.
+    pub fn idempotency(mut self, idempotency: Idempotency) -> Self {
+        self.settings.idempotency = Some(idempotency);
+        self
+    }
+
+    /// Removes the configured fields from the JSON responses of selected routes before
+    /// they're sent, so the host can enforce data minimization without changing plugins.
+    ///
+    /// # Arguments
+    /// * `filter` - The routes and the fields removed from them, see [`JsonFilter`].
+    ///
+    /// # Returns
+    /// The updated `Plugins` instance.
+    pub fn filter_json(mut self, filter: JsonFilter) -> Self {
+        self.settings.json_filter = Some(filter);
+        self
+    }
+
+    /// Restricts the `response_type` values the plugin routes may declare, e.g. only `json`
+    /// to avoid the XSS surface of `html` responses. Routes declaring another type are
+    /// skipped by `load()` with an error. All types are allowed by default.
+    ///
+    /// # Arguments
+    /// * `response_types` - The allowed response types.
+    ///
+    /// # Returns
+    /// The updated `Plugins` instance.
+    pub fn allowed_response_types<I, S>(mut self, response_types: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.settings.response_types = Some(response_types.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Sets the `Content-Security-Policy` sent by routes declared with `"csp_nonce": true`,
+    /// where `{nonce}` is replaced with the request's nonce. Defaults to
+    /// `script-src 'nonce-{nonce}'`.
+    ///
+    /// # Arguments
+    /// * `policy` - The policy template, e.g. `default-src 'self'; script-src 'nonce-{nonce}'`.
+    ///
+    /// # Returns
+    /// The updated `Plugins` instance.
+    pub fn csp_policy(mut self, policy: impl Into<String>) -> Self {
+        self.settings.csp_policy = Some(policy.into());
+        self
+    }
+
+    /// Enables or disables automatic strong ETags (the SHA-256 of the body) for the `GET`
+    /// responses that don't set one, answering matching `If-None-Match` requests with
+    /// `304 Not Modified`. Disabled by default.
+    ///
+    /// # Arguments
+    /// * `enabled` - Whether to generate ETags.
+    ///
+    /// # Returns
+    /// The updated `Plugins` instance.
+    pub fn etag(mut self, enabled: bool) -> Self {
+        self.settings.etag = enabled;
+        self
+    }
+
+    /// Enables or disables indenting the JSON responses of the `json` routes, to make
+    /// them easier to read while developing. Enabled by default when the `DEBUG`
+    /// environment variable is `true`, otherwise the JSON is compact.
+    ///
+    /// # Arguments
+    /// * `enabled` - Whether to pretty-print JSON responses.
+    ///
+    /// # Returns
+    /// The updated `Plugins` instance.
+    pub fn pretty_json(mut self, enabled: bool) -> Self {
+        self.settings.pretty_json = enabled;
+        self
+    }
+
+    /// Sets a global timeout applied to every request of the plugins router, as a hard
+    /// ceiling regardless of any finer-grained timeout (the shorter one wins). Requests
+    /// taking longer are answered with `408 Request Timeout`. Disabled by default.
+    ///
+    /// A plugin function that's already running can't be interrupted: it completes in its
+    /// blocking thread and its result is discarded.
+    ///
+    /// # Arguments
+    /// * `timeout` - The maximum duration of a request.
+    ///
+    /// # Returns
+    /// The updated `Plugins` instance.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.settings.timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the directory the plugins are loaded from, instead of `PLUGINS_DIR` (or
+    /// `plugins` if unset).
+    ///
+    /// Every `Plugins` instance owns the libraries it loads, so instances loading
+    /// different directories can be nested under different prefixes of the same app.
+    ///
+    /// # Arguments
+    /// * `dir` - The plugins directory, with a subdirectory per plugin.
+    ///
+    /// # Returns
+    /// The updated `Plugins` instance.
+    pub fn plugins_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.settings.plugins_dir = Some(dir.into());
+        self
+    }
+
+    /// Inspects a plugin shared library without loading it as a plugin, for plugin
+    /// authors checking a build before deploying it.
+    ///
+    /// The library's `routes` function is called and the declared routes are validated,
+    /// and the report lists the well-known symbols and the route functions the library
+    /// exports.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use axum_router_plugin::Plugins;
+    ///
+    /// let report = Plugins::inspect_library("plugins/foo-bar/libarp_foo_bar.so").unwrap();
+    /// print!("{}", report);
+    /// assert!(report.is_ok());
+    /// ```
+    ///
+    /// # Arguments
+    /// * `path` - The path of the shared library.
+    ///
+    /// # Returns
+    /// The report, or an error if the library couldn't be loaded at all.
+    pub fn inspect_library(path: impl AsRef<std::path::Path>) -> Result<LibraryReport, PluginError> {
+        inspect::inspect(path.as_ref())
+    }
+
+    /// Enables or disables the `x-plugin-version` response header, set to the `version`
+    /// of the plugin's configuration, to find out which plugin version served a request
+    /// during rolling deploys. Disabled by default.
+    ///
+    /// # Arguments
+    /// * `enabled` - Whether to add the `x-plugin-version` header to plugin responses.
+    ///
+    /// # Returns
+    /// The updated `Plugins` instance.
+    pub fn plugin_version(mut self, enabled: bool) -> Self {
+        self.settings.plugin_version = enabled;
+        self
+    }
+
+    /// Sets the reverse proxies whose `X-Forwarded-For`, `X-Forwarded-Proto` and
+    /// `X-Forwarded-Host` headers are trusted, as networks in CIDR notation.
+    ///
+    /// The forwarded headers are honored (for the client address of the access logs and
+    /// the host of virtual hosts) only when the immediate peer is a trusted proxy, which
+    /// requires serving with [`Plugins::into_make_service`] or another way of inserting
+    /// `ConnectInfo`. For other peers they are removed before the request reaches the
+    /// plugin. Invalid networks are skipped with an error. No proxy is trusted by default,
+    /// and the forwarded headers are then ignored by the host but passed to plugins.
+    ///
+    /// # Example
+    /// ```
+    /// use axum_router_plugin::Plugins;
+    ///
+    /// let _plugins = Plugins::new(Some(true))
+    ///     .trusted_proxies(["10.0.0.0/8", "127.0.0.1", "::1"]);
+    /// ```
+    ///
+    /// # Arguments
+    /// * `cidrs` - The trusted networks, e.g. `10.0.0.0/8`, or single addresses.
+    ///
+    /// # Returns
+    /// The updated `Plugins` instance.
+    pub fn trusted_proxies<I, S>(mut self, cidrs: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let cidrs = cidrs.into_iter()
+            .filter_map(|cidr| {
+                let parsed = proxy::Cidr::parse(cidr.as_ref());
+                if parsed.is_none() {
+                    warn!("Skipping trusted proxy: {:?} - invalid CIDR", cidr.as_ref());
+                }
+                parsed
+            })
+            .collect();
+        self.settings.trusted_proxies = Some(cidrs);
+        self
+    }
+
+    /// Registers a named `tower` layer that plugins can apply to their routes by listing
+    /// its name in the `layers` of their `plugin.json`, e.g. `"layers": ["auth"]`.
+    ///
+    /// Layers are applied in the listed order, the first one being the outermost. A
+    /// plugin listing a layer that isn't registered is not mounted.
+    ///
+    /// # Example
+    /// ```
+    /// use axum_router_plugin::Plugins;
+    /// use std::time::Duration;
+    /// use tower_http::timeout::TimeoutLayer;
+    ///
+    /// let _plugins = Plugins::new(Some(true))
+    ///     .layer("slow", TimeoutLayer::new(Duration::from_secs(60)));
+    /// ```
+    ///
+    /// # Arguments
+    /// * `name` - The name plugins reference the layer by.
+    /// * `layer` - The layer.
+    ///
+    /// # Returns
+    /// The updated `Plugins` instance.
+    pub fn layer<L>(mut self, name: impl Into<String>, layer: L) -> Self
+    where
+        L: Layer<Route> + Clone + Send + Sync + 'static,
+        L::Service: Service<Request> + Clone + Send + 'static,
+        <L::Service as Service<Request>>::Response: IntoResponse + 'static,
+        <L::Service as Service<Request>>::Error: Into<Infallible> + 'static,
+        <L::Service as Service<Request>>::Future: Send + 'static,
+    {
+        self.layers.insert(name.into(), Arc::new(move |router: Router| router.layer(layer.clone())));
+        self
+    }
+
+    /// Keeps the values of the plugins' key/value stores in another backend than the
+    /// default in-memory [`MemoryStore`], e.g. one persisted to disk. The plugins exporting
+    /// `plugin_store` access it through the [`HostStore`] callbacks.
+    ///
+    /// # Arguments
+    /// * `store` - The store backend.
+    ///
+    /// # Returns
+    /// The updated `Plugins` instance.
+    pub fn key_value_store(mut self, store: impl KeyValueStore + 'static) -> Self {
+        self.settings.store = store::SharedStore(Arc::new(store));
+        self
+    }
+
+    /// Enables the `GET /_info` endpoint at the root of the plugins router, returning the
+    /// crate version, the [`PLUGIN_ABI_VERSION`], the uptime of the instance and the name
+    /// and version of every plugin served, as JSON. Disabled by default.
+    ///
+    /// # Arguments
+    /// * `enabled` - Whether to serve the endpoint.
+    ///
+    /// # Returns
+    /// The updated `Plugins` instance.
+    pub fn info_endpoint(mut self, enabled: bool) -> Self {
+        self.settings.info_started = enabled.then(Instant::now);
+        self
+    }
+
+    /// Serves a JSON list of the plugins found by the last `load()` or `reload()` at
+    /// `path`, e.g. `/_plugins`, at the root of the plugins router: the name, version,
+    /// enabled state and library path (or remote URL) of each plugin, and the method,
+    /// public path, function and response type of every route it mounted. Disabled by
+    /// default.
+    ///
+    /// # Arguments
+    /// * `path` - The path of the endpoint, chosen not to collide with a plugin route.
+    ///
+    /// # Returns
+    /// The updated `Plugins` instance.
+    pub fn plugins_endpoint(mut self, path: impl Into<String>) -> Self {
+        self.settings.plugins_endpoint = Some(path.into());
+        self
+    }
+
+    /// Serves a favicon at `/favicon.ico` of the plugins router, so browser requests under
+    /// a nested plugins router don't end up as 404s. Its content type is detected from its
+    /// bytes; an empty icon is answered with `204 No Content`. A plugin merged at the root
+    /// that declares the path takes precedence.
+    ///
+    /// # Arguments
+    /// * `icon` - The icon file, e.g. loaded with `include_bytes!`.
+    ///
+    /// # Returns
+    /// The updated `Plugins` instance.
+    pub fn favicon(mut self, icon: impl Into<Bytes>) -> Self {
+        self.settings.favicon = Some(icon.into());
+        self
+    }
+
+    /// Serves a `robots.txt` at `/robots.txt` of the plugins router for crawlers. An empty
+    /// text is answered with `204 No Content`. A plugin merged at the root that declares
+    /// the path takes precedence.
+    ///
+    /// # Arguments
+    /// * `robots_txt` - The content of the `robots.txt` file.
+    ///
+    /// # Returns
+    /// The updated `Plugins` instance.
+    pub fn robots_txt(mut self, robots_txt: impl Into<String>) -> Self {
+        self.settings.robots_txt = Some(robots_txt.into());
+        self
+    }
+
+    /// Caches the routes declared by the plugin libraries in a directory, keyed by the
+    /// SHA-256 checksum of each library, so that later loads of an unchanged library use
+    /// the cached routes instead of calling its `routes` function. Rebuilding a library
+    /// invalidates its entry. Disabled by default.
+    ///
+    /// # Arguments
+    /// * `dir` - The cache directory, created on the first write.
+    ///
+    /// # Returns
+    /// The updated `Plugins` instance.
+    pub fn routes_cache(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.settings.routes_cache = Some(dir.into());
+        self
+    }
+
+    /// Enables or disables the read-only mode, in which `load()` skips the routes of
+    /// state-changing methods (`post`, `put`, `delete`, `patch`), logging each one. An
+    /// `any` route only handles `GET` and `HEAD` requests, and fallback and remote plugins
+    /// answer the other methods with `405 Method Not Allowed`. Disabled by default.
+    ///
+    /// # Arguments
+    /// * `enabled` - Whether to serve the plugins in read-only mode.
+    ///
+    /// # Returns
+    /// The updated `Plugins` instance.
+    pub fn read_only(mut self, enabled: bool) -> Self {
+        self.settings.read_only = enabled;
+        self
+    }
+
+    /// Handles the execution of a plugin's function, passing headers and body as arguments.
+    /// The function is executed in a blocking task through the given invoker, which takes
+    /// care of crossing the FFI boundary and managing the memory of the returned C string.
+    /// A panic during the call is caught, so it only fails this request.
+    ///
+    /// # Arguments
+    /// * `headers` - The request headers.
+    /// * `body` - The request body as a string.
+    /// * `invoker` - The invoker used to call the plugin's function.
+    /// * `timeout` - The maximum duration of the call, if any.
+    /// * `panic_policy` - The panic policy of the plugin.
+    ///
+    /// # Returns
+    /// The response as a string, or why the call produced none.
+    async fn handle_route(
+        headers: HeaderMap,
+        body: String,
+        invoker: Arc<dyn PluginInvoker>,
+        timeout: Option<Duration>,
+        panic_policy: PanicPolicy,
+    ) -> Result<String, CallError> {
+
+        debug!("Handle Route Header Map: {:?}", headers);
+
+        call_blocking(timeout, panic_policy, move || invoker.invoke(headers, body)).await
+    }
+
+    /// Calls a plugin's function for a `bytes` route, returning its output as raw bytes.
+    ///
+    /// # Arguments
+    /// * `headers` - The request headers.
+    /// * `body` - The request body as a string.
+    /// * `invoker` - The invoker used to call the plugin's function.
+    /// * `timeout` - The maximum duration of the call, if any.
+    /// * `panic_policy` - The panic policy of the plugin.
+    ///
+    /// # Returns
+    /// The response bytes, or why the call produced none.
+    async fn handle_bytes_route(
+        headers: HeaderMap,
+        body: String,
+        invoker: Arc<dyn PluginInvoker>,
+        timeout: Option<Duration>,
+        panic_policy: PanicPolicy,
+    ) -> Result<Vec<u8>, CallError> {
+
+        debug!("Handle Route Header Map: {:?}", headers);
+
+        call_blocking(timeout, panic_policy, move || invoker.invoke_bytes(headers, body)).await
+    }
+
+    /// Calls a plugin's function declaring `"abi": "bytes"` with the raw request body,
+    /// returning its output as raw bytes.
+    ///
+    /// # Arguments
+    /// * `headers` - The request headers.
+    /// * `body` - The request body as received.
+    /// * `invoker` - The invoker used to call the plugin's function.
+    /// * `timeout` - The maximum duration of the call, if any.
+    /// * `panic_policy` - The panic policy of the plugin.
+    ///
+    /// # Returns
+    /// The raw output of the plugin, or why the call failed.
+    async fn handle_raw_route(
+        headers: HeaderMap,
+        body: Vec<u8>,
+        invoker: Arc<dyn PluginInvoker>,
+        timeout: Option<Duration>,
+        panic_policy: PanicPolicy,
+    ) -> Result<Vec<u8>, CallError> {
+
+        debug!("Handle Route Header Map: {:?}", headers);
+
+        call_blocking(timeout, panic_policy, move || invoker.invoke_raw(headers, body)).await
+    }
+
+    /// Sets the appropriate response type (text, HTML, JSON, image, bytes) based on the `response_type` argument.
+    ///
+    /// # Arguments
+    /// * `response` - The raw response string.
+    /// * `response_type` - The expected format of the response.
+    /// * `pretty` - Whether JSON is indented.
+    ///
+    /// # Returns
+    /// An Axum response.
+    fn set_response(
+        response: &str,
+        response_type: &str,
+        pretty: bool,
+    ) -> axum::response::Response {
+
+        match response_type.to_lowercase().as_str() {
+            "text" => response.to_string()
+                .into_response(),
+            "html" => Html(response.to_string())
+                .into_response(),
+            "json" => {
+                // println!("Json String Response : {}", response.to_string());
+                let v: Value = match serde_json::from_str(response) {
+                    Ok(json_value) => json_value,
+                    Err(e) => {
+                        error!("Error parsing JSON: {}", e);
+                        serde_json::Value::String(format!("Error parsing JSON: {}", e))
+                    },
+                };
+                if !pretty {
+                    return Json(v).into_response();
+                }
+                match serde_json::to_string_pretty(&v) {
+                    Ok(json) => (
+                        [(hyper::header::CONTENT_TYPE, HeaderValue::from_static("application/json"))],
+                        json,
+                    ).into_response(),
+                    Err(e) => {
+                        error!("Error serializing JSON: {}", e);
+                        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+                    },
+                }
+            },
+            "image" => image::response(response),
+            "bytes" => Plugins::set_binary_response(response.as_bytes().to_vec(), response_type),
+            // Unknown types are rejected by load(), this is only reached by a bug
+            _ => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Unsupported response format: {}", response_type),
+            ).into_response(),
+        }
+    }
+
+    /// Sets the content type of a binary body based on the `response_type` argument.
+    ///
+    /// # Arguments
+    /// * `body` - The decoded body.
+    /// * `response_type` - The expected format of the response.
+    ///
+    /// # Returns
+    /// An Axum response.
+    fn set_binary_response(
+        body: Vec<u8>,
+        response_type: &str,
+    ) -> Response {
+
+        if response_type.eq_ignore_ascii_case("image") {
+            return image::from_bytes(body);
+        }
+        ([(hyper::header::CONTENT_TYPE, content_type(response_type))], body).into_response()
+    }
+
+    /// Loads and merges routes from all enabled plugins into an Axum `Router`.
+    ///
+    /// A plugin that fails to load (its library can't be loaded, lacks a required
+    /// symbol or declares invalid routes) is skipped with a logged error, and so is a
+    /// route whose function is missing or whose options are invalid.
+    ///
+    /// The router is also swapped into [`Plugins::serving_router`].
+    ///
+    /// # Returns
+    /// A result containing the constructed router, or an error if the plugins can't be
+    /// loaded at all, e.g. because the plugins directory doesn't exist.
+    pub fn load(&self) -> Result<Router, PluginError> {
+        let router = self.build()?;
+        self.serving.replace(router.clone());
+        Ok(router)
+    }
+
+    /// Returns the key/value store of the plugins. Its values outlive reloads; a plugin's
+    /// values can be inspected, or cleared with [`KeyValueStore::clear`], by its name.
+    pub fn store(&self) -> Arc<dyn KeyValueStore> {
+        self.settings.store.0.clone()
+    }
+
+    /// Returns a snapshot of the request metrics of the plugin routes: the number of
+    /// calls, failed calls and the latency histogram of each route. The metrics are kept
+    /// across reloads.
+    #[cfg(feature = "metrics")]
+    pub fn stats(&self) -> Stats {
+        self.settings.metrics.snapshot()
+    }
+
+    /// Returns a shared router that always serves the routes of the last successful
+    /// `load()` or `reload()`. A reload builds the new router completely before swapping
+    /// it in atomically, so requests never fail in between; before the first load it
+    /// answers `404`.
+    ///
+    /// # Returns
+    /// The shared router, whose clones all serve the same routes.
+    pub fn serving_router(&self) -> SharedRouter {
+        self.serving.clone()
+    }
+
+    /// Loads the plugins and builds the router with their routes, see [`Plugins::load`].
+    fn build(&self) -> Result<Router, PluginError> {
+
+        let loader::Libraries { loaded: libraries, mut remotes, disabled } = loader::load_libraries(&self.settings)?;
+
+        let mut resolved = Vec::with_capacity(libraries.len());
+        // The plugins to mount, with where they come from
+        let mut plugins: Vec<(String, Arc<dyn SymbolResolver>, String)> = Vec::new();
+        for loaded in libraries {
+            match LibraryResolver::new(loaded.clone(), self.settings.routes_cache.as_deref()) {
+                Ok(resolver) => {
+                    plugins.push((loaded.name.clone(), Arc::new(resolver), loaded.dir.display().to_string()));
+                    resolved.push(loaded);
+                },
+                Err(e) => {
+                    warn!("Skipping plugin: {} - {}", loaded.name, e);
+                    // Its init hook already ran
+                    loaded.shutdown();
+                },
+            }
+        }
+        for (name, resolver) in self.registered.iter() {
+            if let Err(reason) = loader::check_version(&self.settings, name, resolver.version().as_deref()) {
+                warn!("Skipping plugin: {} - {}", name, reason);
+                continue;
+            }
+
+            // The plugins of the plugins directory have unique names, a registered plugin
+            // replaces the one found before it, as a later plugin.json does
+            let earlier = plugins.iter()
+                .find(|(plugin, _, _)| plugin == name)
+                .map(|(_, _, source)| source.clone())
+                .or_else(|| remotes.iter()
+                    .find(|remote| &remote.name == name)
+                    .map(|remote| remote.dir.display().to_string()));
+            if let Some(earlier) = earlier {
+                if self.settings.unique_names {
+                    for plugin in resolved.iter().rev() {
+                        plugin.shutdown();
+                    }
+                    return Err(PluginError::Config(format!(
+                        "duplicate plugin name: {} declared in {} and Plugins::register",
+                        name, earlier
+                    )));
+                }
+                plugins.retain(|(plugin, _, _)| plugin != name);
+                remotes.retain(|remote| &remote.name != name);
+                resolved.retain(|plugin| {
+                    if &plugin.name != name {
+                        return true;
+                    }
+                    plugin.shutdown();
+                    false
+                });
+            }
+            plugins.push((name.clone(), resolver.clone(), "Plugins::register".to_string()));
+        }
+
+        // The path and memory of the plugins loaded from a library, by name
+        let libraries: HashMap<String, (String, usize)> = resolved.iter()
+            .map(|plugin| (plugin.name.clone(), (plugin.path.display().to_string(), plugin.memory)))
+            .collect();
+
+        // Keep track of the loaded plugins so their shutdown hooks can be called
+        match self.loaded.lock() {
+            Ok(mut loaded) => loaded.extend(resolved),
+            Err(e) => e.into_inner().extend(resolved),
+        }
+
+        let message = {
+            let count = plugins.len() + remotes.len();
+            format!("Loaded plugins: {}", count)
+        };
+
+        // The plugins found and the routes they mounted, for the host endpoints
+        let mut found: Vec<info::PluginInfo> = disabled;
+
+        let mut router: Router = Router::new()
+            .route("/", get(|| async {
+                message
+            })
+        );
+
+        // Only the plugins mounted by this load count, it replaces the previous one
+        let mut memory = 0;
+
+        if plugins.is_empty() && remotes.is_empty() {
+            self.memory.store(memory, Ordering::SeqCst);
+            return Ok(self.with_timeout(self.with_host_routes(router, &[], found)));
+        }
+
+        let settings = Arc::new(self.settings.clone());
+
+        // Without name prefixes all plugins share the root, which can only have one fallback
+        let mut root_fallback: Option<String> = None;
+        // The paths merged at the root, with their methods, starting with the `/` route
+        // above. Plugins merged at the root must not conflict with them, and they take
+        // precedence over the built-in routes
+        let mut root_declared: Vec<(String, String)> = vec![("/".to_string(), "get".to_string())];
+
+        for (name, resolver, _) in plugins.iter() {
+
+            if self.name_to_route && !path::is_valid_prefix(name) {
+                warn!(
+                    "Skipping plugin: {:?} - the name can't be used as a route prefix",
+                    name
+                );
+                continue;
+            }
+
+            let json_data = resolver.routes();
+
+            // The route table is accounted by the size of its declaration
+            let (lib_path, lib_memory) = match libraries.get(name) {
+                Some((lib_path, lib_memory)) => (Some(lib_path.clone()), *lib_memory),
+                None => (None, 0),
+            };
+            let needed = lib_memory + json_data.len();
+            if let Some(budget) = self.settings.memory_budget {
+                if memory + needed > budget {
+                    warn!(
+                        "Skipping plugin: {} - memory budget of {} bytes exceeded ({} bytes in use, {} bytes needed)",
+                        name, budget, memory, needed
+                    );
+                    continue;
+                }
+            }
+
+            debug!("Routes Json: {}", json_data);
+
+            let route_list: Vec<PluginRoute> = match serde_json::from_str(&json_data) {
+                Ok(route_list) => route_list,
+                Err(e) => {
+                    warn!("Skipping plugin: {} - {}", name, PluginError::Routes(e.to_string()));
+                    continue;
+                },
+            };
+
+            // Most likely a plugin that failed to populate its routes
+            if route_list.is_empty() {
+                warn!("Plugin {} loaded but declared no routes", name);
+            }
+
+            // The declared routes stay available for tooling through plugin_routes()
+            let mut routes = match self.routes.lock() {
+                Ok(routes) => routes,
+                Err(e) => e.into_inner(),
+            };
+            routes.retain(|(plugin, _)| plugin != name);
+            routes.push((name.clone(), route_list.clone()));
+            drop(routes);
+
+            // Parsed once, the headers are shared by all the routes of the plugin
+            let default_headers = Arc::new(self.plugin_headers(name, resolver.headers(), resolver.version()));
+
+            let host = resolver.host().map(|host| host.to_ascii_lowercase());
+            let plugin_timeout = resolver.timeout();
+            let panic_policy = resolver.panic_policy();
+
+            // Every plugin gets its own subrouter so it can own a fallback for its subtree
+            let mut plugin_router = Router::new();
+            let mut has_fallback = false;
+            // The paths registered so far, with their methods: the plugin's own when it's
+            // nested, and those of every plugin merged at the root otherwise
+            let mut declared: Vec<(String, String)> = match self.name_to_route {
+                true => Vec::new(),
+                false => root_declared.clone(),
+            };
+            let mut mounted: Vec<info::RouteInfo> = Vec::new();
+
+            for route in route_list {
+                if let Some(allowed) = &self.settings.response_types {
+                    if !allowed.iter().any(|allowed| allowed.eq_ignore_ascii_case(&route.response_type)) {
+                        warn!(
+                            "Skipping route: {}: {} - response type {:?} is not allowed (allowed: {})",
+                            name, route.function, route.response_type, allowed.join(", ")
+                        );
+                        continue;
+                    }
+                }
+
+                if let Err(reason) = route.validate() {
+                    warn!("Skipping route: {}: {} - {}", name, route.function, reason);
+                    continue;
+                }
+                let body_base64 = route.body_encoding.is_some();
+                let buffer_abi = route.abi.is_some();
+                let raw_body = route.raw_body();
+                let stream = route.stream();
+
+                // https://www.rfc-editor.org/rfc/rfc8594
+                let mut lifecycle_headers = HeaderMap::new();
+                if route.deprecated {
+                    lifecycle_headers.insert("deprecation", HeaderValue::from_static("true"));
+                }
+                if let Some(Ok(value)) = route.sunset.as_deref().map(HeaderValue::from_str) {
+                    lifecycle_headers.insert("sunset", value);
+                }
+
+                // Load the plugin_route_function
+                let function = if route.request_stream {
+                    resolver.duplex_function(&route.function)
+                } else if stream {
+                    resolver.stream_function(&route.function)
+                } else if raw_body {
+                    resolver.bytes_function(&route.function)
+                } else if buffer_abi {
+                    resolver.buffer_function(&route.function)
+                } else {
+                    resolver.function(&route.function)
+                };
+                let invoker = match function {
+                    Some(invoker) => invoker,
+                    None => {
+                        warn!(
+                            "Skipping route: {}: {} - the function is not exported",
+                            name, route.function
+                        );
+                        continue;
+                    },
+                };
+
+                // The plugin prefix is added by nesting, so `/` maps to `/name`
+                let route_path = path::normalize_path(&route.path);
+                let route_info = info::RouteInfo {
+                    methods: String::new(),
+                    path: self.effective_path(name, &route_path),
+                    function: Some(route.function.clone()),
+                    response_type: Some(route.response_type.clone()),
+                };
+
+                let formats = match route.response_type.eq_ignore_ascii_case("auto") {
+                    true if route.formats.is_empty() => negotiation::DEFAULT_FORMATS.iter().map(|format| format.to_string()).collect(),
+                    true => route.formats.iter().map(|format| format.to_ascii_lowercase()).collect(),
+                    false => Vec::new(),
+                };
+
+                let handler = Arc::new(RouteHandler {
+                    plugin_name: name.clone(),
+                    route_path: route_path.clone(),
+                    response_type: route.response_type,
+                    envelope: route.envelope,
+                    query_json: route.query_json,
+                    csrf: route.csrf,
+                    idempotent: route.idempotent,
+                    csp_nonce: route.csp_nonce,
+                    body_base64,
+                    raw_body,
+                    stream,
+                    request_stream: route.request_stream,
+                    placeholders: route.placeholders,
+                    coalesce: route.coalesce,
+                    query_params: route.query_params,
+                    formats,
+                    timeout: route.timeout_ms.map(Duration::from_millis).or(plugin_timeout),
+                    panic_policy,
+                    lifecycle_headers,
+                    default_headers: default_headers.clone(),
+                    host: host.clone(),
+                    invoker,
+                    settings: settings.clone(),
+                });
+
+                if route.kind == RouteKind::Fallback {
+                    if has_fallback {
+                        warn!(
+                            "Skipping fallback route: {}: {} - plugin already has a fallback",
+                            name, route.function
+                        );
+                        continue;
+                    }
+                    has_fallback = true;
+                    plugin_router = plugin_router.fallback(move |request: Request| async move {
+                        handler.call(request).await
+                    });
+                    mounted.push(info::RouteInfo {
+                        methods: "any".to_string(),
+                        path: self.effective_path(name, "/*"),
+                        ..route_info
+                    });
+                    continue;
+                }
+
+                // gRPC methods are always invoked with POST
+                let methods = if route.method_router.is_empty()
+                    && handler.response_type.eq_ignore_ascii_case("grpc") {
+                    "post".to_string()
+                } else {
+                    route.method_router.to_lowercase()
+                };
+
+                let methods = if self.settings.read_only {
+                    match read_only_methods(&methods) {
+                        Some(methods) => methods,
+                        None => {
+                            warn!(
+                                "Skipping route: {}: {} - method {:?} is not allowed in read-only mode",
+                                name, route.function, route.method_router
+                            );
+                            continue;
+                        },
+                    }
+                } else {
+                    methods
+                };
+
+                let method_router = match method_router_for(&methods, handler) {
+                    Some(method_router) => method_router,
+                    None => {
+                        warn!(
+                            "Skipping route: {}: {} - unsupported method {:?}",
+                            name, route.function, route.method_router
+                        );
+                        continue;
+                    },
+                };
+
+                let conflict = declared.iter().find_map(|(path, declared_methods)| {
+                    if path == &route_path {
+                        overlapping_method(&methods, declared_methods)
+                            .map(|method| format!("method {} already declared for this path", method))
+                    } else {
+                        path::conflict(&route_path, path)
+                    }
+                });
+                if let Some(reason) = conflict {
+                    warn!("Skipping route: {}: {} - {}", name, route.function, reason);
+                    continue;
+                }
+                declared.push((route_path.clone(), methods.clone()));
+
+                debug!("Mounted route: {} {}", methods, route_info.path);
+                mounted.push(info::RouteInfo { methods: methods.clone(), ..route_info });
+
+                // https://docs.rs/axum/latest/axum/extract/index.html
+                // Routes are added to the plugin router in place: a path declared again
+                // with another method is merged into the existing method router
+                plugin_router = plugin_router.route(&route_path, method_router);
+
+                for alias in &route.aliases {
+                    let alias = path::normalize_path(alias);
+                    let conflict = declared.iter().find_map(|(path, declared_methods)| {
+                        if path == &alias {
+                            overlapping_method("get", declared_methods)
+                                .map(|method| format!("method {} already declared for this path", method))
+                        } else {
+                            path::conflict(&alias, path)
+                        }
+                    });
+                    if let Some(reason) = conflict {
+                        warn!("Skipping route alias: {}: {} {} - {}", name, route.function, alias, reason);
+                        continue;
+                    }
+                    declared.push((alias.clone(), "get".to_string()));
+
+                    debug!("Mounted route alias: get {} -> {}", self.effective_path(name, &alias), self.effective_path(name, &route_path));
+                    mounted.push(info::RouteInfo {
+                        methods: "get".to_string(),
+                        path: self.effective_path(name, &alias),
+                        function: None,
+                        response_type: None,
+                    });
+
+                    let settings = settings.clone();
+                    let plugin = name.clone();
+                    let host = host.clone();
+                    let default_headers = default_headers.clone();
+                    let canonical = route_path.clone();
+                    let alias_path = alias.clone();
+                    plugin_router = plugin_router.route(&alias, get(move |request: Request| async move {
+                        serve(
+                            &settings,
+                            &plugin,
+                            &alias_path,
+                            host.as_deref(),
+                            &default_headers,
+                            request,
+                            |request| redirect(request, &canonical),
+                        ).await
+                    }));
+                }
+            }
+
+            let plugin_router = match self.with_layers(name, plugin_router, &resolver.layers()) {
+                Some(plugin_router) => plugin_router,
+                None => continue,
+            };
+
+            if !self.mount(&mut router, name, plugin_router, has_fallback, &mut root_fallback) {
+                continue;
+            }
+            if !self.name_to_route {
+                root_declared = declared;
+            }
+            memory += needed;
+            found.push(info::PluginInfo {
+                name: name.clone(),
+                version: resolver.version(),
+                enabled: true,
+                path: lib_path,
+                routes: mounted,
+            });
+        }
+
+        for remote in remotes {
+            if self.name_to_route && !path::is_valid_prefix(&remote.name) {
+                warn!(
+                    "Skipping plugin: {:?} - the name can't be used as a route prefix",
+                    remote.name
+                );
+                continue;
+            }
+
+            #[cfg(feature = "remote")]
+            {
+                let proxy = match remote::RemoteProxy::new(&remote.url, self.settings.remote_timeouts) {
+                    Ok(proxy) => Arc::new(proxy),
+                    Err(e) => {
+                        warn!("Skipping plugin: {} - {}", remote.name, e);
+                        continue;
+                    },
+                };
+                let version = remote.version.clone();
+                let default_headers = Arc::new(self.plugin_headers(&remote.name, remote.headers, Some(remote.version)));
+                let host = remote.host.map(|host| host.to_ascii_lowercase());
+                let settings = settings.clone();
+                let name = remote.name.clone();
+
+                // The remote service owns every path under the plugin's mount
+                let plugin_router = Router::new().fallback(move |request: Request| async move {
+                    let prefix = mount_prefix(&request);
+                    serve(
+                        &settings,
+                        &name,
+                        "/*",
+                        host.as_deref(),
+                        &default_headers,
+                        request,
+                        |request| proxy.forward(request, &prefix),
+                    ).await
+                });
+                let mounted = self.with_layers(&remote.name, plugin_router, &remote.layers)
+                    .is_some_and(|plugin_router| self.mount(&mut router, &remote.name, plugin_router, true, &mut root_fallback));
+                if mounted {
+                    found.push(info::PluginInfo {
+                        path: Some(remote.url.clone()),
+                        routes: vec![info::RouteInfo {
+                            methods: "any".to_string(),
+                            path: self.effective_path(&remote.name, "/*"),
+                            function: None,
+                            response_type: None,
+                        }],
+                        name: remote.name,
+                        version: Some(version),
+                        enabled: true,
+                    });
+                }
+            }
+
+            #[cfg(not(feature = "remote"))]
+            warn!(
+                "Skipping plugin: {} - remote plugins require the remote feature",
+                remote.name
+            );
+        }
+
+        self.memory.store(memory, Ordering::SeqCst);
+
+        let root_paths: Vec<String> = root_declared.into_iter().map(|(path, _)| path).collect();
+        Ok(self.with_timeout(self.with_host_routes(router, &root_paths, found)))
+    }
+
+    /// Adds the configured `/favicon.ico` and `/robots.txt` routes and the `/_info` and
+    /// plugins endpoints to the plugins router, unless a plugin merged at the root serves
+    /// the same path.
+    fn with_host_routes(
+        &self,
+        mut router: Router,
+        root_paths: &[String],
+        plugins: Vec<info::PluginInfo>,
+    ) -> Router {
+
+        let plugins = Arc::new(plugins);
+
+        if let Some(started) = self.settings.info_started {
+            if root_paths.iter().any(|root_path| root_path == info::PATH) {
+                warn!("Skipping built-in route: {} - served by a plugin", info::PATH);
+            } else {
+                router = router.route(info::PATH, info::route(started, plugins.clone()));
+            }
+        }
+
+        if let Some(endpoint) = &self.settings.plugins_endpoint {
+            let endpoint = path::normalize_path(endpoint);
+            let taken = endpoint == "/" || endpoint == info::PATH || root_paths.contains(&endpoint);
+            match path::validate_path(&endpoint) {
+                Err(reason) => warn!("Skipping built-in route: {} - {}", endpoint, reason),
+                Ok(()) if endpoint.contains([':', '*']) => {
+                    warn!("Skipping built-in route: {} - the path can't have parameters", endpoint);
+                },
+                Ok(()) if taken => warn!("Skipping built-in route: {} - the path is already served", endpoint),
+                Ok(()) => router = router.route(&endpoint, info::plugins_route(plugins)),
+            }
+        }
+
+        let files = [
+            ("/favicon.ico", self.settings.favicon.clone().map(|icon| (image::content_type(&icon), icon))),
+            ("/robots.txt", self.settings.robots_txt.clone().map(|robots| ("text/plain; charset=utf-8", Bytes::from(robots)))),
+        ];
+
+        for (path, file) in files {
+            let (content_type, body) = match file {
+                Some(file) => file,
+                None => continue,
+            };
+            if root_paths.iter().any(|root_path| root_path == path) {
+                warn!("Skipping built-in route: {} - served by a plugin", path);
+                continue;
+            }
+
+            router = router.route(path, get(move || {
+                let body = body.clone();
+                async move {
+                    // An empty file only quiets the 404s
+                    if body.is_empty() {
+                        StatusCode::NO_CONTENT.into_response()
+                    } else {
+                        ([(hyper::header::CONTENT_TYPE, content_type)], body).into_response()
+                    }
+                }
+            }));
+        }
+        router
+    }
+
+    /// Applies the global request timeout, if any, to the plugins router.
+    fn with_timeout(&self, router: Router) -> Router {
+        match self.settings.timeout {
+            Some(timeout) => router.layer(TimeoutLayer::new(timeout)),
+            None => router,
+        }
+    }
+
+    /// Returns the headers added to every response of a plugin: its default headers and,
+    /// when enabled, its version.
+    fn plugin_headers(
+        &self,
+        name: &str,
+        headers: Vec<(String, String)>,
+        version: Option<String>,
+    ) -> HeaderMap {
+
+        let mut map = default_headers(name, headers);
+        if self.settings.plugin_version {
+            if let Some(Ok(value)) = version.as_deref().map(HeaderValue::from_str) {
+                map.insert("x-plugin-version", value);
+            }
+        }
+        map
+    }
+
+    /// Applies the registered layers listed by a plugin to its router.
+    ///
+    /// # Returns
+    /// The plugin router, or `None` if a listed layer isn't registered.
+    fn with_layers(&self, name: &str, plugin_router: Router, layers: &[String]) -> Option<Router> {
+
+        let mut plugin_router = plugin_router;
+        // The last layer applied wraps the others, so the first listed one is applied last
+        for layer in layers.iter().rev() {
+            match self.layers.get(layer) {
+                Some(apply) => plugin_router = apply(plugin_router),
+                None => {
+                    warn!("Skipping plugin: {} - layer {:?} is not registered", name, layer);
+                    return None;
+                },
+            }
+        }
+        Some(plugin_router)
+    }
+
+    /// Returns the routes declared by a loaded plugin, as parsed from its `routes`
+    /// function, e.g. to generate client SDKs or API documentation.
+    ///
+    /// # Arguments
+    /// * `name` - The plugin name.
+    ///
+    /// # Returns
+    /// The declared routes, or `None` if no plugin with that name was loaded.
+    pub fn plugin_routes(&self, name: &str) -> Option<Vec<PluginRoute>> {
+        let routes = match self.routes.lock() {
+            Ok(routes) => routes,
+            Err(e) => e.into_inner(),
+        };
+        routes.iter()
+            .find(|(plugin, _)| plugin == name)
+            .map(|(_, routes)| routes.clone())
+    }
+
+    /// Returns the public path of a plugin route, with the plugin name as prefix when
+    /// `name_to_route` is set. Route registration and introspection go through this
+    /// function, so what's reported always matches what's mounted.
+    ///
+    /// # Arguments
+    /// * `plugin_name` - The plugin name.
+    /// * `route_path` - The route path as declared by the plugin.
+    ///
+    /// # Returns
+    /// The path the route is served at.
+    fn effective_path(&self, plugin_name: &str, route_path: &str) -> String {
+        path::effective_path(self.name_to_route.then_some(plugin_name), route_path)
+    }
+
+    /// Mounts a plugin router, nested under the plugin name when `name_to_route` is set or
+    /// merged at the root otherwise, where only one plugin may own the fallback.
+    ///
+    /// # Arguments
+    /// * `router` - The router holding the plugins mounted so far.
+    /// * `name` - The plugin name.
+    /// * `plugin_router` - The plugin's routes.
+    /// * `has_fallback` - Whether the plugin router has a fallback.
+    /// * `root_fallback` - The plugin owning the root fallback, if any.
+    ///
+    /// # Returns
+    /// Whether the plugin was mounted.
+    fn mount(
+        &self,
+        router: &mut Router,
+        name: &str,
+        plugin_router: Router,
+        has_fallback: bool,
+        root_fallback: &mut Option<String>,
+    ) -> bool {
+
+        if !self.name_to_route && has_fallback && root_fallback.is_some() {
+            warn!(
+                "Skipping plugin: {} - fallback conflicts with plugin {} (enable name_to_route to scope fallbacks)",
+                name, root_fallback.as_deref().unwrap_or_default()
+            );
+            return false;
+        }
+
+        let plugins_router = std::mem::take(router);
+        *router = if self.name_to_route && has_fallback {
+            // Nested as a service, the plugin's fallback keeps owning its subtree even when
+            // the host nests the plugins router again (a nested router's fallback is lost)
+            plugins_router.nest_service(&self.effective_path(name, "/"), plugin_router)
+        } else if self.name_to_route {
+            plugins_router.nest(&self.effective_path(name, "/"), plugin_router)
+        } else {
+            if has_fallback {
+                *root_fallback = Some(name.to_string());
+            }
+            plugins_router.merge(plugin_router)
+        };
+        true
+    }
+
+    /// Loads the plugins and wraps the resulting router in a type-erased `tower` service.
+    ///
+    /// This allows the plugin system to be composed into any `hyper`/`tower` based server,
+    /// not only an `axum::Router`. Handlers that rely on `ConnectInfo` expect the
+    /// `axum::extract::ConnectInfo<SocketAddr>` extension to be inserted into each request
+    /// by the caller; use [`Plugins::into_make_service`] to have Axum do that wiring.
+    ///
+    /// # Returns
+    /// A result containing the boxed service or an error if a plugin fails to load.
+    pub fn into_service(
+        &self,
+    ) -> Result<BoxCloneService<Request, Response, Infallible>, PluginError> {
+
+        let router = self.load()?;
+        Ok(BoxCloneService::new(router))
+    }
+
+    /// Loads the plugins and returns a make-service that injects the peer `SocketAddr`
+    /// as `ConnectInfo` into every request, ready to be passed to `axum::serve`.
+    ///
+    /// # Returns
+    /// A result containing the make-service or an error if a plugin fails to load.
+    pub fn into_make_service(
+        &self,
+    ) -> Result<IntoMakeServiceWithConnectInfo<Router, SocketAddr>, PluginError> {
+
+        let router = self.load()?;
+        Ok(router.into_make_service_with_connect_info::<SocketAddr>())
+    }
+
+    /// Loads the plugins again and returns a new router with their routes, e.g. to pass to
+    /// [`SharedRouter::replace`] after plugins were added, enabled or disabled.
+    ///
+    /// The shutdown hooks of the previously loaded plugins are called once the new router
+    /// is built, except for the libraries reopened unchanged: the dynamic loader shares
+    /// them with the new router, so they only get `plugin_init` again. The libraries stay
+    /// mapped until the routers built from them are dropped, so in-flight requests finish
+    /// on the old code.
+    ///
+    /// # Returns
+    /// A result containing the new router or an error if a plugin fails to load, in which
+    /// case the previously loaded plugins are kept.
+    pub fn reload(&self) -> Result<Router, PluginError> {
+
+        let previous = match self.loaded.lock() {
+            Ok(mut loaded) => std::mem::take(&mut *loaded),
+            Err(e) => std::mem::take(&mut *e.into_inner()),
+        };
+        let previous_routes = match self.routes.lock() {
+            Ok(mut routes) => std::mem::take(&mut *routes),
+            Err(e) => std::mem::take(&mut *e.into_inner()),
+        };
+
+        match self.load() {
+            Ok(router) => {
+                let current = match self.loaded.lock() {
+                    Ok(loaded) => loaded.clone(),
+                    Err(e) => e.into_inner().clone(),
+                };
+                for plugin in previous.iter().rev() {
+                    // A library reopened unchanged is the instance the new router uses,
+                    // already initialized again, so it must not be shut down
+                    if current.iter().any(|loaded| loaded.same_library(plugin)) {
+                        continue;
+                    }
+                    plugin.shutdown();
+                }
+                Ok(router)
+            },
+            Err(e) => {
+                match self.loaded.lock() {
+                    Ok(mut loaded) => *loaded = previous,
+                    Err(e) => *e.into_inner() = previous,
+                }
+                match self.routes.lock() {
+                    Ok(mut routes) => *routes = previous_routes,
+                    Err(e) => *e.into_inner() = previous_routes,
+                }
+                Err(e)
+            },
+        }
+    }
+
+    /// Installs a `SIGHUP` handler that reloads the plugins and swaps the new router into
+    /// `router`, following the usual daemon convention. If the reload fails (or panics),
+    /// the error is printed and the current router keeps serving.
+    ///
+    /// Must be called from within a Tokio runtime.
+    ///
+    /// # Arguments
+    /// * `router` - The shared router the reloaded routes are swapped into.
+    ///
+    /// # Returns
+    /// The handle of the task waiting for the signals.
+    #[cfg(all(unix, feature = "signal"))]
+    pub fn reload_on_sighup(
+        self: &Arc<Self>,
+        router: SharedRouter,
+    ) -> std::io::Result<tokio::task::JoinHandle<()>> {
+
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut hangup = signal(SignalKind::hangup())?;
+        let plugins = self.clone();
+
+        Ok(tokio::spawn(async move {
+            while hangup.recv().await.is_some() {
+                log::info!("SIGHUP received, reloading plugins");
+                let plugins = plugins.clone();
+                match tokio::task::spawn_blocking(move || plugins.reload()).await {
+                    Ok(Ok(reloaded)) => router.replace(reloaded),
+                    Ok(Err(e)) => error!("Error reloading plugins, keeping the current router: {}", e),
+                    Err(e) => error!("Error reloading plugins, keeping the current router: {}", e),
+                }
+            }
+        }))
+    }
+
+    /// Waits for `SIGTERM` (on Unix) or `Ctrl-C`, then drains the plugin requests and
+    /// calls the shutdown hooks of the plugins, for use with Axum's graceful shutdown.
+    ///
+    /// When the signal arrives, new requests to the plugin routes are answered with a
+    /// `503 Service Unavailable`, and the requests already running get up to `grace` to
+    /// complete. A request runs until its plugin call returns, even if its client is gone,
+    /// and its streamed body is sent. The shutdown hooks are then called, as with [`Plugins::unload_all`], and
+    /// the future completes, so Axum stops accepting connections. Requests still running
+    /// after the grace period keep their libraries loaded until they finish.
+    ///
+    /// # Arguments
+    /// * `grace` - How long the requests in flight get to complete.
+    ///
+    /// # Returns
+    /// The future to pass to `with_graceful_shutdown`.
+    #[cfg(feature = "signal")]
+    pub fn shutdown_signal(
+        self: &Arc<Self>,
+        grace: Duration,
+    ) -> impl std::future::Future<Output = ()> + Send + 'static {
+
+        let plugins = self.clone();
+
+        async move {
+            let ctrl_c = async {
+                if let Err(e) = tokio::signal::ctrl_c().await {
+                    error!("Error waiting for Ctrl-C: {}", e);
+                    std::future::pending::<()>().await;
+                }
+            };
+            #[cfg(unix)]
+            let terminate = async {
+                use tokio::signal::unix::{signal, SignalKind};
+                match signal(SignalKind::terminate()) {
+                    Ok(mut terminate) => {
+                        terminate.recv().await;
+                    },
+                    Err(e) => {
+                        error!("Error waiting for SIGTERM: {}", e);
+                        std::future::pending::<()>().await;
+                    },
+                }
+            };
+            #[cfg(not(unix))]
+            let terminate = std::future::pending::<()>();
+
+            tokio::select! {
+                _ = ctrl_c => {},
+                _ = terminate => {},
+            }
+
+            let drain = &plugins.settings.drain;
+            log::info!("Shutdown signal received, draining {} plugin requests", drain.in_flight());
+            drain.start();
+            if tokio::time::timeout(grace, drain.wait()).await.is_err() {
+                warn!(
+                    "Grace period of {:?} elapsed with {} plugin requests in flight, shutting down the plugins",
+                    grace, drain.in_flight()
+                );
+            }
+
+            let plugins = plugins.clone();
+            if let Err(e) = tokio::task::spawn_blocking(move || plugins.unload_all()).await {
+                error!("Error shutting down the plugins: {}", e);
+            }
+        }
+    }
+
+    /// Watches the plugins directory and reloads the plugins when a `plugin.json` file or
+    /// a library changes, so enabling, disabling, adding or removing a plugin takes
+    /// effect without restarting the server. A burst of changes results in a single
+    /// reload; if it fails, the error is printed and the current router keeps serving.
+    ///
+    /// Call it after `load()`. Watching stops when the `Plugins` instance is dropped, and
+    /// calling it again restarts it.
+    ///
+    /// # Returns
+    /// The [`Plugins::serving_router`], which the reloaded routes are swapped into, or an
+    /// error if the plugins directory can't be watched.
+    #[cfg(feature = "watch")]
+    pub fn watch(self: &Arc<Self>) -> Result<SharedRouter, PluginError> {
+
+        let dir = loader::plugins_dir(&self.settings);
+        let watcher = watch::start(Arc::downgrade(self), &dir).map_err(|e| PluginError::PluginsDir {
+            path: dir.clone(),
+            source: Some(match e.kind {
+                notify::ErrorKind::Io(e) => e,
+                _ => std::io::Error::other(e),
+            }),
+        })?;
+
+        match self.watcher.lock() {
+            Ok(mut current) => *current = Some(watcher),
+            Err(e) => *e.into_inner() = Some(watcher),
+        }
+        Ok(self.serving_router())
+    }
+
+    /// Calls the `plugin_shutdown` hook of every loaded plugin, in reverse load order,
+    /// and releases the instance's references to their libraries, emptying the
+    /// [`Plugins::serving_router`].
+    ///
+    /// A library is only unloaded from memory once the routers built from it are dropped
+    /// too, so this should be called after the server has stopped serving requests.
+    /// It's called automatically when the `Plugins` instance is dropped.
+    pub fn unload_all(&self) {
+
+        let loaded = match self.loaded.lock() {
+            Ok(mut loaded) => std::mem::take(&mut *loaded),
+            Err(e) => std::mem::take(&mut *e.into_inner()),
+        };
+        self.serving.replace(Router::new());
+
+        for plugin in loaded.iter().rev() {
+            plugin.shutdown();
+        }
+    }
+}
+
+impl Drop for Plugins {
+    fn drop(&mut self) {
+        self.unload_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TempDir};
+    use axum::body::Body;
+    use tower::ServiceExt;
+
+    /// A plugin serving the canned `response` at `GET path` through `function`.
+    fn get_plugin(path: &str, function: &str, response: &str) -> StaticPlugin {
+        StaticPlugin::new(format!(
+            r#"[{{"path": "{}", "function": "{}", "method_router": "get"}}]"#,
+            path, function
+        ))
+            .function(function, Arc::new(CannedInvoker::new(response)))
+    }
+
+    #[tokio::test]
+    async fn conflicting_routes_of_plugins_merged_at_the_root_are_skipped() {
+        let dir = TempDir::new();
+        let plugins = testing::plugins(&dir, false)
+            .register("first", get_plugin("/same", "same", "first"))
+            .register("second", get_plugin("/same", "same", "second"))
+            .register("users", get_plugin("/users/:id", "user", "users"))
+            .register("names", get_plugin("/users/:name", "user", "names"));
+
+        let router = plugins.load().unwrap();
+
+        let response = testing::get(&router, "/same").await;
+        assert_eq!(testing::body_string(response).await, "first");
+        let response = testing::get(&router, "/users/1").await;
+        assert_eq!(testing::body_string(response).await, "users");
+    }
+
+    #[tokio::test]
+    async fn plugin_root_route_merged_at_the_root_is_skipped() {
+        let dir = TempDir::new();
+        let plugins = testing::plugins(&dir, false)
+            .register("root", get_plugin("/", "index", "plugin index"));
+
+        let router = plugins.load().unwrap();
+
+        let response = testing::get(&router, "/").await;
+        assert_eq!(testing::body_string(response).await, "Loaded plugins: 1");
+    }
+
+    #[tokio::test]
+    async fn plugins_named_like_path_parameters_are_not_nested() {
+        let dir = TempDir::new();
+        let plugins = testing::plugins(&dir, true)
+            .register("*rest", get_plugin("/", "index", "wildcard"))
+            .register(":id", get_plugin("/", "index", "parameter"))
+            .register("ok", get_plugin("/", "index", "ok"));
+
+        let router = plugins.load().unwrap();
+
+        assert_eq!(testing::get(&router, "/anything").await.status(), StatusCode::NOT_FOUND);
+        let response = testing::get(&router, "/ok").await;
+        assert_eq!(testing::body_string(response).await, "ok");
+    }
+
+    #[tokio::test]
+    async fn registered_plugin_replaces_a_plugin_with_the_same_name() {
+        let dir = TempDir::new();
+        dir.plugin("foo-bar", &testing::config("foo-bar"), Some(testing::FOO_BAR));
+        let plugins = testing::plugins(&dir, true)
+            .allow_unversioned_plugins(true)
+            .register("foo-bar", get_plugin("/about", "about", "registered"))
+            .register("twice", get_plugin("/", "index", "first"))
+            .register("twice", get_plugin("/", "index", "last"));
+
+        let router = plugins.load().unwrap();
+
+        let response = testing::get(&router, "/foo-bar/about").await;
+        assert_eq!(testing::body_string(response).await, "registered");
+        assert_eq!(testing::get(&router, "/foo-bar/test-get").await.status(), StatusCode::NOT_FOUND);
+        let response = testing::get(&router, "/twice").await;
+        assert_eq!(testing::body_string(response).await, "last");
+    }
+
+    #[test]
+    fn unique_names_error_names_the_sources_of_the_duplicate() {
+        let dir = TempDir::new();
+        let plugin_dir = dir.plugin("foo-bar", &testing::config("foo-bar"), Some(testing::FOO_BAR));
+        let plugins = testing::plugins(&dir, true)
+            .allow_unversioned_plugins(true)
+            .unique_names(true)
+            .register("foo-bar", get_plugin("/", "index", "registered"));
+
+        let error = plugins.load().unwrap_err().to_string();
+
+        assert!(
+            error.contains(&format!("duplicate plugin name: foo-bar declared in {} and Plugins::register", plugin_dir.display())),
+            "{}", error
+        );
+    }
+
+    #[test]
+    fn unique_names_rejects_plugins_registered_twice() {
+        let dir = TempDir::new();
+        let plugins = testing::plugins(&dir, true)
+            .unique_names(true)
+            .register("twice", get_plugin("/", "index", "first"))
+            .register("twice", get_plugin("/", "index", "last"));
+
+        assert!(matches!(plugins.load(), Err(PluginError::Config(_))));
+    }
+
+    #[test]
+    fn memory_usage_counts_the_plugins_mounted_by_the_last_load() {
+        let routes = r#"[{"path": "/", "function": "index", "method_router": "get"}]"#;
+        let plugin = || StaticPlugin::new(routes).function("index", Arc::new(CannedInvoker::new("")));
+        let dir = TempDir::new();
+        let plugins = testing::plugins(&dir, true)
+            .memory_budget(routes.len() * 2)
+            .register("first", plugin())
+            .register("second", plugin())
+            .register("skipped", plugin());
+
+        let _router = plugins.load().unwrap();
+        assert_eq!(plugins.memory_usage(), routes.len() * 2);
+
+        // The budget isn't taken by the previous load
+        let _router = plugins.load().unwrap();
+        assert_eq!(plugins.memory_usage(), routes.len() * 2);
+        assert!(plugins.plugin_routes("second").is_some());
+        assert!(plugins.plugin_routes("skipped").is_none());
+    }
+
+    #[test]
+    fn memory_usage_skips_plugins_that_are_not_mounted() {
+        let routes = r#"[{"path": "/", "function": "index", "method_router": "get"}]"#;
+        let dir = TempDir::new();
+        let plugins = testing::plugins(&dir, true)
+            .register("layered", StaticPlugin::new(routes).layer("missing"))
+            .register("*bad", StaticPlugin::new(routes));
+
+        let _router = plugins.load().unwrap();
+
+        assert_eq!(plugins.memory_usage(), 0);
+    }
+
+    #[tokio::test]
+    async fn plugin_declaring_no_routes_is_loaded_without_routes() {
+        let dir = TempDir::new();
+        let plugins = testing::plugins(&dir, true)
+            .register("empty", StaticPlugin::new("[]"));
+
+        let router = plugins.load().unwrap();
+
+        assert_eq!(plugins.plugin_routes("empty").map(|routes| routes.len()), Some(0));
+        let response = testing::get(&router, "/").await;
+        assert_eq!(testing::body_string(response).await, "Loaded plugins: 1");
+    }
+
+    #[test]
+    fn plugin_routes_match_the_declaration() {
+        let dir = TempDir::new();
+        let plugin = StaticPlugin::new(
+            r#"[{"path": "/items", "function": "list", "method_router": "get", "response_type": "json"}, {"path": "/items", "function": "create", "method_router": "post", "envelope": true, "timeout_ms": 250}, {"function": "app", "kind": "fallback"}]"#,
+        )
+            .function("list", Arc::new(CannedInvoker::new("[]")))
+            .function("create", Arc::new(CannedInvoker::new("{}")))
+            .function("app", Arc::new(CannedInvoker::new("app")));
+        let plugins = testing::plugins(&dir, true)
+            .register("store", plugin);
+
+        let _router = plugins.load().unwrap();
+
+        let routes = plugins.plugin_routes("store").unwrap();
+        let declared: Vec<_> = routes.iter()
+            .map(|route| (route.path(), route.function(), route.method_router(), route.response_type()))
+            .collect();
+        assert_eq!(declared, [
+            ("/items", "list", "get", "json"),
+            ("/items", "create", "post", "text"),
+            ("", "app", "", "text"),
+        ]);
+        assert!(!routes[0].envelope());
+        assert!(routes[1].envelope());
+        assert_eq!(routes[1].timeout(), Some(Duration::from_millis(250)));
+        assert_eq!(routes[2].kind(), &RouteKind::Fallback);
+        assert!(plugins.plugin_routes("missing").is_none());
+    }
+
+    #[tokio::test]
+    async fn service_serves_the_plugin_routes() {
+        let dir = TempDir::new();
+        let plugins = testing::plugins(&dir, true)
+            .register("greeter", get_plugin("/hello", "hello", "Hello!"));
+
+        let service = plugins.into_service().unwrap();
+        let request = Request::get("/greeter/hello").body(Body::empty()).unwrap();
+        let response = service.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(testing::body_string(response).await, "Hello!");
+    }
+
+    #[tokio::test]
+    async fn server_timing_reports_the_plugin_call_duration() {
+        let dir = TempDir::new();
+        let invoker = testing::SlowInvoker {
+            delay: Duration::from_millis(20),
+            canned: Arc::new(CannedInvoker::new("done")),
+        };
+        let plugins = testing::plugins(&dir, true)
+            .server_timing(true)
+            .register("slow", StaticPlugin::new(
+                r#"[{"path": "/", "function": "slow", "method_router": "get"}]"#,
+            ).function("slow", Arc::new(invoker)));
+
+        let router = plugins.load().unwrap();
+        let response = testing::get(&router, "/slow").await;
+
+        let value = response.headers()["server-timing"].to_str().unwrap().to_string();
+        let (plugin, total) = value.split_once(", ").unwrap();
+        let plugin = plugin.strip_prefix("plugin;desc=\"slow\";dur=").unwrap().parse::<f64>().unwrap();
+        let total = total.strip_prefix("total;dur=").unwrap().parse::<f64>().unwrap();
+        assert!((20.0..10_000.0).contains(&plugin), "{}", value);
+        assert!(total >= plugin, "{}", value);
+    }
+
+    #[tokio::test]
+    async fn server_timing_is_disabled_by_default() {
+        let dir = TempDir::new();
+        let plugins = testing::plugins(&dir, true)
+            .register("fast", get_plugin("/", "fast", "done"));
+
+        let router = plugins.load().unwrap();
+        let response = testing::get(&router, "/fast").await;
+
+        assert!(!response.headers().contains_key("server-timing"));
+    }
+
+    /// Loads a plugin named `plugin` declaring a single `GET /` route of `response_type`,
+    /// answered with `output`, and requests it.
+    async fn respond(response_type: &str, output: &str) -> Response {
+        let dir = TempDir::new();
+        let route = format!(r#"{{"path": "/", "method_router": "get", "response_type": "{}"}}"#, response_type);
+        let (plugin, invoker) = testing::route_plugin(&route, output);
+        let router = testing::plugins(&dir, true)
+            .register("plugin", plugin)
+            .load()
+            .unwrap();
+
+        let response = testing::get(&router, "/plugin").await;
+        assert_eq!(invoker.calls(), 1);
+        response
+    }
+
+    #[tokio::test]
+    async fn response_types_set_the_content_type() {
+        for (response_type, output, content_type) in [
+            ("text", "plain", "text/plain; charset=utf-8"),
+            ("html", "<p>html</p>", "text/html; charset=utf-8"),
+            ("json", r#"{"a": 1}"#, "application/json"),
+        ] {
+            let response = respond(response_type, output).await;
+
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers()[hyper::header::CONTENT_TYPE], content_type);
+            let body = testing::body_string(response).await;
+            match response_type {
+                "json" => assert_eq!(body, r#"{"a":1}"#),
+                _ => assert_eq!(body, output),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn missing_or_empty_response_type_defaults_to_text() {
+        let dir = TempDir::new();
+        let plugin = StaticPlugin::new(
+            r#"[{"path": "/missing", "function": "plain", "method_router": "get"}, {"path": "/empty", "function": "plain", "method_router": "get", "response_type": ""}]"#,
+        ).function("plain", Arc::new(CannedInvoker::new("plain")));
+        let router = testing::plugins(&dir, true)
+            .register("minimal", plugin)
+            .load()
+            .unwrap();
+
+        for uri in ["/minimal/missing", "/minimal/empty"] {
+            let response = testing::get(&router, uri).await;
+            assert_eq!(response.headers()[hyper::header::CONTENT_TYPE], "text/plain; charset=utf-8");
+            assert_eq!(testing::body_string(response).await, "plain");
+        }
+    }
+
+    #[tokio::test]
+    async fn json_response_has_its_content_length() {
+        let response = respond("json", r#"{"name": "caf\u00e9", "items": [1, 2]}"#).await;
+
+        let length = response.headers()[hyper::header::CONTENT_LENGTH].to_str().unwrap().to_string();
+        let body = testing::body_string(response).await;
+        assert_eq!(body, r#"{"items":[1,2],"name":"café"}"#);
+        assert_eq!(length, body.len().to_string());
+    }
+
+    #[tokio::test]
+    async fn json_is_pretty_printed_only_when_enabled() {
+        let dir = TempDir::new();
+        let pretty_json = |enabled| {
+            let (plugin, _) = testing::route_plugin(
+                r#"{"path": "/", "method_router": "get", "response_type": "json"}"#,
+                r#"{"items": [1, 2]}"#,
+            );
+            testing::plugins(&dir, true).pretty_json(enabled).register("plugin", plugin).load().unwrap()
+        };
+
+        let response = testing::get(&pretty_json(true), "/plugin").await;
+        assert_eq!(response.headers()[hyper::header::CONTENT_TYPE], "application/json");
+        assert_eq!(testing::body_string(response).await, "{\n  \"items\": [\n    1,\n    2\n  ]\n}");
+
+        let response = testing::get(&pretty_json(false), "/plugin").await;
+        assert_eq!(response.headers()[hyper::header::CONTENT_TYPE], "application/json");
+        assert_eq!(testing::body_string(response).await, r#"{"items":[1,2]}"#);
+    }
+
+    #[tokio::test]
+    async fn invalid_json_output_is_reported_as_a_json_string() {
+        let response = respond("json", "{not json").await;
+
+        assert_eq!(response.headers()[hyper::header::CONTENT_TYPE], "application/json");
+        let body = testing::body_string(response).await;
+        assert!(body.starts_with("\"Error parsing JSON: "), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn plugin_call_timing_out_is_answered_with_504() {
+        let dir = TempDir::new();
+        let invoker = testing::SlowInvoker {
+            delay: Duration::from_millis(500),
+            canned: Arc::new(CannedInvoker::new("late")),
+        };
+        let plugin = StaticPlugin::new(
+            r#"[{"path": "/", "function": "slow", "method_router": "get", "timeout_ms": 50}]"#,
+        ).function("slow", Arc::new(invoker));
+        let router = testing::plugins(&dir, true)
+            .register("slow", plugin)
+            .load()
+            .unwrap();
+
+        let response = testing::get(&router, "/slow").await;
+
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(testing::body_string(response).await, "Plugin function timed out");
+    }
+
+    #[tokio::test]
+    async fn slow_plugin_is_cut_off_at_the_global_timeout() {
+        let dir = TempDir::new();
+        let invoker = Arc::new(testing::SlowInvoker {
+            delay: Duration::from_millis(500),
+            canned: Arc::new(CannedInvoker::new("late")),
+        });
+        let plugin = StaticPlugin::new(
+            r#"[{"path": "/", "function": "slow", "method_router": "get"}, {"path": "/short", "function": "slow", "method_router": "get", "timeout_ms": 20}]"#,
+        ).function("slow", invoker);
+        let router = testing::plugins(&dir, true)
+            .timeout(Duration::from_millis(100))
+            .register("slow", plugin)
+            .load()
+            .unwrap();
+
+        let response = testing::get(&router, "/slow").await;
+        assert_eq!(response.status(), StatusCode::REQUEST_TIMEOUT);
+        let response = testing::get(&router, "/slow/short").await;
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+    }
+
+    #[tokio::test]
+    async fn base64_encoded_bodies_are_sent_decoded() {
+        let binary = [0u8, 159, 146, 150, 255];
+        let dir = TempDir::new();
+        let plugin = StaticPlugin::new(
+            r#"[{"path": "/raw", "function": "raw", "method_router": "get", "response_type": "bytes", "body_encoding": "base64"}, {"path": "/pdf", "function": "pdf", "method_router": "get", "envelope": true, "body_encoding": "base64"}, {"path": "/json", "function": "json", "method_router": "get", "response_type": "json", "body_encoding": "base64"}, {"path": "/invalid", "function": "invalid", "method_router": "get", "response_type": "bytes", "body_encoding": "base64"}]"#,
+        )
+            .function("raw", Arc::new(CannedInvoker::new(STANDARD.encode(binary))))
+            .function("pdf", Arc::new(CannedInvoker::new(format!(
+                r#"{{"headers": {{"content-type": "application/pdf"}}, "body": "{}"}}"#,
+                STANDARD.encode(b"%PDF-\xff"),
+            ))))
+            .function("json", Arc::new(CannedInvoker::new(STANDARD.encode(r#"{"a": 1}"#))))
+            .function("invalid", Arc::new(CannedInvoker::new("not base64!")));
+        let router = testing::plugins(&dir, true)
+            .register("files", plugin)
+            .load()
+            .unwrap();
+
+        let response = testing::get(&router, "/files/raw").await;
+        assert_eq!(response.headers()[hyper::header::CONTENT_TYPE], "application/octet-stream");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], binary);
+
+        let response = testing::get(&router, "/files/pdf").await;
+        assert_eq!(response.headers()[hyper::header::CONTENT_TYPE], "application/pdf");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"%PDF-\xff");
+
+        let response = testing::get(&router, "/files/json").await;
+        assert_eq!(testing::body_string(response).await, r#"{"a":1}"#);
+
+        let response = testing::get(&router, "/files/invalid").await;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn deprecated_route_responses_carry_the_lifecycle_headers() {
+        let dir = TempDir::new();
+        let plugin = StaticPlugin::new(
+            r#"[{"path": "/v1", "function": "v1", "method_router": "get", "deprecated": true, "sunset": "Sat, 31 Jan 2026 23:59:59 GMT"}, {"path": "/v2", "function": "v2", "method_router": "get"}]"#,
+        )
+            .function("v1", Arc::new(CannedInvoker::new("v1")))
+            .function("v2", Arc::new(CannedInvoker::new("v2")));
+        let router = testing::plugins(&dir, true)
+            .register("api", plugin)
+            .load()
+            .unwrap();
+
+        let response = testing::get(&router, "/api/v1").await;
+        assert_eq!(response.headers()["deprecation"], "true");
+        assert_eq!(response.headers()["sunset"], "Sat, 31 Jan 2026 23:59:59 GMT");
+        let response = testing::get(&router, "/api/v2").await;
+        assert!(!response.headers().contains_key("deprecation"));
+        assert!(!response.headers().contains_key("sunset"));
+    }
+
+    #[tokio::test]
+    async fn instances_with_different_directories_dont_interfere() {
+        let (first_dir, second_dir) = (TempDir::new(), TempDir::new());
+        first_dir.plugin("alpha", &testing::config("alpha"), Some(testing::FOO_BAR));
+        second_dir.plugin("beta", &testing::config("beta"), Some(testing::FOO_BAR));
+        let first = testing::plugins(&first_dir, true).allow_unversioned_plugins(true);
+        let second = testing::plugins(&second_dir, true).allow_unversioned_plugins(true);
+
+        let first_router = first.load().unwrap();
+        let second_router = second.load().unwrap();
+
+        assert!(first.plugin_routes("beta").is_none());
+        assert!(second.plugin_routes("alpha").is_none());
+        assert_eq!(testing::get(&first_router, "/alpha/about").await.status(), StatusCode::OK);
+        assert_eq!(testing::get(&first_router, "/beta/about").await.status(), StatusCode::NOT_FOUND);
+        assert_eq!(testing::get(&second_router, "/alpha/about").await.status(), StatusCode::NOT_FOUND);
+
+        first.unload_all();
+        drop(first);
+        let response = testing::get(&second_router, "/beta/about").await;
+        assert!(testing::body_string(response).await.starts_with("Name: arp-foo-bar"));
+    }
+
+    #[tokio::test]
+    async fn registered_layers_apply_only_to_the_plugins_listing_them() {
+        async fn tag_a(mut response: Response) -> Response {
+            response.headers_mut().append("x-layer", HeaderValue::from_static("a"));
+            response
+        }
+        async fn tag_b(mut response: Response) -> Response {
+            response.headers_mut().append("x-layer", HeaderValue::from_static("b"));
+            response
+        }
+        let dir = TempDir::new();
+        let router = testing::plugins(&dir, true)
+            .layer("a", axum::middleware::map_response(tag_a))
+            .layer("b", axum::middleware::map_response(tag_b))
+            .register("layered", get_plugin("/", "index", "layered").layer("a").layer("b"))
+            .register("plain", get_plugin("/", "index", "plain"))
+            .load()
+            .unwrap();
+
+        let response = testing::get(&router, "/layered").await;
+        let layers: Vec<_> = response.headers().get_all("x-layer").iter().collect();
+        assert_eq!(layers, ["b", "a"]);
+        let response = testing::get(&router, "/plain").await;
+        assert!(!response.headers().contains_key("x-layer"));
+    }
+
+    #[tokio::test]
+    async fn plugin_version_header_matches_the_configured_version() {
+        let dir = TempDir::new();
+        dir.plugin("foo-bar", &testing::config("foo-bar"), Some(testing::FOO_BAR));
+        let router = testing::plugins(&dir, true)
+            .allow_unversioned_plugins(true)
+            .plugin_version(true)
+            .register("static", get_plugin("/", "index", "static").version("2.3.4"))
+            .register("unversioned", get_plugin("/", "index", "unversioned"))
+            .load()
+            .unwrap();
+
+        let response = testing::get(&router, "/foo-bar/about").await;
+        assert_eq!(response.headers()["x-plugin-version"], "0.1.0");
+        let response = testing::get(&router, "/static").await;
+        assert_eq!(response.headers()["x-plugin-version"], "2.3.4");
+        let response = testing::get(&router, "/unversioned").await;
+        assert!(!response.headers().contains_key("x-plugin-version"));
+    }
+
+    #[tokio::test]
+    async fn plugin_version_header_is_disabled_by_default() {
+        let dir = TempDir::new();
+        let router = testing::plugins(&dir, true)
+            .register("static", get_plugin("/", "index", "static").version("2.3.4"))
+            .load()
+            .unwrap();
+
+        let response = testing::get(&router, "/static").await;
+
+        assert!(!response.headers().contains_key("x-plugin-version"));
+    }
+
+    #[tokio::test]
+    async fn read_only_mode_skips_the_state_changing_routes() {
+        let dir = TempDir::new();
+        let plugin = StaticPlugin::new(
+            r#"[{"path": "/items", "function": "list", "method_router": "get"}, {"path": "/items", "function": "create", "method_router": "post"}, {"path": "/items/:id", "function": "remove", "method_router": "delete"}, {"path": "/items/:id", "function": "show", "method_router": "get,put"}]"#,
+        )
+            .function("list", Arc::new(CannedInvoker::new("list")))
+            .function("create", Arc::new(CannedInvoker::new("create")))
+            .function("remove", Arc::new(CannedInvoker::new("remove")))
+            .function("show", Arc::new(CannedInvoker::new("show")));
+        let plugins = testing::plugins(&dir, true)
+            .read_only(true)
+            .register("shop", plugin);
+
+        let (logs, _guard) = testing::Logs::capture();
+        let router = plugins.load().unwrap();
+
+        let messages = logs.messages();
+        for skipped in [r#"create - method "post""#, r#"remove - method "delete""#] {
+            let message = format!("Skipping route: shop: {} is not allowed in read-only mode", skipped);
+            assert!(messages.contains(&message), "{:?}", messages);
+        }
+        let send = |method: &str, uri: &str| testing::send(
+            &router,
+            Request::builder().method(method).uri(uri).body(Body::empty()).unwrap(),
+        );
+        assert_eq!(testing::body_string(send("GET", "/shop/items").await).await, "list");
+        assert_eq!(testing::body_string(send("GET", "/shop/items/1").await).await, "show");
+        assert_eq!(send("POST", "/shop/items").await.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(send("DELETE", "/shop/items/1").await.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(send("PUT", "/shop/items/1").await.status(), StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    #[tokio::test]
+    async fn fallback_route_handles_any_sub_path_under_its_plugin() {
+        let dir = TempDir::new();
+        let spa = StaticPlugin::new(
+            r#"[{"path": "/", "function": "index", "method_router": "get"}, {"function": "app", "kind": "fallback"}]"#,
+        )
+            .function("index", Arc::new(CannedInvoker::new("index")))
+            .function("app", Arc::new(CannedInvoker::new("app")));
+        let plugins = testing::plugins(&dir, true)
+            .register("spa", spa)
+            .register("other", get_plugin("/page", "page", "page"));
+
+        let router = plugins.load().unwrap();
+
+        let response = testing::get(&router, "/spa/users/42/settings").await;
+        assert_eq!(testing::body_string(response).await, "app");
+        let response = testing::get(&router, "/spa").await;
+        assert_eq!(testing::body_string(response).await, "index");
+        let response = testing::get(&router, "/other/page").await;
+        assert_eq!(testing::body_string(response).await, "page");
+        let response = testing::get(&router, "/other/missing").await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn shutdown_hooks_fire_when_the_plugins_are_dropped() {
+        let dir = TempDir::new();
+        let plugin_dir = dir.plugin("hooks", &testing::config("hooks"), None);
+        let events = plugin_dir.join("events");
+        if !testing::compile_plugin(&plugin_dir, &testing::hooks_plugin("hello", &events)) {
+            return;
+        }
+
+        let plugins = testing::plugins(&dir, true);
+        let _router = plugins.load().unwrap();
+        assert_eq!(testing::events(&events), ["init"]);
+
+        drop(plugins);
+        assert_eq!(testing::events(&events), ["init", "shutdown"]);
+    }
+
+    #[test]
+    fn unloaded_plugins_are_not_shut_down_again_when_dropped() {
+        let dir = TempDir::new();
+        let plugin_dir = dir.plugin("hooks", &testing::config("hooks"), None);
+        let events = plugin_dir.join("events");
+        if !testing::compile_plugin(&plugin_dir, &testing::hooks_plugin("hello", &events)) {
+            return;
+        }
+
+        let plugins = testing::plugins(&dir, true);
+        let _router = plugins.load().unwrap();
+        plugins.unload_all();
+        assert_eq!(testing::events(&events), ["init", "shutdown"]);
+
+        drop(plugins);
+        assert_eq!(testing::events(&events), ["init", "shutdown"]);
+    }
+
+    #[tokio::test]
+    async fn routes_are_prefixed_with_the_plugin_name_when_enabled() {
+        for (name_to_route, served, missing) in [(true, "/blog/posts", "/posts"), (false, "/posts", "/blog/posts")] {
+            let dir = TempDir::new();
+            let plugins = testing::plugins(&dir, name_to_route)
+                .register("blog", get_plugin("/posts", "posts", "posts"));
+
+            let router = plugins.load().unwrap();
+
+            let response = testing::get(&router, served).await;
+            assert_eq!(testing::body_string(response).await, "posts");
+            let response = testing::get(&router, missing).await;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        }
+    }
+
+    #[tokio::test]
+    async fn conflicting_routes_of_a_nested_plugin_are_skipped() {
+        let dir = TempDir::new();
+        let plugin = StaticPlugin::new(
+            r#"[{"path": "/users/:id", "function": "id", "method_router": "get"}, {"path": "/users/:name", "function": "name", "method_router": "get"}]"#,
+        )
+            .function("id", Arc::new(CannedInvoker::new("id")))
+            .function("name", Arc::new(CannedInvoker::new("name")));
+        let plugins = testing::plugins(&dir, true)
+            .register("users", plugin);
+
+        let router = plugins.load().unwrap();
+
+        let response = testing::get(&router, "/users/users/1").await;
+        assert_eq!(testing::body_string(response).await, "id");
+    }
+
+    #[tokio::test]
+    async fn handled_by_header_names_the_handling_plugin_and_route() {
+        let dir = TempDir::new();
+        let plugins = testing::plugins(&dir, true)
+            .handled_by(true)
+            .register("alpha", get_plugin("/one", "one", "one"))
+            .register("beta", get_plugin("/two", "two", "two"));
+
+        let router = plugins.load().unwrap();
+
+        let response = testing::get(&router, "/alpha/one").await;
+        assert_eq!(response.headers()["x-handled-by"], "alpha/one");
+        let response = testing::get(&router, "/beta/two").await;
+        assert_eq!(response.headers()["x-handled-by"], "beta/two");
+    }
+
+    #[tokio::test]
+    async fn handled_by_header_is_disabled_by_default() {
+        let dir = TempDir::new();
+        let plugins = testing::plugins(&dir, true)
+            .register("alpha", get_plugin("/one", "one", "one"));
+
+        let router = plugins.load().unwrap();
+        let response = testing::get(&router, "/alpha/one").await;
+
+        assert!(!response.headers().contains_key("x-handled-by"));
+    }
+
+    #[tokio::test]
+    async fn any_route_passes_the_request_method_to_the_plugin() {
+        let dir = TempDir::new();
+        let plugin = StaticPlugin::new(
+            r#"[{"path": "/hook", "function": "method", "method_router": "any"}]"#,
+        ).function("method", Arc::new(testing::EchoHeader("x-http-method")));
+        let router = testing::plugins(&dir, true)
+            .register("webhook", plugin)
+            .load()
+            .unwrap();
+
+        for method in ["GET", "POST", "PUT", "PATCH", "DELETE"] {
+            let request = Request::builder()
+                .method(method)
+                .uri("/webhook/hook")
+                .body(Body::empty())
+                .unwrap();
+            let response = testing::send(&router, request).await;
+
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(testing::body_string(response).await, method);
+        }
+    }
+
+    #[cfg(all(unix, feature = "signal"))]
+    #[tokio::test]
+    async fn sighup_reloads_the_plugins_into_the_shared_router() {
+        let dir = TempDir::new();
+        let plugins = Arc::new(testing::plugins(&dir, true).allow_unversioned_plugins(true));
+        let router = SharedRouter::new(plugins.load().unwrap());
+        let _task = plugins.reload_on_sighup(router.clone()).unwrap();
+        assert_eq!(testing::get(&router.current(), "/foo-bar/about").await.status(), StatusCode::NOT_FOUND);
+
+        dir.plugin("foo-bar", &testing::config("foo-bar"), Some(testing::FOO_BAR));
+        let status = std::process::Command::new("kill")
+            .args(["-HUP", &std::process::id().to_string()])
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        for _ in 0..100 {
+            let response = testing::get(&router.current(), "/foo-bar/about").await;
+            if response.status() == StatusCode::OK {
+                assert!(testing::body_string(response).await.starts_with("Name: arp-foo-bar"));
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("the plugins were not reloaded after SIGHUP");
+    }
+
+    #[cfg(all(unix, feature = "signal"))]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn sigterm_drains_the_requests_then_calls_the_shutdown_hooks() {
+        let dir = TempDir::new();
+        let plugin_dir = dir.plugin("hooks", &testing::config("hooks"), None);
+        let events = plugin_dir.join("events");
+        if !testing::compile_plugin(&plugin_dir, &testing::hooks_plugin("hello", &events)) {
+            return;
+        }
+        let invoker = testing::SlowInvoker {
+            delay: Duration::from_millis(300),
+            canned: Arc::new(CannedInvoker::new("done")),
+        };
+        let slow = StaticPlugin::new(r#"[{"path": "/", "function": "slow", "method_router": "get"}]"#)
+            .function("slow", Arc::new(invoker));
+        let plugins = Arc::new(testing::plugins(&dir, true).register("slow", slow));
+        let router = plugins.load().unwrap();
+        // Handled from now on, so the signal can't end the test process
+        let _terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()).unwrap();
+
+        let in_flight = tokio::spawn({
+            let router = router.clone();
+            async move { testing::get(&router, "/slow").await }
+        });
+        let shutdown = tokio::spawn(plugins.shutdown_signal(Duration::from_secs(5)));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let status = std::process::Command::new("kill")
+            .args(["-TERM", &std::process::id().to_string()])
+            .status()
+            .unwrap();
+        assert!(status.success());
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let response = testing::get(&router, "/hooks").await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(testing::events(&events), ["init"]);
+
+        tokio::time::timeout(Duration::from_secs(2), shutdown).await.unwrap().unwrap();
+        assert!(in_flight.is_finished());
+        let response = in_flight.await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(testing::body_string(response).await, "done");
+        assert_eq!(testing::events(&events), ["init", "shutdown"]);
+    }
+
+    #[tokio::test]
+    async fn default_headers_of_a_plugin_are_added_to_all_its_responses() {
+        let dir = TempDir::new();
+        let plugin = StaticPlugin::new(
+            r#"[{"path": "/", "function": "index", "method_router": "get"}, {"path": "/framed", "function": "framed", "method_router": "get", "envelope": true}]"#,
+        )
+            .function("index", Arc::new(CannedInvoker::new("index")))
+            .function("framed", Arc::new(CannedInvoker::new(
+                r#"{"headers": {"x-frame-options": "SAMEORIGIN"}, "body": "framed"}"#,
+            )))
+            .header("x-frame-options", "DENY")
+            .header("x-content-type-options", "nosniff");
+        let router = testing::plugins(&dir, true)
+            .register("secure", plugin)
+            .register("other", get_plugin("/", "other", "other"))
+            .load()
+            .unwrap();
+
+        let response = testing::get(&router, "/secure").await;
+        assert_eq!(response.headers()["x-frame-options"], "DENY");
+        assert_eq!(response.headers()["x-content-type-options"], "nosniff");
+        let response = testing::get(&router, "/secure/framed").await;
+        assert_eq!(response.headers()["x-frame-options"], "SAMEORIGIN");
+        assert_eq!(response.headers()["x-content-type-options"], "nosniff");
+        let response = testing::get(&router, "/other").await;
+        assert!(!response.headers().contains_key("x-frame-options"));
+    }
+
+    #[tokio::test]
+    async fn requests_for_another_host_dont_reach_the_plugin() {
+        let dir = TempDir::new();
+        let (plugin, invoker) = testing::route_plugin(r#"{"path": "/", "method_router": "get"}"#, "api");
+        let router = testing::plugins(&dir, true)
+            .register("api", plugin.host("api.example.com"))
+            .load()
+            .unwrap();
+        let request = |host: &str| Request::get("/api")
+            .header(hyper::header::HOST, host)
+            .body(Body::empty())
+            .unwrap();
+
+        let response = testing::send(&router, request("API.example.com:3000")).await;
+        assert_eq!(testing::body_string(response).await, "api");
+        let response = testing::send(&router, request("admin.example.com")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(invoker.calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn routes_with_a_forbidden_response_type_are_rejected() {
+        let dir = TempDir::new();
+        let plugin = StaticPlugin::new(
+            r#"[{"path": "/page", "function": "page", "method_router": "get", "response_type": "html"}, {"path": "/data", "function": "data", "method_router": "get", "response_type": "json"}]"#,
+        )
+            .function("page", Arc::new(CannedInvoker::new("<script>alert(1)</script>")))
+            .function("data", Arc::new(CannedInvoker::new("[]")));
+        let plugins = testing::plugins(&dir, true)
+            .allowed_response_types(["json"])
+            .register("mixed", plugin);
+
+        let (logs, _guard) = testing::Logs::capture();
+        let router = plugins.load().unwrap();
+
+        assert!(logs.messages().iter().any(|message| message
+            == r#"Skipping route: mixed: page - response type "html" is not allowed (allowed: json)"#));
+        assert_eq!(testing::get(&router, "/mixed/page").await.status(), StatusCode::NOT_FOUND);
+        let response = testing::get(&router, "/mixed/data").await;
+        assert_eq!(testing::body_string(response).await, "[]");
+    }
+
+    #[tokio::test]
+    async fn all_routes_of_a_large_plugin_are_mounted() {
+        let dir = TempDir::new();
+        let routes: Vec<String> = (0..1000)
+            .map(|i| format!(r#"{{"path": "/item-{}", "function": "item", "method_router": "get"}}"#, i))
+            .collect();
+        let plugin = StaticPlugin::new(format!("[{}]", routes.join(", ")))
+            .function("item", Arc::new(CannedInvoker::new("item")));
+        let plugins = testing::plugins(&dir, true)
+            .register("large", plugin);
+
+        let router = plugins.load().unwrap();
+
+        assert_eq!(plugins.plugin_routes("large").map(|routes| routes.len()), Some(1000));
+        for i in [0, 499, 999] {
+            let response = testing::get(&router, &format!("/large/item-{}", i)).await;
+            assert_eq!(testing::body_string(response).await, "item");
+        }
+    }
+
+    #[tokio::test]
+    async fn configured_favicon_and_robots_txt_are_served() {
+        let dir = TempDir::new();
+        let icon: &[u8] = b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR";
+        let router = testing::plugins(&dir, true)
+            .favicon(icon)
+            .robots_txt("User-agent: *\nDisallow: /\n")
+            .register("items", get_plugin("/", "index", "items"))
+            .load()
+            .unwrap();
+
+        let response = testing::get(&router, "/favicon.ico").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[hyper::header::CONTENT_TYPE], "image/png");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], icon);
+
+        let response = testing::get(&router, "/robots.txt").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[hyper::header::CONTENT_TYPE], "text/plain; charset=utf-8");
+        assert_eq!(testing::body_string(response).await, "User-agent: *\nDisallow: /\n");
+    }
+
+    #[tokio::test]
+    async fn empty_robots_txt_is_answered_with_204() {
+        let dir = TempDir::new();
+        let router = testing::plugins(&dir, true).robots_txt("").load().unwrap();
+
+        let response = testing::get(&router, "/robots.txt").await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let response = testing::get(&router, "/favicon.ico").await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn root_plugin_serving_robots_txt_takes_precedence() {
+        let dir = TempDir::new();
+        let router = testing::plugins(&dir, false)
+            .robots_txt("User-agent: *")
+            .register("site", get_plugin("/robots.txt", "robots", "from the plugin"))
+            .load()
+            .unwrap();
+
+        let response = testing::get(&router, "/robots.txt").await;
+        assert_eq!(testing::body_string(response).await, "from the plugin");
+    }
+
+    #[tokio::test]
+    async fn panicking_plugin_doesnt_stop_the_other_routes() {
+        let dir = TempDir::new();
+        let plugin = StaticPlugin::new(
+            r#"[{"path": "/", "function": "index", "method_router": "get"}, {"path": "/broken", "function": "broken", "method_router": "get"}]"#,
+        )
+            .function("index", Arc::new(CannedInvoker::new("index")))
+            .function("broken", Arc::new(testing::Panicking));
+        let router = testing::plugins(&dir, true)
+            .register("flaky", plugin)
+            .register("other", get_plugin("/", "other", "other"))
+            .load()
+            .unwrap();
+
+        for _ in 0..3 {
+            let response = testing::get(&router, "/flaky/broken").await;
+            assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+            assert_eq!(testing::body_string(response).await, panic::MESSAGE);
+
+            let response = testing::get(&router, "/flaky").await;
+            assert_eq!(testing::body_string(response).await, "index");
+            let response = testing::get(&router, "/other").await;
+            assert_eq!(testing::body_string(response).await, "other");
+        }
+    }
+
+    #[test]
+    fn dropped_request_skips_its_queued_plugin_call() {
+        // A single blocking thread, so the second call waits for the first one
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .max_blocking_threads(1)
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let dir = TempDir::new();
+            let canned = Arc::new(CannedInvoker::new("done"));
+            let invoker = testing::SlowInvoker {
+                delay: Duration::from_millis(300),
+                canned: canned.clone(),
+            };
+            let plugin = StaticPlugin::new(r#"[{"path": "/", "function": "slow", "method_router": "get"}]"#)
+                .function("slow", Arc::new(invoker));
+            let router = testing::plugins(&dir, true).register("slow", plugin).load().unwrap();
+
+            let first = tokio::spawn({
+                let router = router.clone();
+                async move { testing::get(&router, "/slow").await }
+            });
+            tokio::time::sleep(Duration::from_millis(50)).await;
+
+            // The client disconnecting makes hyper drop the request's future
+            let started = Instant::now();
+            let second = tokio::time::timeout(Duration::from_millis(50), testing::get(&router, "/slow")).await;
+            assert!(second.is_err());
+            assert!(started.elapsed() < Duration::from_millis(200), "{:?}", started.elapsed());
+
+            assert_eq!(testing::body_string(first.await.unwrap()).await, "done");
+            // Long enough for a second call to have ended
+            tokio::time::sleep(Duration::from_millis(400)).await;
+            assert_eq!(canned.calls(), 1);
+        });
+    }
+
+    #[tokio::test]
+    async fn route_alias_redirects_to_the_canonical_path() {
+        let dir = TempDir::new();
+        let (plugin, invoker) = testing::route_plugin(
+            r#"{"path": "/items/:id", "method_router": "get", "aliases": ["/products/:id"]}"#,
+            "item",
+        );
+        let router = testing::plugins(&dir, true)
+            .register("shop", plugin)
+            .load()
+            .unwrap();
+
+        let response = testing::get(&router, "/shop/products/7?color=red").await;
+        assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(response.headers()[hyper::header::LOCATION], "/shop/items/7?color=red");
+        assert_eq!(invoker.calls(), 0);
+
+        let nested = Router::new().nest("/api", router);
+        let response = testing::get(&nested, "/api/shop/products/7").await;
+        assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(response.headers()[hyper::header::LOCATION], "/api/shop/items/7");
+
+        let response = testing::get(&nested, "/api/shop/items/7").await;
+        assert_eq!(testing::body_string(response).await, "item");
+    }
+
+    /// Loads a plugin whose route, streamed or not, calls a function taking 300ms.
+    fn slow_plugins(dir: &TempDir, stream: bool) -> Plugins {
+        let invoker = testing::SlowInvoker {
+            delay: Duration::from_millis(300),
+            canned: Arc::new(CannedInvoker::new("done")),
+        };
+        let plugin = StaticPlugin::new(format!(
+            r#"[{{"path": "/", "function": "slow", "method_router": "get", "stream": {}}}]"#,
+            stream,
+        )).function("slow", Arc::new(invoker));
+        testing::plugins(dir, true).register("slow", plugin)
+    }
+
+    #[tokio::test]
+    async fn slow_streamed_call_keeps_its_request_in_flight() {
+        let dir = TempDir::new();
+        let plugins = slow_plugins(&dir, true);
+        let router = plugins.load().unwrap();
+        let drain = plugins.settings.drain.clone();
+
+        // The handler returns as soon as the call is started
+        let started = Instant::now();
+        let response = testing::get(&router, "/slow").await;
+        assert!(drain.in_flight() > 0);
+        drain.start();
+        drop(response);
+
+        tokio::time::timeout(Duration::from_secs(2), drain.wait()).await.unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(300), "{:?}", started.elapsed());
+    }
+
+    #[tokio::test]
+    async fn call_of_a_disconnected_client_keeps_its_request_in_flight() {
+        let dir = TempDir::new();
+        let plugins = slow_plugins(&dir, false);
+        let router = plugins.load().unwrap();
+        let drain = plugins.settings.drain.clone();
+
+        let started = Instant::now();
+        let dropped = tokio::time::timeout(Duration::from_millis(50), testing::get(&router, "/slow")).await;
+        assert!(dropped.is_err());
+        assert!(drain.in_flight() > 0);
+        drain.start();
+
+        tokio::time::timeout(Duration::from_secs(2), drain.wait()).await.unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(300), "{:?}", started.elapsed());
+    }
+
+    #[tokio::test]
+    async fn host_headers_forged_by_the_client_dont_reach_the_plugin() {
+        let forged = |name: &'static str, value: &'static str| async move {
+            let dir = TempDir::new();
+            let plugin = StaticPlugin::new(
+                r#"[{"path": "/", "function": "echo", "method_router": "get", "query_json": true, "query_params": [{"name": "page", "type": "integer"}]}]"#,
+            ).function("echo", Arc::new(testing::EchoHeader(name)));
+            let router = testing::plugins(&dir, true).register("echo", plugin).load().unwrap();
+            let request = Request::get("/echo").header(name, value).body(Body::empty()).unwrap();
+            testing::body_string(testing::send(&router, request).await).await
+        };
+
+        assert_eq!(forged("x-query-json", r#"{"page": "not a number"}"#).await, "");
+        assert_eq!(forged("x-raw-query", "page=not-a-number").await, "");
+        assert_eq!(forged("x-path-params", r#"{"id": "1"}"#).await, "");
+        assert_eq!(forged("x-response-type", "html").await, "");
+        assert_eq!(forged("x-csrf-token", "token").await, "");
+        assert_eq!(forged("x-csp-nonce", "nonce").await, "");
+        assert_eq!(forged("x-http-method", "DELETE").await, "GET");
+    }
+
+    #[tokio::test]
+    async fn same_paths_of_nested_plugins_dont_conflict() {
+        let dir = TempDir::new();
+        let plugins = testing::plugins(&dir, true)
+            .register("first", get_plugin("/", "index", "first"))
+            .register("second", get_plugin("/", "index", "second"));
+
+        let router = plugins.load().unwrap();
+
+        let response = testing::get(&router, "/first").await;
+        assert_eq!(testing::body_string(response).await, "first");
+        let response = testing::get(&router, "/second").await;
+        assert_eq!(testing::body_string(response).await, "second");
+    }
+}
@@ -0,0 +1,1229 @@
+//! Discovery and loading of the plugin shared libraries.
+//!
+//! Each subdirectory of the plugins directory (set with [`crate::Plugins::plugins_dir`],
+//! `PLUGINS_DIR` or `plugins` by default) holds a `plugin.json` file describing the
+//! plugin and where its shared library lives.
+
+use crate::info::PluginInfo;
+use crate::log::{debug, error, info, warn};
+use crate::store::{AttachedStore, SharedStore};
+use crate::{
+    routes_cache, AbiVersionFn, BufferFn, BytesFn, DuplexFn, FfiBufferInvoker, FfiBytesInvoker,
+    FfiDuplexInvoker, FfiInvoker, FfiStreamInvoker, FreeBufferFn, FreeFn, PanicPolicy, PluginFn, PluginError,
+    PluginInitFn, PluginInvoker, PluginStoreFn, Settings, StreamFn, SymbolResolver, PLUGIN_ABI_VERSION,
+};
+use libloading::{Library, Symbol};
+use semver::{Version, VersionReq};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::ffi::{c_char, CStr, CString};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Defines a plugin, with metadata such as:
+/// - `name`: The plugin name.
+/// - `version`: The plugin version.
+/// - `path`: The file system path to the shared library.
+/// - `enabled`: Indicates whether the plugin is enabled.
+/// - `preload`: Dependency libraries loaded with global symbol visibility before the plugin.
+/// - `headers`: Default headers added to every response of the plugin.
+/// - `host`: The `Host` the plugin is served on, any host if unset.
+/// - `layers`: The names of host-registered `tower` layers applied to the plugin's routes.
+/// - `remote_url`: The base URL of a plugin running as a separate HTTP service, used
+///   instead of a shared library.
+/// - `config`: Plugin-specific settings, passed as JSON to the plugin's `plugin_init`.
+/// - `extra`: Any other key, kept for forward-compatible configs and plugin-specific settings.
+#[derive(Debug, Clone, Deserialize)]
+struct Plugin {
+    name: String,
+    // Informational only
+    #[allow(dead_code)]
+    description: Option<String>,
+    version: String,
+    #[allow(dead_code)]
+    license: Option<String>,
+    #[serde(default)]
+    lib_path: String,
+    enabled: bool,
+    #[serde(default)]
+    preload: Vec<String>,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    host: Option<String>,
+    #[serde(default)]
+    layers: Vec<String>,
+    timeout_ms: Option<u64>,
+    #[serde(default)]
+    panic_policy: PanicPolicy,
+    remote_url: Option<String>,
+    config: Option<Value>,
+    #[serde(flatten)]
+    extra: HashMap<String, Value>,
+}
+
+/// A loaded plugin library together with the dependency libraries preloaded for it.
+///
+/// Fields are dropped in declaration order, so the plugin library is always unloaded
+/// before the libraries it depends on.
+#[derive(Debug)]
+pub(crate) struct LoadedPlugin {
+    pub(crate) name: String,
+    pub(crate) lib: Library,
+    /// The plugin directory holding its `plugin.json`.
+    pub(crate) dir: PathBuf,
+    /// The path the library was loaded from.
+    pub(crate) path: PathBuf,
+    /// The identity of the library file when it was loaded, see [`file_id`].
+    file_id: Option<(u64, u64)>,
+    /// Approximate memory of the plugin: the size of its libraries and configuration.
+    pub(crate) memory: usize,
+    version: String,
+    headers: Vec<(String, String)>,
+    host: Option<String>,
+    layers: Vec<String>,
+    timeout: Option<Duration>,
+    panic_policy: PanicPolicy,
+    /// The checksum of the library file, computed when the routes cache is enabled.
+    checksum: Option<String>,
+    _preloaded: Vec<Library>,
+    /// The store callbacks passed to the plugin, released after its library.
+    _store: Option<AttachedStore>,
+    shut_down: AtomicBool,
+}
+
+impl LoadedPlugin {
+
+    /// Checks whether two loaded plugins share the same library instance, as the dynamic
+    /// loader returns the library already loaded when the same unchanged file is opened.
+    pub(crate) fn same_library(&self, other: &LoadedPlugin) -> bool {
+        self.path == other.path && self.file_id == other.file_id
+    }
+
+    /// Calls the plugin's optional `plugin_shutdown` hook.
+    ///
+    /// The hook runs at most once per loaded plugin, no matter how many times this is called.
+    pub(crate) fn shutdown(&self) {
+
+        if self.shut_down.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        let hook: Result<Symbol<extern "C" fn()>, _> = unsafe {
+            self.lib.get(b"plugin_shutdown\0")
+        };
+        if let Ok(hook) = hook {
+            info!("Plugin shutdown: {}", self.name);
+            hook();
+        }
+    }
+}
+
+/// A plugin running as a separate HTTP service, which the host proxies requests to.
+#[derive(Debug, Clone)]
+#[cfg_attr(not(feature = "remote"), allow(dead_code))]
+pub(crate) struct RemotePlugin {
+    pub(crate) name: String,
+    /// The plugin directory holding its `plugin.json`.
+    pub(crate) dir: PathBuf,
+    pub(crate) url: String,
+    pub(crate) version: String,
+    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) host: Option<String>,
+    pub(crate) layers: Vec<String>,
+}
+
+/// Resolves the symbols of a plugin loaded from a shared library.
+pub(crate) struct LibraryResolver {
+    plugin: Arc<LoadedPlugin>,
+    free: FreeFn,
+    routes: String,
+}
+
+impl LibraryResolver {
+
+    /// Creates a resolver for a loaded plugin, resolving its `free` function and calling
+    /// its `routes` function up front, unless its routes are in the routes cache.
+    ///
+    /// # Arguments
+    /// * `plugin` - The loaded plugin.
+    /// * `routes_cache` - The routes cache directory, if enabled.
+    ///
+    /// # Returns
+    /// The resolver, or an error if a required symbol is missing or `routes` returned a
+    /// null pointer.
+    pub(crate) fn new(
+        plugin: Arc<LoadedPlugin>,
+        routes_cache: Option<&Path>,
+    ) -> Result<Self, PluginError> {
+
+        // Clean up memory allocated by plugin if necessary
+        let free: Symbol<FreeFn> = unsafe { plugin.lib.get(b"free\0")? };
+        let free = *free;
+
+        let cache = routes_cache.zip(plugin.checksum.as_deref());
+        if let Some(routes) = cache.and_then(|(dir, checksum)| routes_cache::get(dir, &plugin.name, checksum)) {
+            return Ok(LibraryResolver { plugin, free, routes });
+        }
+
+        let routes_fn: Symbol<extern "C" fn() -> *const c_char> = unsafe {
+            plugin.lib.get(b"routes\0")?
+        };
+
+        let route_list_ptr = routes_fn();
+
+        if route_list_ptr.is_null() {
+            return Err(PluginError::Routes("received null pointer from routes function".to_string()));
+        }
+
+        // clean this from memory
+        let routes = unsafe {
+            CStr::from_ptr(route_list_ptr).to_string_lossy().into_owned()
+        };
+
+        // Free the memory
+        free(route_list_ptr as *mut c_char);
+
+        if let Some((dir, checksum)) = cache {
+            routes_cache::set(dir, &plugin.name, checksum, &routes);
+        }
+
+        Ok(LibraryResolver { plugin, free, routes })
+    }
+}
+
+impl SymbolResolver for LibraryResolver {
+
+    fn routes(&self) -> String {
+        self.routes.clone()
+    }
+
+    fn function(&self, name: &str) -> Option<Arc<dyn PluginInvoker>> {
+
+        let function: Symbol<PluginFn> = unsafe {
+            match self.plugin.lib.get(name.as_bytes()) {
+                Ok(symbol) => symbol,
+                Err(e) => {
+                    error!("Error getting plugin_route_function: {}", e);
+                    return None;
+                }
+            }
+        };
+
+        // Move the loaded function into the invoker to avoid borrowing `lib`
+        Some(Arc::new(
+            FfiInvoker::new(*function, self.free).keep_alive(self.plugin.clone())
+        ))
+    }
+
+    fn stream_function(&self, name: &str) -> Option<Arc<dyn PluginInvoker>> {
+
+        let function: Symbol<StreamFn> = unsafe {
+            match self.plugin.lib.get(name.as_bytes()) {
+                Ok(symbol) => symbol,
+                Err(e) => {
+                    error!("Error getting plugin_route_function: {}", e);
+                    return None;
+                }
+            }
+        };
+
+        Some(Arc::new(
+            FfiStreamInvoker::new(*function).keep_alive(self.plugin.clone())
+        ))
+    }
+
+    fn duplex_function(&self, name: &str) -> Option<Arc<dyn PluginInvoker>> {
+
+        let function: Symbol<DuplexFn> = unsafe {
+            match self.plugin.lib.get(name.as_bytes()) {
+                Ok(symbol) => symbol,
+                Err(e) => {
+                    error!("Error getting plugin_route_function: {}", e);
+                    return None;
+                }
+            }
+        };
+
+        Some(Arc::new(
+            FfiDuplexInvoker::new(*function).keep_alive(self.plugin.clone())
+        ))
+    }
+
+    fn buffer_function(&self, name: &str) -> Option<Arc<dyn PluginInvoker>> {
+
+        let (function, free): (Symbol<BufferFn>, Symbol<FreeBufferFn>) = unsafe {
+            match (self.plugin.lib.get(name.as_bytes()), self.plugin.lib.get(b"free_buffer\0")) {
+                (Ok(function), Ok(free)) => (function, free),
+                (Err(e), _) | (_, Err(e)) => {
+                    error!("Error getting plugin_route_function: {}", e);
+                    return None;
+                }
+            }
+        };
+
+        Some(Arc::new(
+            FfiBufferInvoker::new(*function, *free).keep_alive(self.plugin.clone())
+        ))
+    }
+
+    fn bytes_function(&self, name: &str) -> Option<Arc<dyn PluginInvoker>> {
+
+        let (function, free): (Symbol<BytesFn>, Symbol<FreeBufferFn>) = unsafe {
+            match (self.plugin.lib.get(name.as_bytes()), self.plugin.lib.get(b"free_buffer\0")) {
+                (Ok(function), Ok(free)) => (function, free),
+                (Err(e), _) | (_, Err(e)) => {
+                    error!("Error getting plugin_route_function: {}", e);
+                    return None;
+                }
+            }
+        };
+
+        Some(Arc::new(
+            FfiBytesInvoker::new(*function, *free).keep_alive(self.plugin.clone())
+        ))
+    }
+
+    fn headers(&self) -> Vec<(String, String)> {
+        self.plugin.headers.clone()
+    }
+
+    fn host(&self) -> Option<String> {
+        self.plugin.host.clone()
+    }
+
+    fn version(&self) -> Option<String> {
+        Some(self.plugin.version.clone())
+    }
+
+    fn layers(&self) -> Vec<String> {
+        self.plugin.layers.clone()
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        self.plugin.timeout
+    }
+
+    fn panic_policy(&self) -> PanicPolicy {
+        self.plugin.panic_policy
+    }
+}
+
+/// Passes the store callbacks to a plugin exporting `plugin_store`.
+///
+/// # Returns
+/// The callbacks, to be kept as long as the library is loaded, or `None` if the plugin
+/// doesn't use the store.
+fn attach_store(lib: &Library, store: &SharedStore, plugin: &str) -> Option<AttachedStore> {
+    let plugin_store: Symbol<PluginStoreFn> = unsafe { lib.get(b"plugin_store\0").ok()? };
+    let attached = AttachedStore::new(store, plugin);
+    plugin_store(attached.api());
+    Some(attached)
+}
+
+/// Returns the device and inode of a file on Unix, which is how the dynamic loader tells
+/// whether a library is already loaded: a file replaced with a new one is loaded again.
+/// Other platforms only check the path, and a loaded library's file can't be replaced.
+fn file_id(path: &Path) -> Option<(u64, u64)> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        std::fs::metadata(path).ok().map(|metadata| (metadata.dev(), metadata.ino()))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        None
+    }
+}
+
+/// Calls the optional `plugin_init` function of a plugin with its configuration, so it
+/// can set up its state before handling requests.
+fn init_plugin(lib: &Library, config: Option<&Value>, plugin: &str) {
+    let plugin_init: Symbol<PluginInitFn> = match unsafe { lib.get(b"plugin_init\0") } {
+        Ok(plugin_init) => plugin_init,
+        Err(_) => return,
+    };
+    let config = config.map_or_else(|| "{}".to_string(), Value::to_string);
+    // A JSON string escapes control characters, so it can't contain a NUL byte
+    let config = CString::new(config).unwrap_or_default();
+    info!("Plugin init: {}", plugin);
+    plugin_init(config.as_ptr());
+}
+
+/// Resolves a library path from `plugin.json`, relative paths being relative to the plugin directory.
+fn resolve_lib_path(
+    plugin_dir: &std::path::Path,
+    lib_path: &str,
+) -> std::path::PathBuf {
+
+    if lib_path.starts_with('/') {
+        std::path::PathBuf::new().join(lib_path)
+    } else {
+        plugin_dir.join(lib_path)
+    }
+}
+
+/// Loads a dependency library with its symbols made globally visible (`RTLD_GLOBAL`),
+/// so that libraries loaded afterwards can resolve them.
+///
+/// On platforms without `dlopen` flags the library is loaded normally.
+fn open_global(path: &std::path::Path) -> Result<Library, libloading::Error> {
+
+    #[cfg(unix)]
+    {
+        use libloading::os::unix::{Library as UnixLibrary, RTLD_GLOBAL, RTLD_NOW};
+        unsafe { UnixLibrary::open(Some(path), RTLD_NOW | RTLD_GLOBAL).map(Library::from) }
+    }
+
+    #[cfg(not(unix))]
+    {
+        unsafe { Library::new(path) }
+    }
+}
+
+/// Returns the ABI version a library was built for, if it exports `plugin_abi_version`.
+pub(crate) fn abi_version(lib: &Library) -> Option<u32> {
+    let abi_version: Symbol<AbiVersionFn> = unsafe { lib.get(b"plugin_abi_version\0").ok()? };
+    Some(abi_version())
+}
+
+/// Checks the version of a plugin against the requirement set with `Plugins::version_req`.
+///
+/// # Arguments
+/// * `settings` - The host-side options holding the requirements.
+/// * `name` - The plugin name.
+/// * `version` - The version declared by the plugin, if any.
+///
+/// # Returns
+/// `Ok(())` if there's no requirement or the version satisfies it, or the reason the
+/// plugin is skipped.
+pub(crate) fn check_version(
+    settings: &Settings,
+    name: &str,
+    version: Option<&str>,
+) -> Result<(), String> {
+
+    let req = match settings.version_reqs.get(name) {
+        Some(req) => VersionReq::parse(req)
+            .map_err(|e| format!("invalid version requirement {:?}: {}", req, e))?,
+        None => return Ok(()),
+    };
+
+    let version = match version.map(str::trim).filter(|version| !version.is_empty()) {
+        Some(version) => Version::parse(version)
+            .map_err(|e| format!("invalid version {:?}, required {}: {}", version, req, e))?,
+        None => return Err(format!("no version declared, required {}", req)),
+    };
+
+    if !req.matches(&version) {
+        return Err(format!("version {} doesn't satisfy the requirement {}", version, req));
+    }
+    Ok(())
+}
+
+/// Returns the plugins directory: the one set with `Plugins::plugins_dir`, else
+/// `PLUGINS_DIR`, else `plugins`.
+pub(crate) fn plugins_dir(settings: &Settings) -> PathBuf {
+    match &settings.plugins_dir {
+        Some(dir) => dir.clone(),
+        None => std::env::var("PLUGINS_DIR")
+            .ok()
+            .filter(|val| !val.is_empty())
+            .unwrap_or("plugins".to_string())
+            .into(),
+    }
+}
+
+/// The libraries opened ahead of the sequential load, with the checksums of the plugin
+/// libraries, by path.
+#[derive(Default)]
+struct Opened {
+    libraries: HashMap<PathBuf, Result<Library, libloading::Error>>,
+    checksums: HashMap<PathBuf, std::io::Result<String>>,
+}
+
+impl Opened {
+
+    /// Returns a library opened ahead, or opens it now.
+    ///
+    /// # Arguments
+    /// * `path` - The library path.
+    /// * `global` - Whether the symbols are made available to the libraries loaded later,
+    ///   as for the preloaded dependencies.
+    fn library(&mut self, path: &Path, global: bool) -> Result<Library, libloading::Error> {
+        match self.libraries.remove(path) {
+            Some(lib) => lib,
+            None if global => open_global(path),
+            None => unsafe { Library::new(path) },
+        }
+    }
+
+    /// Returns the checksum of a library computed ahead, or computes it now.
+    fn checksum(&mut self, path: &Path) -> std::io::Result<String> {
+        match self.checksums.remove(path) {
+            Some(checksum) => checksum,
+            None => routes_cache::checksum(path),
+        }
+    }
+}
+
+/// Returns the memory a plugin is expected to take: the mapped size of a library can't
+/// be measured, its file size is a fair estimate.
+fn plugin_memory(path_dir: &Path, lib_path: &Path, preloads: &[String]) -> usize {
+    [path_dir.join("plugin.json"), lib_path.to_path_buf()].into_iter()
+        .chain(preloads.iter().map(|preload| resolve_lib_path(path_dir, preload)))
+        .filter_map(|path| std::fs::metadata(path).ok())
+        .map(|metadata| metadata.len() as usize)
+        .sum()
+}
+
+/// What a load does with a plugin, as far as its `plugin.json` and files tell.
+enum Admission {
+    /// Proxies its requests to the URL.
+    Remote(String),
+    /// Loads its library, unless it fails to open.
+    Library {
+        lib_path: PathBuf,
+        canonical_path: PathBuf,
+        memory: usize,
+    },
+    /// Reports it as disabled in its `plugin.json`.
+    Disabled,
+    /// Skips it, for the reason.
+    Skipped(String),
+    /// Fails, an enabled plugin in the directory has the same name.
+    Duplicate(PathBuf),
+}
+
+/// The plugins a load keeps so far, deciding on the next ones: the strict mode, unique
+/// names, timeout, version, shared library, `max_plugins` and memory budget checks. The
+/// ABI version is checked once the library is opened.
+struct Admissions<'a> {
+    settings: &'a Settings,
+    max_plugins: Option<usize>,
+    /// Directories of the enabled plugins by name, to reject duplicate names
+    names: HashMap<String, PathBuf>,
+    /// Canonical library paths already loaded, with the plugin that loaded them
+    lib_paths: HashMap<PathBuf, String>,
+    /// The plugins kept, by name, with their memory
+    kept: Vec<(String, usize)>,
+}
+
+impl<'a> Admissions<'a> {
+
+    fn new(settings: &'a Settings, max_plugins: Option<usize>) -> Self {
+        Admissions {
+            settings,
+            max_plugins,
+            names: HashMap::new(),
+            lib_paths: HashMap::new(),
+            kept: Vec::new(),
+        }
+    }
+
+    /// Decides what the load does with the next plugin.
+    ///
+    /// # Arguments
+    /// * `path_dir` - The plugin directory.
+    /// * `plugin_conf` - Its `plugin.json`.
+    fn admit(&mut self, path_dir: &Path, plugin_conf: &Plugin) -> Admission {
+        let settings = self.settings;
+
+        // Strict mode catches typos in the configuration keys
+        if settings.strict_config && !plugin_conf.extra.is_empty() {
+            let mut unknown: Vec<&str> = plugin_conf.extra.keys().map(String::as_str).collect();
+            unknown.sort();
+            return Admission::Skipped(format!("unknown keys in plugin.json: {}", unknown.join(", ")));
+        }
+
+        if !plugin_conf.enabled {
+            return Admission::Disabled;
+        }
+
+        if settings.unique_names {
+            if let Some(first) = self.names.get(&plugin_conf.name) {
+                return Admission::Duplicate(first.clone());
+            }
+            self.names.insert(plugin_conf.name.clone(), path_dir.to_path_buf());
+        }
+
+        if plugin_conf.timeout_ms == Some(0) {
+            return Admission::Skipped("timeout_ms must be greater than 0".to_string());
+        }
+
+        if let Err(reason) = check_version(settings, &plugin_conf.name, Some(&plugin_conf.version)) {
+            return Admission::Skipped(reason);
+        }
+
+        let max_reached = match self.max_plugins {
+            Some(max) if self.kept.len() >= max => Some(format!("maximum number of plugins reached ({})", max)),
+            _ => None,
+        };
+
+        // Remote plugins have no library to load, requests are proxied to them
+        if let Some(url) = &plugin_conf.remote_url {
+            return match max_reached {
+                Some(reason) => Admission::Skipped(reason),
+                None => Admission::Remote(url.clone()),
+            };
+        }
+
+        if plugin_conf.lib_path.is_empty() {
+            return Admission::Skipped("no shared library path specified".to_string());
+        }
+
+        let lib_path = resolve_lib_path(path_dir, &plugin_conf.lib_path);
+        if !lib_path.is_file() {
+            return Admission::Skipped("shared library not found".to_string());
+        }
+
+        // Two entries pointing at the same file would load it twice and register
+        // identical routes, which is not a genuine route conflict
+        let canonical_path = std::fs::canonicalize(&lib_path).unwrap_or(lib_path.clone());
+        if let Some(owner) = self.lib_paths.get(&canonical_path) {
+            return Admission::Skipped(format!(
+                "same shared library already loaded by plugin {}: {}", owner, canonical_path.display()
+            ));
+        }
+
+        if let Some(reason) = max_reached {
+            return Admission::Skipped(reason);
+        }
+
+        let memory = plugin_memory(path_dir, &lib_path, &plugin_conf.preload);
+        if let Some(budget) = settings.memory_budget {
+            let used: usize = self.kept.iter()
+                .filter(|(name, _)| *name != plugin_conf.name)
+                .map(|(_, memory)| memory)
+                .sum();
+            if used + memory > budget {
+                return Admission::Skipped(format!(
+                    "memory budget of {} bytes exceeded ({} bytes in use, {} bytes needed)",
+                    budget, used, memory
+                ));
+            }
+        }
+
+        Admission::Library { lib_path, canonical_path, memory }
+    }
+
+    /// Records a plugin kept by the load, replacing an earlier one with the same name.
+    ///
+    /// # Arguments
+    /// * `name` - The plugin name.
+    /// * `library` - The canonical path of its library and its memory, `None` for a
+    ///   remote plugin.
+    fn keep(&mut self, name: &str, library: Option<(PathBuf, usize)>) {
+        let memory = match library {
+            Some((canonical_path, memory)) => {
+                self.lib_paths.insert(canonical_path, name.to_string());
+                memory
+            },
+            None => 0,
+        };
+        self.kept.retain(|(kept, _)| kept != name);
+        self.kept.push((name.to_string(), memory));
+    }
+}
+
+/// Opens the libraries of the enabled plugins on up to `workers` threads, so the load
+/// then only takes them in order.
+///
+/// Each plugin's dependencies are opened before its library, on the same thread, as in a
+/// sequential load. Only the plugins the load keeps as far as their `plugin.json` and
+/// files tell are opened, decided by the same [`Admissions`] as the load, assuming every
+/// library opens. The ABI version is read from the opened library, so a library built
+/// for another one is opened by both loads. A library the load still needs, e.g.
+/// because an earlier one failed, is opened when it's reached.
+fn open_ahead(settings: &Settings, entries: &[PathBuf], max_plugins: Option<usize>, workers: usize) -> Opened {
+
+    // The preloads and the library of each plugin, in load order
+    let mut jobs: Vec<(Vec<PathBuf>, PathBuf)> = Vec::new();
+    let mut admissions = Admissions::new(settings, max_plugins);
+    for path_dir in entries {
+        let plugin_conf: Plugin = match std::fs::read(path_dir.join("plugin.json"))
+            .ok()
+            .and_then(|json| serde_json::from_slice(&json).ok()) {
+            Some(plugin_conf) => plugin_conf,
+            None => continue,
+        };
+        match admissions.admit(path_dir, &plugin_conf) {
+            Admission::Remote(_) => admissions.keep(&plugin_conf.name, None),
+            Admission::Library { lib_path, canonical_path, memory } => {
+                let preloads = plugin_conf.preload.iter().map(|preload| resolve_lib_path(path_dir, preload)).collect();
+                jobs.push((preloads, lib_path));
+                admissions.keep(&plugin_conf.name, Some((canonical_path, memory)));
+            },
+            // The load fails on the duplicate
+            Admission::Duplicate(_) => break,
+            Admission::Disabled | Admission::Skipped(_) => {},
+        }
+    }
+
+    let next = std::sync::atomic::AtomicUsize::new(0);
+    let opened = std::sync::Mutex::new(Opened::default());
+    std::thread::scope(|scope| {
+        for _ in 0..workers.min(jobs.len()) {
+            scope.spawn(|| {
+                while let Some((preloads, lib_path)) = jobs.get(next.fetch_add(1, Ordering::SeqCst)) {
+                    let mut libraries = Vec::with_capacity(preloads.len() + 1);
+                    let mut preloaded = true;
+                    for preload in preloads {
+                        let lib = open_global(preload);
+                        preloaded = lib.is_ok();
+                        libraries.push((preload.clone(), lib));
+                        if !preloaded {
+                            break;
+                        }
+                    }
+                    let checksum = match (&settings.routes_cache, preloaded) {
+                        (Some(_), true) => Some(routes_cache::checksum(lib_path)),
+                        _ => None,
+                    };
+                    if preloaded {
+                        libraries.push((lib_path.clone(), unsafe { Library::new(lib_path) }));
+                    }
+
+                    let mut opened = match opened.lock() {
+                        Ok(opened) => opened,
+                        Err(e) => e.into_inner(),
+                    };
+                    // A library shared by several plugins is taken by the first one
+                    for (path, lib) in libraries {
+                        opened.libraries.entry(path).or_insert(lib);
+                    }
+                    if let Some(checksum) = checksum {
+                        opened.checksums.insert(lib_path.clone(), checksum);
+                    }
+                }
+            });
+        }
+    });
+
+    match opened.into_inner() {
+        Ok(opened) => opened,
+        Err(e) => e.into_inner(),
+    }
+}
+
+/// The plugins found in the plugins directory.
+pub(crate) struct Libraries {
+    pub(crate) loaded: Vec<Arc<LoadedPlugin>>,
+    pub(crate) remotes: Vec<RemotePlugin>,
+    /// The plugins disabled in their `plugin.json`, only reported by the plugins endpoint.
+    pub(crate) disabled: Vec<PluginInfo>,
+}
+
+/// Loads all the enabled plugin libraries from the plugins directory, up to the number
+/// set by the `PLUGINS_MAX` environment variable.
+///
+/// # Arguments
+/// * `settings` - The host-side options.
+///
+/// # Returns
+/// The plugins found, the loaded ones in load order, or an error if the plugins
+/// directory or the `PLUGINS_MAX` value is invalid. Plugins that fail to load are
+/// skipped with an error.
+pub(crate) fn load_libraries(settings: &Settings) -> Result<Libraries, PluginError> {
+    let max_plugins = max_plugins(std::env::var("PLUGINS_MAX").ok().as_deref())?;
+    load_libraries_up_to(settings, max_plugins)
+}
+
+/// Parses the maximum number of plugins to load, unlimited unless `PLUGINS_MAX` is set.
+fn max_plugins(value: Option<&str>) -> Result<Option<usize>, PluginError> {
+    match value {
+        Some(val) if !val.is_empty() => match val.parse::<usize>() {
+            Ok(max) => Ok(Some(max)),
+            Err(e) => Err(PluginError::Config(
+                format!("invalid PLUGINS_MAX value: {}: {}", val, e)
+            )),
+        },
+        _ => Ok(None),
+    }
+}
+
+/// Loads the enabled plugin libraries from the plugins directory, see [`load_libraries`].
+///
+/// # Arguments
+/// * `settings` - The host-side options.
+/// * `max_plugins` - The maximum number of plugins to load, if any.
+fn load_libraries_up_to(settings: &Settings, max_plugins: Option<usize>) -> Result<Libraries, PluginError> {
+
+    let plugins_dir = plugins_dir(settings).to_string_lossy().into_owned();
+
+    let plugins_path = std::path::Path::new(&plugins_dir);
+    if !plugins_path.is_dir() {
+        return Err(PluginError::PluginsDir {
+            path: plugins_path.to_path_buf(),
+            source: None,
+        });
+    }
+
+    info!("Load plugins from: {}", plugins_dir);
+
+    // A requirement that can't be parsed would skip its plugin on every load
+    for (name, req) in &settings.version_reqs {
+        if let Err(e) = VersionReq::parse(req) {
+            return Err(PluginError::Config(format!(
+                "invalid version requirement for plugin {}: {:?}: {}", name, req, e
+            )));
+        }
+    }
+
+    let mut libraries: Vec<Arc<LoadedPlugin>> = Vec::new();
+    let mut remotes: Vec<RemotePlugin> = Vec::new();
+    let mut disabled: Vec<PluginInfo> = Vec::new();
+
+    let mut admissions = Admissions::new(settings, max_plugins);
+
+    // Sort the entries so that the plugins kept under PLUGINS_MAX are deterministic
+    let mut entries = Vec::new();
+    let dir_entries = std::fs::read_dir(plugins_path).map_err(|e| PluginError::PluginsDir {
+        path: plugins_path.to_path_buf(),
+        source: Some(e),
+    })?;
+    for entry in dir_entries {
+        match entry {
+            Ok(entry) => entries.push(entry.path()),
+            Err(e) => error!("Error reading plugin directory entry: {}", e),
+        }
+    }
+    entries.sort();
+
+    // Opening the libraries is the slow part of a large plugin set
+    let mut opened = match settings.parallel_loads {
+        workers if workers > 1 => open_ahead(settings, &entries, max_plugins, workers),
+        _ => Opened::default(),
+    };
+
+    for path_dir in entries {
+        if path_dir.is_dir() {
+            debug!("DIR: {}", path_dir.display());
+            let plugin_conf_path = path_dir.join("plugin.json");
+            if!plugin_conf_path.is_file() {
+                error!("Error: Missing plugin.json in: {}", path_dir.display());
+                continue;
+            }
+
+            let file = match std::fs::File::open(&plugin_conf_path) {
+                Ok(file) => file,
+                Err(e) => {
+                    error!("Error opening plugin.json: {}: {}", plugin_conf_path.display(), e);
+                    continue;
+                }
+            };
+            let reader = std::io::BufReader::new(file);
+        
+            // Deserialize the JSON data into the struct
+            let plugin_conf: Plugin = match serde_json::from_reader(reader) {
+                Ok(config) => config,
+                Err(e) => {
+                    error!("Error parsing plugin.json: {}", e);
+                    continue;
+                }
+            };
+
+            let (lib_path, canonical_path, memory) = match admissions.admit(&path_dir, &plugin_conf) {
+                Admission::Library { lib_path, canonical_path, memory } => (lib_path, canonical_path, memory),
+                Admission::Remote(url) => {
+                    info!("Remote plugin: {} Version: {} URL: {}", plugin_conf.name, plugin_conf.version, url);
+
+                    let mut headers: Vec<(String, String)> = plugin_conf.headers.into_iter().collect();
+                    headers.sort();
+                    let remote = RemotePlugin {
+                        name: plugin_conf.name,
+                        dir: path_dir.clone(),
+                        url,
+                        version: plugin_conf.version,
+                        headers,
+                        host: plugin_conf.host,
+                        layers: plugin_conf.layers,
+                    };
+                    admissions.keep(&remote.name, None);
+                    libraries.retain(|plugin| {
+                        if plugin.name != remote.name {
+                            return true;
+                        }
+                        // Its init hook already ran
+                        plugin.shutdown();
+                        false
+                    });
+                    match remotes.iter_mut().find(|plugin| plugin.name == remote.name) {
+                        Some(existing) => *existing = remote,
+                        None => remotes.push(remote),
+                    }
+                    continue;
+                },
+                Admission::Disabled => {
+                    warn!(
+                        "Skipping plugin: {}: {} - disabled", 
+                        plugin_conf.name, path_dir.display()
+                    );
+                    disabled.push(PluginInfo {
+                        name: plugin_conf.name,
+                        version: Some(plugin_conf.version),
+                        enabled: false,
+                        path: Some(path_dir.display().to_string()),
+                        routes: Vec::new(),
+                    });
+                    continue;
+                },
+                Admission::Skipped(reason) => {
+                    warn!("Skipping plugin: {}: {} - {}", plugin_conf.name, path_dir.display(), reason);
+                    continue;
+                },
+                Admission::Duplicate(first) => {
+                    for plugin in libraries.iter().rev() {
+                        plugin.shutdown();
+                    }
+                    return Err(PluginError::Config(format!(
+                        "duplicate plugin name: {} declared in {} and {}",
+                        plugin_conf.name, first.display(), path_dir.display()
+                    )));
+                },
+            };
+
+            // Dependency libraries must be loaded first so the plugin can resolve their symbols
+            let mut preloaded = Vec::with_capacity(plugin_conf.preload.len());
+            for preload in &plugin_conf.preload {
+                let preload_path = resolve_lib_path(&path_dir, preload);
+                match opened.library(&preload_path, true) {
+                    Ok(lib) => preloaded.push(lib),
+                    Err(e) => {
+                        warn!(
+                            "Skipping plugin: {}: {} - error preloading library {}: {}",
+                            plugin_conf.name, path_dir.display(), preload_path.display(), e
+                        );
+                        break;
+                    }
+                }
+            }
+            if preloaded.len() != plugin_conf.preload.len() {
+                continue;
+            }
+
+            let checksum = match &settings.routes_cache {
+                Some(_) => match opened.checksum(&lib_path) {
+                    Ok(checksum) => Some(checksum),
+                    Err(e) => {
+                        error!("Error reading library for the routes cache: {}: {}", lib_path.display(), e);
+                        None
+                    },
+                },
+                None => None,
+            };
+
+            let lib = match opened.library(&lib_path, false) {
+                Ok(lib) => lib,
+                Err(e) => {
+                    warn!(
+                        "Skipping plugin: {}: {} - error loading library {}: {}",
+                        plugin_conf.name, path_dir.display(), lib_path.display(), e
+                    );
+                    continue;
+                }
+            };
+    
+            // A library built for another contract would crash on the first call
+            match abi_version(&lib) {
+                Some(PLUGIN_ABI_VERSION) => {},
+                Some(version) => {
+                    warn!(
+                        "Skipping plugin: {}: {} - built for ABI version {}, the host expects {}",
+                        plugin_conf.name, path_dir.display(), version, PLUGIN_ABI_VERSION
+                    );
+                    continue;
+                },
+                None if settings.allow_unversioned => {},
+                None => {
+                    warn!(
+                        "Skipping plugin: {}: {} - no plugin_abi_version function, the host expects ABI version {}",
+                        plugin_conf.name, path_dir.display(), PLUGIN_ABI_VERSION
+                    );
+                    continue;
+                },
+            }
+
+            info!("Plugin loaded: {} Version: {}", plugin_conf.name, plugin_conf.version);
+
+            let store = attach_store(&lib, &settings.store, &plugin_conf.name);
+            init_plugin(&lib, plugin_conf.config.as_ref(), &plugin_conf.name);
+    
+            admissions.keep(&plugin_conf.name, Some((canonical_path.clone(), memory)));
+
+            let mut headers: Vec<(String, String)> = plugin_conf.headers.into_iter().collect();
+            headers.sort();
+
+            let loaded = Arc::new(LoadedPlugin {
+                name: plugin_conf.name,
+                lib,
+                dir: path_dir.clone(),
+                file_id: file_id(&canonical_path),
+                path: canonical_path,
+                memory,
+                version: plugin_conf.version,
+                headers,
+                host: plugin_conf.host,
+                layers: plugin_conf.layers,
+                timeout: plugin_conf.timeout_ms.map(Duration::from_millis),
+                panic_policy: plugin_conf.panic_policy,
+                checksum,
+                _preloaded: preloaded,
+                _store: store,
+                shut_down: AtomicBool::new(false),
+            });
+
+            // A later plugin with the same name replaces the earlier one
+            remotes.retain(|plugin| plugin.name != loaded.name);
+            match libraries.iter_mut().find(|plugin| plugin.name == loaded.name) {
+                Some(existing) => {
+                    // Its init hook already ran
+                    existing.shutdown();
+                    *existing = loaded;
+                },
+                None => libraries.push(loaded),
+            }
+        }
+    }
+
+    Ok(Libraries {
+        loaded: libraries,
+        remotes,
+        disabled,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TempDir};
+
+    /// Returns settings loading the plugins of `dir`.
+    fn settings(dir: &TempDir) -> Settings {
+        Settings {
+            plugins_dir: Some(dir.path().to_path_buf()),
+            ..Settings::default()
+        }
+    }
+
+    #[test]
+    fn plugin_replaced_by_a_later_one_with_the_same_name_is_shut_down() {
+        let dir = TempDir::new();
+        let first = dir.plugin("a", &testing::config("hooks"), None);
+        let second = dir.plugin("b", &testing::config("hooks"), None);
+        let (first_events, second_events) = (first.join("events"), second.join("events"));
+        if !testing::compile_plugin(&first, &testing::hooks_plugin("first", &first_events))
+            || !testing::compile_plugin(&second, &testing::hooks_plugin("second", &second_events)) {
+            return;
+        }
+
+        let libraries = load_libraries(&settings(&dir)).unwrap();
+
+        assert_eq!(libraries.loaded.len(), 1);
+        assert_eq!(libraries.loaded[0].dir, second);
+        assert_eq!(testing::events(&first_events), ["init", "shutdown"]);
+        assert_eq!(testing::events(&second_events), ["init"]);
+
+        libraries.loaded[0].shutdown();
+        assert_eq!(testing::events(&second_events), ["init", "shutdown"]);
+    }
+
+    /// Adds `count` plugins named `plugin-<n>` whose library is padded with `padding`
+    /// bytes, which the dynamic loader ignores.
+    ///
+    /// # Returns
+    /// The `events` files of the plugins, or `None` if no C compiler is available.
+    fn open_events_plugins(dir: &TempDir, count: usize, padding: usize) -> Option<Vec<PathBuf>> {
+        let mut events = Vec::new();
+        for n in 0..count {
+            let name = format!("plugin-{}", n);
+            let plugin_dir = dir.plugin(&name, &testing::config(&name), None);
+            let plugin_events = plugin_dir.join("events");
+            if !testing::compile_plugin(&plugin_dir, &testing::open_events_plugin(&plugin_events)) {
+                return None;
+            }
+            let mut lib = std::fs::OpenOptions::new().append(true).open(plugin_dir.join("lib.so")).unwrap();
+            std::io::Write::write_all(&mut lib, &vec![0; padding]).unwrap();
+            events.push(plugin_events);
+        }
+        Some(events)
+    }
+
+    /// Returns the names of the loaded plugins, in load order.
+    fn names(libraries: &Libraries) -> Vec<String> {
+        libraries.loaded.iter().map(|plugin| plugin.name.clone()).collect()
+    }
+
+    #[test]
+    fn parallel_load_loads_the_same_plugins() {
+        let (sequential_dir, parallel_dir) = (TempDir::new(), TempDir::new());
+        if open_events_plugins(&sequential_dir, 4, 0).is_none()
+            || open_events_plugins(&parallel_dir, 4, 0).is_none() {
+            return;
+        }
+        // The routes cache checksums every library, computed ahead by a parallel load
+        let settings = |dir: &TempDir, parallel_loads| Settings {
+            parallel_loads,
+            routes_cache: Some(dir.path().join("routes-cache")),
+            ..settings(dir)
+        };
+
+        let sequential = load_libraries(&settings(&sequential_dir, 1)).unwrap();
+        let parallel = load_libraries(&settings(&parallel_dir, 4)).unwrap();
+
+        assert_eq!(names(&parallel), names(&sequential));
+        assert_eq!(names(&parallel), ["plugin-0", "plugin-1", "plugin-2", "plugin-3"]);
+        for plugin in sequential.loaded.iter().chain(&parallel.loaded) {
+            assert_eq!(plugin.checksum, routes_cache::checksum(&plugin.path).ok());
+        }
+    }
+
+    #[test]
+    fn parallel_load_doesnt_open_plugins_over_the_memory_budget() {
+        let dir = TempDir::new();
+        let Some(events) = open_events_plugins(&dir, 3, 0) else {
+            return;
+        };
+        let first = dir.path().join("plugin-0");
+        let budget = plugin_memory(&first, &first.join("lib.so"), &[]);
+
+        let libraries = load_libraries(&Settings {
+            parallel_loads: 4,
+            memory_budget: Some(budget),
+            ..settings(&dir)
+        }).unwrap();
+
+        assert_eq!(names(&libraries), ["plugin-0"]);
+        assert_eq!(testing::events(&events[0]), ["open"]);
+        assert!(testing::events(&events[1]).is_empty());
+        assert!(testing::events(&events[2]).is_empty());
+    }
+
+    #[tokio::test]
+    async fn plugin_resolves_a_symbol_from_a_preloaded_library() {
+        let dir = TempDir::new();
+        let plugin_dir = dir.plugin("dep", r#"{"name": "dep", "version": "0.1.0", "lib_path": "lib.so", "enabled": true, "preload": ["libanswer.so"]}"#, None);
+        let dependency = r#"const char *dependency_answer(void) { return "from the dependency"; }"#;
+        let plugin = r#"
+#include <stdint.h>
+
+extern const char *dependency_answer(void);
+
+uint32_t plugin_abi_version(void) { return 1; }
+const char *routes(void) {
+    return "[{\"path\": \"/\", \"function\": \"answer\", \"method_router\": \"get\"}]";
+}
+const char *answer(void *headers, const char *body) {
+    (void)headers; (void)body;
+    return dependency_answer();
+}
+void free(void *ptr) { (void)ptr; }
+"#;
+        if !testing::compile_library(&plugin_dir.join("libanswer.so"), dependency)
+            || !testing::compile_plugin(&plugin_dir, plugin) {
+            return;
+        }
+
+        let router = testing::plugins(&dir, true).load().unwrap();
+        let response = testing::get(&router, "/dep").await;
+
+        assert_eq!(testing::body_string(response).await, "from the dependency");
+    }
+
+    #[test]
+    fn plugins_beyond_the_maximum_are_skipped() {
+        let dir = TempDir::new();
+        let Some(events) = open_events_plugins(&dir, 3, 0) else {
+            return;
+        };
+
+        let libraries = load_libraries_up_to(&settings(&dir), Some(2)).unwrap();
+
+        assert_eq!(names(&libraries), ["plugin-0", "plugin-1"]);
+        assert!(testing::events(&events[2]).is_empty());
+    }
+
+    #[test]
+    fn maximum_number_of_plugins_is_parsed() {
+        assert_eq!(max_plugins(None).unwrap(), None);
+        assert_eq!(max_plugins(Some("")).unwrap(), None);
+        assert_eq!(max_plugins(Some("10")).unwrap(), Some(10));
+        assert!(matches!(
+            max_plugins(Some("ten")),
+            Err(PluginError::Config(message)) if message.starts_with("invalid PLUGINS_MAX value: ten")
+        ));
+    }
+
+    #[test]
+    fn unknown_keys_of_plugin_json_are_kept() {
+        let plugin_conf: Plugin = serde_json::from_str(
+            r#"{"name": "a", "version": "0.1.0", "enabled": true, "owner": "team", "limits": {"max": 3}}"#,
+        ).unwrap();
+
+        assert_eq!(plugin_conf.extra.len(), 2);
+        assert_eq!(plugin_conf.extra["owner"], "team");
+        assert_eq!(plugin_conf.extra["limits"]["max"], 3);
+    }
+
+    #[test]
+    fn strict_mode_skips_plugins_with_unknown_keys() {
+        let dir = TempDir::new();
+        let config = r#"{"name": "typo", "version": "0.1.0", "lib_path": "lib.so", "enabled": true, "enbled": false}"#;
+        let plugin_dir = dir.plugin("typo", config, None);
+        if !testing::compile_plugin(&plugin_dir, &testing::open_events_plugin(&plugin_dir.join("events"))) {
+            return;
+        }
+
+        let permissive = load_libraries_up_to(&settings(&dir), None).unwrap();
+        assert_eq!(names(&permissive), ["typo"]);
+        drop(permissive);
+
+        let strict = load_libraries_up_to(&Settings {
+            strict_config: true,
+            ..settings(&dir)
+        }, None).unwrap();
+        assert!(strict.loaded.is_empty());
+    }
+
+    #[test]
+    fn plugin_sharing_the_library_of_another_is_skipped_with_a_clear_message() {
+        let dir = TempDir::new();
+        let first = dir.plugin("a", &testing::config("a"), None);
+        dir.plugin("b", r#"{"name": "b", "version": "0.1.0", "lib_path": "../a/lib.so", "enabled": true}"#, None);
+        if !testing::compile_plugin(&first, &testing::open_events_plugin(&first.join("events"))) {
+            return;
+        }
+
+        let (logs, _guard) = testing::Logs::capture();
+        let libraries = load_libraries_up_to(&settings(&dir), None).unwrap();
+
+        assert_eq!(names(&libraries), ["a"]);
+        let library = std::fs::canonicalize(first.join("lib.so")).unwrap();
+        let message = format!(
+            "Skipping plugin: b: {} - same shared library already loaded by plugin a: {}",
+            dir.path().join("b").display(), library.display(),
+        );
+        assert!(logs.messages().contains(&message), "{:?}", logs.messages());
+    }
+
+    #[test]
+    fn plugins_exceeding_the_memory_budget_are_skipped() {
+        let dir = TempDir::new();
+        if open_events_plugins(&dir, 2, 0).is_none() {
+            return;
+        }
+        let first = dir.path().join("plugin-0");
+        let memory = plugin_memory(&first, &first.join("lib.so"), &[]);
+
+        let libraries = load_libraries_up_to(&Settings {
+            memory_budget: Some(memory * 2 - 1),
+            ..settings(&dir)
+        }, None).unwrap();
+
+        assert_eq!(names(&libraries), ["plugin-0"]);
+        assert_eq!(libraries.loaded[0].memory, memory);
+    }
+}
@@ -0,0 +1,77 @@
+//! Diagnostics output through `tracing`.
+//!
+//! Events are emitted with `tracing` when a subscriber is installed, so they follow the
+//! host's structured logging and level filters. Without one they're printed as before:
+//! info events to stdout, warnings and errors to stderr, and debug events to stdout only
+//! when the `DEBUG` environment variable is `true`.
+
+use once_cell::sync::Lazy;
+
+/// Whether debug events are printed when no subscriber is installed, based on the
+/// `DEBUG` environment variable.
+pub(crate) static DEBUG: Lazy<bool> = Lazy::new(|| {
+    std::env::var("DEBUG")
+        .map(|val| val == "true")
+        .unwrap_or(false)
+});
+
+/// Checks whether a `tracing` subscriber is installed, globally or for the current thread.
+pub(crate) fn has_subscriber() -> bool {
+    tracing::dispatcher::get_default(|dispatch| !dispatch.is::<tracing::subscriber::NoSubscriber>())
+}
+
+/// Checks whether debug events are output, to skip building costly ones.
+pub(crate) fn debug_enabled() -> bool {
+    if has_subscriber() {
+        tracing::enabled!(tracing::Level::DEBUG)
+    } else {
+        *DEBUG
+    }
+}
+
+/// Emits a debug event, printed to stdout without a subscriber if `DEBUG` is `true`.
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        if $crate::log::has_subscriber() {
+            ::tracing::debug!($($arg)*);
+        } else if *$crate::log::DEBUG {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// Emits an info event, printed to stdout without a subscriber.
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if $crate::log::has_subscriber() {
+            ::tracing::info!($($arg)*);
+        } else {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// Emits a warning event, printed to stderr without a subscriber.
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        if $crate::log::has_subscriber() {
+            ::tracing::warn!($($arg)*);
+        } else {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+/// Emits an error event, printed to stderr without a subscriber.
+macro_rules! error {
+    ($($arg:tt)*) => {
+        if $crate::log::has_subscriber() {
+            ::tracing::error!($($arg)*);
+        } else {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+// `warn` is also a built-in attribute, so the macro is exported under that name by alias
+pub(crate) use {debug, error, info, warning as warn};
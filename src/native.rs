@@ -0,0 +1,297 @@
+//! A safe Rust interface for writing plugins, as an alternative to the raw C ABI.
+//!
+//! A plugin crate built as a `cdylib` implements [`AxumPlugin`] and calls
+//! [`crate::export_plugin!`] with the names of its route functions. The macro generates
+//! the `routes`, `free`, `plugin_abi_version`, `plugin_init` and `plugin_shutdown` symbols
+//! and one `extern "C"` shim per route function, which copy the request into a [`RequestCtx`],
+//! call [`AxumPlugin::handle`] and hand the returned string to the host. The host loads the
+//! library like any other plugin, it doesn't know the plugin was written this way.
+
+use hyper::HeaderMap;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+/// A route declared by an [`AxumPlugin`], serialized into the JSON returned by the
+/// plugin's `routes` function.
+#[derive(Debug, Clone, Serialize)]
+pub struct RouteDef {
+    path: String,
+    function: String,
+    method_router: String,
+    response_type: String,
+    #[serde(flatten)]
+    options: Map<String, Value>,
+}
+
+impl RouteDef {
+
+    /// Creates a `text` route.
+    ///
+    /// # Arguments
+    /// * `method_router` - The HTTP method, e.g. `get`, or a comma-separated list of methods.
+    /// * `path` - The URL path, relative to the plugin's mount.
+    /// * `function` - The route function, one of the names given to `export_plugin!`.
+    ///
+    /// # Returns
+    /// A new `RouteDef` instance.
+    pub fn new(
+        method_router: impl Into<String>,
+        path: impl Into<String>,
+        function: impl Into<String>,
+    ) -> Self {
+        RouteDef {
+            path: path.into(),
+            function: function.into(),
+            method_router: method_router.into(),
+            response_type: "text".to_string(),
+            options: Map::new(),
+        }
+    }
+
+    /// Sets the response format, e.g. `html` or `json`.
+    ///
+    /// # Arguments
+    /// * `response_type` - The response type, as in the routes JSON.
+    ///
+    /// # Returns
+    /// The updated `RouteDef` instance.
+    pub fn response_type(mut self, response_type: impl Into<String>) -> Self {
+        self.response_type = response_type.into();
+        self
+    }
+
+    /// Sets any other option of the routes JSON, e.g. `envelope` or `timeout_ms`.
+    ///
+    /// # Arguments
+    /// * `name` - The option name.
+    /// * `value` - The option value.
+    ///
+    /// # Returns
+    /// The updated `RouteDef` instance.
+    pub fn option(mut self, name: impl Into<String>, value: impl Into<Value>) -> Self {
+        self.options.insert(name.into(), value.into());
+        self
+    }
+}
+
+/// The request passed to [`AxumPlugin::handle`].
+#[derive(Debug, Clone)]
+pub struct RequestCtx {
+    function: &'static str,
+    headers: HeaderMap,
+    body: String,
+}
+
+impl RequestCtx {
+
+    /// Returns the name of the route function called, telling the routes apart.
+    pub fn function(&self) -> &str {
+        self.function
+    }
+
+    /// Returns the request headers, including the ones added by the host such as
+    /// `x-path-params`.
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    /// Returns the value of a request header, if it's present and valid UTF-8.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|value| value.to_str().ok())
+    }
+
+    /// Returns the request body.
+    pub fn body(&self) -> &str {
+        &self.body
+    }
+}
+
+/// A plugin written in safe Rust, exported with [`crate::export_plugin!`].
+///
+/// # Example
+/// ```no_run
+/// use axum_router_plugin::{export_plugin, AxumPlugin, RequestCtx, RouteDef};
+///
+/// struct Hello;
+///
+/// impl AxumPlugin for Hello {
+///     fn routes(&self) -> Vec<RouteDef> {
+///         vec![RouteDef::new("get", "/hello", "hello")]
+///     }
+///
+///     fn handle(&self, ctx: RequestCtx) -> String {
+///         format!("Hello {}!", ctx.header("x-user").unwrap_or("world"))
+///     }
+/// }
+///
+/// export_plugin!(Hello, [hello]);
+/// # fn main() {}
+/// ```
+pub trait AxumPlugin: Send + Sync + 'static {
+
+    /// Returns the routes of the plugin.
+    fn routes(&self) -> Vec<RouteDef>;
+
+    /// Handles a request to one of the routes, returning what a route function returns:
+    /// the body, or the JSON envelope for routes declaring `envelope`. A panic is caught
+    /// and the request is answered with a `500`.
+    fn handle(&self, ctx: RequestCtx) -> String;
+
+    /// Sets up the plugin with the `config` of its `plugin.json`, called by the host's
+    /// `plugin_init` before any request.
+    fn init(&self, _config: &Value) {}
+
+    /// Releases the resources of the plugin, called by the host's `plugin_shutdown`.
+    fn shutdown(&self) {}
+}
+
+/// Exports an [`AxumPlugin`] from a `cdylib` plugin crate, generating the symbols the host
+/// loads: `routes`, `free`, `plugin_abi_version`, `plugin_init`, `plugin_shutdown` and one
+/// route function per name listed.
+///
+/// The plugin value is created on the first call. Every function named in the routes
+/// must be listed, as the host resolves each of them by name.
+///
+/// # Example
+/// ```ignore
+/// export_plugin!(MyPlugin::new(), [list_users, get_user]);
+/// ```
+#[macro_export]
+macro_rules! export_plugin {
+    ($plugin:expr, [$($function:ident),* $(,)?]) => {
+        fn __axum_plugin() -> &'static dyn $crate::AxumPlugin {
+            static PLUGIN: ::std::sync::OnceLock<::std::boxed::Box<dyn $crate::AxumPlugin>> =
+                ::std::sync::OnceLock::new();
+            PLUGIN.get_or_init(|| ::std::boxed::Box::new($plugin)).as_ref()
+        }
+
+        #[no_mangle]
+        pub extern "C" fn routes() -> *const ::std::ffi::c_char {
+            $crate::__native::routes(__axum_plugin())
+        }
+
+        #[no_mangle]
+        pub extern "C" fn free(ptr: *mut ::std::ffi::c_char) {
+            unsafe { $crate::__native::free(ptr) }
+        }
+
+        #[no_mangle]
+        pub extern "C" fn plugin_abi_version() -> u32 {
+            $crate::PLUGIN_ABI_VERSION
+        }
+
+        #[no_mangle]
+        pub extern "C" fn plugin_init(config: *const ::std::ffi::c_char) {
+            unsafe { $crate::__native::init(__axum_plugin(), config) }
+        }
+
+        #[no_mangle]
+        pub extern "C" fn plugin_shutdown() {
+            $crate::__native::shutdown(__axum_plugin())
+        }
+
+        $(
+            #[no_mangle]
+            pub extern "C" fn $function(
+                headers: *mut $crate::__native::HeaderMap,
+                body: *const ::std::ffi::c_char,
+            ) -> *const ::std::ffi::c_char {
+                unsafe { $crate::__native::call(__axum_plugin(), stringify!($function), headers, body) }
+            }
+        )*
+    };
+}
+
+/// The functions called by the code generated by [`crate::export_plugin!`].
+#[doc(hidden)]
+pub mod support {
+
+    use super::{AxumPlugin, RequestCtx};
+    use std::ffi::{c_char, CStr, CString};
+    use std::panic::{self, AssertUnwindSafe};
+
+    pub use hyper::HeaderMap;
+
+    /// Converts a string to a C string owned by the caller, or a null pointer if it
+    /// contains a NUL byte.
+    fn into_raw(output: String) -> *const c_char {
+        match CString::new(output) {
+            Ok(output) => output.into_raw(),
+            Err(_) => std::ptr::null(),
+        }
+    }
+
+    /// Returns the routes JSON of the plugin.
+    pub fn routes(plugin: &dyn AxumPlugin) -> *const c_char {
+        let routes = panic::catch_unwind(AssertUnwindSafe(|| plugin.routes()));
+        match routes.ok().and_then(|routes| serde_json::to_string(&routes).ok()) {
+            Some(routes) => into_raw(routes),
+            None => std::ptr::null(),
+        }
+    }
+
+    /// Releases a string returned to the host.
+    ///
+    /// # Safety
+    /// `ptr` must be null or a string returned by `routes` or `call`, not freed yet.
+    pub unsafe fn free(ptr: *mut c_char) {
+        if !ptr.is_null() {
+            unsafe { drop(CString::from_raw(ptr)) };
+        }
+    }
+
+    /// Calls the plugin's init hook with its configuration, an invalid one being passed
+    /// as `null`.
+    ///
+    /// # Safety
+    /// `config` must be null or a NUL-terminated string valid for the duration of the call.
+    pub unsafe fn init(plugin: &dyn AxumPlugin, config: *const c_char) {
+        let config = if config.is_null() {
+            serde_json::Value::Null
+        } else {
+            let config = unsafe { CStr::from_ptr(config) }.to_string_lossy();
+            serde_json::from_str(&config).unwrap_or_default()
+        };
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| plugin.init(&config)));
+    }
+
+    /// Calls the plugin's shutdown hook.
+    pub fn shutdown(plugin: &dyn AxumPlugin) {
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| plugin.shutdown()));
+    }
+
+    /// Calls the plugin for a route function. A panic can't unwind into the host, so it
+    /// returns a null pointer, which the host answers with a `500`.
+    ///
+    /// # Safety
+    /// `headers` and `body` must be null or valid for the duration of the call, `body`
+    /// being NUL-terminated, as passed by the host.
+    pub unsafe fn call(
+        plugin: &dyn AxumPlugin,
+        function: &'static str,
+        headers: *mut HeaderMap,
+        body: *const c_char,
+    ) -> *const c_char {
+
+        // The headers are only lent for the call, the plugin gets its own copy
+        let headers = match unsafe { headers.as_ref() } {
+            Some(headers) => headers.clone(),
+            None => HeaderMap::new(),
+        };
+        let body = if body.is_null() {
+            String::new()
+        } else {
+            unsafe { CStr::from_ptr(body) }.to_string_lossy().into_owned()
+        };
+
+        let ctx = RequestCtx {
+            function,
+            headers,
+            body,
+        };
+        match panic::catch_unwind(AssertUnwindSafe(|| plugin.handle(ctx))) {
+            Ok(output) => into_raw(output),
+            Err(_) => std::ptr::null(),
+        }
+    }
+}
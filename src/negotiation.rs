@@ -0,0 +1,154 @@
+//! Content negotiation for the routes declaring `"response_type": "auto"`.
+//!
+//! Such a route can render its body in several formats, `text`, `html` or `json`, listed
+//! in preference order in its `formats`. The format of each response is picked from the
+//! request's `Accept` header: the one with the highest quality, the route's preference
+//! breaking ties. Requests without an acceptable format, or without the header, get the
+//! first declared one.
+
+use hyper::header::HeaderValue;
+
+/// The formats an `auto` route can render.
+pub(crate) const FORMATS: [&str; 3] = ["text", "html", "json"];
+
+/// The formats of an `auto` route that doesn't declare them.
+pub(crate) const DEFAULT_FORMATS: [&str; 3] = ["json", "html", "text"];
+
+/// The header forwarded to the plugin with the negotiated format.
+pub(crate) const RESPONSE_TYPE_HEADER: &str = "x-response-type";
+
+/// Returns the media type of a format.
+fn media_type(format: &str) -> (&'static str, &'static str) {
+    match format {
+        "html" => ("text", "html"),
+        "json" => ("application", "json"),
+        _ => ("text", "plain"),
+    }
+}
+
+/// Returns the quality of a format for an `Accept` header, from its most specific
+/// matching media range, or `None` if no range matches. A `q` that isn't a number
+/// between 0 and 1 counts as missing, i.e. 1.
+fn quality(accept: &str, format: &str) -> Option<f32> {
+    let (kind, subtype) = media_type(format);
+
+    let mut best: Option<(u8, f32)> = None;
+    for range in accept.split(',') {
+        let mut params = range.split(';');
+        let media_range = params.next().unwrap_or_default().trim().to_ascii_lowercase();
+        let (range_kind, range_subtype) = match media_range.split_once('/') {
+            Some(media_range) => media_range,
+            None => continue,
+        };
+
+        let specificity = match (range_kind, range_subtype) {
+            (k, s) if k == kind && s == subtype => 2,
+            (k, "*") if k == kind => 1,
+            ("*", "*") => 0,
+            _ => continue,
+        };
+        let q = params
+            .filter_map(|param| param.trim().split_once('='))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+            .and_then(|(_, value)| value.trim().parse::<f32>().ok())
+            .filter(|q| (0.0..=1.0).contains(q))
+            .unwrap_or(1.0);
+
+        if best.is_none_or(|(best, _)| specificity > best) {
+            best = Some((specificity, q));
+        }
+    }
+    best.map(|(_, q)| q)
+}
+
+/// Picks the format of a response.
+///
+/// # Arguments
+/// * `formats` - The formats of the route, in preference order, not empty.
+/// * `accept` - The request's `Accept` header, if any.
+///
+/// # Returns
+/// The negotiated format, the first one if none is acceptable.
+pub(crate) fn negotiate<'a>(formats: &'a [String], accept: Option<&HeaderValue>) -> &'a str {
+    let first = formats.first().map(String::as_str).unwrap_or("text");
+    let Some(accept) = accept.and_then(|accept| accept.to_str().ok()) else {
+        return first;
+    };
+
+    let mut best: Option<(&str, f32)> = None;
+    for format in formats {
+        match quality(accept, format) {
+            Some(q) if q > 0.0 && best.is_none_or(|(_, best)| q > best) => best = Some((format, q)),
+            _ => {},
+        }
+    }
+    best.map(|(format, _)| format).unwrap_or(first)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Negotiates a format of `formats` for an `Accept` header.
+    fn pick<'a>(formats: &'a [String], accept: &str) -> &'a str {
+        negotiate(formats, Some(&HeaderValue::from_str(accept).unwrap()))
+    }
+
+    fn formats(formats: &[&str]) -> Vec<String> {
+        formats.iter().map(|format| format.to_string()).collect()
+    }
+
+    #[test]
+    fn highest_quality_wins() {
+        let formats = formats(&["json", "html", "text"]);
+        assert_eq!(pick(&formats, "text/html"), "html");
+        assert_eq!(pick(&formats, "application/json;q=0.5, text/plain;q=0.8"), "text");
+        assert_eq!(pick(&formats, "TEXT/HTML; Q=0.9, application/json; q=0.1"), "html");
+    }
+
+    #[test]
+    fn zero_quality_excludes_a_format() {
+        let formats = formats(&["json", "html"]);
+        assert_eq!(pick(&formats, "application/json;q=0, */*"), "html");
+        assert_eq!(pick(&formats, "application/json;q=0.0, text/html;q=0.1"), "html");
+        assert_eq!(quality("text/html;q=0", "html"), Some(0.0));
+    }
+
+    #[test]
+    fn wildcards_match_with_less_priority_than_exact_types() {
+        assert_eq!(quality("*/*", "json"), Some(1.0));
+        assert_eq!(quality("text/*;q=0.4", "html"), Some(0.4));
+        assert_eq!(quality("text/*;q=0.4", "json"), None);
+        assert_eq!(quality("text/html;q=0.2, text/*;q=0.6, */*;q=0.9", "html"), Some(0.2));
+        assert_eq!(quality("*/*;q=0.9, text/*;q=0.6", "text"), Some(0.6));
+
+        let formats = formats(&["json", "html"]);
+        assert_eq!(pick(&formats, "text/*, */*;q=0.1"), "html");
+    }
+
+    #[test]
+    fn ties_go_to_the_route_preference() {
+        assert_eq!(pick(&formats(&["json", "html"]), "text/html, application/json"), "json");
+        assert_eq!(pick(&formats(&["html", "json"]), "application/json, text/html"), "html");
+        assert_eq!(pick(&formats(&["text", "html"]), "*/*"), "text");
+    }
+
+    #[test]
+    fn malformed_quality_counts_as_one() {
+        for q in ["abc", "", "2", "-1", "NaN", "inf"] {
+            assert_eq!(quality(&format!("text/html;q={}", q), "html"), Some(1.0), "{:?}", q);
+        }
+        assert_eq!(quality("text/html;level=1;q=0.3", "html"), Some(0.3));
+        assert_eq!(pick(&formats(&["json", "html"]), "application/json;q=0.5, text/html;q=high"), "html");
+    }
+
+    #[test]
+    fn first_format_is_the_fallback() {
+        let formats = formats(&["html", "json"]);
+        assert_eq!(negotiate(&formats, None), "html");
+        assert_eq!(pick(&formats, "image/png"), "html");
+        assert_eq!(pick(&formats, "text/html;q=0, application/json;q=0"), "html");
+        assert_eq!(pick(&formats, "garbage, ;;, /"), "html");
+        assert_eq!(negotiate(&[], None), "text");
+    }
+}
@@ -0,0 +1,214 @@
+//! OpenTelemetry spans around plugin requests.
+//!
+//! Every plugin request runs inside a `plugin_request` span carrying the plugin name, the
+//! route, the method and the response status. The span's parent is extracted from the
+//! incoming `traceparent` header through the global text map propagator, so plugin
+//! traffic joins the caller's distributed trace.
+
+use axum::extract::Request;
+use hyper::{HeaderMap, StatusCode};
+use opentelemetry::propagation::Extractor;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Reads the propagation headers (`traceparent`, `tracestate`, ...) of a request.
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|name| name.as_str()).collect()
+    }
+}
+
+/// Creates the span of a plugin request, child of the request's remote trace context.
+///
+/// # Arguments
+/// * `plugin` - The plugin name.
+/// * `route` - The route path as declared by the plugin.
+/// * `request` - The incoming request.
+pub(crate) fn request_span(plugin: &str, route: &str, request: &Request) -> Span {
+
+    let span = tracing::info_span!(
+        "plugin_request",
+        otel.name = %format!("{} {}", request.method(), route),
+        otel.kind = "server",
+        plugin.name = %plugin,
+        http.route = %route,
+        http.request.method = %request.method(),
+        http.response.status_code = tracing::field::Empty,
+    );
+
+    let parent = opentelemetry::global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(request.headers()))
+    });
+    let _ = span.set_parent(parent);
+
+    span
+}
+
+/// Records the response status on the span of a plugin request.
+pub(crate) fn record_status(span: &Span, status: StatusCode) {
+    span.record("http.response.status_code", status.as_u16());
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{self, TempDir};
+    use axum::{body::Body, extract::Request};
+    use opentelemetry::{
+        propagation::{Extractor, Injector, TextMapPropagator},
+        trace::{self as otel, SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState},
+        Context, KeyValue,
+    };
+    use std::borrow::Cow;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use std::time::SystemTime;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+
+    /// A span as ended: its name, attributes and the trace id of its parent.
+    #[derive(Debug, Default)]
+    struct Ended {
+        name: String,
+        attributes: HashMap<String, String>,
+        parent: Option<TraceId>,
+    }
+
+    /// A tracer keeping the ended spans.
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<Ended>>>);
+
+    struct RecordedSpan {
+        ended: Option<Ended>,
+        context: SpanContext,
+        recorder: Recorder,
+    }
+
+    impl otel::Tracer for Recorder {
+        type Span = RecordedSpan;
+
+        fn build_with_context(&self, builder: otel::SpanBuilder, parent_cx: &Context) -> RecordedSpan {
+            let parent = parent_cx.span().span_context().clone();
+            let mut ended = Ended {
+                name: builder.name.to_string(),
+                parent: parent.is_valid().then(|| parent.trace_id()),
+                ..Ended::default()
+            };
+            for attribute in builder.attributes.unwrap_or_default() {
+                ended.attributes.insert(attribute.key.to_string(), attribute.value.to_string());
+            }
+            RecordedSpan { ended: Some(ended), context: parent, recorder: self.clone() }
+        }
+    }
+
+    impl otel::Span for RecordedSpan {
+        fn add_event_with_timestamp<T>(&mut self, _name: T, _timestamp: SystemTime, _attributes: Vec<KeyValue>)
+        where
+            T: Into<Cow<'static, str>>,
+        {}
+
+        fn span_context(&self) -> &SpanContext {
+            &self.context
+        }
+
+        fn is_recording(&self) -> bool {
+            self.ended.is_some()
+        }
+
+        fn set_attribute(&mut self, attribute: KeyValue) {
+            if let Some(ended) = &mut self.ended {
+                ended.attributes.insert(attribute.key.to_string(), attribute.value.to_string());
+            }
+        }
+
+        fn set_status(&mut self, _status: otel::Status) {}
+
+        fn update_name<T>(&mut self, new_name: T)
+        where
+            T: Into<Cow<'static, str>>,
+        {
+            if let Some(ended) = &mut self.ended {
+                ended.name = new_name.into().into_owned();
+            }
+        }
+
+        fn add_link(&mut self, _span_context: SpanContext, _attributes: Vec<KeyValue>) {}
+
+        fn end_with_timestamp(&mut self, _timestamp: SystemTime) {
+            if let Some(ended) = self.ended.take() {
+                self.recorder.0.lock().unwrap().push(ended);
+            }
+        }
+    }
+
+    impl Drop for RecordedSpan {
+        fn drop(&mut self) {
+            otel::Span::end(self);
+        }
+    }
+
+    /// Extracts the remote context of a W3C `traceparent` header.
+    #[derive(Debug)]
+    struct TraceParent;
+
+    impl TextMapPropagator for TraceParent {
+        fn inject_context(&self, _cx: &Context, _injector: &mut dyn Injector) {}
+
+        fn extract_with_context(&self, cx: &Context, extractor: &dyn Extractor) -> Context {
+            let parts: Vec<&str> = extractor.get("traceparent").unwrap_or_default().split('-').collect();
+            match parts[..] {
+                [_, trace_id, span_id, _] => cx.with_remote_span_context(SpanContext::new(
+                    TraceId::from_hex(trace_id).unwrap(),
+                    SpanId::from_hex(span_id).unwrap(),
+                    TraceFlags::SAMPLED,
+                    true,
+                    TraceState::default(),
+                )),
+                _ => cx.clone(),
+            }
+        }
+
+        fn fields(&self) -> opentelemetry::propagation::text_map_propagator::FieldIter<'_> {
+            opentelemetry::propagation::text_map_propagator::FieldIter::new(&[])
+        }
+    }
+
+    #[tokio::test]
+    async fn plugin_request_span_has_the_route_attributes_and_the_remote_parent() {
+        opentelemetry::global::set_text_map_propagator(TraceParent);
+        let recorder = Recorder::default();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(recorder.clone()));
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let dir = TempDir::new();
+        let (plugin, _) = testing::route_plugin(r#"{"path": "/items/:id", "method_router": "get"}"#, "item");
+        let router = testing::plugins(&dir, true)
+            .register("shop", plugin)
+            .load()
+            .unwrap();
+
+        let request = Request::get("/shop/items/7")
+            .header("traceparent", format!("00-{}-00f067aa0ba902b7-01", TRACE_ID))
+            .body(Body::empty())
+            .unwrap();
+        let response = testing::send(&router, request).await;
+        assert_eq!(testing::body_string(response).await, "item");
+
+        let spans = recorder.0.lock().unwrap();
+        let span = spans.iter().find(|span| span.attributes.contains_key("plugin.name")).unwrap();
+        assert_eq!(span.name, "GET /items/:id");
+        assert_eq!(span.attributes["plugin.name"], "shop");
+        assert_eq!(span.attributes["http.route"], "/items/:id");
+        assert_eq!(span.attributes["http.request.method"], "GET");
+        assert_eq!(span.attributes["http.response.status_code"], "200");
+        assert_eq!(span.parent, Some(TraceId::from_hex(TRACE_ID).unwrap()));
+    }
+}
@@ -0,0 +1,117 @@
+//! `Link` headers (RFC 8288) for paginated plugin responses.
+//!
+//! An enveloped route returns the cursors of the adjacent pages in the `pagination`
+//! field of its envelope, and the host turns each one into a link to the current request
+//! URL with the cursor in its query:
+//!
+//! ```json
+//! {
+//!   "pagination": { "next": "abc", "prev": "xyz" },
+//!   "body": [1, 2, 3]
+//! }
+//! ```
+//!
+//! A request to `/items?limit=3` then gets
+//! `Link: </items?limit=3&cursor=abc>; rel="next"` and
+//! `Link: </items?limit=3&cursor=xyz>; rel="prev"`.
+
+use crate::log::warn;
+use hyper::{header::{self, HeaderMap, HeaderValue}, Uri};
+use serde::Deserialize;
+
+/// The pagination cursors returned in a response envelope.
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct Pagination {
+    /// The cursor of the next page, if any.
+    #[serde(default)]
+    next: Option<String>,
+    /// The cursor of the previous page, if any.
+    #[serde(default)]
+    prev: Option<String>,
+    /// The query parameter carrying the cursor, `cursor` by default.
+    #[serde(default = "default_param")]
+    param: String,
+}
+
+/// The query parameter of pages that don't declare one.
+fn default_param() -> String {
+    "cursor".to_string()
+}
+
+impl Pagination {
+
+    /// Adds a `Link` header for each cursor to the response headers.
+    ///
+    /// # Arguments
+    /// * `headers` - The outgoing response headers.
+    /// * `uri` - The original request URI, including the plugin's mount prefix.
+    pub(crate) fn apply(&self, headers: &mut HeaderMap, uri: &Uri) {
+        for (rel, cursor) in [("next", &self.next), ("prev", &self.prev)] {
+            if let Some(cursor) = cursor {
+                match HeaderValue::from_str(&self.link(uri, cursor, rel)) {
+                    Ok(value) => { headers.append(header::LINK, value); },
+                    Err(_) => warn!("Skipping invalid pagination cursor from plugin: {}", cursor),
+                }
+            }
+        }
+    }
+
+    /// Builds a link to the current URL with the cursor set in the query, keeping the
+    /// other query parameters.
+    fn link(&self, uri: &Uri, cursor: &str, rel: &str) -> String {
+        let mut query = form_urlencoded::Serializer::new(String::new());
+        for (key, value) in form_urlencoded::parse(uri.query().unwrap_or_default().as_bytes()) {
+            if key != self.param.as_str() {
+                query.append_pair(&key, &value);
+            }
+        }
+        query.append_pair(&self.param, cursor);
+
+        format!("<{}?{}>; rel=\"{}\"", uri.path(), query.finish(), rel)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{self, TempDir};
+    use hyper::header;
+
+    #[tokio::test]
+    async fn cursors_become_links_to_the_request_url() {
+        let dir = TempDir::new();
+        let (plugin, _) = testing::route_plugin(
+            r#"{"path": "/items", "method_router": "get", "envelope": true}"#,
+            r#"{"pagination": {"next": "a b&c", "prev": "xyz"}, "body": "page"}"#,
+        );
+        let router = testing::plugins(&dir, true)
+            .register("shop", plugin)
+            .load()
+            .unwrap();
+
+        let response = testing::get(&router, "/shop/items?limit=3&cursor=old").await;
+
+        let links: Vec<_> = response.headers().get_all(header::LINK).iter().collect();
+        assert_eq!(links, [
+            r#"</shop/items?limit=3&cursor=a+b%26c>; rel="next""#,
+            r#"</shop/items?limit=3&cursor=xyz>; rel="prev""#,
+        ]);
+        assert_eq!(testing::body_string(response).await, "page");
+    }
+
+    #[tokio::test]
+    async fn cursor_parameter_can_be_renamed() {
+        let dir = TempDir::new();
+        let (plugin, _) = testing::route_plugin(
+            r#"{"path": "/", "method_router": "get", "envelope": true}"#,
+            r#"{"pagination": {"next": "2", "param": "page"}, "body": ""}"#,
+        );
+        let router = testing::plugins(&dir, true)
+            .register("list", plugin)
+            .load()
+            .unwrap();
+
+        let response = testing::get(&router, "/list").await;
+
+        assert_eq!(response.headers()[header::LINK], r#"</list?page=2>; rel="next""#);
+    }
+}
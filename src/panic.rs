@@ -0,0 +1,126 @@
+//! Isolation of panics raised while calling plugin functions.
+//!
+//! A plugin call runs in a blocking task, and a panic there, e.g. the host rejecting a
+//! null pointer returned by the plugin or a request body containing a NUL byte, would
+//! otherwise surface as a failed join in the request task. The call is run under
+//! `catch_unwind` instead, so the route answers with a `500` and the plugin keeps
+//! serving the next requests. A panic unwinding out of a plugin's own `extern "C"`
+//! function can't be caught: Rust aborts the process at that boundary, so plugins must
+//! catch their own panics.
+//!
+//! The [`PanicPolicy`] of a plugin can instead abort the process on a caught panic, for
+//! plugins whose failure leaves the server in a state not worth serving from.
+
+use crate::log::error;
+use serde::Deserialize;
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+
+/// What happens when a call to one of the plugin's functions panics, set with
+/// `"panic_policy"` in `plugin.json`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PanicPolicy {
+    /// The request is answered with a `500` and the plugin keeps serving.
+    #[default]
+    Catch,
+    /// The panic is logged and the process is aborted.
+    Abort,
+}
+
+/// The body of the response sent when a plugin call panics.
+pub(crate) const MESSAGE: &str = "Plugin function panicked";
+
+/// Runs a plugin call, catching a panic.
+///
+/// # Arguments
+/// * `policy` - The panic policy of the plugin, `Abort` aborting the process on a panic.
+/// * `call` - The plugin call.
+///
+/// # Returns
+/// The result of the call, or the panic message.
+pub(crate) fn catch<T>(policy: PanicPolicy, call: impl FnOnce() -> T) -> Result<T, String> {
+    // The invokers hold no state a panic could leave half-updated
+    let result = panic::catch_unwind(AssertUnwindSafe(call)).map_err(message);
+    if let (PanicPolicy::Abort, Err(e)) = (policy, &result) {
+        error!("{}, aborting as required by the plugin's panic policy: {}", MESSAGE, e);
+        std::process::abort();
+    }
+    result
+}
+
+/// Returns the message of a panic payload.
+fn message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => match payload.downcast::<&'static str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => "unknown panic".to_string(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TempDir};
+    use crate::StaticPlugin;
+    use axum::Router;
+    use hyper::StatusCode;
+    use std::process::Command;
+    use std::sync::Arc;
+
+    /// Set in the child process running the aborting test.
+    const CHILD: &str = "AXUM_ROUTER_PLUGIN_ABORT_CHILD";
+
+    fn router(dir: &TempDir, policy: PanicPolicy) -> Router {
+        let plugin = StaticPlugin::new(r#"[{"path": "/", "function": "broken", "method_router": "get"}]"#)
+            .function("broken", Arc::new(testing::Panicking))
+            .panic_policy(policy);
+        testing::plugins(dir, true).register("broken", plugin).load().unwrap()
+    }
+
+    #[tokio::test]
+    async fn catch_policy_answers_a_panic_with_500() {
+        let dir = TempDir::new();
+        let router = router(&dir, PanicPolicy::Catch);
+
+        let response = testing::get(&router, "/broken").await;
+
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(testing::body_string(response).await, MESSAGE);
+    }
+
+    #[tokio::test]
+    async fn abort_policy_aborts_the_process_on_a_panic() {
+        if std::env::var_os(CHILD).is_some() {
+            let dir = TempDir::new();
+            let router = router(&dir, PanicPolicy::Abort);
+            testing::get(&router, "/broken").await;
+            return;
+        }
+
+        // The abort would take the test runner down, so the test runs in a child process
+        let output = Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "panic::tests::abort_policy_aborts_the_process_on_a_panic", "--nocapture"])
+            .env(CHILD, "1")
+            .output()
+            .unwrap();
+
+        assert!(!output.status.success());
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt;
+            assert_eq!(output.status.signal(), Some(6), "not aborted: {:?}", output.status);
+        }
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("the plugin returned a null pointer"), "{}", stderr);
+    }
+
+    #[test]
+    fn panic_message_is_kept() {
+        assert_eq!(catch(PanicPolicy::Catch, || panic!("boom {}", 1)), Err::<(), _>("boom 1".to_string()));
+        assert_eq!(catch(PanicPolicy::Catch, || panic!("boom")), Err::<(), _>("boom".to_string()));
+        assert_eq!(catch(PanicPolicy::Abort, || 1), Ok(1));
+    }
+}
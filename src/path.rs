@@ -0,0 +1,302 @@
+//! Normalization and validation of the route paths declared by plugins.
+//!
+//! Axum panics when a route path breaks its matcher rules or conflicts with another route
+//! of the same router, so paths are checked before registration and the offending route
+//! is skipped with an error telling the plugin author what's wrong.
+
+use std::collections::HashMap;
+
+/// Normalizes a route path: ensures a single leading slash, collapses repeated slashes
+/// and removes any trailing slash, except for the root route `/`.
+///
+/// # Arguments
+/// * `path` - The path as declared by the plugin.
+///
+/// # Returns
+/// The normalized path.
+pub(crate) fn normalize_path(path: &str) -> String {
+
+    let segments: Vec<&str> = path.split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect();
+
+    format!("/{}", segments.join("/"))
+}
+
+/// Checks a route path against the matcher rules: a `:parameter` or `*wildcard` needs a
+/// name, a segment holds at most one parameter, and a wildcard is a whole segment and
+/// the last one.
+///
+/// # Arguments
+/// * `path` - The path as declared by the plugin.
+///
+/// # Returns
+/// An error describing the first rule the path breaks.
+pub(crate) fn validate_path(path: &str) -> Result<(), String> {
+
+    let segments: Vec<&str> = path.split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect();
+
+    for (index, segment) in segments.iter().enumerate() {
+        if let Some(position) = segment.find('*') {
+            let name = &segment[position + 1..];
+            if position != 0 {
+                return Err(format!("wildcard must start a segment: {:?}", segment));
+            }
+            if index + 1 != segments.len() {
+                return Err(format!("wildcard must be the last segment: {:?}", segment));
+            }
+            if name.is_empty() {
+                return Err(format!("wildcard name missing: {:?}", segment));
+            }
+            if name.contains(':') || name.contains('*') {
+                return Err(format!("invalid wildcard name: {:?}", segment));
+            }
+        } else if let Some(position) = segment.find(':') {
+            let name = &segment[position + 1..];
+            if name.is_empty() {
+                return Err(format!("parameter name missing: {:?}", segment));
+            }
+            if name.contains(':') {
+                return Err(format!("only one parameter per segment: {:?}", segment));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns a segment with its parameter or wildcard name removed.
+fn segment_shape(segment: &str) -> String {
+    match segment.find([':', '*']) {
+        Some(position) => segment[..=position].to_string(),
+        None => segment.to_string(),
+    }
+}
+
+/// Checks whether two normalized and valid paths can't be registered in the same router:
+/// they only differ by their parameter names, or one has a wildcard where the other has
+/// a parameter.
+///
+/// # Returns
+/// The reason of the conflict, if any.
+pub(crate) fn conflict(path: &str, other: &str) -> Option<String> {
+
+    if path == other {
+        return None;
+    }
+
+    let segments: Vec<&str> = path.split('/').collect();
+    let other_segments: Vec<&str> = other.split('/').collect();
+
+    let shapes: Vec<String> = segments.iter().map(|segment| segment_shape(segment)).collect();
+    let other_shapes: Vec<String> = other_segments.iter().map(|segment| segment_shape(segment)).collect();
+    if shapes == other_shapes {
+        return Some(format!("parameter names differ from route {:?}", other));
+    }
+
+    // Below a common prefix, a wildcard and a parameter compete for the same segment
+    let common = segments.iter().zip(&other_segments).take_while(|(a, b)| a == b).count();
+    let (segment, other_segment) = (segments.get(common)?, other_segments.get(common)?);
+    let wildcard_vs_parameter = (segment.starts_with('*') && other_segment.starts_with(':'))
+        || (segment.starts_with(':') && other_segment.starts_with('*'));
+    if wildcard_vs_parameter {
+        return Some(format!("wildcard and parameter compete for a segment with route {:?}", other));
+    }
+
+    None
+}
+
+/// Returns the public path of a route: its normalized path under the plugin prefix, if
+/// any. The root route of a prefixed plugin is the prefix itself, `/name`, as mounted by
+/// nesting.
+///
+/// # Arguments
+/// * `prefix` - The plugin name when it's added to the routes, `None` otherwise.
+/// * `path` - The path as declared by the plugin.
+///
+/// # Returns
+/// The path the route is served at.
+pub(crate) fn effective_path(prefix: Option<&str>, path: &str) -> String {
+
+    let path = normalize_path(path);
+    match prefix {
+        Some(prefix) if path == "/" => normalize_path(prefix),
+        Some(prefix) => format!("{}{}", normalize_path(prefix), path),
+        None => path,
+    }
+}
+
+/// Checks whether a plugin name can be used as a route prefix.
+///
+/// The name must be a single literal path segment: not empty, not a dot segment, which
+/// clients remove from URLs, and only made of the characters allowed in a segment
+/// (RFC 3986) other than `:` and `*`, which start a parameter or a wildcard.
+pub(crate) fn is_valid_prefix(name: &str) -> bool {
+    let literal = |byte: u8| matches!(byte,
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9'
+        | b'-' | b'.' | b'_' | b'~' | b'!' | b'$' | b'&' | b'\'' | b'(' | b')'
+        | b'+' | b',' | b';' | b'=' | b'@'
+    );
+    !name.is_empty() && name != "." && name != ".." && name.bytes().all(literal)
+}
+
+/// Returns the names of the `:parameter` and `*wildcard` segments of a path.
+pub(crate) fn params(path: &str) -> Vec<&str> {
+    path.split('/')
+        .filter_map(|segment| segment.find([':', '*']).map(|position| &segment[position + 1..]))
+        .collect()
+}
+
+/// Percent-encodes a captured value so it stays in its segments: only the characters
+/// allowed in a path segment are kept, and `/` for a wildcard.
+fn encode_segment(value: &str, wildcard: bool) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9'
+            | b'-' | b'.' | b'_' | b'~' | b'!' | b'$' | b'&' | b'\'' | b'(' | b')'
+            | b'*' | b'+' | b',' | b';' | b'=' | b':' | b'@' => encoded.push(byte as char),
+            b'/' if wildcard => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Builds a concrete path from a route path, replacing its parameters and wildcard with
+/// the captured values.
+///
+/// # Arguments
+/// * `path` - The normalized route path.
+/// * `values` - The decoded values by parameter name, missing ones being left empty.
+///
+/// # Returns
+/// The path with the values percent-encoded.
+pub(crate) fn fill_params(path: &str, values: &HashMap<String, String>) -> String {
+    path.split('/')
+        .map(|segment| match segment.find([':', '*']) {
+            Some(position) => {
+                let value = values.get(&segment[position + 1..]).map(String::as_str).unwrap_or_default();
+                let wildcard = segment[position..].starts_with('*');
+                format!("{}{}", &segment[..position], encode_segment(value, wildcard))
+            },
+            None => segment.to_string(),
+        })
+        .collect::<Vec<String>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TempDir};
+    use crate::{CannedInvoker, StaticPlugin};
+    use hyper::StatusCode;
+    use std::sync::Arc;
+
+    #[test]
+    fn prefixes_are_literal_segments() {
+        for name in ["foo-bar", "arp_gmail", "v1.2", "a~b", "user@host"] {
+            assert!(is_valid_prefix(name), "{:?} should be valid", name);
+        }
+        for name in ["", " ", ".", "..", "a/b", "*x", ":id", "a:b", "a*", "a b", "a%20b", "a?b", "a#b", "ção"] {
+            assert!(!is_valid_prefix(name), "{:?} should be invalid", name);
+        }
+    }
+
+    #[test]
+    fn invalid_paths_are_rejected_with_the_broken_rule() {
+        for (path, error) in [
+            ("/files/*path/edit", r#"wildcard must be the last segment: "*path""#),
+            ("/files/file*", r#"wildcard must start a segment: "file*""#),
+            ("/files/*", r#"wildcard name missing: "*""#),
+            ("/files/*a:b", r#"invalid wildcard name: "*a:b""#),
+            ("/users/:", r#"parameter name missing: ":""#),
+            ("/users/:id:name", r#"only one parameter per segment: ":id:name""#),
+        ] {
+            assert_eq!(validate_path(path), Err(error.to_string()), "{}", path);
+        }
+        for path in ["/", "/users/:id", "/files/*path", "/v:version/items", "//users//:id/"] {
+            assert_eq!(validate_path(path), Ok(()), "{}", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn route_with_an_invalid_path_is_skipped_with_its_error() {
+        let dir = TempDir::new();
+        let plugin = StaticPlugin::new(
+            r#"[{"path": "/files/*path/edit", "function": "edit", "method_router": "get"}, {"path": "/files/:name", "function": "file", "method_router": "get"}]"#,
+        )
+            .function("edit", Arc::new(CannedInvoker::new("edit")))
+            .function("file", Arc::new(CannedInvoker::new("file")));
+        let plugins = testing::plugins(&dir, true)
+            .register("docs", plugin);
+
+        let (logs, _guard) = testing::Logs::capture();
+        let router = plugins.load().unwrap();
+
+        let message = r#"Skipping route: docs: edit - invalid path "/files/*path/edit": wildcard must be the last segment: "*path""#;
+        assert!(logs.messages().iter().any(|logged| logged == message), "{:?}", logs.messages());
+        let response = testing::get(&router, "/docs/files/readme").await;
+        assert_eq!(testing::body_string(response).await, "file");
+    }
+
+    #[test]
+    fn effective_paths_combine_the_prefix_and_the_route_path() {
+        for (prefix, path, effective) in [
+            (Some("docs"), "/guide", "/docs/guide"),
+            (Some("docs"), "guide", "/docs/guide"),
+            (Some("docs"), "/guide/", "/docs/guide"),
+            (Some("docs"), "//guide//:id", "/docs/guide/:id"),
+            (Some("docs"), "/", "/docs"),
+            (Some("docs"), "", "/docs"),
+            (None, "/guide", "/guide"),
+            (None, "guide/", "/guide"),
+            (None, "/", "/"),
+            (None, "", "/"),
+        ] {
+            assert_eq!(effective_path(prefix, path), effective, "{:?} {:?}", prefix, path);
+        }
+    }
+
+    #[tokio::test]
+    async fn root_and_relative_routes_are_served_under_the_plugin_name() {
+        let dir = TempDir::new();
+        let plugin = StaticPlugin::new(
+            r#"[{"path": "/", "function": "index", "method_router": "get"}, {"path": "/guide", "function": "guide", "method_router": "get"}, {"path": "faq", "function": "faq", "method_router": "get"}]"#,
+        )
+            .function("index", Arc::new(CannedInvoker::new("index")))
+            .function("guide", Arc::new(CannedInvoker::new("guide")))
+            .function("faq", Arc::new(CannedInvoker::new("faq")));
+        let router = testing::plugins(&dir, true)
+            .register("docs", plugin)
+            .load()
+            .unwrap();
+
+        for (path, body) in [("/docs", "index"), ("/docs/guide", "guide"), ("/docs/faq", "faq")] {
+            let response = testing::get(&router, path).await;
+            assert_eq!(testing::body_string(response).await, body, "{}", path);
+        }
+        let response = testing::get(&router, "/docs//guide").await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn plugins_with_invalid_names_are_not_mounted() {
+        let dir = TempDir::new();
+        let plugin = || StaticPlugin::new(r#"[{"path": "/", "function": "f", "method_router": "get"}]"#)
+            .function("f", Arc::new(CannedInvoker::new("f")));
+        let plugins = testing::plugins(&dir, true)
+            .register("", plugin())
+            .register("valid", plugin());
+
+        let router = plugins.load().unwrap();
+
+        assert!(plugins.plugin_routes("").is_none());
+        assert!(plugins.plugin_routes("valid").is_some());
+        let response = testing::get(&router, "/valid").await;
+        assert_eq!(testing::body_string(response).await, "f");
+    }
+}
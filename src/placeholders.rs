@@ -0,0 +1,180 @@
+//! Substitution of request values in the bodies of plugin responses.
+//!
+//! A route declared with `"placeholders": true` can return a mostly static body with
+//! `{{request_id}}`, `{{path}}` and `{{method}}` placeholders, replaced with the values
+//! of the current request. The substitution is a single pass over the body: unknown
+//! names are left as is, nothing is evaluated, and substituted values are never expanded
+//! again. Values are escaped for the route's response type, since the request id and the
+//! path come from the client.
+
+use hyper::HeaderMap;
+use rand::{distributions::Alphanumeric, Rng};
+
+/// The request header carrying the request id.
+pub(crate) const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// The longest request id reused from the request.
+const MAX_REQUEST_ID: usize = 128;
+
+/// The request values available to the placeholders.
+#[derive(Debug, Clone)]
+pub(crate) struct Context {
+    pub(crate) request_id: String,
+    pub(crate) path: String,
+    pub(crate) method: String,
+}
+
+impl Context {
+
+    /// Returns the value of a placeholder, `None` for unknown names.
+    fn value(&self, name: &str) -> Option<&str> {
+        match name {
+            "request_id" => Some(&self.request_id),
+            "path" => Some(&self.path),
+            "method" => Some(&self.method),
+            _ => None,
+        }
+    }
+}
+
+/// Returns the id of a request: its `x-request-id` header, as set by a proxy or a
+/// `tower` layer, if it's made of letters, digits, `-`, `_` and `.`, or a new random id.
+pub(crate) fn request_id(headers: &HeaderMap) -> String {
+    let valid = |id: &&str| {
+        !id.is_empty()
+            && id.len() <= MAX_REQUEST_ID
+            && id.bytes().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'))
+    };
+
+    match headers.get(REQUEST_ID_HEADER).and_then(|value| value.to_str().ok()).filter(valid) {
+        Some(id) => id.to_string(),
+        None => rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(32)
+            .map(char::from)
+            .collect(),
+    }
+}
+
+/// Replaces the placeholders of a body with the request values.
+///
+/// # Arguments
+/// * `body` - The body returned by the plugin.
+/// * `context` - The request values.
+/// * `response_type` - The route's response type, which selects the escaping.
+///
+/// # Returns
+/// The body with the known placeholders replaced.
+pub(crate) fn substitute(body: &str, context: &Context, response_type: &str) -> String {
+
+    let mut output = String::with_capacity(body.len());
+    let mut rest = body;
+
+    while let Some(start) = rest.find("{{") {
+        output.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+
+        let value = after.find("}}")
+            .and_then(|end| context.value(after[..end].trim()).map(|value| (value, end)));
+        match value {
+            Some((value, end)) => {
+                output.push_str(&escape(value, response_type));
+                rest = &after[end + 2..];
+            },
+            None => {
+                output.push_str("{{");
+                rest = after;
+            },
+        }
+    }
+    output.push_str(rest);
+    output
+}
+
+/// Escapes a value for the body of a response type.
+fn escape(value: &str, response_type: &str) -> String {
+    match response_type.to_lowercase().as_str() {
+        "html" => value.chars()
+            .map(|c| match c {
+                '&' => "&amp;".to_string(),
+                '<' => "&lt;".to_string(),
+                '>' => "&gt;".to_string(),
+                '"' => "&quot;".to_string(),
+                '\'' => "&#39;".to_string(),
+                c => c.to_string(),
+            })
+            .collect(),
+        // Placeholders are expected inside JSON strings
+        "json" => {
+            let quoted = serde_json::Value::String(value.to_string()).to_string();
+            quoted[1..quoted.len() - 1].to_string()
+        },
+        _ => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TempDir};
+    use axum::{body::Body, extract::Request};
+
+    /// Loads a plugin answering `GET /page/:name` of `response_type` with `output`,
+    /// with the placeholders substituted.
+    fn router(dir: &TempDir, response_type: &str, output: &str) -> axum::Router {
+        let route = format!(
+            r#"{{"path": "/page/:name", "method_router": "get", "response_type": "{}", "placeholders": true}}"#,
+            response_type,
+        );
+        let (plugin, _) = testing::route_plugin(&route, output);
+        testing::plugins(dir, true)
+            .register("site", plugin)
+            .load()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn request_id_placeholder_is_replaced_with_the_request_id() {
+        let dir = TempDir::new();
+        let router = router(&dir, "text", "id={{request_id}} {{ method }} {{path}} {{unknown}} {{");
+
+        let request = Request::get("/site/page/home")
+            .header(REQUEST_ID_HEADER, "abc-123")
+            .body(Body::empty())
+            .unwrap();
+        let response = testing::send(&router, request).await;
+
+        assert_eq!(response.headers()[REQUEST_ID_HEADER], "abc-123");
+        assert_eq!(
+            testing::body_string(response).await,
+            "id=abc-123 GET /site/page/home {{unknown}} {{",
+        );
+    }
+
+    #[tokio::test]
+    async fn generated_request_id_is_sent_back() {
+        let dir = TempDir::new();
+        let router = router(&dir, "text", "{{request_id}}");
+
+        let request = Request::get("/site/page/home")
+            .header(REQUEST_ID_HEADER, "not valid!")
+            .body(Body::empty())
+            .unwrap();
+        let response = testing::send(&router, request).await;
+
+        let id = response.headers()[REQUEST_ID_HEADER].to_str().unwrap().to_string();
+        assert_eq!(id.len(), 32);
+        assert_eq!(testing::body_string(response).await, id);
+    }
+
+    #[test]
+    fn substituted_values_are_escaped_for_the_response_type() {
+        let context = Context {
+            request_id: "1".to_string(),
+            path: "/a\"<b>".to_string(),
+            method: "GET".to_string(),
+        };
+        assert_eq!(substitute("{{path}}", &context, "html"), "/a&quot;&lt;b&gt;");
+        assert_eq!(substitute(r#"{"path": "{{path}}"}"#, &context, "json"), r#"{"path": "/a\"<b>"}"#);
+    }
+}
@@ -0,0 +1,217 @@
+//! Trusted reverse proxies and the `X-Forwarded-*` headers they set.
+//!
+//! The `X-Forwarded-For`, `X-Forwarded-Proto` and `X-Forwarded-Host` headers can be set
+//! by any client, so they are only honored when the immediate peer (from `ConnectInfo`)
+//! is one of the trusted proxies configured with [`crate::Plugins::trusted_proxies`].
+//! Otherwise the host uses the peer address and the `Host` header, and the forwarded
+//! headers are removed before the request reaches the plugin.
+
+use axum::extract::{ConnectInfo, Request};
+use hyper::HeaderMap;
+use std::net::{IpAddr, SocketAddr};
+
+/// The forwarding headers honored from trusted proxies and stripped from the others.
+const FORWARDED_HEADERS: [&str; 4] = [
+    "forwarded",
+    "x-forwarded-for",
+    "x-forwarded-proto",
+    "x-forwarded-host",
+];
+
+/// An IP network in CIDR notation, e.g. `10.0.0.0/8` or `::1/128`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+
+    /// Parses a network in CIDR notation. A bare address is a network of one address.
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        let (address, prefix) = match value.trim().split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix.parse::<u8>().ok()?)),
+            None => (value.trim(), None),
+        };
+
+        let network: IpAddr = address.parse().ok()?;
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix.unwrap_or(max);
+        if prefix > max {
+            return None;
+        }
+        Some(Cidr { network, prefix })
+    }
+
+    /// Checks whether an address belongs to the network.
+    pub(crate) fn contains(&self, address: IpAddr) -> bool {
+        match (self.network, canonical(address)) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(address) & mask
+            },
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(address) & mask
+            },
+            _ => false,
+        }
+    }
+}
+
+/// Maps IPv4-mapped IPv6 addresses (`::ffff:10.0.0.1`) to IPv4, as dual-stack sockets
+/// report IPv4 peers.
+fn canonical(address: IpAddr) -> IpAddr {
+    match address {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(address, IpAddr::V4),
+        _ => address,
+    }
+}
+
+/// The client of a request, as derived from the peer address and, behind a trusted
+/// proxy, the forwarded headers.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Client {
+    /// The client address, if known.
+    pub(crate) ip: Option<IpAddr>,
+    /// The forwarded host, set only behind a trusted proxy.
+    pub(crate) host: Option<String>,
+}
+
+/// Derives the client of a request, removing the forwarded headers of untrusted peers.
+///
+/// # Arguments
+/// * `trusted` - The trusted proxies, `None` if none are configured.
+/// * `request` - The incoming request.
+///
+/// # Returns
+/// The client of the request.
+pub(crate) fn client(trusted: Option<&[Cidr]>, request: &mut Request) -> Client {
+
+    let peer = request.extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| canonical(addr.ip()));
+
+    let is_trusted = |address: IpAddr| {
+        trusted.is_some_and(|trusted| trusted.iter().any(|cidr| cidr.contains(address)))
+    };
+
+    match peer {
+        Some(peer) if is_trusted(peer) => {
+            let headers = request.headers();
+            Client {
+                ip: forwarded_for(headers, &is_trusted).or(Some(peer)),
+                host: last_value(headers, "x-forwarded-host"),
+            }
+        },
+        _ => {
+            // Plugins can only rely on the forwarded headers if they can't be spoofed
+            if trusted.is_some() {
+                for name in FORWARDED_HEADERS {
+                    request.headers_mut().remove(name);
+                }
+            }
+            Client {
+                ip: peer,
+                host: None,
+            }
+        },
+    }
+}
+
+/// Returns the client address from `X-Forwarded-For`: the last address that isn't a
+/// trusted proxy, as the addresses on its left can be set by the client.
+fn forwarded_for(headers: &HeaderMap, is_trusted: &impl Fn(IpAddr) -> bool) -> Option<IpAddr> {
+    let addresses: Vec<IpAddr> = headers.get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|address| address.trim().parse().ok())
+        .map(canonical)
+        .collect();
+
+    addresses.iter()
+        .rev()
+        .find(|address| !is_trusted(**address))
+        .or(addresses.first())
+        .copied()
+}
+
+/// Returns the last value of a header that proxies may have set several times.
+fn last_value(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers.get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .rfind(|value| !value.is_empty())
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, EchoHeader, TempDir};
+    use crate::StaticPlugin;
+    use axum::body::Body;
+    use std::sync::Arc;
+
+    /// Sends `GET /echo` from `peer` with an `x-forwarded-for` header through a plugin
+    /// answering with the `x-forwarded-for` header it gets.
+    async fn forwarded_from(peer: &str) -> axum::response::Response {
+        let dir = TempDir::new();
+        let plugin = StaticPlugin::new(r#"[{"path": "/", "function": "echo", "method_router": "get"}]"#)
+            .function("echo", Arc::new(EchoHeader("x-forwarded-for")));
+        let router = testing::plugins(&dir, true)
+            .trusted_proxies(["10.0.0.0/8"])
+            .register("echo", plugin)
+            .load()
+            .unwrap();
+
+        let mut request = Request::get("/echo")
+            .header("x-forwarded-for", "203.0.113.7")
+            .body(Body::empty())
+            .unwrap();
+        request.extensions_mut().insert(ConnectInfo(SocketAddr::new(peer.parse().unwrap(), 4000)));
+        testing::send(&router, request).await
+    }
+
+    #[tokio::test]
+    async fn forwarded_headers_of_an_untrusted_peer_are_ignored() {
+        let response = forwarded_from("192.0.2.1").await;
+
+        assert_eq!(testing::body_string(response).await, "");
+    }
+
+    #[tokio::test]
+    async fn forwarded_headers_of_a_trusted_proxy_are_honored() {
+        let response = forwarded_from("10.1.2.3").await;
+
+        assert_eq!(testing::body_string(response).await, "203.0.113.7");
+    }
+
+    #[test]
+    fn client_address_is_the_last_untrusted_forwarded_address() {
+        let trusted = [Cidr::parse("10.0.0.0/8").unwrap()];
+        let mut request = Request::get("/")
+            .header("x-forwarded-for", "198.51.100.1, 203.0.113.7, 10.0.0.2")
+            .body(Body::empty())
+            .unwrap();
+        request.extensions_mut().insert(ConnectInfo(SocketAddr::from(([10, 0, 0, 1], 4000))));
+
+        let client = client(Some(&trusted), &mut request);
+
+        assert_eq!(client.ip, Some(IpAddr::from([203, 0, 113, 7])));
+    }
+
+    #[test]
+    fn cidrs_match_their_networks() {
+        let network = Cidr::parse("10.0.0.0/8").unwrap();
+
+        assert!(network.contains(IpAddr::from([10, 255, 0, 1])));
+        assert!(network.contains("::ffff:10.0.0.1".parse().unwrap()));
+        assert!(!network.contains(IpAddr::from([11, 0, 0, 1])));
+        assert!(Cidr::parse("::1").unwrap().contains("::1".parse().unwrap()));
+        assert_eq!(Cidr::parse("10.0.0.0/33"), None);
+    }
+}
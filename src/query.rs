@@ -0,0 +1,259 @@
+//! Parsing of query strings into structured JSON for plugins.
+//!
+//! Repeated keys (`?id=1&id=2`) always produce arrays, and when the bracket convention is
+//! enabled, keys ending with `[]` (`?ids[]=1&ids[]=2`) produce arrays under the key
+//! without the brackets, even if they appear only once.
+//!
+//! Routes can also declare the parameters they expect in `query_params`, which the host
+//! checks before calling the plugin, answering `400 Bad Request` with the errors.
+
+use crate::ValidationErrors;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+/// The type of a declared query parameter.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QueryParamType {
+    /// Any value.
+    #[default]
+    String,
+    /// A signed integer, e.g. `-3`.
+    Integer,
+    /// A decimal number, e.g. `2.5`.
+    Number,
+    /// `true` or `false`.
+    Boolean,
+}
+
+impl QueryParamType {
+
+    /// Checks whether a value has the type.
+    fn accepts(self, value: &str) -> bool {
+        match self {
+            QueryParamType::String => true,
+            QueryParamType::Integer => value.parse::<i64>().is_ok(),
+            QueryParamType::Number => value.parse::<f64>().is_ok_and(f64::is_finite),
+            QueryParamType::Boolean => value == "true" || value == "false",
+        }
+    }
+
+    /// Returns the name of the type, as declared.
+    fn name(self) -> &'static str {
+        match self {
+            QueryParamType::String => "string",
+            QueryParamType::Integer => "integer",
+            QueryParamType::Number => "number",
+            QueryParamType::Boolean => "boolean",
+        }
+    }
+}
+
+/// A query parameter declared by a route in `query_params`, e.g.
+/// `{"name": "page", "type": "integer", "required": true}`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct QueryParam {
+    name: String,
+    #[serde(default, rename = "type")]
+    kind: QueryParamType,
+    #[serde(default)]
+    required: bool,
+}
+
+impl QueryParam {
+
+    /// Returns the parameter name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the type of the parameter's values, `string` if not declared.
+    pub fn kind(&self) -> QueryParamType {
+        self.kind
+    }
+
+    /// Returns whether requests must carry the parameter.
+    pub fn required(&self) -> bool {
+        self.required
+    }
+}
+
+/// Parses a raw query string into a JSON object.
+///
+/// Values are kept as strings; a key that appears more than once maps to an array of
+/// its values in order.
+///
+/// # Arguments
+/// * `query` - The raw (still URL-encoded) query string.
+/// * `brackets` - Whether to recognize the `key[]` array convention.
+///
+/// # Returns
+/// The parsed query as a JSON object.
+pub(crate) fn parse_query(query: &str, brackets: bool) -> Map<String, Value> {
+
+    let mut map = Map::new();
+
+    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+        let value = Value::String(value.into_owned());
+
+        let (key, force_array) = match key.strip_suffix("[]") {
+            Some(stripped) if brackets => (stripped.to_string(), true),
+            _ => (key.into_owned(), false),
+        };
+
+        match map.get_mut(&key) {
+            Some(Value::Array(values)) => values.push(value),
+            Some(existing) => {
+                let first = existing.take();
+                *existing = Value::Array(vec![first, value]);
+            },
+            None if force_array => { map.insert(key, Value::Array(vec![value])); },
+            None => { map.insert(key, value); },
+        }
+    }
+
+    map
+}
+
+/// Serializes a JSON value escaping every non-ASCII character, so the result is always
+/// a valid HTTP header value.
+///
+/// # Arguments
+/// * `value` - The JSON value to serialize.
+///
+/// # Returns
+/// The ASCII-only JSON string.
+pub(crate) fn to_ascii_json(value: &Value) -> String {
+
+    let json = value.to_string();
+    if json.is_ascii() {
+        return json;
+    }
+
+    // Non-ASCII characters can only appear inside JSON strings, where `\uXXXX` is valid
+    let mut escaped = String::with_capacity(json.len());
+    for c in json.chars() {
+        if c.is_ascii() {
+            escaped.push(c);
+        } else {
+            let mut units = [0u16; 2];
+            for unit in c.encode_utf16(&mut units) {
+                escaped.push_str(&format!("\\u{:04x}", unit));
+            }
+        }
+    }
+    escaped
+}
+
+/// Checks a query string against the parameters declared by a route. Undeclared
+/// parameters are accepted, and every value of a repeated parameter must have its type.
+///
+/// # Arguments
+/// * `params` - The declared parameters.
+/// * `query` - The raw query string, if the request has one.
+/// * `brackets` - Whether to recognize the `key[]` array convention.
+///
+/// # Returns
+/// The errors, located at `query.<name>`, empty if the query is valid.
+pub(crate) fn validate_query(
+    params: &[QueryParam],
+    query: Option<&str>,
+    brackets: bool,
+) -> ValidationErrors {
+
+    let parsed = query.map(|query| parse_query(query, brackets)).unwrap_or_default();
+    let mut errors = ValidationErrors::new();
+
+    for param in params {
+        let field = format!("query.{}", param.name);
+        let values: Vec<&str> = match parsed.get(&param.name) {
+            Some(Value::Array(values)) => values.iter().filter_map(Value::as_str).collect(),
+            Some(Value::String(value)) => vec![value.as_str()],
+            _ => Vec::new(),
+        };
+
+        if values.is_empty() {
+            if param.required {
+                errors = errors.field(field, "missing required query parameter");
+            }
+            continue;
+        }
+
+        if let Some(value) = values.iter().find(|value| !param.kind.accepts(value)) {
+            errors = errors.field(field, format!("expected {}, got {:?}", param.kind.name(), value));
+        }
+    }
+
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, EchoHeader, TempDir};
+    use crate::StaticPlugin;
+    use hyper::StatusCode;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    #[test]
+    fn repeated_keys_produce_arrays() {
+        let parsed = parse_query("id=1&id=2&name=a", false);
+
+        assert_eq!(Value::Object(parsed), json!({"id": ["1", "2"], "name": "a"}));
+    }
+
+    #[test]
+    fn bracket_keys_produce_arrays_when_enabled() {
+        assert_eq!(Value::Object(parse_query("ids[]=1", true)), json!({"ids": ["1"]}));
+        assert_eq!(Value::Object(parse_query("ids[]=1&ids[]=2", true)), json!({"ids": ["1", "2"]}));
+        assert_eq!(Value::Object(parse_query("ids[]=1", false)), json!({"ids[]": "1"}));
+    }
+
+    #[tokio::test]
+    async fn plugin_gets_the_parsed_query() {
+        let dir = TempDir::new();
+        let plugin = StaticPlugin::new(
+            r#"[{"path": "/", "function": "query", "method_router": "get", "query_json": true}]"#,
+        )
+            .function("query", Arc::new(EchoHeader("x-query-json")));
+        let router = testing::plugins(&dir, true)
+            .register("search", plugin)
+            .load()
+            .unwrap();
+
+        let response = testing::get(&router, "/search?tag=a&tag=b&ids[]=1").await;
+
+        let body: Value = serde_json::from_str(&testing::body_string(response).await).unwrap();
+        assert_eq!(body, json!({"tag": ["a", "b"], "ids": ["1"]}));
+    }
+
+    #[tokio::test]
+    async fn required_integer_param_is_validated_before_the_call() {
+        let dir = TempDir::new();
+        let (plugin, invoker) = testing::route_plugin(
+            r#"{"path": "/", "method_router": "get", "query_params": [{"name": "page", "type": "integer", "required": true}]}"#,
+            "page",
+        );
+        let router = testing::plugins(&dir, true)
+            .register("items", plugin)
+            .load()
+            .unwrap();
+
+        let response = testing::get(&router, "/items").await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body: Value = serde_json::from_str(&testing::body_string(response).await).unwrap();
+        assert_eq!(body, json!({"errors": [{"field": "query.page", "message": "missing required query parameter"}]}));
+
+        let response = testing::get(&router, "/items?page=two").await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body: Value = serde_json::from_str(&testing::body_string(response).await).unwrap();
+        assert_eq!(body["errors"][0]["field"], "query.page");
+        assert_eq!(invoker.calls(), 0);
+
+        let response = testing::get(&router, "/items?page=2").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(testing::body_string(response).await, "page");
+        assert_eq!(invoker.calls(), 1);
+    }
+}
@@ -0,0 +1,231 @@
+//! Reverse proxying to plugins running as separate HTTP services.
+//!
+//! A plugin whose `plugin.json` sets `remote_url` has no shared library: every request
+//! under its mount is forwarded to the service (method, path, query, headers and body)
+//! and the service's response is relayed back. Hop-by-hop headers are not forwarded in
+//! either direction, since they only apply to a single connection.
+//!
+//! Both bodies are streamed: the request body is sent to the service as it's received
+//! from the client, and the response body is sent to the client as it's received from
+//! the service, so large uploads and downloads are never buffered in memory. A slow
+//! reader on either side slows the other side down through the connections' flow control.
+//!
+//! Redirects are relayed to the client rather than followed, so a service can't point
+//! the host's request at another address, and a stalled service is cut off by the
+//! connect and read timeouts.
+
+use crate::log::error;
+use crate::envelope;
+use axum::{
+    body::Body,
+    extract::Request,
+    response::{IntoResponse, Response},
+};
+use hyper::{header, HeaderMap, StatusCode};
+use reqwest::redirect::Policy;
+use std::time::Duration;
+
+/// How long the host waits for a remote service.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Timeouts {
+    /// The timeout to connect to the service.
+    pub(crate) connect: Duration,
+    /// The longest wait between two reads of the service's response.
+    pub(crate) read: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            connect: Duration::from_secs(5),
+            read: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Forwards the requests of a remote plugin to its base URL.
+#[derive(Debug, Clone)]
+pub(crate) struct RemoteProxy {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl RemoteProxy {
+
+    /// Creates a proxy to the service at `url`.
+    ///
+    /// # Returns
+    /// The proxy, or an error if its HTTP client couldn't be built.
+    pub(crate) fn new(url: &str, timeouts: Timeouts) -> Result<Self, reqwest::Error> {
+        let client = reqwest::Client::builder()
+            .redirect(Policy::none())
+            .connect_timeout(timeouts.connect)
+            .read_timeout(timeouts.read)
+            .build()?;
+        Ok(RemoteProxy {
+            client,
+            url: url.trim_end_matches('/').to_string(),
+        })
+    }
+
+    /// Forwards a request to the service and relays its response.
+    ///
+    /// # Arguments
+    /// * `request` - The request, with the plugin's mount prefix already stripped.
+    /// * `prefix` - The mount prefix, sent to the service in `x-forwarded-prefix`.
+    ///
+    /// # Returns
+    /// The service's response, or `502 Bad Gateway` if it couldn't be reached.
+    pub(crate) async fn forward(&self, request: Request, prefix: &str) -> Response {
+
+        let target = request.uri()
+            .path_and_query()
+            .map(|target| target.as_str().to_string())
+            .unwrap_or_else(|| request.uri().path().to_string());
+        let url = format!("{}{}", self.url, target);
+
+        let (parts, body) = request.into_parts();
+        let mut headers = parts.headers;
+
+        // The body is streamed as is, so its Content-Length, if any, is kept
+        envelope::strip_hop_by_hop(&mut headers);
+        headers.remove(header::HOST);
+        if let Ok(value) = prefix.parse() {
+            headers.insert("x-forwarded-prefix", value);
+        }
+
+        let result = self.client
+            .request(parts.method, &url)
+            .headers(headers)
+            .body(reqwest::Body::wrap_stream(body.into_data_stream()))
+            .send()
+            .await;
+
+        let upstream = match result {
+            Ok(upstream) => upstream,
+            Err(e) if e.is_timeout() => {
+                error!("Timeout proxying to remote plugin: {}: {}", url, e);
+                return (StatusCode::GATEWAY_TIMEOUT, "Remote plugin timed out").into_response();
+            },
+            Err(e) => {
+                error!("Error proxying to remote plugin: {}: {}", url, e);
+                return (StatusCode::BAD_GATEWAY, "Remote plugin unavailable").into_response();
+            },
+        };
+
+        let status = upstream.status();
+        let mut headers: HeaderMap = upstream.headers().clone();
+        envelope::strip_hop_by_hop(&mut headers);
+
+        // An error in the middle of the body aborts the response, the status is already sent
+        let mut response = Response::new(Body::from_stream(upstream.bytes_stream()));
+        *response.status_mut() = status;
+        *response.headers_mut() = headers;
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{self, TempDir};
+    use axum::{
+        body::Body,
+        extract::Request,
+        response::Redirect,
+        routing::{any, get},
+        Router,
+    };
+    use hyper::{header, StatusCode};
+    use std::time::Duration;
+
+    /// The `plugin.json` of a remote plugin.
+    fn config(name: &str, url: &str) -> String {
+        format!(r#"{{"name": "{}", "version": "0.1.0", "enabled": true, "remote_url": "{}"}}"#, name, url)
+    }
+
+    /// A service answering with what it received.
+    async fn echo(request: Request) -> String {
+        let (parts, body) = request.into_parts();
+        let header = |name: &str| parts.headers
+            .get(name)
+            .map(|value| value.to_str().unwrap().to_string())
+            .unwrap_or_default();
+        let body = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+        format!(
+            "{} {} prefix={} custom={} keep-alive={} body={}",
+            parts.method, parts.uri, header("x-forwarded-prefix"), header("x-custom"), header("keep-alive"),
+            String::from_utf8_lossy(&body),
+        )
+    }
+
+    #[tokio::test]
+    async fn requests_are_forwarded_to_the_service_and_relayed_back() {
+        let addr = testing::serve(Router::new().route("/*path", any(echo))).await;
+        let dir = TempDir::new();
+        dir.plugin("service", &config("service", &format!("http://{}", addr)), None);
+        let router = testing::plugins(&dir, true).load().unwrap();
+
+        let request = Request::post("/service/items/1?full=true")
+            .header("x-custom", "yes")
+            .header("keep-alive", "timeout=5")
+            .body(Body::from("payload"))
+            .unwrap();
+        let response = testing::send(&router, request).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            testing::body_string(response).await,
+            "POST /items/1?full=true prefix=/service custom=yes keep-alive= body=payload",
+        );
+    }
+
+    #[tokio::test]
+    async fn unreachable_service_is_answered_with_502() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        let dir = TempDir::new();
+        dir.plugin("down", &config("down", &format!("http://{}", addr)), None);
+        let router = testing::plugins(&dir, true).load().unwrap();
+
+        let response = testing::get(&router, "/down/anything").await;
+
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    }
+
+    #[tokio::test]
+    async fn redirects_of_the_service_are_relayed_not_followed() {
+        let internal = testing::serve(Router::new().route("/secret", get(|| async { "secret" }))).await;
+        let location = format!("http://{}/secret", internal);
+        let service = Router::new().route("/*path", get(move || async move { Redirect::temporary(&location) }));
+        let addr = testing::serve(service).await;
+        let dir = TempDir::new();
+        dir.plugin("service", &config("service", &format!("http://{}", addr)), None);
+        let router = testing::plugins(&dir, true).load().unwrap();
+
+        let response = testing::get(&router, "/service/moved").await;
+
+        assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT);
+        assert_eq!(response.headers()[header::LOCATION], format!("http://{}/secret", internal).as_str());
+        assert_eq!(testing::body_string(response).await, "");
+    }
+
+    #[tokio::test]
+    async fn stalled_service_is_answered_with_504() {
+        let service = Router::new().route("/*path", get(|| async {
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            "late"
+        }));
+        let addr = testing::serve(service).await;
+        let dir = TempDir::new();
+        dir.plugin("slow", &config("slow", &format!("http://{}", addr)), None);
+        let router = testing::plugins(&dir, true)
+            .remote_timeouts(Duration::from_secs(1), Duration::from_millis(200))
+            .load()
+            .unwrap();
+
+        let response = tokio::time::timeout(Duration::from_secs(5), testing::get(&router, "/slow/anything")).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+    }
+}
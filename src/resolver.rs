@@ -0,0 +1,296 @@
+//! Abstraction over the symbols a plugin exports.
+//!
+//! `load()` discovers a plugin's routes and resolves its route functions through a
+//! [`SymbolResolver`]. Plugins loaded from shared libraries use a `libloading`-backed
+//! resolver, while [`StaticPlugin`] lets tests register an in-process fake plugin that
+//! "exports" a routes JSON string and named functions without a real `.so` file.
+
+use crate::{PanicPolicy, PluginInvoker};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Resolves the symbols a plugin exports.
+pub trait SymbolResolver: Send + Sync {
+
+    /// Returns the JSON array of routes declared by the plugin's `routes` function.
+    fn routes(&self) -> String;
+
+    /// Resolves a route function by name.
+    ///
+    /// # Arguments
+    /// * `name` - The name of the exported function.
+    ///
+    /// # Returns
+    /// An invoker for the function, or `None` if the plugin doesn't export it.
+    fn function(&self, name: &str) -> Option<Arc<dyn PluginInvoker>>;
+
+    /// Resolves the function of a streamed route (`"stream": true`) by name.
+    ///
+    /// The default implementation resolves it like any other function, so its output is
+    /// sent as a single chunk unless the invoker overrides
+    /// [`PluginInvoker::invoke_stream`].
+    ///
+    /// # Arguments
+    /// * `name` - The name of the exported function.
+    ///
+    /// # Returns
+    /// An invoker for the function, or `None` if the plugin doesn't export it.
+    fn stream_function(&self, name: &str) -> Option<Arc<dyn PluginInvoker>> {
+        self.function(name)
+    }
+
+    /// Resolves the function of a route streaming its request body
+    /// (`"request_stream": true`) by name.
+    ///
+    /// The default implementation resolves it like a streamed route, so the function
+    /// gets the whole body unless the invoker overrides [`PluginInvoker::invoke_duplex`].
+    ///
+    /// # Arguments
+    /// * `name` - The name of the exported function.
+    ///
+    /// # Returns
+    /// An invoker for the function, or `None` if the plugin doesn't export it.
+    fn duplex_function(&self, name: &str) -> Option<Arc<dyn PluginInvoker>> {
+        self.stream_function(name)
+    }
+
+    /// Resolves the function of a route using the length-carrying ABI
+    /// (`"abi": "buffer"`) by name.
+    ///
+    /// The default implementation resolves it like any other function.
+    ///
+    /// # Arguments
+    /// * `name` - The name of the exported function.
+    ///
+    /// # Returns
+    /// An invoker for the function, or `None` if the plugin doesn't export it.
+    fn buffer_function(&self, name: &str) -> Option<Arc<dyn PluginInvoker>> {
+        self.function(name)
+    }
+
+    /// Resolves the function of a route taking its request body with its length
+    /// (`"abi": "bytes"`) by name.
+    ///
+    /// The default implementation resolves it like any other function, so the function
+    /// gets the body through [`PluginInvoker::invoke_raw`], whose default implementation
+    /// replaces invalid UTF-8.
+    ///
+    /// # Arguments
+    /// * `name` - The name of the exported function.
+    ///
+    /// # Returns
+    /// An invoker for the function, or `None` if the plugin doesn't export it.
+    fn bytes_function(&self, name: &str) -> Option<Arc<dyn PluginInvoker>> {
+        self.function(name)
+    }
+
+    /// Returns the default headers added to every response of the plugin that doesn't
+    /// already set them, e.g. security headers such as `X-Frame-Options`.
+    fn headers(&self) -> Vec<(String, String)> {
+        Vec::new()
+    }
+
+    /// Returns the `Host` the plugin is served on, or `None` to serve it on any host.
+    fn host(&self) -> Option<String> {
+        None
+    }
+
+    /// Returns the plugin version, sent in the `x-plugin-version` header when enabled
+    /// with [`crate::Plugins::plugin_version`].
+    fn version(&self) -> Option<String> {
+        None
+    }
+
+    /// Returns the names of the host-registered layers applied to the plugin's routes,
+    /// see [`crate::Plugins::layer`].
+    fn layers(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Returns the maximum duration of a call to one of the plugin's functions, for the
+    /// routes that don't declare their own `timeout_ms`.
+    fn timeout(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Returns what happens when a call to one of the plugin's functions panics.
+    fn panic_policy(&self) -> PanicPolicy {
+        PanicPolicy::Catch
+    }
+}
+
+/// An in-process plugin made of a routes JSON string and named invokers.
+///
+/// # Example
+/// ```
+/// use axum_router_plugin::{CannedInvoker, Plugins, StaticPlugin};
+/// use std::sync::Arc;
+///
+/// let plugin = StaticPlugin::new(r#"[
+///     {"path": "/hello", "function": "hello", "method_router": "get", "response_type": "text"}
+/// ]"#)
+///     .function("hello", Arc::new(CannedInvoker::new("Hello world!")));
+///
+/// let _plugins = Plugins::new(Some(true))
+///     .register("fake", plugin);
+/// ```
+#[derive(Default)]
+pub struct StaticPlugin {
+    routes: String,
+    functions: HashMap<String, Arc<dyn PluginInvoker>>,
+    headers: Vec<(String, String)>,
+    host: Option<String>,
+    layers: Vec<String>,
+    version: Option<String>,
+    timeout: Option<Duration>,
+    panic_policy: PanicPolicy,
+}
+
+impl StaticPlugin {
+
+    /// Creates a plugin that declares the given routes JSON.
+    ///
+    /// # Arguments
+    /// * `routes` - The JSON array of routes, as a plugin's `routes` function would return it.
+    ///
+    /// # Returns
+    /// A new `StaticPlugin` instance.
+    pub fn new(routes: impl Into<String>) -> Self {
+        StaticPlugin {
+            routes: routes.into(),
+            functions: HashMap::new(),
+            headers: Vec::new(),
+            host: None,
+            layers: Vec::new(),
+            version: None,
+            timeout: None,
+            panic_policy: PanicPolicy::Catch,
+        }
+    }
+
+    /// Adds a named function to the plugin.
+    ///
+    /// # Arguments
+    /// * `name` - The function name referenced by the routes.
+    /// * `invoker` - The invoker called for the function.
+    ///
+    /// # Returns
+    /// The updated `StaticPlugin` instance.
+    pub fn function(
+        mut self,
+        name: impl Into<String>,
+        invoker: Arc<dyn PluginInvoker>,
+    ) -> Self {
+        self.functions.insert(name.into(), invoker);
+        self
+    }
+
+    /// Adds a default header to every response of the plugin.
+    ///
+    /// # Arguments
+    /// * `name` - The header name.
+    /// * `value` - The header value.
+    ///
+    /// # Returns
+    /// The updated `StaticPlugin` instance.
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Serves the plugin only to requests for the given `Host`.
+    ///
+    /// # Arguments
+    /// * `host` - The host name, e.g. `api.example.com`.
+    ///
+    /// # Returns
+    /// The updated `StaticPlugin` instance.
+    pub fn host(mut self, host: impl Into<String>) -> Self {
+        self.host = Some(host.into());
+        self
+    }
+
+    /// Sets the plugin version.
+    ///
+    /// # Arguments
+    /// * `version` - The version, e.g. `0.1.0`.
+    ///
+    /// # Returns
+    /// The updated `StaticPlugin` instance.
+    pub fn version(mut self, version: impl Into<String>) -> Self {
+        self.version = Some(version.into());
+        self
+    }
+
+    /// Applies a host-registered layer to the plugin's routes.
+    ///
+    /// # Arguments
+    /// * `name` - The name the layer was registered with through [`crate::Plugins::layer`].
+    ///
+    /// # Returns
+    /// The updated `StaticPlugin` instance.
+    pub fn layer(mut self, name: impl Into<String>) -> Self {
+        self.layers.push(name.into());
+        self
+    }
+
+    /// Sets the maximum duration of a call to one of the plugin's functions.
+    ///
+    /// # Arguments
+    /// * `timeout` - The timeout, overridden by the routes declaring `timeout_ms`.
+    ///
+    /// # Returns
+    /// The updated `StaticPlugin` instance.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Sets what happens when a call to one of the plugin's functions panics.
+    ///
+    /// # Arguments
+    /// * `panic_policy` - The panic policy, `Catch` by default.
+    ///
+    /// # Returns
+    /// The updated `StaticPlugin` instance.
+    pub fn panic_policy(mut self, panic_policy: PanicPolicy) -> Self {
+        self.panic_policy = panic_policy;
+        self
+    }
+}
+
+impl SymbolResolver for StaticPlugin {
+
+    fn routes(&self) -> String {
+        self.routes.clone()
+    }
+
+    fn function(&self, name: &str) -> Option<Arc<dyn PluginInvoker>> {
+        self.functions.get(name).cloned()
+    }
+
+    fn headers(&self) -> Vec<(String, String)> {
+        self.headers.clone()
+    }
+
+    fn host(&self) -> Option<String> {
+        self.host.clone()
+    }
+
+    fn layers(&self) -> Vec<String> {
+        self.layers.clone()
+    }
+
+    fn version(&self) -> Option<String> {
+        self.version.clone()
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    fn panic_policy(&self) -> PanicPolicy {
+        self.panic_policy
+    }
+}
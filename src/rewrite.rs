@@ -0,0 +1,218 @@
+//! Rewriting of root-relative URLs in plugin responses.
+//!
+//! A plugin mounted under a prefix (e.g. `/plugin/name`) usually emits links such as
+//! `/static/x.css` without knowing its mount. The rewrite prepends the mount prefix to
+//! those URLs in HTML attributes and JSON fields, so prefixed plugins keep working.
+
+use crate::log::error;
+use axum::{
+    body::{self, Body},
+    response::{IntoResponse, Response},
+};
+use hyper::{
+    header::{CONTENT_LENGTH, CONTENT_TYPE},
+    StatusCode,
+};
+use serde_json::Value;
+
+/// Which HTML attributes and JSON fields of a plugin's responses are rewritten.
+#[derive(Debug, Clone)]
+pub struct UrlRewrite {
+    attributes: Vec<String>,
+    json_fields: Vec<String>,
+}
+
+impl Default for UrlRewrite {
+    fn default() -> Self {
+        UrlRewrite {
+            attributes: vec!["href".into(), "src".into(), "action".into()],
+            json_fields: Vec::new(),
+        }
+    }
+}
+
+impl UrlRewrite {
+
+    /// Creates a rewrite of the `href`, `src` and `action` HTML attributes and no JSON field.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the names of the HTML attributes whose values are rewritten.
+    pub fn attributes<I, S>(mut self, attributes: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.attributes = attributes.into_iter()
+            .map(|attribute| attribute.into().to_ascii_lowercase())
+            .collect();
+        self
+    }
+
+    /// Sets the names of the JSON fields (at any depth) whose string values are rewritten.
+    pub fn json_fields<I, S>(mut self, fields: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.json_fields = fields.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Rewrites the root-relative URLs of an HTML or JSON response to include `prefix`.
+    /// Other content types, and empty prefixes, leave the response untouched. A body that
+    /// can't be read gives `500 Internal Server Error`.
+    pub(crate) async fn apply(&self, response: Response, prefix: &str) -> Response {
+
+        if prefix.is_empty() {
+            return response;
+        }
+
+        let content_type = response.headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+
+        let html = content_type.starts_with("text/html");
+        let json = content_type.starts_with("application/json");
+        if !(html || (json && !self.json_fields.is_empty())) {
+            return response;
+        }
+
+        let (mut parts, body) = response.into_parts();
+        let bytes = match body::to_bytes(body, usize::MAX).await {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                error!("Error reading response body for URL rewriting: {}", e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            },
+        };
+
+        let rewritten = match (html, String::from_utf8(bytes.to_vec())) {
+            (true, Ok(text)) => self.rewrite_html(&text, prefix).into_bytes(),
+            (false, Ok(text)) => match serde_json::from_str::<Value>(&text) {
+                Ok(mut value) => {
+                    self.rewrite_json(&mut value, prefix);
+                    value.to_string().into_bytes()
+                },
+                Err(_) => return Response::from_parts(parts, Body::from(bytes)),
+            },
+            (_, Err(_)) => return Response::from_parts(parts, Body::from(bytes)),
+        };
+
+        parts.headers.remove(CONTENT_LENGTH);
+        Response::from_parts(parts, Body::from(rewritten))
+    }
+
+    /// Prepends `prefix` to the root-relative values of the configured attributes.
+    fn rewrite_html(&self, html: &str, prefix: &str) -> String {
+
+        // ASCII lowercasing keeps the byte offsets of the original string
+        let lower = html.to_ascii_lowercase();
+        let bytes = lower.as_bytes();
+        let mut insertions = Vec::new();
+
+        for attribute in &self.attributes {
+            for (start, _) in lower.match_indices(attribute.as_str()) {
+                // The attribute name must not be the tail of a longer name
+                if start == 0 || !bytes[start - 1].is_ascii_whitespace() {
+                    continue;
+                }
+
+                let mut i = start + attribute.len();
+                while i < bytes.len() && bytes[i].is_ascii_whitespace() { i += 1; }
+                if i >= bytes.len() || bytes[i] != b'=' { continue; }
+                i += 1;
+                while i < bytes.len() && bytes[i].is_ascii_whitespace() { i += 1; }
+                if i >= bytes.len() || (bytes[i] != b'"' && bytes[i] != b'\'') { continue; }
+                i += 1;
+
+                if is_root_relative(&lower[i..]) {
+                    insertions.push(i);
+                }
+            }
+        }
+
+        insertions.sort_unstable();
+        insertions.dedup();
+
+        let mut output = String::with_capacity(html.len() + insertions.len() * prefix.len());
+        let mut last = 0;
+        for position in insertions {
+            output.push_str(&html[last..position]);
+            output.push_str(prefix);
+            last = position;
+        }
+        output.push_str(&html[last..]);
+        output
+    }
+
+    /// Prepends `prefix` to the root-relative string values of the configured fields.
+    fn rewrite_json(&self, value: &mut Value, prefix: &str) {
+        match value {
+            Value::Object(map) => {
+                for (key, value) in map.iter_mut() {
+                    match value {
+                        Value::String(url) if self.json_fields.contains(key) => {
+                            if is_root_relative(url) {
+                                url.insert_str(0, prefix);
+                            }
+                        },
+                        _ => self.rewrite_json(value, prefix),
+                    }
+                }
+            },
+            Value::Array(items) => {
+                for item in items {
+                    self.rewrite_json(item, prefix);
+                }
+            },
+            _ => (),
+        }
+    }
+}
+
+/// Checks whether a URL is root-relative (`/x`), excluding protocol-relative ones (`//host/x`).
+fn is_root_relative(url: &str) -> bool {
+    url.starts_with('/') && !url.starts_with("//")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TempDir};
+    use axum::Router;
+
+    #[tokio::test]
+    async fn body_read_failure_is_a_server_error() {
+        let rewrite = UrlRewrite::new();
+
+        let response = rewrite.apply(testing::failing_response("text/html"), "/plugin/page").await;
+
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn root_relative_urls_get_the_mount_prefix() {
+        let dir = TempDir::new();
+        let (plugin, _) = testing::route_plugin(
+            r#"{"path": "/", "method_router": "get", "response_type": "html"}"#,
+            r#"<link href="/static/x.css"><img src="https://cdn.example.com/a.png"><a href="//example.com/">"#,
+        );
+        let plugins_router = testing::plugins(&dir, true)
+            .rewrite_urls("name", UrlRewrite::new())
+            .register("name", plugin)
+            .load()
+            .unwrap();
+        let router = Router::new().nest("/plugin", plugins_router);
+
+        let response = testing::get(&router, "/plugin/name").await;
+
+        assert_eq!(
+            testing::body_string(response).await,
+            r#"<link href="/plugin/name/static/x.css"><img src="https://cdn.example.com/a.png"><a href="//example.com/">"#,
+        );
+    }
+}
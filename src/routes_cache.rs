@@ -0,0 +1,140 @@
+//! On-disk cache of the routes declared by plugin libraries.
+//!
+//! With [`crate::Plugins::routes_cache`] set, the routes returned by a library's `routes`
+//! function are saved to `<dir>/<plugin name>.json` together with the SHA-256 checksum
+//! of the library file. On the next load the cached routes are used instead of calling
+//! `routes` as long as the checksum matches, so rebuilding the library invalidates them.
+
+use crate::log::error;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+/// A cache file.
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    /// The hex-encoded SHA-256 checksum of the library the routes were read from.
+    checksum: String,
+    /// The routes declared by the library.
+    routes: Value,
+}
+
+/// Returns the hex-encoded SHA-256 checksum of a library file.
+pub(crate) fn checksum(path: &Path) -> std::io::Result<String> {
+    let bytes = std::fs::read(path)?;
+    Ok(Sha256::digest(&bytes).iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// Returns the path of the cache file of a plugin. Path separators in the name are
+/// replaced, so every plugin gets a file in the cache directory.
+fn entry_path(dir: &Path, plugin: &str) -> PathBuf {
+    let file_name: String = plugin.chars()
+        .map(|c| if c == '/' || c == '\\' { '_' } else { c })
+        .collect();
+    dir.join(format!("{}.json", file_name))
+}
+
+/// Returns the cached routes JSON of a plugin, if the cache was written for a library
+/// with the same checksum.
+///
+/// # Arguments
+/// * `dir` - The cache directory.
+/// * `plugin` - The plugin name.
+/// * `checksum` - The checksum of the plugin's library.
+pub(crate) fn get(dir: &Path, plugin: &str, checksum: &str) -> Option<String> {
+    let data = std::fs::read(entry_path(dir, plugin)).ok()?;
+    let entry: CacheEntry = serde_json::from_slice(&data).ok()?;
+    (entry.checksum == checksum).then(|| entry.routes.to_string())
+}
+
+/// Saves the routes JSON of a plugin to the cache, unless it's invalid JSON. Errors are
+/// logged, the cache is only an optimization.
+///
+/// # Arguments
+/// * `dir` - The cache directory, created if missing.
+/// * `plugin` - The plugin name.
+/// * `checksum` - The checksum of the plugin's library.
+/// * `routes` - The routes JSON returned by the library.
+pub(crate) fn set(dir: &Path, plugin: &str, checksum: &str, routes: &str) {
+    let routes = match serde_json::from_str(routes) {
+        Ok(routes) => routes,
+        Err(_) => return,
+    };
+
+    let entry = CacheEntry {
+        checksum: checksum.to_string(),
+        routes,
+    };
+    let result = std::fs::create_dir_all(dir)
+        .and_then(|_| std::fs::write(entry_path(dir, plugin), serde_json::to_vec(&entry)?));
+    if let Err(e) = result {
+        error!("Error writing routes cache: {}: {}", plugin, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{self, TempDir};
+    use std::path::Path;
+
+    /// The C source of a plugin recording each call to its `routes` function in the file
+    /// `events`, serving `GET /` with `answer`.
+    fn counting_plugin(answer: &str, events: &Path) -> String {
+        format!(
+            r#"
+#include <stdint.h>
+#include <stdio.h>
+
+uint32_t plugin_abi_version(void) {{ return 1; }}
+const char *routes(void) {{
+    FILE *file = fopen("{events}", "a");
+    if (file) {{
+        fprintf(file, "routes\n");
+        fclose(file);
+    }}
+    return "[{{\"path\": \"/\", \"function\": \"answer\", \"method_router\": \"get\"}}]";
+}}
+const char *answer(void *headers, const char *body) {{
+    (void)headers; (void)body;
+    return "{answer}";
+}}
+void free(void *ptr) {{ (void)ptr; }}
+"#,
+            events = events.display(),
+            answer = answer,
+        )
+    }
+
+    /// Loads the plugins of `dir` with the routes cache in `cache` and requests the
+    /// `counter` plugin.
+    async fn load(dir: &TempDir, cache: &Path) -> String {
+        let plugins = testing::plugins(dir, true).routes_cache(cache);
+        let router = plugins.load().unwrap();
+        testing::body_string(testing::get(&router, "/counter").await).await
+    }
+
+    #[tokio::test]
+    async fn cached_routes_are_used_until_the_library_changes() {
+        let dir = TempDir::new();
+        let cache = TempDir::new();
+        let plugin_dir = dir.plugin("counter", &testing::config("counter"), None);
+        let events = plugin_dir.join("events");
+        if !testing::compile_plugin(&plugin_dir, &counting_plugin("first", &events)) {
+            return;
+        }
+
+        assert_eq!(load(&dir, cache.path()).await, "first");
+        assert_eq!(testing::events(&events), ["routes"]);
+        assert!(cache.path().join("counter.json").exists());
+
+        assert_eq!(load(&dir, cache.path()).await, "first");
+        assert_eq!(testing::events(&events), ["routes"]);
+
+        // Replaced rather than rewritten, the old library may still be mapped
+        std::fs::remove_file(plugin_dir.join("lib.so")).unwrap();
+        testing::compile_plugin(&plugin_dir, &counting_plugin("second", &events));
+        assert_eq!(load(&dir, cache.path()).await, "second");
+        assert_eq!(testing::events(&events), ["routes", "routes"]);
+    }
+}
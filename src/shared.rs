@@ -0,0 +1,132 @@
+//! A router that can be swapped while it's being served, for reloading plugins.
+//!
+//! The router is held in an `ArcSwap`, so each request loads the current router without
+//! taking a lock and a swap is a single atomic store: there's no moment when a request
+//! finds no router, and a reload never waits for the requests in flight.
+
+use arc_swap::ArcSwap;
+use axum::{
+    extract::Request,
+    response::Response,
+    Router,
+};
+use std::convert::Infallible;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tower::{Service, ServiceExt};
+
+/// A cloneable service that forwards every request to the router it currently holds.
+///
+/// Mount it with `Router::nest_service` (or serve it directly) and call
+/// [`SharedRouter::replace`] with the router returned by `Plugins::reload()` to swap the
+/// plugin routes without restarting the server, or use the one kept up to date by
+/// [`crate::Plugins::serving_router`]. Requests already being handled finish on the
+/// previous router.
+#[derive(Debug, Clone)]
+pub struct SharedRouter {
+    router: Arc<ArcSwap<Router>>,
+}
+
+impl SharedRouter {
+
+    /// Creates a shared router serving `router`.
+    pub fn new(router: Router) -> Self {
+        SharedRouter {
+            router: Arc::new(ArcSwap::from_pointee(router)),
+        }
+    }
+
+    /// Replaces the router that serves the next requests.
+    pub fn replace(&self, router: Router) {
+        self.router.store(Arc::new(router));
+    }
+
+    /// Returns the router currently serving the requests.
+    pub fn current(&self) -> Router {
+        Router::clone(&self.router.load())
+    }
+}
+
+impl Default for SharedRouter {
+    /// Creates a shared router serving an empty router, answering `404` until replaced.
+    fn default() -> Self {
+        SharedRouter::new(Router::new())
+    }
+}
+
+impl Service<Request> for SharedRouter {
+    type Response = Response;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, Infallible>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let router = self.current();
+        Box::pin(router.oneshot(request))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TempDir};
+    use crate::{CannedInvoker, StaticPlugin};
+    use axum::body::Body;
+    use hyper::StatusCode;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn replaced_router_serves_the_next_requests() {
+        let shared = SharedRouter::default();
+        let request = || Request::get("/").body(Body::empty()).unwrap();
+
+        let response = shared.clone().oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        shared.replace(Router::new().route("/", axum::routing::get(|| async { "new" })));
+        let response = shared.clone().oneshot(request()).await.unwrap();
+        assert_eq!(testing::body_string(response).await, "new");
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn reloads_under_concurrent_load_drop_no_requests() {
+        let dir = TempDir::new();
+        let invoker = testing::SlowInvoker {
+            delay: Duration::from_millis(2),
+            canned: Arc::new(CannedInvoker::new("item")),
+        };
+        let plugin = StaticPlugin::new(r#"[{"path": "/", "function": "item", "method_router": "get"}]"#)
+            .function("item", Arc::new(invoker));
+        let plugins = testing::plugins(&dir, true).register("items", plugin);
+        let _ = plugins.load().unwrap();
+        let serving = plugins.serving_router();
+
+        let clients: Vec<_> = (0..8).map(|_| {
+            let serving = serving.clone();
+            tokio::spawn(async move {
+                for _ in 0..50 {
+                    let request = Request::get("/items").body(Body::empty()).unwrap();
+                    let response = serving.clone().oneshot(request).await.unwrap();
+                    assert_eq!(response.status(), StatusCode::OK);
+                    assert_eq!(testing::body_string(response).await, "item");
+                }
+            })
+        }).collect();
+
+        let mut reloads = 0;
+        while !clients.iter().all(|client| client.is_finished()) {
+            let _ = plugins.reload().unwrap();
+            reloads += 1;
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        for client in clients {
+            client.await.unwrap();
+        }
+        assert!(reloads > 1, "only {} reloads happened during the requests", reloads);
+    }
+}
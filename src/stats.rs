@@ -0,0 +1,268 @@
+//! Request metrics of the plugin routes, enabled by the `metrics` feature.
+//!
+//! Every call to a route function is counted by plugin and route, along with the calls
+//! that failed, i.e. panicked or timed out, and the call latency. The counters are kept
+//! by the host and returned as a [`Stats`] snapshot by [`crate::Plugins::stats`], and are
+//! also emitted through the [`metrics`](https://docs.rs/metrics) facade, so an installed
+//! recorder such as a Prometheus exporter gets them without any extra code:
+//! `plugin_requests_total` and `plugin_errors_total` counters and a
+//! `plugin_request_duration_seconds` histogram, labelled with `plugin` and `route`.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The upper bounds of the latency histogram buckets, in milliseconds.
+const BUCKETS: [u64; 12] = [1, 5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000, 10000];
+
+/// A latency histogram with cumulative buckets, as in Prometheus.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    counts: [u64; BUCKETS.len()],
+    count: u64,
+    sum: Duration,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram {
+            counts: [0; BUCKETS.len()],
+            count: 0,
+            sum: Duration::ZERO,
+        }
+    }
+}
+
+impl Histogram {
+
+    /// Records a latency.
+    fn record(&mut self, latency: Duration) {
+        for (count, bound) in self.counts.iter_mut().zip(BUCKETS) {
+            if latency <= Duration::from_millis(bound) {
+                *count += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += latency;
+    }
+
+    /// Returns the buckets: each upper bound with the number of latencies not above it.
+    pub fn buckets(&self) -> Vec<(Duration, u64)> {
+        BUCKETS.iter()
+            .map(|bound| Duration::from_millis(*bound))
+            .zip(self.counts)
+            .collect()
+    }
+
+    /// Returns the number of latencies recorded, including the ones above the last bucket.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Returns the sum of the latencies recorded.
+    pub fn sum(&self) -> Duration {
+        self.sum
+    }
+}
+
+/// The metrics of a single route.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteStats {
+    plugin: String,
+    route: String,
+    requests: u64,
+    errors: u64,
+    latency: Histogram,
+}
+
+impl RouteStats {
+
+    /// Returns the plugin name.
+    pub fn plugin(&self) -> &str {
+        &self.plugin
+    }
+
+    /// Returns the route path as declared by the plugin.
+    pub fn route(&self) -> &str {
+        &self.route
+    }
+
+    /// Returns the number of calls to the route function.
+    pub fn requests(&self) -> u64 {
+        self.requests
+    }
+
+    /// Returns the number of calls that panicked or timed out.
+    pub fn errors(&self) -> u64 {
+        self.errors
+    }
+
+    /// Returns the latency histogram of the calls.
+    pub fn latency(&self) -> &Histogram {
+        &self.latency
+    }
+}
+
+/// A snapshot of the route metrics, returned by [`crate::Plugins::stats`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Stats {
+    routes: Vec<RouteStats>,
+}
+
+impl Stats {
+
+    /// Returns the metrics of every route called at least once, sorted by plugin and route.
+    pub fn routes(&self) -> &[RouteStats] {
+        &self.routes
+    }
+
+    /// Returns the metrics of a route, if it was called.
+    ///
+    /// # Arguments
+    /// * `plugin` - The plugin name.
+    /// * `route` - The route path as declared by the plugin.
+    pub fn route(&self, plugin: &str, route: &str) -> Option<&RouteStats> {
+        self.routes.iter().find(|stats| stats.plugin == plugin && stats.route == route)
+    }
+
+    /// Returns the metrics of the routes of a plugin.
+    pub fn plugin<'a>(&'a self, plugin: &'a str) -> impl Iterator<Item = &'a RouteStats> + 'a {
+        self.routes.iter().filter(move |stats| stats.plugin == plugin)
+    }
+}
+
+/// The metrics of a `Plugins` instance, shared by its routes and kept across reloads.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Metrics {
+    routes: Arc<Mutex<HashMap<(String, String), RouteStats>>>,
+}
+
+impl Metrics {
+
+    /// Records a call to a route function.
+    ///
+    /// # Arguments
+    /// * `plugin` - The plugin name.
+    /// * `route` - The route path as declared by the plugin.
+    /// * `latency` - The time the call took.
+    /// * `failed` - Whether the call panicked or timed out.
+    pub(crate) fn record(&self, plugin: &str, route: &str, latency: Duration, failed: bool) {
+        let labels = [("plugin", plugin.to_string()), ("route", route.to_string())];
+        ::metrics::counter!("plugin_requests_total", &labels).increment(1);
+        if failed {
+            ::metrics::counter!("plugin_errors_total", &labels).increment(1);
+        }
+        ::metrics::histogram!("plugin_request_duration_seconds", &labels).record(latency.as_secs_f64());
+
+        let mut routes = match self.routes.lock() {
+            Ok(routes) => routes,
+            Err(e) => e.into_inner(),
+        };
+        let stats = routes.entry((plugin.to_string(), route.to_string()))
+            .or_insert_with(|| RouteStats {
+                plugin: plugin.to_string(),
+                route: route.to_string(),
+                requests: 0,
+                errors: 0,
+                latency: Histogram::default(),
+            });
+        stats.requests += 1;
+        if failed {
+            stats.errors += 1;
+        }
+        stats.latency.record(latency);
+    }
+
+    /// Returns a snapshot of the metrics.
+    pub(crate) fn snapshot(&self) -> Stats {
+        let routes = match self.routes.lock() {
+            Ok(routes) => routes,
+            Err(e) => e.into_inner(),
+        };
+        let mut routes: Vec<RouteStats> = routes.values().cloned().collect();
+        routes.sort_by(|a, b| (&a.plugin, &a.route).cmp(&(&b.plugin, &b.route)));
+        Stats { routes }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, SlowInvoker, TempDir};
+    use crate::{CannedInvoker, StaticPlugin};
+    use hyper::StatusCode;
+
+    #[test]
+    fn latencies_fill_the_buckets_up_to_their_bound() {
+        let mut histogram = Histogram::default();
+        for latency in [0, 5, 7, 100, 20_000] {
+            histogram.record(Duration::from_millis(latency));
+        }
+
+        let buckets = histogram.buckets();
+        let count = |bound| buckets.iter()
+            .find(|(upper, _)| *upper == Duration::from_millis(bound))
+            .map(|(_, count)| *count);
+        assert_eq!(buckets.len(), BUCKETS.len());
+        assert_eq!(count(1), Some(1));
+        assert_eq!(count(5), Some(2));
+        assert_eq!(count(10), Some(3));
+        assert_eq!(count(50), Some(3));
+        assert_eq!(count(100), Some(4));
+        assert_eq!(count(10_000), Some(4));
+        assert_eq!(histogram.count(), 5);
+        assert_eq!(histogram.sum(), Duration::from_millis(20_112));
+    }
+
+    #[test]
+    fn calls_are_counted_by_plugin_and_route() {
+        let metrics = Metrics::default();
+        metrics.record("b", "/x", Duration::from_millis(2), false);
+        metrics.record("a", "/y", Duration::from_millis(3), true);
+        metrics.record("a", "/x", Duration::from_millis(4), false);
+        metrics.record("a", "/y", Duration::from_millis(30), false);
+
+        let stats = metrics.snapshot();
+        let routes: Vec<(&str, &str)> = stats.routes().iter().map(|route| (route.plugin(), route.route())).collect();
+        assert_eq!(routes, [("a", "/x"), ("a", "/y"), ("b", "/x")]);
+
+        let y = stats.route("a", "/y").unwrap();
+        assert_eq!((y.requests(), y.errors()), (2, 1));
+        assert_eq!(y.latency().count(), 2);
+        assert_eq!(y.latency().sum(), Duration::from_millis(33));
+        assert_eq!(stats.route("b", "/x").map(RouteStats::errors), Some(0));
+        assert_eq!(stats.route("b", "/y"), None);
+        assert_eq!(stats.plugin("a").count(), 2);
+    }
+
+    #[tokio::test]
+    async fn route_calls_are_recorded_across_reloads() {
+        let dir = TempDir::new();
+        let canned = Arc::new(CannedInvoker::new("ok"));
+        let slow = SlowInvoker { delay: Duration::from_millis(30), canned };
+        let plugin = StaticPlugin::new(r#"[
+            {"path": "/slow", "function": "slow", "method_router": "get"},
+            {"path": "/broken", "function": "broken", "method_router": "get"}
+        ]"#)
+            .function("slow", Arc::new(slow))
+            .function("broken", Arc::new(testing::Panicking));
+        let plugins = testing::plugins(&dir, true).register("demo", plugin);
+
+        let router = plugins.load().unwrap();
+        assert_eq!(testing::get(&router, "/demo/slow").await.status(), StatusCode::OK);
+        assert_eq!(testing::get(&router, "/demo/broken").await.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let router = plugins.reload().unwrap();
+        assert_eq!(testing::get(&router, "/demo/slow").await.status(), StatusCode::OK);
+
+        let stats = plugins.stats();
+        let slow = stats.route("demo", "/slow").unwrap();
+        assert_eq!((slow.requests(), slow.errors()), (2, 0));
+        assert!(slow.latency().sum() >= Duration::from_millis(60), "{:?}", slow.latency().sum());
+        let under_25ms = slow.latency().buckets().iter()
+            .find(|(bound, _)| *bound == Duration::from_millis(25))
+            .map(|(_, count)| *count);
+        assert_eq!(under_25ms, Some(0));
+        let broken = stats.route("demo", "/broken").unwrap();
+        assert_eq!((broken.requests(), broken.errors()), (1, 1));
+    }
+}
@@ -0,0 +1,322 @@
+//! A key/value store kept by the host on behalf of the plugins.
+//!
+//! Plugin globals are lost when a plugin is reloaded, and they can't be seen from the
+//! host. A plugin that exports `plugin_store` instead receives a [`HostStore`] when it's
+//! loaded: a table of callbacks reading and writing byte values in the host's
+//! [`KeyValueStore`]. The keys of each plugin are kept apart, and they outlive the
+//! plugin's library, so its state is still there after a reload and can be inspected or
+//! cleared through [`crate::Plugins::store`]. [`MemoryStore`] is the default backend.
+
+use crate::PluginBuffer;
+use std::collections::HashMap;
+use std::ffi::c_void;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+
+/// A store of byte values, namespaced by plugin name.
+pub trait KeyValueStore: Send + Sync {
+
+    /// Returns the value stored by a plugin under a key.
+    fn get(&self, plugin: &str, key: &[u8]) -> Option<Vec<u8>>;
+
+    /// Stores a value for a plugin under a key, replacing any previous value.
+    fn set(&self, plugin: &str, key: &[u8], value: Vec<u8>);
+
+    /// Deletes the value stored by a plugin under a key.
+    ///
+    /// # Returns
+    /// Whether there was a value.
+    fn delete(&self, plugin: &str, key: &[u8]) -> bool;
+
+    /// Deletes all the values of a plugin.
+    fn clear(&self, plugin: &str);
+}
+
+/// The values of the plugins, by plugin name and key.
+type Values = HashMap<String, HashMap<Vec<u8>, Vec<u8>>>;
+
+/// The default in-memory store. Its values are lost when the process exits.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    plugins: Mutex<Values>,
+}
+
+impl MemoryStore {
+
+    /// Creates an empty in-memory store.
+    pub fn new() -> Self {
+        MemoryStore::default()
+    }
+
+    /// Runs a closure on the values of all the plugins.
+    fn with<T>(&self, f: impl FnOnce(&mut Values) -> T) -> T {
+        match self.plugins.lock() {
+            Ok(mut plugins) => f(&mut plugins),
+            Err(e) => f(&mut e.into_inner()),
+        }
+    }
+}
+
+impl KeyValueStore for MemoryStore {
+
+    fn get(&self, plugin: &str, key: &[u8]) -> Option<Vec<u8>> {
+        self.with(|plugins| plugins.get(plugin)?.get(key).cloned())
+    }
+
+    fn set(&self, plugin: &str, key: &[u8], value: Vec<u8>) {
+        self.with(|plugins| {
+            plugins.entry(plugin.to_string()).or_default().insert(key.to_vec(), value);
+        });
+    }
+
+    fn delete(&self, plugin: &str, key: &[u8]) -> bool {
+        self.with(|plugins| {
+            plugins.get_mut(plugin).is_some_and(|values| values.remove(key).is_some())
+        })
+    }
+
+    fn clear(&self, plugin: &str) {
+        self.with(|plugins| {
+            plugins.remove(plugin);
+        });
+    }
+}
+
+/// The store of a `Plugins` instance, shared by the plugins it loads and their reloads.
+#[derive(Clone)]
+pub(crate) struct SharedStore(pub(crate) Arc<dyn KeyValueStore>);
+
+impl Default for SharedStore {
+    fn default() -> Self {
+        SharedStore(Arc::new(MemoryStore::new()))
+    }
+}
+
+impl std::fmt::Debug for SharedStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SharedStore").finish_non_exhaustive()
+    }
+}
+
+/// The callbacks a plugin uses to access its values in the host's store, passed to its
+/// `plugin_store` function.
+///
+/// Every callback takes `context` as its first argument. Keys and values are byte
+/// buffers given as a pointer and a length. The table and its context stay valid until
+/// the plugin is unloaded, after its `plugin_shutdown` hook, so the plugin can keep the
+/// pointer. The callbacks are thread-safe.
+#[repr(C)]
+#[derive(Debug)]
+pub struct HostStore {
+    /// The host's context, passed back to every callback.
+    pub context: *mut c_void,
+    /// Returns the value of a key, to be released with `free`, or a buffer with a null
+    /// `data` pointer if the key has no value.
+    pub get: extern "C" fn(*mut c_void, *const u8, usize) -> PluginBuffer,
+    /// Stores the value of a key: `context`, key, key length, value, value length.
+    pub set: extern "C" fn(*mut c_void, *const u8, usize, *const u8, usize),
+    /// Deletes the value of a key, returning whether there was one.
+    pub delete: extern "C" fn(*mut c_void, *const u8, usize) -> bool,
+    /// Releases a value returned by `get`, given its pointer and length.
+    pub free: extern "C" fn(*mut u8, usize),
+}
+
+/// Signature of the optional plugin function receiving the [`HostStore`] when the
+/// plugin is loaded.
+pub type PluginStoreFn = extern "C" fn(*const HostStore);
+
+/// The context of the callbacks: the store and the plugin whose keys they access.
+struct StoreContext {
+    store: Arc<dyn KeyValueStore>,
+    plugin: String,
+}
+
+/// The callback table handed to a plugin, owned by its loaded plugin so it lives as long
+/// as the library.
+pub(crate) struct AttachedStore {
+    _context: Box<StoreContext>,
+    api: Box<HostStore>,
+}
+
+// The raw context pointer only refers to the boxed context, which is thread-safe
+unsafe impl Send for AttachedStore {}
+unsafe impl Sync for AttachedStore {}
+
+impl std::fmt::Debug for AttachedStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AttachedStore").finish_non_exhaustive()
+    }
+}
+
+impl AttachedStore {
+
+    /// Builds the callback table of a plugin.
+    pub(crate) fn new(store: &SharedStore, plugin: &str) -> Self {
+        let mut context = Box::new(StoreContext {
+            store: store.0.clone(),
+            plugin: plugin.to_string(),
+        });
+        let api = Box::new(HostStore {
+            context: &mut *context as *mut StoreContext as *mut c_void,
+            get: store_get,
+            set: store_set,
+            delete: store_delete,
+            free: store_free,
+        });
+        AttachedStore {
+            _context: context,
+            api,
+        }
+    }
+
+    /// Returns the pointer passed to the plugin's `plugin_store` function.
+    pub(crate) fn api(&self) -> *const HostStore {
+        &*self.api
+    }
+}
+
+/// Borrows a key or value passed by a plugin.
+///
+/// # Safety
+/// `data` must be null or point to `len` readable bytes.
+unsafe fn bytes<'a>(data: *const u8, len: usize) -> &'a [u8] {
+    if data.is_null() || len == 0 {
+        &[]
+    } else {
+        std::slice::from_raw_parts(data, len)
+    }
+}
+
+/// Runs a callback on the context, a panic in the backend can't unwind into the plugin.
+fn with_context<T>(context: *mut c_void, fallback: T, f: impl FnOnce(&StoreContext) -> T) -> T {
+    let context = unsafe { &*(context as *const StoreContext) };
+    panic::catch_unwind(AssertUnwindSafe(|| f(context))).unwrap_or(fallback)
+}
+
+extern "C" fn store_get(context: *mut c_void, key: *const u8, key_len: usize) -> PluginBuffer {
+    let missing = PluginBuffer { data: std::ptr::null(), len: 0 };
+    with_context(context, missing, |context| {
+        let key = unsafe { bytes(key, key_len) };
+        match context.store.get(&context.plugin, key) {
+            Some(value) => {
+                let value = value.into_boxed_slice();
+                let len = value.len();
+                PluginBuffer { data: Box::into_raw(value) as *const u8, len }
+            },
+            None => missing,
+        }
+    })
+}
+
+extern "C" fn store_set(
+    context: *mut c_void,
+    key: *const u8,
+    key_len: usize,
+    value: *const u8,
+    value_len: usize,
+) {
+    with_context(context, (), |context| {
+        let (key, value) = unsafe { (bytes(key, key_len), bytes(value, value_len)) };
+        context.store.set(&context.plugin, key, value.to_vec());
+    })
+}
+
+extern "C" fn store_delete(context: *mut c_void, key: *const u8, key_len: usize) -> bool {
+    with_context(context, false, |context| {
+        let key = unsafe { bytes(key, key_len) };
+        context.store.delete(&context.plugin, key)
+    })
+}
+
+extern "C" fn store_free(data: *mut u8, len: usize) {
+    if !data.is_null() {
+        unsafe { drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(data, len))) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TempDir};
+
+    /// The C source of a plugin counting its requests in the host's store.
+    const COUNTER: &str = r#"
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+typedef struct { const uint8_t *data; size_t len; } PluginBuffer;
+
+typedef struct {
+    void *context;
+    PluginBuffer (*get)(void *context, const uint8_t *key, size_t key_len);
+    void (*set)(void *context, const uint8_t *key, size_t key_len, const uint8_t *value, size_t value_len);
+    bool (*delete)(void *context, const uint8_t *key, size_t key_len);
+    void (*free)(uint8_t *data, size_t len);
+} HostStore;
+
+static const HostStore *store;
+static char answer[32];
+
+uint32_t plugin_abi_version(void) { return 1; }
+void plugin_store(const HostStore *host_store) { store = host_store; }
+const char *routes(void) {
+    return "[{\"path\": \"/\", \"function\": \"count\", \"method_router\": \"get\"}]";
+}
+const char *count(void *headers, const char *body) {
+    (void)headers; (void)body;
+    long count = 0;
+    PluginBuffer value = store->get(store->context, (const uint8_t *)"count", 5);
+    if (value.data) {
+        char digits[32] = {0};
+        for (size_t i = 0; i < value.len && i < sizeof(digits) - 1; i++) digits[i] = (char)value.data[i];
+        count = strtol(digits, NULL, 10);
+        store->free((uint8_t *)value.data, value.len);
+    }
+    int len = snprintf(answer, sizeof(answer), "%ld", count + 1);
+    store->set(store->context, (const uint8_t *)"count", 5, (const uint8_t *)answer, (size_t)len);
+    return answer;
+}
+void free(void *ptr) { (void)ptr; }
+"#;
+
+    #[tokio::test]
+    async fn plugin_counter_is_kept_across_requests_and_reloads() {
+        let dir = TempDir::new();
+        let plugin_dir = dir.plugin("counter", &testing::config("counter"), None);
+        if !testing::compile_plugin(&plugin_dir, COUNTER) {
+            return;
+        }
+        let plugins = testing::plugins(&dir, true);
+
+        let router = plugins.load().unwrap();
+        assert_eq!(testing::body_string(testing::get(&router, "/counter").await).await, "1");
+        assert_eq!(testing::body_string(testing::get(&router, "/counter").await).await, "2");
+
+        let router = plugins.reload().unwrap();
+        assert_eq!(testing::body_string(testing::get(&router, "/counter").await).await, "3");
+        assert_eq!(plugins.store().get("counter", b"count"), Some(b"3".to_vec()));
+
+        plugins.store().clear("counter");
+        assert_eq!(testing::body_string(testing::get(&router, "/counter").await).await, "1");
+    }
+
+    #[test]
+    fn callbacks_only_see_the_keys_of_their_plugin() {
+        let store = SharedStore::default();
+        let attached = [AttachedStore::new(&store, "first"), AttachedStore::new(&store, "second")];
+        let (first, second) = unsafe { (&*attached[0].api(), &*attached[1].api()) };
+
+        (first.set)(first.context, b"key".as_ptr(), 3, b"value".as_ptr(), 5);
+
+        let value = (first.get)(first.context, b"key".as_ptr(), 3);
+        assert_eq!(unsafe { std::slice::from_raw_parts(value.data, value.len) }, b"value");
+        (first.free)(value.data as *mut u8, value.len);
+        assert!((second.get)(second.context, b"key".as_ptr(), 3).data.is_null());
+        assert!(!(second.delete)(second.context, b"key".as_ptr(), 3));
+        assert!((first.delete)(first.context, b"key".as_ptr(), 3));
+        assert_eq!(store.0.get("first", b"key"), None);
+    }
+}
@@ -0,0 +1,447 @@
+//! Chunked responses for routes producing output of unknown length incrementally.
+//!
+//! A route declared with `"stream": true` is called through the streaming ABI: the plugin
+//! passes each chunk of its output to a callback as soon as it's produced, and the host
+//! sends it to the client right away. The response has no `Content-Length`, so HTTP/1.1
+//! clients receive it with `Transfer-Encoding: chunked`.
+//!
+//! A route also declaring `"request_stream": true` is called through the duplex ABI and
+//! reads its request body through a second callback as it's received, so a plugin can
+//! pass a large upload through to an upstream without either body being held in memory.
+//! Both directions are bounded: when the plugin reads slower than the client sends, or
+//! the client reads slower than the plugin emits, the faster side waits.
+//!
+//! Routes with the `sse` response type are always streamed and sent as Server-Sent
+//! Events: each chunk is the data of one event, unless it's already a complete event
+//! ending with a blank line, and the stream ends when the plugin function returns.
+
+use crate::drain::{self, InFlight};
+use crate::log::error;
+use crate::{panic::{self, PanicPolicy}, PluginInvoker};
+use axum::{body::{Body, Bytes}, response::Response};
+use hyper::{
+    body::{Frame, SizeHint},
+    header::{self, HeaderMap, HeaderValue},
+};
+use std::convert::Infallible;
+use std::io::{self, Read};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::sync::mpsc;
+
+/// The number of chunks buffered before the plugin is blocked waiting for the client.
+const BUFFERED_CHUNKS: usize = 16;
+
+/// Calls a streaming plugin function on a blocking task and streams its chunks.
+///
+/// # Arguments
+/// * `invoker` - The invoker of the route function.
+/// * `headers` - The request headers.
+/// * `body` - The request body.
+/// * `content_type` - The `Content-Type` of the response.
+/// * `events` - Whether the chunks are sent as Server-Sent Events.
+/// * `panic_policy` - The panic policy of the plugin.
+///
+/// # Returns
+/// An Axum response whose body is sent as the plugin produces it.
+pub(crate) fn response(
+    invoker: Arc<dyn PluginInvoker>,
+    headers: HeaderMap,
+    body: String,
+    content_type: &'static str,
+    events: bool,
+    panic_policy: PanicPolicy,
+) -> Response {
+
+    let (sender, receiver) = mpsc::channel(BUFFERED_CHUNKS);
+    let in_flight = drain::current();
+    let call_in_flight = in_flight.clone();
+
+    tokio::task::spawn_blocking(move || {
+        let _in_flight = call_in_flight;
+        let result = panic::catch(panic_policy, || {
+            invoker.invoke_stream(headers, body, &mut |chunk| send(&sender, Bytes::from(chunk), events));
+        });
+        // The response has started, dropping the sender ends it early
+        if let Err(e) = result {
+            error!("{}: {}", panic::MESSAGE, e);
+        }
+    });
+
+    chunked_response(receiver, in_flight, content_type, events)
+}
+
+/// Sends a chunk of the plugin output to the response body.
+///
+/// # Returns
+/// Whether the client is still there.
+fn send(sender: &mpsc::Sender<Bytes>, chunk: Bytes, events: bool) -> bool {
+    // Empty chunks would end a chunked body early, there's nothing to send anyway
+    if chunk.is_empty() {
+        return true;
+    }
+    // Events are text, other chunks are sent as they are
+    let chunk = if events { Bytes::from(event(&String::from_utf8_lossy(&chunk))) } else { chunk };
+    sender.blocking_send(chunk).is_ok()
+}
+
+/// Formats a chunk as a Server-Sent Event: a chunk ending with a blank line is already
+/// an event, any other chunk is the event data, one `data:` field per line.
+fn event(chunk: &str) -> String {
+    if chunk.ends_with("\n\n") {
+        return chunk.to_string();
+    }
+    let mut event: String = chunk.lines().map(|line| format!("data: {}\n", line)).collect();
+    event.push('\n');
+    event
+}
+
+/// Builds the response streaming the chunks received from the plugin, keeping its
+/// request in flight until the body is sent or dropped.
+fn chunked_response(
+    receiver: mpsc::Receiver<Bytes>,
+    in_flight: Option<InFlight>,
+    content_type: &'static str,
+    events: bool,
+) -> Response {
+    let mut response = Response::new(Body::new(ChunkBody { receiver, _in_flight: in_flight }));
+    response.headers_mut().insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+    if events {
+        // Caches and buffering proxies would hold the events back
+        response.headers_mut().insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+        response.headers_mut().insert("x-accel-buffering", HeaderValue::from_static("no"));
+    }
+    response
+}
+
+/// Calls a duplex streaming plugin function on a blocking task, feeding it the request
+/// body as it's received and streaming its chunks.
+///
+/// # Arguments
+/// * `invoker` - The invoker of the route function.
+/// * `headers` - The request headers.
+/// * `body` - The request body, not read yet.
+/// * `content_type` - The `Content-Type` of the response.
+/// * `events` - Whether the chunks are sent as Server-Sent Events.
+/// * `panic_policy` - The panic policy of the plugin.
+///
+/// # Returns
+/// An Axum response whose body is sent as the plugin produces it.
+pub(crate) fn duplex_response(
+    invoker: Arc<dyn PluginInvoker>,
+    headers: HeaderMap,
+    body: Body,
+    content_type: &'static str,
+    events: bool,
+    panic_policy: PanicPolicy,
+) -> Response {
+
+    let (body_sender, body_receiver) = mpsc::channel(BUFFERED_CHUNKS);
+    tokio::spawn(async move {
+        let mut body = body;
+        while let Some(frame) = std::future::poll_fn(|cx| hyper::body::Body::poll_frame(Pin::new(&mut body), cx)).await {
+            let data = match frame {
+                Ok(frame) => match frame.into_data() {
+                    Ok(data) => Ok(data),
+                    // Trailers carry no body data
+                    Err(_) => continue,
+                },
+                Err(e) => Err(io::Error::other(e)),
+            };
+            let failed = data.is_err();
+            if body_sender.send(data).await.is_err() || failed {
+                break;
+            }
+        }
+    });
+
+    let (sender, receiver) = mpsc::channel(BUFFERED_CHUNKS);
+    let in_flight = drain::current();
+    let call_in_flight = in_flight.clone();
+    tokio::task::spawn_blocking(move || {
+        let _in_flight = call_in_flight;
+        let mut reader = BodyReader {
+            receiver: body_receiver,
+            pending: Bytes::new(),
+        };
+        let result = panic::catch(panic_policy, || {
+            invoker.invoke_duplex(headers, &mut reader, &mut |chunk| send(&sender, chunk, events));
+        });
+        if let Err(e) = result {
+            error!("{}: {}", panic::MESSAGE, e);
+        }
+    });
+
+    chunked_response(receiver, in_flight, content_type, events)
+}
+
+/// A blocking reader over the request body chunks forwarded by the async task polling
+/// the body.
+struct BodyReader {
+    receiver: mpsc::Receiver<io::Result<Bytes>>,
+    pending: Bytes,
+}
+
+impl Read for BodyReader {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+        while self.pending.is_empty() {
+            match self.receiver.blocking_recv() {
+                Some(Ok(chunk)) => self.pending = chunk,
+                Some(Err(e)) => return Err(e),
+                None => return Ok(0),
+            }
+        }
+
+        let len = buffer.len().min(self.pending.len());
+        buffer[..len].copy_from_slice(&self.pending.split_to(len));
+        Ok(len)
+    }
+}
+
+/// A response body made of the chunks received from the plugin, ending when the plugin
+/// function returns.
+struct ChunkBody {
+    receiver: mpsc::Receiver<Bytes>,
+    _in_flight: Option<InFlight>,
+}
+
+impl hyper::body::Body for ChunkBody {
+    type Data = Bytes;
+    type Error = Infallible;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        self.receiver.poll_recv(cx).map(|chunk| chunk.map(|chunk| Ok(Frame::data(chunk))))
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        // The length is unknown until the plugin is done
+        SizeHint::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TempDir};
+    use crate::StaticPlugin;
+    use hyper::body::Body as _;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{mpsc as std_mpsc, Mutex};
+
+    /// An invoker emitting its chunks one by one, each after the test allows it.
+    struct Chunks {
+        chunks: Vec<&'static str>,
+        allowed: Mutex<std_mpsc::Receiver<()>>,
+    }
+
+    impl PluginInvoker for Chunks {
+        fn invoke(&self, _headers: HeaderMap, _body: String) -> String {
+            self.chunks.concat()
+        }
+
+        fn invoke_stream(&self, _headers: HeaderMap, _body: String, emit: &mut dyn FnMut(String) -> bool) {
+            let allowed = self.allowed.lock().unwrap();
+            for chunk in &self.chunks {
+                if allowed.recv().is_err() || !emit(chunk.to_string()) {
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Loads a plugin streaming `chunks` at `GET /`, each emitted once allowed through
+    /// the returned sender.
+    fn router(dir: &TempDir, chunks: Vec<&'static str>) -> (axum::Router, std_mpsc::Sender<()>) {
+        let (allow, allowed) = std_mpsc::channel();
+        let plugin = StaticPlugin::new(r#"[{"path": "/", "function": "chunks", "method_router": "get", "stream": true}]"#)
+            .function("chunks", Arc::new(Chunks { chunks, allowed: Mutex::new(allowed) }));
+        let router = testing::plugins(dir, true)
+            .register("feed", plugin)
+            .load()
+            .unwrap();
+        (router, allow)
+    }
+
+    #[tokio::test]
+    async fn chunks_are_received_as_the_plugin_emits_them() {
+        let dir = TempDir::new();
+        let (router, allow) = router(&dir, vec!["first", "second"]);
+
+        let response = testing::get(&router, "/feed").await;
+        assert!(!response.headers().contains_key(header::CONTENT_LENGTH));
+        let mut body = response.into_body();
+
+        for chunk in ["first", "second"] {
+            allow.send(()).unwrap();
+            let frame = std::future::poll_fn(|cx| Pin::new(&mut body).poll_frame(cx)).await;
+            assert_eq!(frame.unwrap().unwrap().into_data().unwrap(), chunk);
+        }
+        drop(allow);
+        assert!(std::future::poll_fn(|cx| Pin::new(&mut body).poll_frame(cx)).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn streamed_response_is_sent_chunked_over_http_1_1() {
+        let dir = TempDir::new();
+        let (router, allow) = router(&dir, vec!["hello ", "world"]);
+        allow.send(()).unwrap();
+        allow.send(()).unwrap();
+        let addr = testing::serve(router).await;
+
+        let response = testing::raw_request(
+            addr,
+            "GET /feed HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        ).await.to_ascii_lowercase();
+
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.contains("\r\ntransfer-encoding: chunked"), "{}", head);
+        assert!(!head.contains("content-length"), "{}", head);
+        assert_eq!(body, "6\r\nhello \r\n5\r\nworld\r\n0\r\n\r\n");
+    }
+
+    /// An invoker passing its request body through to the response as it's read.
+    struct PassThrough;
+
+    impl PluginInvoker for PassThrough {
+        fn invoke(&self, _headers: HeaderMap, body: String) -> String {
+            body
+        }
+
+        fn invoke_duplex(&self, _headers: HeaderMap, body: &mut dyn Read, emit: &mut dyn FnMut(Bytes) -> bool) {
+            let mut buffer = [0u8; 8192];
+            loop {
+                match body.read(&mut buffer) {
+                    Ok(0) | Err(_) => return,
+                    Ok(len) => if !emit(Bytes::copy_from_slice(&buffer[..len])) {
+                        return;
+                    },
+                }
+            }
+        }
+    }
+
+    /// A request body of `remaining` bytes generated as it's read, counting the bytes
+    /// handed out in `sent`.
+    struct Generated {
+        remaining: usize,
+        sent: Arc<AtomicUsize>,
+    }
+
+    const CHUNK: usize = 64 * 1024;
+
+    impl hyper::body::Body for Generated {
+        type Data = Bytes;
+        type Error = Infallible;
+
+        fn poll_frame(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+            let len = self.remaining.min(CHUNK);
+            if len == 0 {
+                return Poll::Ready(None);
+            }
+            self.remaining -= len;
+            self.sent.fetch_add(len, Ordering::SeqCst);
+            Poll::Ready(Some(Ok(Frame::data(Bytes::from(vec![b'a'; len])))))
+        }
+    }
+
+    #[tokio::test]
+    async fn large_body_is_passed_through_without_being_buffered() {
+        const SIZE: usize = 32 * 1024 * 1024;
+        // Both directions buffer up to BUFFERED_CHUNKS chunks, plus the ones in transit
+        const BOUND: usize = 4 * BUFFERED_CHUNKS * CHUNK;
+
+        let dir = TempDir::new();
+        let plugin = StaticPlugin::new(
+            r#"[{"path": "/", "function": "pass", "method_router": "post", "stream": true, "request_stream": true}]"#,
+        ).function("pass", Arc::new(PassThrough));
+        let router = testing::plugins(&dir, true)
+            .register("proxy", plugin)
+            .load()
+            .unwrap();
+        let sent = Arc::new(AtomicUsize::new(0));
+        let request = axum::extract::Request::post("/proxy")
+            .body(Body::new(Generated { remaining: SIZE, sent: sent.clone() }))
+            .unwrap();
+
+        let mut body = testing::send(&router, request).await.into_body();
+        let mut received = 0;
+        while let Some(frame) = std::future::poll_fn(|cx| Pin::new(&mut body).poll_frame(cx)).await {
+            received += frame.unwrap().into_data().unwrap().len();
+            let in_flight = sent.load(Ordering::SeqCst) - received;
+            assert!(in_flight <= BOUND, "{} bytes in flight", in_flight);
+        }
+
+        assert_eq!(received, SIZE);
+    }
+
+    /// A request body with NUL bytes and invalid UTF-8.
+    const BINARY: &[u8] = b"\x00\xff\xfebinary\x00\x80\xc3(end";
+
+    /// Sends `BINARY` to `POST /proxy` and returns the response body.
+    async fn pass_binary(router: &axum::Router) -> Bytes {
+        let request = axum::extract::Request::post("/proxy").body(Body::from(BINARY)).unwrap();
+        let response = testing::send(router, request).await;
+        axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn binary_body_is_passed_through_unchanged() {
+        let dir = TempDir::new();
+        let plugin = StaticPlugin::new(
+            r#"[{"path": "/", "function": "pass", "method_router": "post", "stream": true, "request_stream": true}]"#,
+        ).function("pass", Arc::new(PassThrough));
+        let router = testing::plugins(&dir, true)
+            .register("proxy", plugin)
+            .load()
+            .unwrap();
+
+        assert_eq!(&pass_binary(&router).await[..], BINARY);
+    }
+
+    #[tokio::test]
+    async fn binary_body_is_passed_through_a_library_unchanged() {
+        let dir = TempDir::new();
+        let plugin_dir = dir.plugin("proxy", &testing::config("proxy"), None);
+        let source = r#"
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <sys/types.h>
+
+typedef ssize_t (*ReadFn)(void *context, uint8_t *buffer, size_t len);
+typedef bool (*BytesChunkFn)(void *context, const uint8_t *chunk, size_t len);
+
+uint32_t plugin_abi_version(void) { return 1; }
+const char *routes(void) {
+    return "[{\"path\": \"/\", \"function\": \"pass\", \"method_router\": \"post\", \"stream\": true, \"request_stream\": true}]";
+}
+void pass(void *headers, ReadFn read, void *read_context, BytesChunkFn emit, void *emit_context) {
+    (void)headers;
+    uint8_t buffer[4];
+    for (;;) {
+        ssize_t len = read(read_context, buffer, sizeof(buffer));
+        if (len <= 0 || !emit(emit_context, buffer, (size_t)len)) {
+            return;
+        }
+    }
+}
+void free(void *ptr) { (void)ptr; }
+"#;
+        if !testing::compile_plugin(&plugin_dir, source) {
+            return;
+        }
+        let router = testing::plugins(&dir, true).load().unwrap();
+
+        assert_eq!(&pass_binary(&router).await[..], BINARY);
+    }
+}
@@ -0,0 +1,122 @@
+//! Server-side rendering of plugin data through host-registered Tera templates.
+//!
+//! A route declared with `"response_type": "template"` returns a JSON object naming the
+//! template and the data to render it with, so the plugin doesn't need to bundle a
+//! template engine:
+//!
+//! ```json
+//! {
+//!   "template": "plugin_page.html",
+//!   "data": { "title": "Hello", "items": [1, 2, 3] }
+//! }
+//! ```
+
+use crate::log::error;
+use axum::response::{Html, IntoResponse, Response};
+use hyper::StatusCode;
+use serde::Deserialize;
+use serde_json::Value;
+use tera::{Context, Tera};
+
+/// The JSON object returned by template routes.
+#[derive(Debug, Deserialize)]
+struct TemplateResponse {
+    template: String,
+    #[serde(default)]
+    data: Value,
+}
+
+/// Renders the plugin output through the named template.
+///
+/// # Arguments
+/// * `tera` - The host-registered templates.
+/// * `output` - The JSON object returned by the plugin.
+///
+/// # Returns
+/// An HTML response, or a `500 Internal Server Error` if the output is invalid or the
+/// template fails to render.
+pub(crate) fn render(tera: &Tera, output: &str) -> Response {
+
+    let response: TemplateResponse = match serde_json::from_str(output) {
+        Ok(response) => response,
+        Err(e) => {
+            error!("Error parsing template response: {}", e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Error parsing template response: {}", e),
+            ).into_response();
+        }
+    };
+
+    let context = match response.data {
+        Value::Null => Context::new(),
+        data => match Context::from_value(data) {
+            Ok(context) => context,
+            Err(e) => {
+                error!("Error building template context: {}", e);
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("Error building template context: {}", e),
+                ).into_response();
+            }
+        },
+    };
+
+    match tera.render(&response.template, &context) {
+        Ok(rendered) => Html(rendered).into_response(),
+        Err(e) => {
+            error!("Error rendering template {}: {}", response.template, e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Error rendering template {}", response.template),
+            ).into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{self, TempDir};
+    use hyper::{header, StatusCode};
+    use tera::Tera;
+
+    #[tokio::test]
+    async fn plugin_json_is_rendered_through_a_registered_template() {
+        let dir = TempDir::new();
+        let mut tera = Tera::default();
+        tera.add_raw_template("page.html", "<h1>{{ title }}</h1>{% for item in items %}<i>{{ item }}</i>{% endfor %}").unwrap();
+        let (plugin, _) = testing::route_plugin(
+            r#"{"path": "/", "method_router": "get", "response_type": "template"}"#,
+            r#"{"template": "page.html", "data": {"title": "Hello", "items": [1, 2]}}"#,
+        );
+        let router = testing::plugins(&dir, true)
+            .templates(tera)
+            .register("pages", plugin)
+            .load()
+            .unwrap();
+
+        let response = testing::get(&router, "/pages").await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/html; charset=utf-8");
+        assert_eq!(testing::body_string(response).await, "<h1>Hello</h1><i>1</i><i>2</i>");
+    }
+
+    #[tokio::test]
+    async fn unknown_template_is_an_error() {
+        let dir = TempDir::new();
+        let (plugin, _) = testing::route_plugin(
+            r#"{"path": "/", "method_router": "get", "response_type": "template"}"#,
+            r#"{"template": "missing.html"}"#,
+        );
+        let router = testing::plugins(&dir, true)
+            .templates(Tera::default())
+            .register("pages", plugin)
+            .load()
+            .unwrap();
+
+        let response = testing::get(&router, "/pages").await;
+
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+}
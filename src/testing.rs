@@ -0,0 +1,398 @@
+//! Helpers shared by the unit tests: temporary plugins directories and requests sent
+//! through a plugins router without a server.
+
+use crate::{CannedInvoker, PluginInvoker, Plugins, StaticPlugin};
+use axum::{body::Body, extract::Request, response::Response, Router};
+use hyper::HeaderMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tower::ServiceExt;
+
+/// A directory removed with its content when dropped.
+pub(crate) struct TempDir(PathBuf);
+
+impl TempDir {
+
+    /// Creates a new empty directory under the system's temporary directory.
+    pub(crate) fn new() -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "axum-router-plugin-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::SeqCst),
+        ));
+        std::fs::create_dir_all(&path).expect("create temporary directory");
+        TempDir(path)
+    }
+
+    /// Returns the path of the directory.
+    pub(crate) fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// Adds a plugin directory holding a `plugin.json` and, if `library` is set, a copy
+    /// of one of the prebuilt example libraries of the `plugins` directory as `lib.so`.
+    ///
+    /// # Arguments
+    /// * `dir` - The name of the plugin directory.
+    /// * `config` - The content of its `plugin.json`.
+    /// * `library` - The example library, e.g. `foo-bar/libarp_foo_bar.so`.
+    ///
+    /// # Returns
+    /// The path of the plugin directory.
+    pub(crate) fn plugin(&self, dir: &str, config: &str, library: Option<&str>) -> PathBuf {
+        let plugin_dir = self.0.join(dir);
+        std::fs::create_dir_all(&plugin_dir).expect("create plugin directory");
+        std::fs::write(plugin_dir.join("plugin.json"), config).expect("write plugin.json");
+        if let Some(library) = library {
+            let source = Path::new(env!("CARGO_MANIFEST_DIR")).join("plugins").join(library);
+            std::fs::copy(source, plugin_dir.join("lib.so")).expect("copy example library");
+        }
+        plugin_dir
+    }
+}
+
+/// Compiles a plugin library from C source with the system's C compiler, for the tests
+/// needing symbols the example libraries don't export.
+///
+/// # Arguments
+/// * `plugin_dir` - The directory the library is written to, as `lib.so`.
+/// * `source` - The C source.
+///
+/// # Returns
+/// `false` if no C compiler is available, in which case the test is skipped.
+pub(crate) fn compile_plugin(plugin_dir: &Path, source: &str) -> bool {
+    compile_library(&plugin_dir.join("lib.so"), source)
+}
+
+/// Compiles a shared library from C source with the system's C compiler. Undefined
+/// symbols are left to be resolved when the library is opened.
+///
+/// # Arguments
+/// * `library` - The path of the library, its source is written next to it.
+/// * `source` - The C source.
+///
+/// # Returns
+/// `false` if no C compiler is available, in which case the test is skipped.
+pub(crate) fn compile_library(library: &Path, source: &str) -> bool {
+    let source_path = library.with_extension("c");
+    std::fs::write(&source_path, source).expect("write library source");
+    let status = std::process::Command::new("cc")
+        .args(["-shared", "-fPIC", "-o"])
+        .arg(library)
+        .arg(&source_path)
+        .status();
+    match status {
+        Ok(status) if status.success() => true,
+        Ok(status) => panic!("compiling {} failed: {}", source_path.display(), status),
+        Err(e) => {
+            eprintln!("Skipping test: no C compiler: {}", e);
+            false
+        },
+    }
+}
+
+/// The C source of a plugin serving `GET /` with `answer` and recording its `init` and
+/// `shutdown` hooks, one per line, in the file `events`.
+pub(crate) fn hooks_plugin(answer: &str, events: &Path) -> String {
+    format!(
+        r#"
+#include <stdint.h>
+#include <stdio.h>
+
+static void record(const char *event) {{
+    FILE *file = fopen("{events}", "a");
+    if (file) {{
+        fprintf(file, "%s\n", event);
+        fclose(file);
+    }}
+}}
+
+uint32_t plugin_abi_version(void) {{ return 1; }}
+void plugin_init(const char *config) {{ (void)config; record("init"); }}
+void plugin_shutdown(void) {{ record("shutdown"); }}
+const char *routes(void) {{
+    return "[{{\"path\": \"/\", \"function\": \"answer\", \"method_router\": \"get\"}}]";
+}}
+const char *answer(void *headers, const char *body) {{
+    (void)headers; (void)body;
+    return "{answer}";
+}}
+void free(void *ptr) {{ (void)ptr; }}
+"#,
+        events = events.display(),
+        answer = answer,
+    )
+}
+
+/// The C source of a plugin declaring no routes and recording `open` in the file
+/// `events` when its library is opened.
+pub(crate) fn open_events_plugin(events: &Path) -> String {
+    format!(
+        r#"
+#include <stdint.h>
+#include <stdio.h>
+
+__attribute__((constructor)) static void opened(void) {{
+    FILE *file = fopen("{events}", "a");
+    if (file) {{
+        fprintf(file, "open\n");
+        fclose(file);
+    }}
+}}
+
+uint32_t plugin_abi_version(void) {{ return 1; }}
+const char *routes(void) {{ return "[]"; }}
+void free(void *ptr) {{ (void)ptr; }}
+"#,
+        events = events.display(),
+    )
+}
+
+/// Returns the hook events recorded by a [`hooks_plugin`].
+pub(crate) fn events(events: &Path) -> Vec<String> {
+    std::fs::read_to_string(events)
+        .unwrap_or_default()
+        .lines()
+        .map(str::to_string)
+        .collect()
+}
+
+/// The `plugin.json` of an enabled plugin whose library was added as `lib.so`.
+pub(crate) fn config(name: &str) -> String {
+    format!(r#"{{"name": "{}", "version": "0.1.0", "lib_path": "lib.so", "enabled": true}}"#, name)
+}
+
+/// The prebuilt example library declaring `GET /test-get`, `POST /test-post`,
+/// `POST /test-json` and `GET /about`, which answers with text starting with
+/// `Name: arp-foo-bar`. It doesn't export `plugin_abi_version`, so it's only loaded with
+/// `allow_unversioned_plugins`.
+pub(crate) const FOO_BAR: &str = "foo-bar/libarp_foo_bar.so";
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// A plugin declaring a single route answered with the canned `response` by its
+/// `handler` function.
+///
+/// # Arguments
+/// * `route` - The route as a JSON object, without its `function`.
+/// * `response` - The raw output of the function.
+///
+/// # Returns
+/// The plugin, and the invoker of its function to count the calls.
+pub(crate) fn route_plugin(route: &str, response: &str) -> (StaticPlugin, Arc<CannedInvoker>) {
+    let mut route: serde_json::Value = serde_json::from_str(route).expect("valid route JSON");
+    route["function"] = "handler".into();
+    let invoker = Arc::new(CannedInvoker::new(response));
+    let plugin = StaticPlugin::new(serde_json::Value::Array(vec![route]).to_string())
+        .function("handler", invoker.clone());
+    (plugin, invoker)
+}
+
+/// Returns a `Plugins` instance loading its plugins from `dir`, with the plugin names
+/// added to the routes or not.
+pub(crate) fn plugins(dir: &TempDir, name_to_route: bool) -> Plugins {
+    Plugins::new(Some(name_to_route)).plugins_dir(dir.path())
+}
+
+/// Sends a request through a router and returns the response.
+pub(crate) async fn send(router: &Router, request: Request) -> Response {
+    router.clone().oneshot(request).await.expect("infallible router")
+}
+
+/// Sends a `GET` request without a body through a router.
+pub(crate) async fn get(router: &Router, uri: &str) -> Response {
+    send(router, Request::get(uri).body(Body::empty()).expect("valid request")).await
+}
+
+/// Reads the body of a response as a string.
+pub(crate) async fn body_string(response: Response) -> String {
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.expect("readable body");
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// An invoker answering like a [`CannedInvoker`] after a delay, to keep requests in flight.
+pub(crate) struct SlowInvoker {
+    pub(crate) delay: Duration,
+    pub(crate) canned: Arc<CannedInvoker>,
+}
+
+impl PluginInvoker for SlowInvoker {
+    fn invoke(&self, headers: HeaderMap, body: String) -> String {
+        std::thread::sleep(self.delay);
+        self.canned.invoke(headers, body)
+    }
+}
+
+/// Serves a router on a local port, for the tests needing a real HTTP/1.1 connection.
+///
+/// # Returns
+/// The address the router is served on.
+pub(crate) async fn serve(router: Router) -> SocketAddr {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.expect("bind a local port");
+    let addr = listener.local_addr().expect("local address");
+    tokio::spawn(async move {
+        axum::serve(listener, router.into_make_service_with_connect_info::<SocketAddr>()).await
+    });
+    addr
+}
+
+/// Sends a raw HTTP/1.1 request, which should ask to close the connection, and returns
+/// the raw response.
+pub(crate) async fn raw_request(addr: SocketAddr, request: &str) -> String {
+    let request = request.to_string();
+    tokio::task::spawn_blocking(move || {
+        use std::io::{Read, Write};
+        let mut stream = std::net::TcpStream::connect(addr).expect("connect to the server");
+        stream.write_all(request.as_bytes()).expect("send the request");
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).expect("read the response");
+        String::from_utf8_lossy(&response).into_owned()
+    }).await.expect("request task")
+}
+
+/// An invoker answering with the value of a request header, empty if it's missing, to
+/// check what the host passes to the plugin.
+pub(crate) struct EchoHeader(pub(crate) &'static str);
+
+impl PluginInvoker for EchoHeader {
+    fn invoke(&self, headers: HeaderMap, _body: String) -> String {
+        headers.get(self.0)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_string()
+    }
+}
+
+/// A response body of a known size failing when it's read, like a connection reset.
+struct FailingBody;
+
+impl hyper::body::Body for FailingBody {
+    type Data = axum::body::Bytes;
+    type Error = std::io::Error;
+
+    fn poll_frame(
+        self: std::pin::Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Result<hyper::body::Frame<Self::Data>, Self::Error>>> {
+        std::task::Poll::Ready(Some(Err(std::io::Error::other("connection reset"))))
+    }
+
+    fn size_hint(&self) -> hyper::body::SizeHint {
+        hyper::body::SizeHint::with_exact(16)
+    }
+}
+
+/// Returns a `200 OK` response of the given content type whose body fails when it's read.
+pub(crate) fn failing_response(content_type: &str) -> Response {
+    let mut response = Response::new(Body::new(FailingBody));
+    response.headers_mut().insert(
+        hyper::header::CONTENT_TYPE,
+        content_type.parse().expect("valid content type"),
+    );
+    response
+}
+
+/// An invoker panicking on every call, like a plugin handing the host an invalid output.
+pub(crate) struct Panicking;
+
+impl PluginInvoker for Panicking {
+    fn invoke(&self, _headers: HeaderMap, _body: String) -> String {
+        panic!("the plugin returned a null pointer");
+    }
+}
+
+/// A `tracing` subscriber recording the messages of the events, for the tests asserting
+/// what's logged.
+#[derive(Clone, Default)]
+pub(crate) struct Logs(Arc<Mutex<Vec<String>>>);
+
+impl Logs {
+
+    /// Records the events of the current thread until the guard is dropped.
+    pub(crate) fn capture() -> (Self, tracing::subscriber::DefaultGuard) {
+        let logs = Logs::default();
+        let guard = tracing::subscriber::set_default(logs.clone());
+        (logs, guard)
+    }
+
+    /// Returns the messages recorded so far.
+    pub(crate) fn messages(&self) -> Vec<String> {
+        self.0.lock().expect("logs lock").clone()
+    }
+}
+
+/// Formats the `message` field of an event.
+struct Message(String);
+
+impl tracing::field::Visit for Message {
+    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            self.0 = format!("{:?}", value);
+        }
+    }
+}
+
+impl tracing::Subscriber for Logs {
+    fn enabled(&self, _metadata: &tracing::Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, _span: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+        tracing::span::Id::from_u64(1)
+    }
+
+    fn record(&self, _span: &tracing::span::Id, _values: &tracing::span::Record<'_>) {}
+
+    fn record_follows_from(&self, _span: &tracing::span::Id, _follows: &tracing::span::Id) {}
+
+    fn event(&self, event: &tracing::Event<'_>) {
+        let mut message = Message(String::new());
+        event.record(&mut message);
+        self.0.lock().expect("logs lock").push(message.0);
+    }
+
+    fn enter(&self, _span: &tracing::span::Id) {}
+
+    fn exit(&self, _span: &tracing::span::Id) {}
+}
+
+/// The global allocator of the tests, counting the bytes allocated by each thread so
+/// a test can check that code it runs doesn't leak.
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATED: std::cell::Cell<isize> = const { std::cell::Cell::new(0) };
+}
+
+/// Adds to the bytes allocated by the current thread, unless it's being torn down.
+fn count(bytes: isize) {
+    let _ = ALLOCATED.try_with(|allocated| allocated.set(allocated.get() + bytes));
+}
+
+unsafe impl std::alloc::GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
+        count(layout.size() as isize);
+        std::alloc::System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+        count(-(layout.size() as isize));
+        std::alloc::System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Returns the bytes allocated and not yet freed by the current thread.
+pub(crate) fn allocated_bytes() -> isize {
+    ALLOCATED.with(std::cell::Cell::get)
+}
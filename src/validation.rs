@@ -0,0 +1,107 @@
+//! A consistent shape for the `400 Bad Request` responses of rejected input.
+//!
+//! The errors are returned as `{"errors": [{"field": "email", "message": "invalid"}]}`,
+//! so frontends can map them to form fields.
+
+use axum::response::{IntoResponse, Json, Response};
+use hyper::StatusCode;
+use serde::Serialize;
+
+/// A single validation error, optionally located at a request field.
+#[derive(Debug, Clone, Serialize)]
+pub struct ValidationError {
+    /// The rejected field (e.g. `email`, `body`, `query.page`), if the error has a location.
+    pub field: Option<String>,
+    /// A human-readable description of the error.
+    pub message: String,
+}
+
+/// A list of validation errors, rendered as a `400 Bad Request` JSON response.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ValidationErrors {
+    errors: Vec<ValidationError>,
+}
+
+impl ValidationErrors {
+
+    /// Creates an empty list of validation errors.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an error located at a field.
+    pub fn field(mut self, field: impl Into<String>, message: impl Into<String>) -> Self {
+        self.errors.push(ValidationError {
+            field: Some(field.into()),
+            message: message.into(),
+        });
+        self
+    }
+
+    /// Adds an error that isn't tied to a field.
+    pub fn error(mut self, message: impl Into<String>) -> Self {
+        self.errors.push(ValidationError {
+            field: None,
+            message: message.into(),
+        });
+        self
+    }
+
+    /// Returns whether no error was added.
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    /// Returns the errors added so far.
+    pub fn errors(&self) -> &[ValidationError] {
+        &self.errors
+    }
+}
+
+impl IntoResponse for ValidationErrors {
+    fn into_response(self) -> Response {
+        (StatusCode::BAD_REQUEST, Json(self)).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TempDir};
+    use axum::{body::Body, extract::Request};
+    use serde_json::{json, Value};
+
+    #[tokio::test]
+    async fn rejected_body_is_reported_in_the_envelope() {
+        let dir = TempDir::new();
+        let (plugin, invoker) = testing::route_plugin(r#"{"path": "/", "method_router": "post"}"#, "ok");
+        let router = testing::plugins(&dir, true)
+            .register("form", plugin)
+            .load()
+            .unwrap();
+
+        let request = Request::post("/form").body(Body::from(vec![0xff, 0xfe])).unwrap();
+        let response = testing::send(&router, request).await;
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.headers()[hyper::header::CONTENT_TYPE], "application/json");
+        let body: Value = serde_json::from_str(&testing::body_string(response).await).unwrap();
+        let errors = body["errors"].as_array().unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0]["field"], "body");
+        assert!(errors[0]["message"].as_str().unwrap().starts_with("Request body didn't contain valid UTF-8"));
+        assert_eq!(invoker.calls(), 0);
+    }
+
+    #[test]
+    fn errors_without_a_field_have_a_null_field() {
+        let errors = ValidationErrors::new()
+            .field("email", "invalid")
+            .error("too many requests");
+
+        assert_eq!(serde_json::to_value(&errors).unwrap(), json!({"errors": [
+            {"field": "email", "message": "invalid"},
+            {"field": null, "message": "too many requests"},
+        ]}));
+    }
+}
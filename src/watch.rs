@@ -0,0 +1,87 @@
+//! Reloading of the plugins when the plugins directory changes, enabled by the `watch`
+//! feature.
+//!
+//! The plugins directory is watched recursively with `notify`. A change to a
+//! `plugin.json` file or a library, or a removed file or directory, reloads the
+//! plugins, which swaps the new router into [`crate::Plugins::serving_router`]: newly
+//! enabled plugins are mounted and disabled or removed ones are dropped, without
+//! restarting the server. The events are debounced, since copying a library or editing
+//! a file raises several of them, so a burst of changes results in a single reload.
+
+use crate::log::{error, info};
+use crate::Plugins;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::{mpsc, Weak};
+use std::time::Duration;
+
+/// How long the directory must be quiet after a change before the plugins are reloaded.
+const DEBOUNCE: Duration = Duration::from_millis(250);
+
+/// The file extensions of the plugin libraries.
+const LIBRARY_EXTENSIONS: [&str; 3] = ["so", "dylib", "dll"];
+
+/// Checks whether an event can change the loaded plugins. Other files, such as a routes
+/// cache kept in the plugins directory, are ignored.
+fn is_relevant(event: &Event) -> bool {
+    match event.kind {
+        EventKind::Remove(_) => true,
+        EventKind::Create(_) | EventKind::Modify(_) => event.paths.iter().any(|path| {
+            path.file_name().is_some_and(|name| name == "plugin.json")
+                || path.extension().is_some_and(|ext| LIBRARY_EXTENSIONS.iter().any(|lib| ext == *lib))
+        }),
+        _ => false,
+    }
+}
+
+/// Starts watching the plugins directory, reloading `plugins` on every relevant change.
+///
+/// The reloads run on a dedicated thread, which stops when the returned watcher or the
+/// `Plugins` instance is dropped.
+///
+/// # Arguments
+/// * `plugins` - The instance to reload, not kept alive by the watcher.
+/// * `dir` - The plugins directory.
+///
+/// # Returns
+/// The watcher, which must be kept for the directory to be watched.
+pub(crate) fn start(plugins: Weak<Plugins>, dir: &Path) -> notify::Result<RecommendedWatcher> {
+
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+        match event {
+            Ok(event) if is_relevant(&event) => {
+                let _ = sender.send(());
+            },
+            Ok(_) => {},
+            Err(e) => error!("Error watching plugins directory: {}", e),
+        }
+    })?;
+    watcher.watch(dir, RecursiveMode::Recursive)?;
+
+    std::thread::spawn(move || {
+        while receiver.recv().is_ok() {
+            // Waits for the burst of events to end
+            loop {
+                match receiver.recv_timeout(DEBOUNCE) {
+                    Ok(()) => continue,
+                    Err(mpsc::RecvTimeoutError::Timeout) => break,
+                    Err(mpsc::RecvTimeoutError::Disconnected) => return,
+                }
+            }
+
+            let Some(plugins) = plugins.upgrade() else {
+                return;
+            };
+            info!("Plugins directory changed, reloading plugins");
+            match panic::catch_unwind(AssertUnwindSafe(|| plugins.reload())) {
+                Ok(Ok(_)) => {},
+                Ok(Err(e)) => error!("Error reloading plugins, keeping the current router: {}", e),
+                Err(_) => error!("Error reloading plugins, keeping the current router: reload panicked"),
+            }
+        }
+    });
+
+    Ok(watcher)
+}